@@ -221,6 +221,23 @@ impl FileStructureSegment {
 	}
 }
 
+// Case fold a relative path, keeping the casing of the segments found in the file structure
+pub fn case_fold_to_structure(file_structure: &Vec<FileStructureSegment>, path: &str) -> String {
+	let case_folded_path = path.replace("\\", "/").to_lowercase();
+	let split_path = case_folded_path
+		.split("/")
+		.map(|s| s.to_string())
+		.collect::<Vec<String>>();
+
+	for segment in file_structure {
+		if let Some(found_path) = segment.case_fold_path(split_path.clone()) {
+			return found_path;
+		}
+	}
+
+	return case_folded_path;
+}
+
 pub fn list_file_structure_relatively(
 	base_path: PathBuf,
 ) -> std::io::Result<Vec<FileStructureSegment>> {
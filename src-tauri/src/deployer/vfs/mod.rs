@@ -1,6 +1,7 @@
 use self::base_vfs::{BaseVFS, VFSMountConfig};
 
 pub mod base_vfs;
+pub mod namespace;
 pub mod union_fs;
 pub mod union_fs_fuse;
 
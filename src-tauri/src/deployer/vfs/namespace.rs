@@ -0,0 +1,361 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::controllers::file_controller;
+
+use super::base_vfs::VFSMountConfig;
+
+// Mount options are limited to a page
+static MAX_MOUNT_OPTIONS_LENGTH: usize = 4000;
+
+// Quote for "sh", as a single argument
+fn shell_quote(value: &str) -> String {
+	return format!("'{}'", value.replace('\'', "'\\''"));
+}
+
+// Paths in overlay options can't contain unescaped separators
+fn overlay_path(path: &PathBuf) -> String {
+	return path
+		.to_string_lossy()
+		.replace('\\', "\\\\")
+		.replace(',', "\\,")
+		.replace(':', "\\:");
+}
+
+// Layers to combine, top-most first
+fn lower_layers(mount_config: &VFSMountConfig) -> Vec<&PathBuf> {
+	let paths = &mount_config.paths;
+
+	let mut lower_layers: Vec<&PathBuf> = source_layers(mount_config);
+	if mount_config.should_overlay_target {
+		lower_layers.push(&paths.target);
+	}
+
+	return lower_layers;
+}
+
+fn source_layers(mount_config: &VFSMountConfig) -> Vec<&PathBuf> {
+	return mount_config
+		.paths
+		.sources
+		.iter()
+		.rev()
+		.filter(|path| path.exists())
+		.collect();
+}
+
+// Deepest folder containing every layer, mods ones all live in the instance
+fn layers_base(layers: &Vec<&PathBuf>) -> Option<PathBuf> {
+	let mut base = layers.first()?.parent()?.to_path_buf();
+	for layer in layers.iter() {
+		while !layer.parent()?.starts_with(&base) {
+			base = base.parent()?.to_path_buf();
+		}
+	}
+
+	return match base.parent() {
+		Some(_) => Some(base),
+		// Nothing gained from the filesystem root
+		None => None,
+	};
+}
+
+// Folders an isolated run writes to, kept while it runs. Overlayfs can't share
+// an upper or work folder between mounts, so each run gets its own work folders.
+#[derive(Debug, Clone)]
+pub struct NamespaceRun {
+	pub upper_dirs: Vec<PathBuf>,
+	pub workdirs: Vec<PathBuf>,
+}
+
+impl NamespaceRun {
+	pub fn new(mount_configs: &Vec<VFSMountConfig>) -> Self {
+		let run_id = uuid::Uuid::new_v4().simple().to_string();
+
+		return Self {
+			// Bind mounts can share their folder
+			upper_dirs: mount_configs
+				.iter()
+				.filter(|mount_config| !lower_layers(mount_config).is_empty())
+				.map(|mount_config| mount_config.paths.overwrite.clone())
+				.collect(),
+			workdirs: mount_configs
+				.iter()
+				.map(|mount_config| mount_config.paths.workdir.join(&run_id))
+				.collect(),
+		};
+	}
+
+	// Upper folder of this run also written by the other one, if any
+	pub fn shared_upper_dir(&self, other: &NamespaceRun) -> Option<&PathBuf> {
+		return self
+			.upper_dirs
+			.iter()
+			.find(|upper_dir| other.upper_dirs.contains(upper_dir));
+	}
+
+	pub fn delete_workdirs(&self) {
+		for workdir in self.workdirs.iter() {
+			// Overlayfs leaves its own work folder without any permission
+			let work_path = workdir.join("work");
+			if work_path.is_dir() {
+				let _ =
+					std::fs::set_permissions(&work_path, std::fs::Permissions::from_mode(0o700));
+			}
+
+			if workdir.exists() {
+				if let Err(e) = std::fs::remove_dir_all(workdir) {
+					println!("Failed to delete VFS work folder {:?}: {}", workdir, e);
+				}
+			}
+		}
+	}
+}
+
+// Shell command mounting one sub-VFS. Kernel overlayfs is used, the target
+// itself being the lowest layer, so nothing has to be moved out of the way.
+fn mount_command(mount_config: &VFSMountConfig, workdir: &PathBuf) -> Result<String, String> {
+	let paths = &mount_config.paths;
+
+	// Nothing to combine, the writable folder is used as is
+	if lower_layers(mount_config).is_empty() {
+		return Ok(format!(
+			"mount --bind {} {}",
+			shell_quote(&paths.overwrite.to_string_lossy()),
+			shell_quote(&paths.target.to_string_lossy())
+		));
+	}
+
+	// Sources are relative to their common folder, options are limited to a page
+	let sources = source_layers(mount_config);
+	let base = layers_base(&sources);
+	let mut layers: Vec<String> = sources
+		.iter()
+		.map(|path| match &base {
+			Some(base) => overlay_path(&path.strip_prefix(base).unwrap().to_path_buf()),
+			None => overlay_path(path),
+		})
+		.collect();
+	if mount_config.should_overlay_target {
+		layers.push(overlay_path(&paths.target));
+	}
+	let other_options = format!(
+		"upperdir={},workdir={},userxattr",
+		overlay_path(&paths.overwrite),
+		overlay_path(workdir)
+	);
+
+	let mut options = format!("lowerdir={},{}", layers.join(":"), other_options);
+	if options.len() > MAX_MOUNT_OPTIONS_LENGTH {
+		if layers
+			.iter()
+			.any(|layer| layer.len() > MAX_MOUNT_OPTIONS_LENGTH)
+		{
+			return Err(format!(
+				"Paths too long to mount \"{}\" in a mount namespace",
+				mount_config.mount_name
+			));
+		}
+
+		// One option per layer (Linux 6.8+), each one is passed to the kernel on its own
+		options = format!(
+			"{},{}",
+			layers
+				.iter()
+				.map(|layer| format!("lowerdir+={}", layer))
+				.collect::<Vec<String>>()
+				.join(","),
+			other_options
+		);
+	}
+
+	let mount = format!(
+		"mount -t overlay overlay -o {} {}",
+		shell_quote(&options),
+		shell_quote(&paths.target.to_string_lossy())
+	);
+
+	return Ok(match base {
+		// In a subshell, the command still runs from the current folder
+		Some(base) => format!("(cd {}; {})", shell_quote(&base.to_string_lossy()), mount),
+		None => mount,
+	});
+}
+
+// Script mounting everything, then replacing itself with the command.
+// Parent folders are mounted before the folders they contain.
+pub fn namespace_script(
+	mount_configs: &Vec<VFSMountConfig>,
+	run: &NamespaceRun,
+	command: &str,
+) -> Result<String, String> {
+	let mut mounts: Vec<(&VFSMountConfig, &PathBuf)> =
+		mount_configs.iter().zip(run.workdirs.iter()).collect();
+	mounts.sort_by_key(|(mount_config, _)| mount_config.paths.target.components().count());
+
+	let mut lines = vec![String::from("set -e")];
+	for (mount_config, workdir) in mounts {
+		lines.push(mount_command(mount_config, workdir)?);
+	}
+	lines.push(format!("exec sh -c {}", shell_quote(command)));
+
+	return Ok(lines.join("\n"));
+}
+
+// "unshare" gives the command its own user and mount namespaces: mounts are private
+// to the process tree and gone once it exits, the user id stays the same.
+pub fn namespace_command(
+	mount_configs: &Vec<VFSMountConfig>,
+	run: &NamespaceRun,
+	command: &str,
+) -> Result<Command, String> {
+	for (mount_config, workdir) in mount_configs.iter().zip(run.workdirs.iter()) {
+		for folder in [&mount_config.paths.overwrite, workdir] {
+			file_controller::create_folder(folder)
+				.map_err(|e| format!("Failed to create folder {:?}: {}", folder, e.to_string()))?;
+		}
+	}
+
+	let script = namespace_script(mount_configs, run, command)?;
+
+	let mut process_command = Command::new("unshare");
+	process_command
+		.arg("--user")
+		.arg("--map-current-user")
+		.arg("--mount")
+		.arg("sh")
+		.arg("-c")
+		.arg(script);
+
+	return Ok(process_command);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::deployer::vfs::base_vfs::VFSMountPaths;
+
+	fn mount_config(name: &str, target: &str, sources: Vec<&str>, overlay: bool) -> VFSMountConfig {
+		return VFSMountConfig {
+			mount_name: name.to_string(),
+			command: None,
+			paths: VFSMountPaths {
+				target: PathBuf::from(target),
+				sources: sources.into_iter().map(PathBuf::from).collect(),
+				overwrite: PathBuf::from("/instance/mods/overwrite"),
+				workdir: PathBuf::from("/instance/.vfs_workdir").join(name),
+			},
+			should_overlay_target: overlay,
+		};
+	}
+
+	fn test_run(mount_configs: &Vec<VFSMountConfig>) -> NamespaceRun {
+		let mut run = NamespaceRun::new(mount_configs);
+		run.workdirs = mount_configs
+			.iter()
+			.map(|mount_config| mount_config.paths.workdir.join("run"))
+			.collect();
+
+		return run;
+	}
+
+	#[test]
+	fn builds_namespace_script() {
+		let dir = tempfile::tempdir().unwrap();
+		let mod_a = dir.path().join("a");
+		let mod_b = dir.path().join("b,c");
+		std::fs::create_dir_all(&mod_a).unwrap();
+		std::fs::create_dir_all(&mod_b).unwrap();
+
+		let mount_configs = vec![
+			mount_config(
+				"mods",
+				"/game/Data",
+				vec![mod_a.to_str().unwrap(), mod_b.to_str().unwrap(), "/missing"],
+				true,
+			),
+			mount_config("root", "/game", vec![mod_a.to_str().unwrap()], true),
+			mount_config("saves", "/saves", Vec::new(), false),
+		];
+		let script = namespace_script(
+			&mount_configs,
+			&test_run(&mount_configs),
+			"wine 'Skyrim SE.exe'",
+		)
+		.unwrap();
+
+		let lines: Vec<&str> = script.lines().collect();
+		assert_eq!(lines[0], "set -e");
+		// The game folder is mounted before the Data folder it contains
+		assert!(lines[1].ends_with(" '/game')"));
+		// Top-most layer first, relative to their folder, missing ones skipped,
+		// separators escaped
+		assert_eq!(
+			lines[3],
+			format!(
+				"(cd '{}'; mount -t overlay overlay -o 'lowerdir=b\\,c:a:/game/Data,upperdir=/instance/mods/overwrite,workdir=/instance/.vfs_workdir/mods/run,userxattr' '/game/Data')",
+				dir.path().to_str().unwrap(),
+			)
+		);
+		assert_eq!(lines[2], "mount --bind '/instance/mods/overwrite' '/saves'");
+		assert_eq!(lines[4], "exec sh -c 'wine '\\''Skyrim SE.exe'\\'''");
+	}
+
+	#[test]
+	fn mounts_many_layers() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut sources = Vec::new();
+		for index in 0..300 {
+			let source = dir
+				.path()
+				.join(format!("mods/Mod number {}/versions/1.0", index));
+			std::fs::create_dir_all(&source).unwrap();
+			sources.push(source.to_str().unwrap().to_string());
+		}
+
+		let mount_configs = vec![mount_config(
+			"mods",
+			"/game/Data",
+			sources.iter().map(|source| source.as_str()).collect(),
+			true,
+		)];
+		let script = namespace_script(&mount_configs, &test_run(&mount_configs), "game").unwrap();
+
+		// Too long for a single "lowerdir" option, even with relative paths
+		let mount = script.lines().nth(1).unwrap();
+		assert!(mount.starts_with(&format!(
+			"(cd '{}'; mount -t overlay overlay -o 'lowerdir+=Mod number 299/versions/1.0,",
+			dir.path().join("mods").to_str().unwrap()
+		)));
+		assert!(mount.contains(",lowerdir+=/game/Data,upperdir="));
+	}
+
+	#[test]
+	fn refuses_shared_upper_folders() {
+		let dir = tempfile::tempdir().unwrap();
+		let mod_a = dir.path().join("a");
+		std::fs::create_dir_all(&mod_a).unwrap();
+
+		let mount_configs = vec![
+			mount_config("mods", "/game/Data", vec![mod_a.to_str().unwrap()], true),
+			mount_config("saves", "/saves", Vec::new(), false),
+		];
+		let run = NamespaceRun::new(&mount_configs);
+		let other_run = NamespaceRun::new(&mount_configs);
+
+		// Each run has its own work folders
+		assert_ne!(run.workdirs, other_run.workdirs);
+		assert_eq!(
+			run.shared_upper_dir(&other_run),
+			Some(&PathBuf::from("/instance/mods/overwrite"))
+		);
+
+		// Bind mounts can be shared
+		let bind_configs = vec![mount_configs[1].clone()];
+		assert_eq!(
+			NamespaceRun::new(&bind_configs).shared_upper_dir(&other_run),
+			None
+		);
+	}
+}
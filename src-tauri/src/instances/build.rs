@@ -0,0 +1,888 @@
+use base64::engine::general_purpose;
+use base64::Engine;
+use file_integrity::hash_file;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::modlist::{ModlistArchive, ModlistManifest, ModlistNexusIds};
+use super::GameInstance;
+use crate::controllers::file_controller;
+use crate::mods::installer;
+
+// Bumped whenever the build format changes in a non backwards-compatible way
+pub const BUILD_FORMAT_VERSION: u32 = 2;
+
+// Bigger files not found in any archive are left out of the build (e.g. generated LODs)
+pub const MAX_INLINE_FILE_SIZE: u64 = 8 * 1024 * 1024;
+
+// Where a file comes from
+#[derive(Type, Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum BuildDirective {
+	// Copied from an archive, extracted (and case-folded) like a regular install
+	FromArchive {
+		archive_md5: String,
+		archive_path: String,
+	},
+	// Not found in any archive (generated or edited by hand), stored in the build
+	Inline {
+		data: String,
+	},
+	// Not found in any archive and too big to be stored, to be provided by hand
+	External,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct BuildFile {
+	// Relative to the version folder
+	pub path: String,
+	pub md5: String,
+	pub directive: BuildDirective,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct BuildModVersion {
+	pub mod_name: String,
+	pub identifier: String,
+	pub files: Vec<BuildFile>,
+}
+
+// Everything needed to rebuild the mods of an instance from its archives
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct BuildManifest {
+	pub format_version: u32,
+	// Order, enabled state, selected versions and plugins
+	pub modlist: ModlistManifest,
+	pub archives: Vec<ModlistArchive>,
+	pub versions: Vec<BuildModVersion>,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct BuildHashMismatch {
+	pub mod_name: String,
+	pub version: String,
+	pub path: String,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct BuildInstallReport {
+	// Nothing is installed while archives are missing
+	pub missing_archives: Vec<ModlistArchive>,
+	pub installed_versions: Vec<String>,
+	pub mismatches: Vec<BuildHashMismatch>,
+	// External files that could not be found
+	pub missing_files: Vec<BuildHashMismatch>,
+}
+
+// A file in an extracted archive
+#[derive(Clone, Debug)]
+pub struct ArchiveFileSource {
+	pub archive_md5: String,
+	pub archive_path: String,
+}
+
+impl BuildManifest {
+	pub fn load(path: &PathBuf) -> Result<Self, String> {
+		let json = file_controller::read_file(path.clone())
+			.map_err(|e| format!("Failed to read build: {}", e))?;
+
+		let manifest: BuildManifest =
+			serde_json::from_str(&json).map_err(|e| format!("Failed to parse build: {}", e))?;
+
+		if manifest.format_version > BUILD_FORMAT_VERSION {
+			return Err(format!(
+				"Build format version {} is not supported, please update",
+				manifest.format_version
+			));
+		}
+
+		return Ok(manifest);
+	}
+
+	pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+		let json =
+			serde_json::to_string(self).map_err(|e| format!("Failed to serialize build: {}", e))?;
+
+		return file_controller::save_file(path.clone(), json.as_bytes())
+			.map_err(|e| format!("Failed to save build: {}", e));
+	}
+}
+
+fn md5_of(path: &PathBuf) -> String {
+	return hash_file(path.to_string_lossy().to_string())
+		.md5_hash
+		.to_lowercase();
+}
+
+// Files of a folder, relative to it, with "/" separators
+fn list_relative_files(root: &PathBuf) -> Result<Vec<String>, String> {
+	if !root.is_dir() {
+		return Ok(Vec::new());
+	}
+
+	let mut files = file_controller::list_files_recursively_flattened(root.clone())
+		.map_err(|e| format!("Failed to list files: {}", e))?
+		.into_iter()
+		.filter_map(|file| {
+			PathBuf::from(file)
+				.strip_prefix(root)
+				.ok()
+				.map(|relative| relative.to_string_lossy().replace("\\", "/"))
+		})
+		.collect::<Vec<String>>();
+	files.sort();
+
+	return Ok(files);
+}
+
+// Index every file of an extracted archive by its hash
+pub fn index_extracted_archive(
+	index: &mut HashMap<String, ArchiveFileSource>,
+	archive_md5: &str,
+	extracted_path: &PathBuf,
+) -> Result<(), String> {
+	for relative_path in list_relative_files(extracted_path)? {
+		let md5 = md5_of(&extracted_path.join(&relative_path));
+
+		index.entry(md5).or_insert(ArchiveFileSource {
+			archive_md5: archive_md5.to_string(),
+			archive_path: relative_path,
+		});
+	}
+
+	return Ok(());
+}
+
+// Directives for every file of a mod version
+pub fn compile_version_files(
+	version_path: &PathBuf,
+	index: &HashMap<String, ArchiveFileSource>,
+) -> Result<Vec<BuildFile>, String> {
+	let mut files = Vec::new();
+
+	for relative_path in list_relative_files(version_path)? {
+		let absolute_path = version_path.join(&relative_path);
+		let md5 = md5_of(&absolute_path);
+
+		let directive = match index.get(&md5) {
+			Some(source) => BuildDirective::FromArchive {
+				archive_md5: source.archive_md5.clone(),
+				archive_path: source.archive_path.clone(),
+			},
+			None => {
+				let size = std::fs::metadata(&absolute_path)
+					.map(|metadata| metadata.len())
+					.unwrap_or(0);
+				if size > MAX_INLINE_FILE_SIZE {
+					BuildDirective::External
+				} else {
+					let bytes = file_controller::read_file_bytes(absolute_path)
+						.map_err(|e| format!("Failed to read {}: {}", relative_path, e))?;
+					BuildDirective::Inline {
+						data: general_purpose::STANDARD.encode(bytes),
+					}
+				}
+			}
+		};
+
+		files.push(BuildFile {
+			path: relative_path,
+			md5,
+			directive,
+		});
+	}
+
+	return Ok(files);
+}
+
+// Write the files of a version from the extracted archives (archive md5 -> extracted path)
+// Returns the files whose hash does not match. External files are left to the caller.
+pub fn install_version_files(
+	version_path: &PathBuf,
+	files: &Vec<BuildFile>,
+	extracted_archives: &HashMap<String, PathBuf>,
+) -> Result<Vec<String>, String> {
+	let mut mismatches = Vec::new();
+
+	for file in files.iter() {
+		if file.directive == BuildDirective::External {
+			continue;
+		}
+
+		let destination =
+			file_controller::join_paths(version_path.clone(), PathBuf::from(&file.path));
+		if let Some(parent) = destination.parent() {
+			file_controller::create_folder(&parent.to_path_buf())
+				.map_err(|e| format!("Failed to create folder: {}", e))?;
+		}
+
+		match &file.directive {
+			BuildDirective::FromArchive {
+				archive_md5,
+				archive_path,
+			} => {
+				let extracted_path = extracted_archives
+					.get(archive_md5)
+					.ok_or(format!("Archive {} is not extracted", archive_md5))?;
+				let source = file_controller::join_paths(
+					extracted_path.clone(),
+					PathBuf::from(archive_path),
+				);
+
+				// The same archive file can be used more than once, copy it
+				std::fs::copy(&source, &destination)
+					.map_err(|e| format!("Failed to copy {}: {}", archive_path, e))?;
+			}
+			BuildDirective::Inline { data } => {
+				let bytes = general_purpose::STANDARD
+					.decode(data)
+					.map_err(|e| format!("Invalid inline file {}: {}", file.path, e))?;
+				file_controller::save_file(destination.clone(), &bytes)
+					.map_err(|e| format!("Failed to write {}: {}", file.path, e))?;
+			}
+			BuildDirective::External => {}
+		}
+
+		if md5_of(&destination) != file.md5 {
+			mismatches.push(file.path.clone());
+		}
+	}
+
+	return Ok(mismatches);
+}
+
+// External files are taken from the version being replaced, when they match.
+// Returns the ones that could not be found.
+fn keep_external_files(
+	previous_version_path: &PathBuf,
+	version_path: &PathBuf,
+	files: &Vec<BuildFile>,
+) -> Result<Vec<String>, String> {
+	let mut missing_files = Vec::new();
+
+	for file in files.iter() {
+		if file.directive != BuildDirective::External {
+			continue;
+		}
+
+		let previous =
+			file_controller::join_paths(previous_version_path.clone(), PathBuf::from(&file.path));
+		if !previous.is_file() || md5_of(&previous) != file.md5 {
+			missing_files.push(file.path.clone());
+			continue;
+		}
+
+		let destination =
+			file_controller::join_paths(version_path.clone(), PathBuf::from(&file.path));
+		if let Some(parent) = destination.parent() {
+			file_controller::create_folder(&parent.to_path_buf())
+				.map_err(|e| format!("Failed to create folder: {}", e))?;
+		}
+		// Hardlink when possible, the previous version is deleted afterwards
+		if std::fs::hard_link(&previous, &destination).is_err() {
+			std::fs::copy(&previous, &destination)
+				.map_err(|e| format!("Failed to copy {}: {}", file.path, e))?;
+		}
+	}
+
+	return Ok(missing_files);
+}
+
+fn archive_md5(archive: &ModlistArchive) -> String {
+	return archive.md5.clone().unwrap_or_default().to_lowercase();
+}
+
+// What compiling a build needs, taken from the instance so the archives
+// can be extracted and hashed without holding on to it
+pub struct BuildCompilation {
+	modlist: ModlistManifest,
+	downloads_path: PathBuf,
+	// Archives the versions were installed from, not hashed yet when md5 is None
+	archives: Vec<ModlistArchive>,
+	versions: Vec<(BuildModVersion, PathBuf)>,
+}
+
+impl BuildCompilation {
+	// Record where every file of every mod version comes from
+	pub fn compile(self) -> Result<BuildManifest, String> {
+		let mut archives = self.archives;
+		let downloads_path = self.downloads_path;
+
+		// Index the content of the archives
+		let mut index: HashMap<String, ArchiveFileSource> = HashMap::new();
+		for archive in archives.iter_mut() {
+			let archive_path = downloads_path.join(&archive.file_name);
+			if archive.md5.is_none() {
+				archive.md5 = Some(md5_of(&archive_path));
+			}
+
+			let extracted_path = installer::extract_download(
+				&downloads_path,
+				archive_path,
+				&format!("{}_compile", archive.file_name),
+			)?;
+
+			let indexed =
+				index_extracted_archive(&mut index, &archive_md5(archive), &extracted_path);
+			let _ = file_controller::delete_folder_safe(extracted_path, downloads_path.clone());
+			indexed?;
+		}
+
+		let mut versions = Vec::new();
+		for (mut version, version_path) in self.versions {
+			version.files = compile_version_files(&version_path, &index)?;
+			versions.push(version);
+		}
+
+		// Only keep the archives that are used
+		let mut used_archives: Vec<ModlistArchive> = archives
+			.into_iter()
+			.filter(|archive| {
+				let md5 = archive_md5(archive);
+				versions.iter().any(|version| {
+					version.files.iter().any(|file| match &file.directive {
+						BuildDirective::FromArchive { archive_md5, .. } => *archive_md5 == md5,
+						_ => false,
+					})
+				})
+			})
+			.collect();
+		used_archives.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+
+		return Ok(BuildManifest {
+			format_version: BUILD_FORMAT_VERSION,
+			modlist: self.modlist,
+			archives: used_archives,
+			versions,
+		});
+	}
+}
+
+impl GameInstance {
+	fn modlist_archive(&self, file_name: &str, md5: Option<String>) -> ModlistArchive {
+		let download = self
+			.downloads
+			.iter()
+			.find(|download| download.file_name == file_name);
+
+		return ModlistArchive {
+			file_name: file_name.to_string(),
+			md5: md5.map(|md5| md5.to_lowercase()),
+			size: std::fs::metadata(self.get_downloads_absolute_path().join(file_name))
+				.ok()
+				.map(|metadata| metadata.len().to_string()),
+			url: download
+				.filter(|download| download.nexus_data.is_none() && !download.url.is_empty())
+				.map(|download| download.url.clone()),
+			nexus: download
+				.and_then(|download| download.nexus_data.as_ref())
+				.map(|nexus_data| ModlistNexusIds {
+					game_domain: nexus_data.game_domain.clone(),
+					mod_id: nexus_data.mod_id.clone(),
+					file_id: nexus_data.file_id.clone(),
+				}),
+		};
+	}
+
+	// Downloaded archives, hashed, by md5
+	fn downloaded_archives_by_md5(&mut self) -> Result<HashMap<String, ModlistArchive>, String> {
+		let mut archives = HashMap::new();
+
+		for (file_name, path) in self.list_downloaded_archives()? {
+			let download = self
+				.downloads
+				.iter_mut()
+				.find(|download| download.file_name == file_name);
+
+			// Remember the hash, compiling the next time is faster
+			let md5 = match download {
+				Some(download) if download.md5.is_some() => download.md5.clone().unwrap(),
+				Some(download) => {
+					download.md5 = Some(md5_of(&path));
+					download.md5.clone().unwrap()
+				}
+				None => md5_of(&path),
+			}
+			.to_lowercase();
+
+			archives.insert(md5.clone(), self.modlist_archive(&file_name, Some(md5)));
+		}
+
+		return Ok(archives);
+	}
+
+	// Versions to compile, and the downloaded archives they were installed from.
+	// Versions installed before sources were recorded may come from any archive, every
+	// archive is indexed then (unused ones are left out of the build).
+	pub fn prepare_build_compilation(&self) -> Result<BuildCompilation, String> {
+		let mut versions = Vec::new();
+		let mut sources = Vec::new();
+		let mut has_unknown_sources = false;
+		for instance_mod in self.mods.iter() {
+			if instance_mod.name == "base" || instance_mod.name == "overwrite" {
+				continue;
+			}
+
+			for version in instance_mod.versions.iter() {
+				match &version.source {
+					Some(source) => sources.push(source.clone()),
+					None => has_unknown_sources = true,
+				}
+
+				versions.push((
+					BuildModVersion {
+						mod_name: instance_mod.name.clone(),
+						identifier: version.identifier.clone(),
+						files: Vec::new(),
+					},
+					instance_mod.get_version_absolute_path(version.identifier.clone()),
+				));
+			}
+		}
+
+		let mut archives = Vec::new();
+		for (file_name, _) in self.list_downloaded_archives()? {
+			let md5 = self
+				.downloads
+				.iter()
+				.find(|download| download.file_name == file_name)
+				.and_then(|download| download.md5.clone())
+				.map(|md5| md5.to_lowercase());
+
+			let is_source = sources.iter().any(|source| {
+				source.archive_file_name == file_name
+					|| (md5.is_some()
+						&& source.archive_md5.as_ref().map(|md5| md5.to_lowercase()) == md5)
+			});
+			if is_source || has_unknown_sources {
+				archives.push(self.modlist_archive(&file_name, md5));
+			}
+		}
+
+		return Ok(BuildCompilation {
+			modlist: self.export_modlist()?,
+			downloads_path: self.get_downloads_absolute_path(),
+			archives,
+			versions,
+		});
+	}
+
+	// Keep the hashes computed while compiling, the next compilation is faster
+	pub fn remember_archive_hashes(
+		&mut self,
+		archives: &Vec<ModlistArchive>,
+	) -> Result<(), String> {
+		for archive in archives.iter() {
+			if let Some(download) = self
+				.downloads
+				.iter_mut()
+				.find(|download| download.file_name == archive.file_name && download.md5.is_none())
+			{
+				download.md5 = archive.md5.clone();
+			}
+		}
+
+		return self.save();
+	}
+
+	// Rebuild the mod versions of a build from the downloaded archives
+	// Existing versions with the same identifier are replaced
+	pub fn install_build(&mut self, build: &BuildManifest) -> Result<BuildInstallReport, String> {
+		let mut report = BuildInstallReport::default();
+		let downloads_path = self.get_downloads_absolute_path();
+
+		let downloaded = self.downloaded_archives_by_md5()?;
+		report.missing_archives = build
+			.archives
+			.iter()
+			.filter(|archive| !downloaded.contains_key(&archive_md5(archive)))
+			.cloned()
+			.collect();
+		if report.missing_archives.len() > 0 {
+			return Ok(report);
+		}
+
+		// Extract every archive once
+		let mut extracted_archives: HashMap<String, PathBuf> = HashMap::new();
+		let mut result = Ok(());
+		for archive in build.archives.iter() {
+			let md5 = archive_md5(archive);
+			let file_name = downloaded.get(&md5).unwrap().file_name.clone();
+
+			match installer::extract_download(
+				&downloads_path,
+				downloads_path.join(&file_name),
+				&format!("{}_build", file_name),
+			) {
+				Ok(extracted_path) => {
+					extracted_archives.insert(md5, extracted_path);
+				}
+				Err(e) => {
+					result = Err(e);
+					break;
+				}
+			}
+		}
+
+		if result.is_ok() {
+			result = self.install_build_versions(build, &extracted_archives, &mut report);
+		}
+
+		// Delete extracted files
+		for extracted_path in extracted_archives.into_values() {
+			let _ = file_controller::delete_folder_safe(extracted_path, downloads_path.clone());
+		}
+
+		result?;
+
+		// Order, enabled state, selected versions and plugins
+		self.load_mods()?;
+		self.refresh_file_index(
+			build
+				.versions
+				.iter()
+				.map(|version| version.mod_name.clone())
+				.collect(),
+		)?;
+		self.apply_modlist(&build.modlist)?;
+
+		return Ok(report);
+	}
+
+	fn install_build_versions(
+		&mut self,
+		build: &BuildManifest,
+		extracted_archives: &HashMap<String, PathBuf>,
+		report: &mut BuildInstallReport,
+	) -> Result<(), String> {
+		for version in build.versions.iter() {
+			let info = build
+				.modlist
+				.mods
+				.iter()
+				.find(|modlist_mod| modlist_mod.name == version.mod_name)
+				.map(|modlist_mod| modlist_mod.info.clone())
+				.unwrap_or_default();
+
+			if self
+				.get_mod_by_name(version.mod_name.clone())
+				.filter(|instance_mod| instance_mod.has_version(version.identifier.clone()))
+				.is_none()
+			{
+				self.create_mod_version(
+					version.mod_name.clone(),
+					version.identifier.clone(),
+					info,
+				)?;
+				self.load_mods()?;
+			}
+			let instance_mod = self
+				.get_mod_by_name(version.mod_name.clone())
+				.ok_or(format!("Mod {} not found", version.mod_name))?
+				.clone();
+
+			// Built apart, so the existing version is kept if anything fails,
+			// then swapped in: the tree is exactly the recorded one
+			let version_path = instance_mod.get_version_absolute_path(version.identifier.clone());
+			let staged_path = instance_mod.get_staged_version_path(&version.identifier);
+			let staging_path = staged_path.parent().unwrap().to_path_buf();
+			if staged_path.exists() {
+				file_controller::delete_folder_safe(staged_path.clone(), staging_path.clone())
+					.map_err(|e| format!("Failed to clear staged version: {}", e))?;
+			}
+			file_controller::create_folder(&staged_path)
+				.map_err(|e| format!("Failed to create version folder: {}", e))?;
+
+			let installed = install_version_files(&staged_path, &version.files, extracted_archives)
+				.and_then(|mismatches| {
+					let missing_files =
+						keep_external_files(&version_path, &staged_path, &version.files)?;
+					return Ok((mismatches, missing_files));
+				})
+				.and_then(|result| {
+					instance_mod.replace_version_folder(&version.identifier)?;
+					return Ok(result);
+				});
+			let (mismatches, missing_files) = match installed {
+				Ok(result) => result,
+				Err(e) => {
+					let _ = file_controller::delete_folder_safe(staged_path, staging_path);
+					return Err(e);
+				}
+			};
+
+			for path in mismatches {
+				report.mismatches.push(BuildHashMismatch {
+					mod_name: version.mod_name.clone(),
+					version: version.identifier.clone(),
+					path,
+				});
+			}
+			for path in missing_files {
+				report.missing_files.push(BuildHashMismatch {
+					mod_name: version.mod_name.clone(),
+					version: version.identifier.clone(),
+					path,
+				});
+			}
+
+			report
+				.installed_versions
+				.push(format!("{} {}", version.mod_name, version.identifier));
+		}
+
+		return Ok(());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::instances::instance_mod::{InstanceModVersionSource, ModInfo};
+	use crate::test_utils::test_instance;
+
+	fn write(path: PathBuf, content: &str) {
+		std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+		std::fs::write(path, content).unwrap();
+	}
+
+	#[test]
+	fn rebuilds_version_from_directives() {
+		let dir = tempfile::tempdir().unwrap();
+
+		// Archive, as extracted and case-folded
+		let extracted_path = dir.path().join("extracted");
+		write(extracted_path.join("textures/sky.dds"), "sky texture");
+		write(extracted_path.join("readme.txt"), "readme");
+
+		// Installed version, with a file edited by hand
+		let version_path = dir.path().join("versions/1.0");
+		write(version_path.join("Textures/sky.dds"), "sky texture");
+		write(version_path.join("Textures/sky_copy.dds"), "sky texture");
+		write(version_path.join("settings.ini"), "[General]\nenabled=1");
+
+		let mut index = HashMap::new();
+		index_extracted_archive(&mut index, "archivemd5", &extracted_path).unwrap();
+
+		let files = compile_version_files(&version_path, &index).unwrap();
+		let paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+		assert_eq!(
+			paths,
+			vec!["Textures/sky.dds", "Textures/sky_copy.dds", "settings.ini"]
+		);
+		assert_eq!(
+			files[1].directive,
+			BuildDirective::FromArchive {
+				archive_md5: "archivemd5".to_string(),
+				archive_path: "textures/sky.dds".to_string(),
+			}
+		);
+		assert!(matches!(files[2].directive, BuildDirective::Inline { .. }));
+
+		// Too big to be stored
+		let big_path = dir.path().join("versions/lod/terrain.bto");
+		std::fs::create_dir_all(big_path.parent().unwrap()).unwrap();
+		std::fs::File::create(&big_path)
+			.unwrap()
+			.set_len(MAX_INLINE_FILE_SIZE + 1)
+			.unwrap();
+		let big_files = compile_version_files(&dir.path().join("versions/lod"), &index).unwrap();
+		assert_eq!(big_files[0].directive, BuildDirective::External);
+
+		// Rebuild somewhere else
+		let rebuilt_path = dir.path().join("rebuilt/1.0");
+		let extracted_archives =
+			HashMap::from([("archivemd5".to_string(), extracted_path.clone())]);
+		let mismatches = install_version_files(&rebuilt_path, &files, &extracted_archives).unwrap();
+
+		assert!(mismatches.is_empty());
+		assert_eq!(
+			std::fs::read_to_string(rebuilt_path.join("Textures/sky_copy.dds")).unwrap(),
+			"sky texture"
+		);
+		assert_eq!(
+			std::fs::read_to_string(rebuilt_path.join("settings.ini")).unwrap(),
+			"[General]\nenabled=1"
+		);
+		assert!(!rebuilt_path.join("readme.txt").exists());
+
+		// A different archive content is reported
+		write(extracted_path.join("textures/sky.dds"), "other texture");
+		let mismatches = install_version_files(&rebuilt_path, &files, &extracted_archives).unwrap();
+		assert_eq!(
+			mismatches,
+			vec!["Textures/sky.dds", "Textures/sky_copy.dds"]
+		);
+	}
+
+	#[test]
+	fn compiles_from_source_archives() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_path_buf();
+		let mut instance = test_instance(&root, "build");
+
+		let downloads_path = instance.get_downloads_absolute_path();
+		write(downloads_path.join("skyui.zip"), "skyui archive");
+		write(downloads_path.join("unrelated.zip"), "unrelated archive");
+
+		let mut instance_mod = instance
+			.create_mod_version(
+				String::from("Skyui"),
+				String::from("1.0"),
+				ModInfo::default(),
+			)
+			.unwrap();
+		instance_mod.get_version_mut("1.0").unwrap().source = Some(InstanceModVersionSource {
+			archive_file_name: String::from("skyui.zip"),
+			archive_md5: None,
+			nexus: None,
+			files: Vec::new(),
+		});
+		instance_mod.save().unwrap();
+		instance.load_mods().unwrap();
+
+		let compilation = instance.prepare_build_compilation().unwrap();
+		let file_names: Vec<&str> = compilation
+			.archives
+			.iter()
+			.map(|archive| archive.file_name.as_str())
+			.collect();
+		assert_eq!(file_names, vec!["skyui.zip"]);
+		assert_eq!(compilation.versions.len(), 1);
+
+		// Installed before sources were recorded, it may come from any archive
+		instance
+			.create_mod_version(
+				String::from("Skse"),
+				String::from("2.0"),
+				ModInfo::default(),
+			)
+			.unwrap();
+		instance.load_mods().unwrap();
+
+		let compilation = instance.prepare_build_compilation().unwrap();
+		let mut file_names: Vec<&str> = compilation
+			.archives
+			.iter()
+			.map(|archive| archive.file_name.as_str())
+			.collect();
+		file_names.sort();
+		assert_eq!(file_names, vec!["skyui.zip", "unrelated.zip"]);
+		assert_eq!(compilation.versions.len(), 2);
+	}
+
+	#[test]
+	fn keeps_version_when_install_fails() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_path_buf();
+		let mut instance = test_instance(&root, "build");
+
+		let instance_mod = instance
+			.create_mod_version(
+				String::from("Lods"),
+				String::from("1.0"),
+				ModInfo::default(),
+			)
+			.unwrap();
+		let version_path = instance_mod.get_selected_version_absolute_path();
+		write(version_path.join("old.txt"), "old");
+		write(version_path.join("terrain.bto"), "generated");
+		instance.load_mods().unwrap();
+
+		let mut build = BuildManifest {
+			format_version: BUILD_FORMAT_VERSION,
+			modlist: instance.export_modlist().unwrap(),
+			archives: Vec::new(),
+			versions: vec![BuildModVersion {
+				mod_name: String::from("Lods"),
+				identifier: String::from("1.0"),
+				files: vec![BuildFile {
+					path: String::from("a.esp"),
+					md5: String::from("0"),
+					directive: BuildDirective::FromArchive {
+						archive_md5: String::from("missing"),
+						archive_path: String::from("a.esp"),
+					},
+				}],
+			}],
+		};
+
+		// The archive is not there, nothing changes
+		let mut report = BuildInstallReport::default();
+		assert!(instance
+			.install_build_versions(&build, &HashMap::new(), &mut report)
+			.is_err());
+		assert!(version_path.join("old.txt").is_file());
+		assert!(!instance_mod.get_staged_version_path("1.0").exists());
+
+		// Replaced, external files are kept from the previous version
+		build.versions[0].files = vec![
+			BuildFile {
+				path: String::from("a.ini"),
+				md5: md5_of(&version_path.join("old.txt")),
+				directive: BuildDirective::Inline {
+					data: general_purpose::STANDARD.encode("old"),
+				},
+			},
+			BuildFile {
+				path: String::from("terrain.bto"),
+				md5: md5_of(&version_path.join("terrain.bto")),
+				directive: BuildDirective::External,
+			},
+			BuildFile {
+				path: String::from("other.bto"),
+				md5: String::from("0"),
+				directive: BuildDirective::External,
+			},
+		];
+		let mut report = BuildInstallReport::default();
+		instance
+			.install_build_versions(&build, &HashMap::new(), &mut report)
+			.unwrap();
+		assert!(!version_path.join("old.txt").exists());
+		assert!(version_path.join("a.ini").is_file());
+		assert!(version_path.join("terrain.bto").is_file());
+		assert!(report.mismatches.is_empty());
+		assert_eq!(report.missing_files[0].path, "other.bto");
+		assert!(!version_path
+			.parent()
+			.unwrap()
+			.with_file_name(".staged_versions")
+			.exists());
+	}
+
+	#[test]
+	fn directives_round_trip() {
+		let files = vec![
+			BuildFile {
+				path: "a.esp".to_string(),
+				md5: "1".to_string(),
+				directive: BuildDirective::FromArchive {
+					archive_md5: "2".to_string(),
+					archive_path: "data/a.esp".to_string(),
+				},
+			},
+			BuildFile {
+				path: "b.ini".to_string(),
+				md5: "3".to_string(),
+				directive: BuildDirective::Inline {
+					data: "YQ==".to_string(),
+				},
+			},
+		];
+
+		let json = serde_json::to_string(&files).unwrap();
+		assert!(json.contains(r#""type":"FromArchive""#));
+
+		let parsed: Vec<BuildFile> = serde_json::from_str(&json).unwrap();
+		assert_eq!(parsed[0].directive, files[0].directive);
+		assert_eq!(parsed[1].directive, files[1].directive);
+	}
+}
@@ -0,0 +1,127 @@
+use std::collections::HashSet;
+
+use super::instance_mod::{InstanceMod, ModInfo};
+use super::GameInstance;
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct ModCategory {
+	pub name: String,
+	// Ids of the game's NexusMods categories that belong to this one
+	#[serde(default)]
+	pub nexus_category_ids: Vec<u32>,
+}
+
+// Trimmed, without empty or repeated (case insensitive) values
+fn clean_labels(labels: &Vec<String>) -> Vec<String> {
+	let mut seen: HashSet<String> = HashSet::new();
+
+	return labels
+		.iter()
+		.map(|label| label.trim().to_string())
+		.filter(|label| !label.is_empty() && seen.insert(label.to_lowercase()))
+		.collect();
+}
+
+fn clean_text(text: &Option<String>) -> Option<String> {
+	return text
+		.as_ref()
+		.map(|text| text.trim().to_string())
+		.filter(|text| !text.is_empty());
+}
+
+impl GameInstance {
+	// Mods lose the categories that are no longer defined
+	pub fn set_categories(&mut self, categories: Vec<ModCategory>) -> Result<(), String> {
+		let mut names: HashSet<String> = HashSet::new();
+		for category in categories.iter() {
+			if category.name.trim().is_empty() {
+				return Err(String::from("Category name cannot be empty"));
+			}
+
+			if !names.insert(category.name.clone()) {
+				return Err(format!("Category \"{}\" is repeated", category.name));
+			}
+		}
+
+		for instance_mod in self.mods.iter_mut() {
+			let categories_count = instance_mod.info.categories.len();
+			instance_mod
+				.info
+				.categories
+				.retain(|category| names.contains(category));
+
+			if instance_mod.info.categories.len() != categories_count {
+				instance_mod.save()?;
+			}
+		}
+
+		self.categories = categories;
+
+		return self.save();
+	}
+
+	pub fn get_categories_for_nexus_id(&self, nexus_category_id: u32) -> Vec<String> {
+		return self
+			.categories
+			.iter()
+			.filter(|category| category.nexus_category_ids.contains(&nexus_category_id))
+			.map(|category| category.name.clone())
+			.collect();
+	}
+
+	// Categories of the NexusMods mod an archive was downloaded from
+	pub fn get_archive_nexus_categories(&self, file_name: &str) -> Vec<String> {
+		return self
+			.downloads
+			.iter()
+			.find(|download| download.file_name == file_name)
+			.and_then(|download| download.nexus_data.as_ref())
+			.and_then(|nexus_data| nexus_data.category_id)
+			.map(|category_id| self.get_categories_for_nexus_id(category_id))
+			.unwrap_or_default();
+	}
+
+	// Categories, tags, notes and color are edited by the user
+	pub fn update_mod_info(
+		&mut self,
+		mod_name: String,
+		mut info: ModInfo,
+	) -> Result<InstanceMod, String> {
+		for category in info.categories.iter() {
+			if !self.categories.iter().any(|c| &c.name == category) {
+				return Err(format!("Category \"{}\" does not exist", category));
+			}
+		}
+
+		info.categories = clean_labels(&info.categories);
+		info.tags = clean_labels(&info.tags);
+		info.notes = clean_text(&info.notes);
+		info.color = clean_text(&info.color);
+
+		let instance_mod = match self.get_mod_by_name(mod_name.clone()) {
+			Some(instance_mod) => instance_mod,
+			None => {
+				return Err(format!("Mod {} not found", mod_name));
+			}
+		};
+
+		instance_mod.info = info;
+		instance_mod.save()?;
+
+		return Ok(instance_mod.clone());
+	}
+
+	// Every tag in use, to suggest them
+	pub fn get_mods_tags(&self) -> Vec<String> {
+		let mut tags: Vec<String> = self
+			.mods
+			.iter()
+			.flat_map(|instance_mod| instance_mod.info.tags.iter().cloned())
+			.collect();
+		tags.sort_by_key(|tag| tag.to_lowercase());
+		tags.dedup_by_key(|tag| tag.to_lowercase());
+
+		return tags;
+	}
+}
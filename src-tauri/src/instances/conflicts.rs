@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::instance_mod::{normalize_mod_file_path, InstanceMod};
+
+// "For this file, the winner mod wins over the loser mod", regardless of the mods order
+#[taurpc::ipc_type]
+#[derive(Debug, PartialEq)]
+pub struct FileConflictRule {
+	// Path relative to the mods deployment folder (case folded)
+	pub path: String,
+	pub winner: String,
+	pub loser: String,
+}
+
+impl FileConflictRule {
+	pub fn new(path: String, winner: String, loser: String) -> Result<Self, String> {
+		let path = normalize_mod_file_path(&path);
+		if path.is_empty() {
+			return Err(String::from("Invalid file path"));
+		}
+
+		if winner == loser {
+			return Err(String::from("A mod can't win over itself"));
+		}
+
+		return Ok(Self {
+			path,
+			winner,
+			loser,
+		});
+	}
+
+	// Whether both rules are about the same file and pair of mods
+	pub fn overlaps(&self, other: &FileConflictRule) -> bool {
+		return self.path == other.path
+			&& ((self.winner == other.winner && self.loser == other.loser)
+				|| (self.winner == other.loser && self.loser == other.winner));
+	}
+}
+
+// A file whose deployed version no longer comes from the top-most mod
+#[derive(Debug, Clone)]
+pub struct ConflictOverride {
+	pub relative_path: String,
+	pub winner: String,
+	pub source: PathBuf,
+}
+
+// Index of the provider (mods ordered from lowest to highest priority) that deploys a file:
+// the top-most one that isn't beaten by another provider. Cycles beat every provider,
+// then the mods order decides.
+pub fn resolve_file_winner(
+	path: &str,
+	providers: &Vec<&str>,
+	rules: &Vec<FileConflictRule>,
+) -> usize {
+	let is_beaten = |name: &str| {
+		rules.iter().any(|rule| {
+			rule.path == path
+				&& rule.loser == name
+				&& providers.iter().any(|other| *other == rule.winner)
+		})
+	};
+
+	return (0..providers.len())
+		.rev()
+		.find(|index| !is_beaten(providers[*index]))
+		.unwrap_or(providers.len().saturating_sub(1));
+}
+
+// Resolve the rules against the mods to deploy (ordered from lowest to highest priority),
+// an override is returned for every file not won by the top-most mod providing it.
+pub fn resolve_conflict_overrides(
+	mods: &Vec<&InstanceMod>,
+	rules: &Vec<FileConflictRule>,
+) -> Result<Vec<ConflictOverride>, String> {
+	let mut overrides: Vec<ConflictOverride> = Vec::new();
+	if rules.is_empty() {
+		return Ok(overrides);
+	}
+
+	// Path -> (mod index, path as found on disk), only for files with rules
+	let mut providers: HashMap<String, Vec<(usize, String)>> = HashMap::new();
+	for (index, instance_mod) in mods.iter().enumerate() {
+		for file in instance_mod.list_deployed_files()? {
+			let normalized_path = normalize_mod_file_path(&file);
+			if rules.iter().any(|rule| rule.path == normalized_path) {
+				providers
+					.entry(normalized_path)
+					.or_default()
+					.push((index, file));
+			}
+		}
+	}
+
+	let mut paths: Vec<&String> = providers.keys().collect();
+	paths.sort();
+
+	for path in paths {
+		let path_providers = &providers[path];
+		if path_providers.len() < 2 {
+			continue;
+		}
+
+		let provider_names: Vec<&str> = path_providers
+			.iter()
+			.map(|(index, _)| mods[*index].name.as_str())
+			.collect();
+		let natural_winner = path_providers.last().unwrap();
+		let winner = &path_providers[resolve_file_winner(path, &provider_names, rules)];
+
+		if winner.0 != natural_winner.0 {
+			let winner_mod = mods[winner.0];
+			overrides.push(ConflictOverride {
+				relative_path: winner.1.clone(),
+				winner: winner_mod.name.clone(),
+				source: winner_mod
+					.get_selected_version_absolute_path()
+					.join(&winner.1),
+			});
+		}
+	}
+
+	return Ok(overrides);
+}
+
+// (Re)create the shim layer, deployed above every mod, with the overriding files
+pub fn build_shim_layer(
+	shim_path: &PathBuf,
+	overrides: &Vec<ConflictOverride>,
+) -> Result<(), String> {
+	if shim_path.exists() {
+		std::fs::remove_dir_all(shim_path)
+			.map_err(|e| format!("Failed to clear conflicts shim: {}", e.to_string()))?;
+	}
+
+	std::fs::create_dir_all(shim_path)
+		.map_err(|e| format!("Failed to create conflicts shim: {}", e.to_string()))?;
+
+	for conflict_override in overrides {
+		let destination = shim_path.join(&conflict_override.relative_path);
+		if let Some(parent) = destination.parent() {
+			std::fs::create_dir_all(parent)
+				.map_err(|e| format!("Failed to create conflicts shim: {}", e.to_string()))?;
+		}
+
+		// Hardlink when possible, mods and instance usually share a filesystem
+		if std::fs::hard_link(&conflict_override.source, &destination).is_err() {
+			std::fs::copy(&conflict_override.source, &destination).map_err(|e| {
+				format!(
+					"Failed to copy \"{}\" into conflicts shim: {}",
+					conflict_override.relative_path,
+					e.to_string()
+				)
+			})?;
+		}
+	}
+
+	return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{add_mod_with_files, test_instance};
+
+	#[test]
+	fn resolves_overrides() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "conflicts");
+		let a = add_mod_with_files(&mut instance, "A", "1.0", vec!["textures/sky.dds", "a.esp"]);
+		let b = add_mod_with_files(&mut instance, "B", "1.0", vec!["textures/sky.dds"]);
+		let c = add_mod_with_files(&mut instance, "C", "1.0", vec!["textures/sky.dds"]);
+
+		// No rules, nothing to override
+		let mods = vec![&a, &b];
+		assert!(resolve_conflict_overrides(&mods, &vec![])
+			.unwrap()
+			.is_empty());
+
+		let rules = vec![FileConflictRule::new(
+			String::from("Textures/Sky.dds"),
+			String::from("A"),
+			String::from("B"),
+		)
+		.unwrap()];
+
+		let overrides = resolve_conflict_overrides(&mods, &rules).unwrap();
+		assert_eq!(overrides.len(), 1);
+		assert_eq!(overrides[0].winner, "A");
+		assert_eq!(
+			overrides[0].source,
+			a.get_selected_version_absolute_path()
+				.join("textures/sky.dds")
+		);
+
+		// C isn't part of the rule, it still wins over both
+		let mods = vec![&a, &b, &c];
+		assert!(resolve_conflict_overrides(&mods, &rules)
+			.unwrap()
+			.is_empty());
+
+		// B is beaten, C is the next one in line
+		let mods = vec![&a, &c, &b];
+		let overrides = resolve_conflict_overrides(&mods, &rules).unwrap();
+		assert_eq!(overrides[0].winner, "C");
+
+		// Cycles fall back to the mods order
+		let mut rules = rules;
+		rules.push(
+			FileConflictRule::new(
+				String::from("textures/sky.dds"),
+				String::from("B"),
+				String::from("A"),
+			)
+			.unwrap(),
+		);
+		let mods = vec![&a, &b];
+		assert!(resolve_conflict_overrides(&mods, &rules)
+			.unwrap()
+			.is_empty());
+	}
+
+	#[test]
+	fn builds_shim_layer() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "conflicts");
+		let a = add_mod_with_files(&mut instance, "A", "1.0", vec!["textures/sky.dds"]);
+		let b = add_mod_with_files(&mut instance, "B", "1.0", vec!["textures/sky.dds"]);
+		let rules = vec![FileConflictRule::new(
+			String::from("textures/sky.dds"),
+			String::from("A"),
+			String::from("B"),
+		)
+		.unwrap()];
+
+		let shim_path = dir.path().join("shim");
+		std::fs::create_dir_all(&shim_path).unwrap();
+		std::fs::write(shim_path.join("stale.esp"), "stale").unwrap();
+
+		let overrides = resolve_conflict_overrides(&vec![&a, &b], &rules).unwrap();
+		build_shim_layer(&shim_path, &overrides).unwrap();
+
+		assert_eq!(
+			std::fs::read_to_string(shim_path.join("textures/sky.dds")).unwrap(),
+			"A"
+		);
+		assert!(!shim_path.join("stale.esp").exists());
+
+		// Hidden files don't take part in conflicts
+		let mut a = a;
+		a.hide_file(String::from("textures/sky.dds")).unwrap();
+		assert!(resolve_conflict_overrides(&vec![&a, &b], &rules)
+			.unwrap()
+			.is_empty());
+	}
+}
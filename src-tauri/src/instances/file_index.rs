@@ -0,0 +1,366 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::conflicts::resolve_file_winner;
+use super::instance_mod::{normalize_mod_file_path, InstanceMod};
+use super::GameInstance;
+use crate::controllers::file_controller;
+
+const FILE_INDEX_FILE_NAME: &str = "file_index.json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct IndexedModFiles {
+	pub version: String,
+	// Case folded, like the deployed files
+	pub files: Vec<String>,
+}
+
+// Files of the selected version of every mod, and which mods provide each file.
+// Only the mod -> files side is saved, the reverse index is rebuilt when loaded.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FileIndex {
+	pub mods: HashMap<String, IndexedModFiles>,
+	// Path -> names of the mods providing it
+	#[serde(skip)]
+	providers: HashMap<String, Vec<String>>,
+	#[serde(skip)]
+	loaded: bool,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct FileProvider {
+	pub mod_name: String,
+	pub version: String,
+	// Index in "GameInstance::mods"
+	pub mod_index: u32,
+	pub enabled: bool,
+	// Whether this is the file that gets deployed
+	pub winner: bool,
+}
+
+impl FileIndex {
+	// A missing or corrupt index is rebuilt from scratch
+	pub fn load(path: &PathBuf) -> Self {
+		let mut file_index: FileIndex = file_controller::read_file(path.clone())
+			.ok()
+			.and_then(|json| serde_json::from_str(&json).ok())
+			.unwrap_or_default();
+
+		for (mod_name, indexed_mod) in file_index.mods.iter() {
+			for file in indexed_mod.files.iter() {
+				file_index
+					.providers
+					.entry(file.clone())
+					.or_default()
+					.push(mod_name.clone());
+			}
+		}
+		file_index.loaded = true;
+
+		return file_index;
+	}
+
+	pub fn is_loaded(&self) -> bool {
+		return self.loaded;
+	}
+
+	pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+		let json = serde_json::to_string(&self)
+			.map_err(|e| format!("Failed to serialize file index: {}", e.to_string()))?;
+
+		return file_controller::save_file(path.clone(), json.as_bytes())
+			.map_err(|e| format!("Failed to save file index: {}", e.to_string()));
+	}
+
+	fn remove_mod(&mut self, mod_name: &str) {
+		let indexed_mod = match self.mods.remove(mod_name) {
+			Some(indexed_mod) => indexed_mod,
+			None => return,
+		};
+
+		for file in indexed_mod.files {
+			if let Some(providers) = self.providers.get_mut(&file) {
+				providers.retain(|provider| provider != mod_name);
+				if providers.is_empty() {
+					self.providers.remove(&file);
+				}
+			}
+		}
+	}
+
+	fn index_mod(&mut self, instance_mod: &InstanceMod) -> Result<(), String> {
+		self.remove_mod(&instance_mod.name);
+
+		let mut files: Vec<String> = instance_mod
+			.list_deployed_files()?
+			.iter()
+			.map(|file| normalize_mod_file_path(file))
+			.collect();
+		files.sort();
+
+		for file in files.iter() {
+			self.providers
+				.entry(file.clone())
+				.or_default()
+				.push(instance_mod.name.clone());
+		}
+
+		self.mods.insert(
+			instance_mod.name.clone(),
+			IndexedModFiles {
+				version: instance_mod.selected_version_identifier.clone(),
+				files,
+			},
+		);
+
+		return Ok(());
+	}
+
+	// Index the mods that are new or changed version, and drop the removed ones.
+	// Mods in "reindex" are indexed again, their files changed. Returns whether it changed.
+	pub fn sync(&mut self, mods: &Vec<InstanceMod>, reindex: &Vec<String>) -> Result<bool, String> {
+		let indexable_mods: Vec<&InstanceMod> = mods
+			.iter()
+			.filter(|instance_mod| instance_mod.name != "base" && !instance_mod.is_separator())
+			.collect();
+
+		let removed_mods: Vec<String> = self
+			.mods
+			.keys()
+			.filter(|mod_name| !indexable_mods.iter().any(|m| &&m.name == mod_name))
+			.cloned()
+			.collect();
+		let mut changed = removed_mods.len() > 0;
+		for mod_name in removed_mods {
+			self.remove_mod(&mod_name);
+		}
+
+		for instance_mod in indexable_mods {
+			let is_current = self
+				.mods
+				.get(&instance_mod.name)
+				.is_some_and(|indexed_mod| {
+					indexed_mod.version == instance_mod.selected_version_identifier
+				});
+			if is_current && !reindex.contains(&instance_mod.name) {
+				continue;
+			}
+
+			let previous = self.mods.get(&instance_mod.name).cloned();
+			self.index_mod(instance_mod)?;
+			let indexed_mod = &self.mods[&instance_mod.name];
+			changed = changed
+				|| match previous {
+					Some(previous) => {
+						previous.version != indexed_mod.version
+							|| previous.files != indexed_mod.files
+					}
+					None => true,
+				};
+		}
+
+		return Ok(changed);
+	}
+
+	pub fn get_mod_files(&self, mod_name: &str) -> Option<&Vec<String>> {
+		return self
+			.mods
+			.get(mod_name)
+			.map(|indexed_mod| &indexed_mod.files);
+	}
+
+	pub fn get_providers(&self, path: &str) -> Vec<String> {
+		return self
+			.providers
+			.get(&normalize_mod_file_path(path))
+			.cloned()
+			.unwrap_or_default();
+	}
+}
+
+impl GameInstance {
+	pub fn get_file_index_path(&self) -> PathBuf {
+		return self.instance_absolute_path().join(FILE_INDEX_FILE_NAME);
+	}
+
+	// Sync the file index with the mods, "reindex" lists mods whose files changed.
+	// Overwrite is always indexed again, tools write to it while the game runs.
+	pub fn refresh_file_index(&mut self, reindex: Vec<String>) -> Result<(), String> {
+		let index_path = self.get_file_index_path();
+		if !self.file_index.is_loaded() {
+			self.file_index = FileIndex::load(&index_path);
+		}
+
+		let mut reindex = reindex;
+		reindex.push(String::from("overwrite"));
+
+		if self.file_index.sync(&self.mods, &reindex)? {
+			self.file_index.save(&index_path)?;
+		}
+
+		return Ok(());
+	}
+
+	// Index every mod again, for files changed outside the app
+	pub fn rebuild_file_index(&mut self) -> Result<(), String> {
+		let mod_names = self
+			.mods
+			.iter()
+			.map(|instance_mod| instance_mod.name.clone())
+			.collect();
+
+		return self.refresh_file_index(mod_names);
+	}
+
+	// Mods providing a file (relative to the mods deployment folder), in mod order
+	pub fn find_file_providers(&mut self, path: String) -> Result<Vec<FileProvider>, String> {
+		self.refresh_file_index(vec![])?;
+
+		let provider_names = self.file_index.get_providers(&path);
+		let mut providers: Vec<FileProvider> = self
+			.mods
+			.iter()
+			.enumerate()
+			.filter(|(_, instance_mod)| provider_names.contains(&instance_mod.name))
+			.map(|(index, instance_mod)| FileProvider {
+				mod_name: instance_mod.name.clone(),
+				version: self.file_index.mods[&instance_mod.name].version.clone(),
+				mod_index: index as u32,
+				enabled: instance_mod.enabled,
+				winner: false,
+			})
+			.collect();
+
+		// The winner is picked among the enabled mods, like when deploying
+		let enabled_indexes: Vec<usize> = (0..providers.len())
+			.filter(|index| providers[*index].enabled)
+			.collect();
+		let enabled_names: Vec<&str> = enabled_indexes
+			.iter()
+			.map(|index| providers[*index].mod_name.as_str())
+			.collect();
+		if enabled_names.len() > 0 {
+			let winner = resolve_file_winner(
+				&normalize_mod_file_path(&path),
+				&enabled_names,
+				&self.conflict_rules,
+			);
+			providers[enabled_indexes[winner]].winner = true;
+		}
+
+		return Ok(providers);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::instances::conflicts::FileConflictRule;
+	use crate::test_utils::{add_mod_with_files, test_instance};
+
+	fn providers(instance: &mut GameInstance, path: &str) -> Vec<(String, String, bool)> {
+		return instance
+			.find_file_providers(path.to_string())
+			.unwrap()
+			.into_iter()
+			.map(|provider| (provider.mod_name, provider.version, provider.winner))
+			.collect();
+	}
+
+	#[test]
+	fn finds_file_providers() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "index");
+		add_mod_with_files(&mut instance, "Trees", "1.0", vec!["meshes/foo/bar.nif"]);
+		add_mod_with_files(&mut instance, "Trees HD", "1.0", vec!["meshes/foo/bar.nif"]);
+		add_mod_with_files(&mut instance, "Rocks", "1.0", vec!["meshes/rock.nif"]);
+
+		assert_eq!(
+			providers(&mut instance, "Meshes\\Foo\\Bar.nif"),
+			vec![
+				("Trees".to_string(), "1.0".to_string(), false),
+				("Trees HD".to_string(), "1.0".to_string(), true),
+			]
+		);
+
+		// Disabled mods are listed, but don't win
+		instance
+			.set_mod_enabled("Trees HD".to_string(), false)
+			.unwrap();
+		assert_eq!(
+			providers(&mut instance, "meshes/foo/bar.nif"),
+			vec![
+				("Trees".to_string(), "1.0".to_string(), true),
+				("Trees HD".to_string(), "1.0".to_string(), false),
+			]
+		);
+		instance
+			.set_mod_enabled("Trees HD".to_string(), true)
+			.unwrap();
+
+		// Conflict rules pick the winner
+		instance
+			.add_conflict_rule(
+				FileConflictRule::new(
+					"meshes/foo/bar.nif".to_string(),
+					"Trees".to_string(),
+					"Trees HD".to_string(),
+				)
+				.unwrap(),
+			)
+			.unwrap();
+		assert!(providers(&mut instance, "meshes/foo/bar.nif")[0].2);
+
+		// Version changes and deleted mods update the index
+		add_mod_with_files(&mut instance, "Trees", "2.0", vec!["meshes/tree.nif"]);
+		assert_eq!(
+			providers(&mut instance, "meshes/foo/bar.nif"),
+			vec![("Trees HD".to_string(), "1.0".to_string(), true)]
+		);
+		instance
+			.set_mod_active_version("Trees".to_string(), "1.0".to_string())
+			.unwrap();
+		assert_eq!(providers(&mut instance, "meshes/foo/bar.nif").len(), 2);
+
+		instance.delete_mod("Trees HD".to_string()).unwrap();
+		assert_eq!(providers(&mut instance, "meshes/foo/bar.nif").len(), 1);
+
+		// Saved, and loaded by another instance
+		let index = FileIndex::load(&instance.get_file_index_path());
+		assert_eq!(index.get_providers("meshes/rock.nif"), vec!["Rocks"]);
+		assert_eq!(index.mods["Trees"].version, "1.0");
+	}
+
+	#[test]
+	fn indexes_overwrite_and_rebuilds() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "index");
+		add_mod_with_files(
+			&mut instance,
+			"Tool Output",
+			"1.0",
+			vec!["skse/plugins/a.ini"],
+		);
+
+		// Written by tools while the game runs
+		let overwrite_path = instance.parse_path_variables(instance.overwrite_relative_path());
+		std::fs::create_dir_all(overwrite_path.join("skse/plugins")).unwrap();
+		std::fs::write(overwrite_path.join("skse/plugins/a.ini"), "new").unwrap();
+		assert_eq!(
+			providers(&mut instance, "skse/plugins/a.ini"),
+			vec![
+				("Tool Output".to_string(), "1.0".to_string(), false),
+				("overwrite".to_string(), "0.0.0".to_string(), true),
+			]
+		);
+
+		// Files changed outside the app
+		let version_path = instance.mods[1].get_selected_version_absolute_path();
+		std::fs::write(version_path.join("b.esp"), "b").unwrap();
+		assert!(providers(&mut instance, "b.esp").is_empty());
+		instance.rebuild_file_index().unwrap();
+		assert_eq!(providers(&mut instance, "b.esp").len(), 1);
+	}
+}
@@ -1,12 +1,72 @@
 use crate::controllers::plugin_controller::BethesdaPlugin;
 use crate::controllers::{file_controller, plugin_controller};
 use std::collections::HashMap;
+use std::io::BufRead;
 use std::{
 	ffi::OsString,
 	path::{Path, PathBuf},
 };
 
+use super::modlist::ModlistNexusIds;
 use super::GameIdentifier;
+use crate::mods::downloader::default_date;
+use crate::mods::installer::InstallModFile;
+
+// MO2 convention: a file renamed with this suffix is hidden, adopted folders get it back
+static HIDDEN_FILE_SUFFIX: &str = ".mohidden";
+
+// Selected version of mods without any version
+static NO_VERSION_IDENTIFIER: &str = "invalid";
+
+// Mod files are case folded when installed, so relative paths are compared lowercased
+pub fn normalize_mod_file_path(path: &str) -> String {
+	return path
+		.replace('\\', "/")
+		.split('/')
+		.filter(|segment| !segment.is_empty())
+		.collect::<Vec<&str>>()
+		.join("/")
+		.to_lowercase();
+}
+
+// Compare version identifiers with their numbers read as numbers, "1.10" comes after "1.9"
+pub fn compare_version_identifiers(a: &str, b: &str) -> std::cmp::Ordering {
+	fn chunks(identifier: &str) -> Vec<String> {
+		let mut chunks: Vec<String> = Vec::new();
+		for character in identifier.chars() {
+			match chunks.last_mut() {
+				Some(chunk)
+					if chunk.chars().all(|c| c.is_ascii_digit()) == character.is_ascii_digit() =>
+				{
+					chunk.push(character)
+				}
+				_ => chunks.push(character.to_string()),
+			}
+		}
+		return chunks;
+	}
+
+	for (a, b) in chunks(a).iter().zip(chunks(b).iter()) {
+		let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+			(Ok(a), Ok(b)) => a.cmp(&b),
+			_ => a.cmp(b),
+		};
+		if ordering != std::cmp::Ordering::Equal {
+			return ordering;
+		}
+	}
+
+	return chunks(a).len().cmp(&chunks(b).len());
+}
+
+// Modification date of a folder, in seconds since epoch
+fn folder_date(path: &PathBuf) -> Option<String> {
+	return std::fs::metadata(path)
+		.and_then(|metadata| metadata.modified())
+		.ok()
+		.and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+		.map(|duration| duration.as_secs().to_string());
+}
 
 #[taurpc::ipc_type]
 #[derive(Debug)]
@@ -17,6 +77,14 @@ pub struct ModInfo {
 	pub website: Option<String>,
 	pub description: Option<String>,
 	pub categories: Vec<String>,
+	// Free-form labels set by the user
+	#[serde(default)]
+	pub tags: Vec<String>,
+	#[serde(default)]
+	pub notes: Option<String>,
+	// Highlight color in the mods list
+	#[serde(default)]
+	pub color: Option<String>,
 }
 
 impl Default for ModInfo {
@@ -26,38 +94,163 @@ impl Default for ModInfo {
 			website: None,
 			description: None,
 			categories: vec![],
+			tags: vec![],
+			notes: None,
+			color: None,
+		}
+	}
+}
+
+impl ModInfo {
+	pub fn is_empty(&self) -> bool {
+		return self.author.is_none()
+			&& self.website.is_none()
+			&& self.description.is_none()
+			&& self.categories.is_empty()
+			&& self.tags.is_empty()
+			&& self.notes.is_none()
+			&& self.color.is_none();
+	}
+
+	// Installing a new version must not lose what the user set on the mod
+	pub fn keep_user_data(&mut self, previous: &ModInfo) {
+		if self.categories.is_empty() {
+			self.categories = previous.categories.clone();
+		}
+		if self.tags.is_empty() {
+			self.tags = previous.tags.clone();
+		}
+		if self.notes.is_none() {
+			self.notes = previous.notes.clone();
 		}
+		if self.color.is_none() {
+			self.color = previous.color.clone();
+		}
+	}
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct InstanceModVersion {
+	// Version identifier, unique per instance mod (name of its folder)
+	pub identifier: String,
+	// Seconds since epoch, like download dates
+	#[serde(default)]
+	pub installed_at: Option<String>,
+	// Version given by the mod author, as found on NexusMods
+	#[serde(default)]
+	pub upstream_version: Option<String>,
+	#[serde(default)]
+	pub source: Option<InstanceModVersionSource>,
+	#[serde(default)]
+	pub notes: Option<String>,
+	#[serde(default)]
+	pub files_count: u32,
+	// Total size of its files, in bytes
+	#[serde(default)]
+	pub size: String,
+	// Pinned versions are never deleted by the retention policy
+	#[serde(default)]
+	pub pinned: bool,
+}
+
+impl PartialEq<InstanceModVersion> for InstanceModVersion {
+	fn eq(&self, other: &InstanceModVersion) -> bool {
+		self.identifier == *other.identifier
 	}
 }
 
-// #[taurpc::ipc_type]
-// #[derive(Debug)]
-// pub struct InstanceModVersion {
-// 	// Version identifier, unique per instance mod
-// 	pub identifier: String,
-// }
+impl InstanceModVersion {
+	// By install date, then by identifier. Versions without a date come first.
+	pub fn compare_install_order(&self, other: &InstanceModVersion) -> std::cmp::Ordering {
+		let date = self
+			.installed_at
+			.as_ref()
+			.and_then(|date| date.parse::<u64>().ok());
+		let other_date = other
+			.installed_at
+			.as_ref()
+			.and_then(|date| date.parse::<u64>().ok());
+
+		return date
+			.cmp(&other_date)
+			.then_with(|| compare_version_identifiers(&self.identifier, &other.identifier));
+	}
+
+	pub fn new(identifier: String) -> Self {
+		return Self {
+			identifier,
+			installed_at: Some(default_date()),
+			upstream_version: None,
+			source: None,
+			notes: None,
+			files_count: 0,
+			size: String::from("0"),
+			pinned: false,
+		};
+	}
+}
 
-// impl PartialEq<InstanceModVersion> for InstanceModVersion {
-// 	fn eq(&self, other: &InstanceModVersion) -> bool {
-// 		self.identifier == *other.identifier
-// 	}
-// }
+// Archive a version was installed from, and how, so it can be installed again
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct InstanceModVersionSource {
+	pub archive_file_name: String,
+	#[serde(default)]
+	pub archive_md5: Option<String>,
+	#[serde(default)]
+	pub nexus: Option<ModlistNexusIds>,
+	// Archive paths -> version paths, as picked in the installer
+	#[serde(default)]
+	pub files: Vec<InstallModFile>,
+}
 
-// impl InstanceModVersion {
-// 	// pub fn save(&self, mod_path: PathBuf) -> Result<(), String> {
-// 	// 	let absolute_path = mod_path.join(&self.identifier);
+// Files that differ between two versions of a mod, as relative case folded paths
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct ModVersionsDiff {
+	pub mod_name: String,
+	pub from_version: String,
+	pub to_version: String,
+	// Only in "to_version"
+	pub added: Vec<String>,
+	// Only in "from_version"
+	pub removed: Vec<String>,
+	// In both, with a different content
+	pub changed: Vec<String>,
+}
 
-// 	// 	let json = serde_json::to_string(&self).map_err(|e| e.to_string())?;
+// What changed when syncing the versions of a mod with its "versions" folder
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct ModVersionsReconciliation {
+	pub mod_name: String,
+	// Version folders that were not tracked
+	pub adopted_versions: Vec<String>,
+	// Tracked versions without a folder
+	pub dropped_versions: Vec<String>,
+	// Previous selected version, if it had to be replaced
+	pub repaired_selection: Option<String>,
+	pub selected_version_identifier: String,
+}
 
-// 	// 	file_controller::save_file(
-// 	// 		absolute_path.join("modVersion.json"),
-// 	// 		json.as_bytes(),
-// 	// 	)
-// 	// 	.map_err(|e| e.to_string())?;
+impl ModVersionsReconciliation {
+	pub fn has_changes(&self) -> bool {
+		return self.adopted_versions.len() > 0
+			|| self.dropped_versions.len() > 0
+			|| self.repaired_selection.is_some();
+	}
+}
 
-// 	// 	Ok(())
-// 	// }
-// }
+// Named, colored marker grouping the mods below it (until the next separator)
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct ModSeparator {
+	pub color: String,
+	// Whether the group is collapsed in the mods list
+	#[serde(default)]
+	pub collapsed: bool,
+}
 
 #[taurpc::ipc_type]
 #[derive(Debug)]
@@ -67,20 +260,85 @@ pub struct InstanceMod {
 	pub absolute_path: PathBuf,
 	// Mod name (independent of folder name)
 	pub name: String,
-	// Vector of versions
-	pub versions: Vec<String>,
-	// pub versions: HashMap<String, InstanceModVersion>,
+	// Versions, in install order ("versions" used to be a list of identifiers)
+	pub versions: Vec<InstanceModVersion>,
 	// Unique name of the selected version
 	pub selected_version_identifier: String,
 	// Whether the mod is enabled (if disabled, it won't be deployed)
 	pub enabled: bool,
 	// Mod info (author, website, etc)
 	pub info: ModInfo,
+	// Relative paths (case folded) excluded from deployment
+	#[serde(default)]
+	pub hidden_files: Vec<String>,
+	// Set when this entry is a separator instead of a mod, it has no versions
+	#[serde(default)]
+	pub separator: Option<ModSeparator>,
+	// Folder of the mod in the shared library, its versions are stored there
+	#[serde(default)]
+	pub library_path: Option<PathBuf>,
 }
 
 impl InstanceMod {
 	pub fn get_versions_path(&self) -> PathBuf {
-		self.absolute_path.join("versions")
+		match &self.library_path {
+			Some(library_path) => library_path.join("versions"),
+			None => self.absolute_path.join("versions"),
+		}
+	}
+
+	pub fn is_from_library(&self) -> bool {
+		return self.library_path.is_some();
+	}
+
+	// Next to the "versions" folder: same filesystem, but not taken for a version
+	fn get_staging_path(&self) -> PathBuf {
+		return self.get_versions_path().with_file_name(".staged_versions");
+	}
+
+	// Where a version is prepared before replacing the existing one
+	pub fn get_staged_version_path(&self, version_identifier: &str) -> PathBuf {
+		return self.get_staging_path().join("new").join(version_identifier);
+	}
+
+	// Put the staged version in place of the existing one, which is only deleted
+	// once the staged one is in place
+	pub fn replace_version_folder(&self, version_identifier: &str) -> Result<(), String> {
+		let staged_path = self.get_staged_version_path(version_identifier);
+		let staging_path = self.get_staging_path();
+		let replaced_path = staging_path.join("old").join(version_identifier);
+		let version_path = self.get_version_absolute_path(version_identifier.to_string());
+
+		if replaced_path.exists() {
+			file_controller::delete_folder_safe(replaced_path.clone(), staging_path.clone())
+				.map_err(|e| format!("Failed to clear replaced version: {}", e))?;
+		}
+
+		if version_path.exists() {
+			file_controller::move_file(version_path.clone(), replaced_path.clone())
+				.map_err(|e| format!("Failed to move version {}: {}", version_identifier, e))?;
+		}
+
+		if let Err(e) = file_controller::move_file(staged_path, version_path.clone()) {
+			// Put the previous one back
+			if replaced_path.exists() {
+				let _ = file_controller::move_file(replaced_path, version_path);
+			}
+			return Err(format!(
+				"Failed to replace version {}: {}",
+				version_identifier, e
+			));
+		}
+
+		if replaced_path.exists() {
+			file_controller::delete_folder_safe(replaced_path, staging_path.clone())
+				.map_err(|e| format!("Failed to delete replaced version: {}", e))?;
+		}
+		let _ = file_controller::delete_folder_if_empty(staging_path.join("new"));
+		let _ = file_controller::delete_folder_if_empty(staging_path.join("old"));
+		let _ = file_controller::delete_folder_if_empty(staging_path);
+
+		return Ok(());
 	}
 
 	pub fn new(
@@ -95,9 +353,14 @@ impl InstanceMod {
 			absolute_path: location,
 			name,
 			versions: Vec::new(),
-			selected_version_identifier: version.clone().unwrap_or(String::from("invalid")),
+			selected_version_identifier: version
+				.clone()
+				.unwrap_or(String::from(NO_VERSION_IDENTIFIER)),
 			enabled: true,
 			info,
+			hidden_files: Vec::new(),
+			separator: None,
+			library_path: None,
 		};
 
 		// Save mod
@@ -111,23 +374,207 @@ impl InstanceMod {
 		return Ok(instanceMod);
 	}
 
+	pub fn new_separator(
+		mut location: PathBuf,
+		name: String,
+		color: String,
+	) -> Result<Self, String> {
+		location.push(name.clone());
+
+		if location.join("mod.json").exists() {
+			return Err(format!("\"{}\" already exists", name));
+		}
+
+		let separator = InstanceMod {
+			absolute_path: location,
+			name,
+			versions: Vec::new(),
+			selected_version_identifier: String::from(NO_VERSION_IDENTIFIER),
+			enabled: true,
+			info: ModInfo::default(),
+			hidden_files: Vec::new(),
+			separator: Some(ModSeparator {
+				color,
+				collapsed: false,
+			}),
+			library_path: None,
+		};
+
+		separator.save()?;
+
+		return Ok(separator);
+	}
+
+	pub fn is_separator(&self) -> bool {
+		return self.separator.is_some();
+	}
+
 	pub fn load_from_path(path: PathBuf) -> Result<Self, String> {
 		let json = match file_controller::read_file(path.join("mod.json")) {
 			Ok(json) => json,
 			Err(e) => return Err(e.to_string()),
 		};
 
-		let mut instanceMod: InstanceMod = serde_json::from_str(&json)
-			.map_err(|e| format!("Failed to parse instanceMod from json: {}", e.to_string()))?;
+		let (mut instanceMod, migrated) = Self::from_json(&json)
+			.map_err(|e| format!("Failed to parse instanceMod from json: {}", e))?;
 
 		instanceMod.absolute_path = path;
 
-		// Load versions
-		// TODO: Load versions from path, in case they were added/deleted
+		if migrated {
+			instanceMod.migrate_versions()?;
+		}
+
+		// Versions are synced with the filesystem by "reconcile_versions"
 
 		Ok(instanceMod)
 	}
 
+	// Older mod.json files list versions by identifier only, and keep their
+	// sources apart in "versions_sources". Returns whether it had to be converted.
+	fn from_json(json: &str) -> Result<(Self, bool), String> {
+		let mut value: serde_json::Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+		let sources = value
+			.as_object_mut()
+			.and_then(|object| object.remove("versions_sources"))
+			.unwrap_or_default();
+
+		let mut migrated = false;
+		if let Some(versions) = value
+			.get_mut("versions")
+			.and_then(|versions| versions.as_array_mut())
+		{
+			for version in versions.iter_mut() {
+				let identifier = match version.as_str() {
+					Some(identifier) => identifier.to_string(),
+					None => continue,
+				};
+
+				migrated = true;
+				*version = serde_json::json!({
+					"identifier": identifier,
+					"source": sources.get(&identifier),
+				});
+			}
+		}
+
+		let instance_mod = serde_json::from_value(value).map_err(|e| e.to_string())?;
+
+		return Ok((instance_mod, migrated));
+	}
+
+	// Fill what is known of versions converted from plain identifiers
+	fn migrate_versions(&mut self) -> Result<(), String> {
+		for identifier in self.version_identifiers() {
+			let version_path = self.get_version_absolute_path(identifier.clone());
+			if let Some(version) = self.get_version_mut(&identifier) {
+				// The folder date is the closest we have to an install date
+				version.installed_at = folder_date(&version_path);
+			}
+			self.refresh_version_stats(identifier)?;
+		}
+
+		return self.save();
+	}
+
+	// Turn a folder without metadata into a mod
+	// Its content becomes the given version, unless it already has version folders
+	pub fn adopt_folder(path: PathBuf, version_identifier: String) -> Result<Self, String> {
+		let name = path
+			.file_name()
+			.ok_or("Invalid mod folder")?
+			.to_string_lossy()
+			.to_string();
+
+		if path.join("mod.json").exists() {
+			return Err(format!("\"{}\" already is a mod", name));
+		}
+
+		let mut instanceMod = InstanceMod {
+			absolute_path: path.clone(),
+			name: name.clone(),
+			versions: Vec::new(),
+			selected_version_identifier: String::from(NO_VERSION_IDENTIFIER),
+			enabled: true,
+			info: ModInfo::default(),
+			hidden_files: Vec::new(),
+			separator: None,
+			library_path: None,
+		};
+
+		// Only the metadata was lost, keep the versions as they are
+		let has_version_folders =
+			file_controller::list_entries_absolute_path(instanceMod.get_versions_path())
+				.is_ok_and(|entries| entries.iter().any(|entry| entry.is_dir()));
+
+		if !has_version_folders {
+			// Move the folder aside, then back as the version folder
+			let temporary_path = path.with_file_name(format!(".{}.adopting", name));
+			file_controller::move_file(path.clone(), temporary_path.clone())
+				.map_err(|e| format!("Failed to move mod folder: {}", e.to_string()))?;
+			file_controller::move_file(
+				temporary_path,
+				instanceMod.get_version_absolute_path(version_identifier),
+			)
+			.map_err(|e| format!("Failed to move mod folder: {}", e.to_string()))?;
+		}
+
+		instanceMod.reconcile_versions()?;
+
+		// Files hidden MO2-style get their name back and stay hidden through "hidden_files"
+		let version_path = instanceMod.get_selected_version_absolute_path();
+		for file in instanceMod.list_selected_version_files()? {
+			let original = match file.strip_suffix(HIDDEN_FILE_SUFFIX) {
+				Some(original) => original.to_string(),
+				None => continue,
+			};
+
+			// Never overwrite a file with the same name
+			if version_path.join(&original).exists() {
+				println!("Skipping \"{}\", \"{}\" already exists", file, original);
+				continue;
+			}
+
+			std::fs::rename(version_path.join(&file), version_path.join(&original))
+				.map_err(|e| format!("Failed to rename \"{}\": {}", file, e.to_string()))?;
+			instanceMod
+				.hidden_files
+				.push(normalize_mod_file_path(&original));
+		}
+		instanceMod.hidden_files.sort();
+
+		instanceMod.save()?;
+
+		return Ok(instanceMod);
+	}
+
+	// Restore a corrupt mod.json from its backup, the corrupt file is kept aside
+	pub fn recover_from_backup(path: PathBuf) -> Result<Self, String> {
+		let backup_path = path.join("mod.json.backup");
+		let json = file_controller::read_file(backup_path)
+			.map_err(|e| format!("Failed to read mod.json backup: {}", e.to_string()))?;
+
+		let (mut instanceMod, migrated) = Self::from_json(&json)
+			.map_err(|e| format!("The mod.json backup is corrupt too: {}", e))?;
+		instanceMod.absolute_path = path.clone();
+
+		let mod_json_path = path.join("mod.json");
+		if mod_json_path.exists() {
+			std::fs::rename(&mod_json_path, path.join("mod.json.corrupt"))
+				.map_err(|e| format!("Failed to move corrupt mod.json: {}", e.to_string()))?;
+		}
+
+		file_controller::save_file(mod_json_path, json.as_bytes())
+			.map_err(|e| format!("Failed to restore mod.json: {}", e.to_string()))?;
+
+		if migrated {
+			instanceMod.migrate_versions()?;
+		}
+		instanceMod.reconcile_versions()?;
+
+		return Ok(instanceMod);
+	}
+
 	pub fn save(&self) -> Result<(), String> {
 		println!("Saving mod: {:?}", self.absolute_path);
 		let json = serde_json::to_string(&self)
@@ -176,11 +623,20 @@ impl InstanceMod {
 	}
 
 	pub fn add_version(&mut self, version_identifier: String) -> Result<(), String> {
+		if self.is_separator() {
+			return Err(format!("\"{}\" is a separator", self.name));
+		}
+
 		if self.has_version(version_identifier.clone()) {
 			return Err("Version already exists!".to_string());
 		}
 
-		self.versions.push(version_identifier.clone());
+		self.versions
+			.push(InstanceModVersion::new(version_identifier.clone()));
+
+		// Create the version folder, so it is not dropped when reconciling
+		file_controller::create_folder(&self.get_version_absolute_path(version_identifier.clone()))
+			.map_err(|e| format!("Failed to create version folder: {}", e.to_string()))?;
 
 		// Set active version and save
 		self.set_active_version(version_identifier)?;
@@ -188,8 +644,214 @@ impl InstanceMod {
 		return Ok(());
 	}
 
+	// Sync "versions" with the folders in "versions/": folders added outside the app
+	// are adopted and missing ones are dropped. The selected version falls back
+	// to the latest one when it no longer exists.
+	pub fn reconcile_versions(&mut self) -> Result<ModVersionsReconciliation, String> {
+		let mut reconciliation = ModVersionsReconciliation {
+			mod_name: self.name.clone(),
+			selected_version_identifier: self.selected_version_identifier.clone(),
+			..Default::default()
+		};
+
+		// Overwrite, base and separators have no versions
+		if self.name == "overwrite" || self.name == "base" || self.is_separator() {
+			return Ok(reconciliation);
+		}
+
+		let mut folders: Vec<String> = Vec::new();
+		let versions_path = self.get_versions_path();
+		if versions_path.is_dir() {
+			for entry in std::fs::read_dir(&versions_path).map_err(|e| e.to_string())? {
+				let entry = entry.map_err(|e| e.to_string())?;
+				if entry.path().is_dir() {
+					folders.push(entry.file_name().to_string_lossy().to_string());
+				}
+			}
+		}
+		folders.sort();
+
+		let identifiers = self.version_identifiers();
+		reconciliation.dropped_versions = identifiers
+			.iter()
+			.filter(|version| !folders.contains(version))
+			.cloned()
+			.collect();
+		reconciliation.adopted_versions = folders
+			.into_iter()
+			.filter(|folder| !identifiers.contains(folder))
+			.collect();
+
+		self.versions.retain(|version| {
+			!reconciliation
+				.dropped_versions
+				.contains(&version.identifier)
+		});
+		for identifier in reconciliation.adopted_versions.iter() {
+			let mut version = InstanceModVersion::new(identifier.clone());
+			// The folder date is the closest we have to an install date
+			version.installed_at = folder_date(&self.get_version_absolute_path(identifier.clone()));
+			self.versions.push(version);
+			self.refresh_version_stats(identifier.clone())?;
+		}
+
+		if !self.has_selected_version() {
+			// Without any version left, nothing stays selected
+			let latest_identifier = match self.latest_version() {
+				Some(latest_version) => latest_version.identifier.clone(),
+				None => String::from(NO_VERSION_IDENTIFIER),
+			};
+			if latest_identifier != self.selected_version_identifier {
+				reconciliation.repaired_selection = Some(self.selected_version_identifier.clone());
+				self.selected_version_identifier = latest_identifier;
+			}
+		}
+		reconciliation.selected_version_identifier = self.selected_version_identifier.clone();
+
+		if reconciliation.has_changes() {
+			println!(
+				"Reconciled versions of \"{}\": {:?}",
+				self.name, reconciliation
+			);
+			self.save()?;
+		}
+
+		return Ok(reconciliation);
+	}
+
+	// Mods whose versions were all removed have nothing to deploy
+	pub fn has_selected_version(&self) -> bool {
+		return self.has_version(self.selected_version_identifier.clone());
+	}
+
 	pub fn has_version(&self, version_identifier: String) -> bool {
-		return self.versions.contains(&version_identifier);
+		return self.get_version(&version_identifier).is_some();
+	}
+
+	pub fn get_version(&self, version_identifier: &str) -> Option<&InstanceModVersion> {
+		return self
+			.versions
+			.iter()
+			.find(|version| version.identifier == version_identifier);
+	}
+
+	pub fn get_version_mut(&mut self, version_identifier: &str) -> Option<&mut InstanceModVersion> {
+		return self
+			.versions
+			.iter_mut()
+			.find(|version| version.identifier == version_identifier);
+	}
+
+	// Last installed version, identifiers break ties
+	pub fn latest_version(&self) -> Option<&InstanceModVersion> {
+		return self
+			.versions
+			.iter()
+			.max_by(|a, b| a.compare_install_order(b));
+	}
+
+	pub fn version_identifiers(&self) -> Vec<String> {
+		return self
+			.versions
+			.iter()
+			.map(|version| version.identifier.clone())
+			.collect();
+	}
+
+	// Count the files of a version, and their size
+	pub fn refresh_version_stats(&mut self, version_identifier: String) -> Result<(), String> {
+		let version_path = self.get_version_absolute_path(version_identifier.clone());
+		let files = match version_path.is_dir() {
+			true => file_controller::list_files_recursively_flattened(version_path)
+				.map_err(|e| format!("Failed to list version files: {}", e.to_string()))?,
+			false => Vec::new(),
+		};
+		let size: u64 = files
+			.iter()
+			.filter_map(|file| std::fs::metadata(file).ok())
+			.map(|metadata| metadata.len())
+			.sum();
+
+		let version = self
+			.get_version_mut(&version_identifier)
+			.ok_or(format!("Version {} not found", version_identifier))?;
+		version.files_count = files.len() as u32;
+		version.size = size.to_string();
+
+		return Ok(());
+	}
+
+	pub fn update_version_info(
+		&mut self,
+		version_identifier: String,
+		upstream_version: Option<String>,
+		notes: Option<String>,
+	) -> Result<(), String> {
+		let version = self
+			.get_version_mut(&version_identifier)
+			.ok_or(format!("Version {} not found", version_identifier))?;
+		version.upstream_version = upstream_version.filter(|value| !value.trim().is_empty());
+		version.notes = notes.filter(|value| !value.trim().is_empty());
+
+		return self.save();
+	}
+
+	pub fn diff_versions(
+		&self,
+		from_version: String,
+		to_version: String,
+	) -> Result<ModVersionsDiff, String> {
+		let from_files = self.map_version_files(&from_version)?;
+		let to_files = self.map_version_files(&to_version)?;
+
+		let mut diff = ModVersionsDiff {
+			mod_name: self.name.clone(),
+			from_version,
+			to_version,
+			..Default::default()
+		};
+		for (file, to_path) in to_files.iter() {
+			match from_files.get(file) {
+				Some(from_path) => {
+					if !same_file_content(from_path, to_path) {
+						diff.changed.push(file.clone());
+					}
+				}
+				None => diff.added.push(file.clone()),
+			}
+		}
+		diff.removed = from_files
+			.keys()
+			.filter(|file| !to_files.contains_key(*file))
+			.cloned()
+			.collect();
+
+		diff.added.sort();
+		diff.removed.sort();
+		diff.changed.sort();
+
+		return Ok(diff);
+	}
+
+	// Case folded relative path -> absolute path, of each file of a version
+	fn map_version_files(
+		&self,
+		version_identifier: &str,
+	) -> Result<HashMap<String, PathBuf>, String> {
+		if !self.has_version(version_identifier.to_string()) {
+			return Err(format!("Version {} not found", version_identifier));
+		}
+
+		let version_path = self.get_version_absolute_path(version_identifier.to_string());
+		let files =
+			file_controller::list_files_recursively_relative_flattened(version_path.clone())
+				.map_err(|e| format!("Failed to list version files: {}", e.to_string()))?;
+
+		return Ok(files
+			.iter()
+			.map(|file| file.trim_start_matches('/'))
+			.map(|file| (normalize_mod_file_path(file), version_path.join(file)))
+			.collect());
 	}
 
 	pub fn delete_version(&mut self, version_identifier: String) -> Result<(), String> {
@@ -197,8 +859,16 @@ impl InstanceMod {
 			return Err("Cannot delete inexistent version.".to_string());
 		}
 
+		// Other instances may be using it
+		if self.is_from_library() {
+			return Err(format!(
+				"Versions of \"{}\" are in the mod library, delete them from there",
+				self.name
+			));
+		}
+
 		// Remove version from vector
-		self.versions.retain(|v| v != &version_identifier);
+		self.versions.retain(|v| v.identifier != version_identifier);
 
 		// Delete folder version
 		file_controller::delete_folder_safe(
@@ -210,6 +880,75 @@ impl InstanceMod {
 		return self.save();
 	}
 
+	pub fn list_selected_version_files(&self) -> Result<Vec<String>, String> {
+		let version_path = self.get_selected_version_absolute_path();
+		if !version_path.is_dir() {
+			return Ok(Vec::new());
+		}
+
+		return file_controller::list_files_recursively_relative_flattened(version_path)
+			.map(|files| {
+				files
+					.iter()
+					.map(|file| file.trim_start_matches('/').to_string())
+					.collect()
+			})
+			.map_err(|e| format!("Failed to list mod files: {}", e.to_string()));
+	}
+
+	// Files of the selected version that get deployed, relative to the version folder
+	pub fn list_deployed_files(&self) -> Result<Vec<String>, String> {
+		return Ok(self
+			.list_selected_version_files()?
+			.into_iter()
+			.filter(|file| !self.is_file_hidden(file))
+			.collect());
+	}
+
+	pub fn is_file_hidden(&self, relative_path: &str) -> bool {
+		return self
+			.hidden_files
+			.contains(&normalize_mod_file_path(relative_path));
+	}
+
+	pub fn hide_file(&mut self, relative_path: String) -> Result<(), String> {
+		if self.name == "overwrite" || self.name == "base" {
+			return Err(format!("Files of \"{}\" can't be hidden", self.name));
+		}
+
+		let normalized_path = normalize_mod_file_path(&relative_path);
+		if self.hidden_files.contains(&normalized_path) {
+			return Err(format!("\"{}\" is already hidden", relative_path));
+		}
+
+		let exists = self
+			.list_selected_version_files()?
+			.iter()
+			.any(|file| normalize_mod_file_path(file) == normalized_path);
+		if !exists {
+			return Err(format!(
+				"\"{}\" not found in \"{}\"",
+				relative_path, self.name
+			));
+		}
+
+		self.hidden_files.push(normalized_path);
+		self.hidden_files.sort();
+
+		return self.save();
+	}
+
+	pub fn unhide_file(&mut self, relative_path: String) -> Result<(), String> {
+		let normalized_path = normalize_mod_file_path(&relative_path);
+		if !self.hidden_files.contains(&normalized_path) {
+			return Err(format!("\"{}\" is not hidden", relative_path));
+		}
+
+		self.hidden_files.retain(|file| file != &normalized_path);
+
+		return self.save();
+	}
+
 	pub fn get_plugins(
 		&mut self,
 		game_identifier: GameIdentifier,
@@ -232,3 +971,358 @@ impl InstanceMod {
 		return Ok(plugins);
 	}
 }
+
+pub fn same_file_content(a: &PathBuf, b: &PathBuf) -> bool {
+	let same_size = match (std::fs::metadata(a), std::fs::metadata(b)) {
+		(Ok(a), Ok(b)) => a.len() == b.len(),
+		_ => false,
+	};
+
+	if !same_size {
+		return false;
+	}
+
+	// Files can be large, they are compared a chunk at a time
+	let (mut a, mut b) = match (std::fs::File::open(a), std::fs::File::open(b)) {
+		(Ok(a), Ok(b)) => (std::io::BufReader::new(a), std::io::BufReader::new(b)),
+		_ => return false,
+	};
+	loop {
+		let (a_chunk, b_chunk) = match (a.fill_buf(), b.fill_buf()) {
+			(Ok(a_chunk), Ok(b_chunk)) => (a_chunk, b_chunk),
+			_ => return false,
+		};
+		if a_chunk.is_empty() || b_chunk.is_empty() {
+			return a_chunk.is_empty() && b_chunk.is_empty();
+		}
+
+		let length = a_chunk.len().min(b_chunk.len());
+		if a_chunk[..length] != b_chunk[..length] {
+			return false;
+		}
+		a.consume(length);
+		b.consume(length);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn reconciles_versions_with_folders() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance_mod = InstanceMod::new(
+			dir.path().to_path_buf(),
+			String::from("Skyui"),
+			Some(String::from("1.0")),
+			ModInfo::default(),
+		)
+		.unwrap();
+		assert!(instance_mod
+			.get_version_absolute_path(String::from("1.0"))
+			.is_dir());
+
+		// Nothing changed on disk
+		assert!(!instance_mod.reconcile_versions().unwrap().has_changes());
+
+		// Versions added and removed outside the app
+		std::fs::create_dir_all(instance_mod.get_versions_path().join("2.0")).unwrap();
+		std::fs::create_dir_all(instance_mod.get_versions_path().join("1.5")).unwrap();
+		std::fs::remove_dir_all(instance_mod.get_versions_path().join("1.0")).unwrap();
+
+		let reconciliation = instance_mod.reconcile_versions().unwrap();
+		assert_eq!(reconciliation.adopted_versions, vec!["1.5", "2.0"]);
+		assert_eq!(reconciliation.dropped_versions, vec!["1.0"]);
+		assert_eq!(reconciliation.repaired_selection, Some(String::from("1.0")));
+		assert_eq!(instance_mod.selected_version_identifier, "2.0");
+
+		// Saved to mod.json
+		let loaded = InstanceMod::load_from_path(instance_mod.absolute_path.clone()).unwrap();
+		assert_eq!(loaded.version_identifiers(), vec!["1.5", "2.0"]);
+		assert_eq!(loaded.selected_version_identifier, "2.0");
+	}
+
+	#[test]
+	fn migrates_plain_versions() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("Skyui");
+		std::fs::create_dir_all(path.join("versions/1.0/interface")).unwrap();
+		std::fs::write(path.join("versions/1.0/interface/skyui.swf"), "skyui").unwrap();
+
+		// Written by older versions
+		let json = r#"{
+			"name": "Skyui",
+			"versions": ["1.0"],
+			"selected_version_identifier": "1.0",
+			"enabled": true,
+			"info": {"author": null, "website": null, "description": null, "categories": []},
+			"versions_sources": {"1.0": {"archive_file_name": "skyui.7z"}}
+		}"#;
+		std::fs::write(path.join("mod.json"), json).unwrap();
+
+		let instance_mod = InstanceMod::load_from_path(path.clone()).unwrap();
+		let version = instance_mod.get_version("1.0").unwrap();
+		assert_eq!(version.files_count, 1);
+		assert_eq!(version.size, "5");
+		assert!(version.installed_at.is_some());
+		assert_eq!(
+			version.source.as_ref().unwrap().archive_file_name,
+			"skyui.7z"
+		);
+
+		// Saved in the new format
+		let saved = std::fs::read_to_string(path.join("mod.json")).unwrap();
+		assert!(!saved.contains("versions_sources"));
+		let loaded = InstanceMod::load_from_path(path).unwrap();
+		assert_eq!(loaded.get_version("1.0").unwrap().files_count, 1);
+	}
+
+	#[test]
+	fn diffs_versions() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance_mod = InstanceMod::new(
+			dir.path().to_path_buf(),
+			String::from("Skyui"),
+			Some(String::from("1.0")),
+			ModInfo::default(),
+		)
+		.unwrap();
+		instance_mod.add_version(String::from("2.0")).unwrap();
+
+		let old_path = instance_mod.get_version_absolute_path(String::from("1.0"));
+		let new_path = instance_mod.get_version_absolute_path(String::from("2.0"));
+		std::fs::create_dir_all(old_path.join("interface")).unwrap();
+		std::fs::create_dir_all(new_path.join("interface")).unwrap();
+		std::fs::write(old_path.join("interface/skyui.swf"), "old").unwrap();
+		std::fs::write(new_path.join("interface/skyui.swf"), "new").unwrap();
+		std::fs::write(old_path.join("same.esp"), "same").unwrap();
+		std::fs::write(new_path.join("same.esp"), "same").unwrap();
+		std::fs::write(old_path.join("removed.txt"), "removed").unwrap();
+		std::fs::write(new_path.join("added.txt"), "added").unwrap();
+
+		let diff = instance_mod
+			.diff_versions(String::from("1.0"), String::from("2.0"))
+			.unwrap();
+		assert_eq!(diff.added, vec!["added.txt"]);
+		assert_eq!(diff.removed, vec!["removed.txt"]);
+		assert_eq!(diff.changed, vec!["interface/skyui.swf"]);
+
+		assert!(instance_mod
+			.diff_versions(String::from("1.0"), String::from("3.0"))
+			.is_err());
+	}
+
+	#[test]
+	fn adopts_loose_folders() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("Loose Mod");
+		std::fs::create_dir_all(path.join("textures")).unwrap();
+		std::fs::write(path.join("textures/sky.dds"), "sky").unwrap();
+		std::fs::write(path.join("plugin.esp"), "plugin").unwrap();
+
+		let instance_mod = InstanceMod::adopt_folder(path.clone(), String::from("1.0")).unwrap();
+		assert_eq!(instance_mod.name, "Loose Mod");
+		assert_eq!(instance_mod.version_identifiers(), vec!["1.0"]);
+		assert_eq!(instance_mod.selected_version_identifier, "1.0");
+
+		let version_path = instance_mod.get_selected_version_absolute_path();
+		assert!(version_path.join("textures/sky.dds").is_file());
+		assert!(version_path.join("plugin.esp").is_file());
+		assert!(!path.join("plugin.esp").exists());
+		assert!(path.join("mod.json").is_file());
+
+		// Can't adopt twice
+		assert!(InstanceMod::adopt_folder(path, String::from("2.0")).is_err());
+	}
+
+	#[test]
+	fn adopts_folders_with_versions() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("Lost Metadata");
+		std::fs::create_dir_all(path.join("versions/1.0")).unwrap();
+		std::fs::create_dir_all(path.join("versions/2.0")).unwrap();
+
+		let instance_mod = InstanceMod::adopt_folder(path, String::from("main")).unwrap();
+		assert_eq!(instance_mod.version_identifiers(), vec!["1.0", "2.0"]);
+		assert_eq!(instance_mod.selected_version_identifier, "2.0");
+	}
+
+	#[test]
+	fn recovers_corrupt_metadata() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance_mod = InstanceMod::new(
+			dir.path().to_path_buf(),
+			String::from("Skyui"),
+			Some(String::from("1.0")),
+			ModInfo::default(),
+		)
+		.unwrap();
+
+		// Saving again keeps the previous mod.json as backup
+		instance_mod.set_enabled(false).unwrap();
+		std::fs::write(instance_mod.absolute_path.join("mod.json"), "{ corrupt").unwrap();
+		assert!(InstanceMod::load_from_path(instance_mod.absolute_path.clone()).is_err());
+
+		let recovered =
+			InstanceMod::recover_from_backup(instance_mod.absolute_path.clone()).unwrap();
+		assert_eq!(recovered.version_identifiers(), vec!["1.0"]);
+		assert!(instance_mod
+			.absolute_path
+			.join("mod.json.corrupt")
+			.is_file());
+		assert!(InstanceMod::load_from_path(instance_mod.absolute_path.clone()).is_ok());
+	}
+
+	#[test]
+	fn repairs_invalid_selection() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance_mod = InstanceMod::new(
+			dir.path().to_path_buf(),
+			String::from("Manual"),
+			None,
+			ModInfo::default(),
+		)
+		.unwrap();
+		assert_eq!(instance_mod.selected_version_identifier, "invalid");
+
+		// No version yet, nothing to select
+		assert!(!instance_mod.reconcile_versions().unwrap().has_changes());
+
+		std::fs::create_dir_all(instance_mod.get_versions_path().join("main")).unwrap();
+
+		let reconciliation = instance_mod.reconcile_versions().unwrap();
+		assert_eq!(
+			reconciliation.repaired_selection,
+			Some(String::from("invalid"))
+		);
+		assert_eq!(instance_mod.selected_version_identifier, "main");
+
+		// Every version removed outside the app, the selection is cleared
+		std::fs::remove_dir_all(instance_mod.get_versions_path().join("main")).unwrap();
+
+		let reconciliation = instance_mod.reconcile_versions().unwrap();
+		assert_eq!(
+			reconciliation.repaired_selection,
+			Some(String::from("main"))
+		);
+		assert_eq!(instance_mod.selected_version_identifier, "invalid");
+		assert!(!instance_mod.has_selected_version());
+	}
+
+	#[test]
+	fn compares_file_contents() {
+		let dir = tempfile::tempdir().unwrap();
+		let large = "skyui".repeat(10000);
+		std::fs::write(dir.path().join("a"), &large).unwrap();
+		std::fs::write(dir.path().join("b"), &large).unwrap();
+		// Same size, only the end differs
+		std::fs::write(
+			dir.path().join("c"),
+			format!("{}x", &large[..large.len() - 1]),
+		)
+		.unwrap();
+
+		assert!(same_file_content(
+			&dir.path().join("a"),
+			&dir.path().join("b")
+		));
+		assert!(!same_file_content(
+			&dir.path().join("a"),
+			&dir.path().join("c")
+		));
+		assert!(!same_file_content(
+			&dir.path().join("a"),
+			&dir.path().join("missing")
+		));
+	}
+
+	#[test]
+	fn selects_the_latest_version() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance_mod = InstanceMod::new(
+			dir.path().to_path_buf(),
+			String::from("Skyui"),
+			None,
+			ModInfo::default(),
+		)
+		.unwrap();
+		for identifier in ["1.9", "1.10", "1.2"] {
+			std::fs::create_dir_all(instance_mod.get_versions_path().join(identifier)).unwrap();
+		}
+
+		// Same install date, the identifiers are compared as versions
+		instance_mod.reconcile_versions().unwrap();
+		for version in instance_mod.versions.iter_mut() {
+			version.installed_at = Some(String::from("1700000000"));
+		}
+		assert_eq!(instance_mod.latest_version().unwrap().identifier, "1.10");
+
+		// The last installed version wins
+		instance_mod.get_version_mut("1.2").unwrap().installed_at =
+			Some(String::from("1800000000"));
+		assert_eq!(instance_mod.latest_version().unwrap().identifier, "1.2");
+	}
+
+	#[test]
+	fn hides_and_unhides_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance_mod = InstanceMod::new(
+			dir.path().to_path_buf(),
+			String::from("Skyui"),
+			Some(String::from("1.0")),
+			ModInfo::default(),
+		)
+		.unwrap();
+		let version_path = instance_mod.get_selected_version_absolute_path();
+		std::fs::create_dir_all(version_path.join("textures")).unwrap();
+		std::fs::write(version_path.join("textures/sky.dds"), "sky").unwrap();
+
+		assert!(instance_mod.hide_file(String::from("missing.esp")).is_err());
+
+		// Paths are matched case insensitively
+		instance_mod
+			.hide_file(String::from("/Textures\\Sky.dds"))
+			.unwrap();
+		assert_eq!(instance_mod.hidden_files, vec!["textures/sky.dds"]);
+		assert!(instance_mod.list_deployed_files().unwrap().is_empty());
+		// The version folder is left as is
+		assert!(version_path.join("textures/sky.dds").is_file());
+		assert!(instance_mod
+			.hide_file(String::from("textures/sky.dds"))
+			.is_err());
+
+		// Saved to mod.json
+		let loaded = InstanceMod::load_from_path(instance_mod.absolute_path.clone()).unwrap();
+		assert!(loaded.is_file_hidden("TEXTURES/SKY.DDS"));
+
+		instance_mod
+			.unhide_file(String::from("textures/sky.dds"))
+			.unwrap();
+		assert!(instance_mod.hidden_files.is_empty());
+		assert_eq!(
+			instance_mod.list_deployed_files().unwrap(),
+			vec!["textures/sky.dds"]
+		);
+	}
+
+	#[test]
+	fn adopts_mo2_hidden_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let path = dir.path().join("From MO2");
+		std::fs::create_dir_all(&path).unwrap();
+		std::fs::write(path.join("plugin.esp"), "plugin").unwrap();
+		std::fs::write(path.join("readme.txt.mohidden"), "readme").unwrap();
+
+		let instance_mod = InstanceMod::adopt_folder(path, String::from("1.0")).unwrap();
+		assert_eq!(instance_mod.hidden_files, vec!["readme.txt"]);
+
+		assert_eq!(
+			instance_mod.list_deployed_files().unwrap(),
+			vec!["plugin.esp"]
+		);
+		let version_path = instance_mod.get_selected_version_absolute_path();
+		assert!(version_path.join("readme.txt").is_file());
+		assert!(!version_path.join("readme.txt.mohidden").exists());
+	}
+}
@@ -0,0 +1,320 @@
+use std::path::PathBuf;
+
+use crate::controllers::file_controller;
+
+use super::instance_mod::{compare_version_identifiers, InstanceMod, ModInfo};
+use super::GameInstance;
+
+static LIBRARY_MOD_FILE_NAME: &str = "library.json";
+
+// Mod in the shared library, its versions can be used by several instances
+// Each instance keeps its own mod.json (enabled, order, selected version)
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct LibraryMod {
+	pub name: String,
+	pub info: ModInfo,
+	// Read from the "versions" folder
+	#[serde(default)]
+	pub versions: Vec<String>,
+}
+
+impl LibraryMod {
+	fn load(path: &PathBuf) -> Result<Self, String> {
+		let json = file_controller::read_file(path.join(LIBRARY_MOD_FILE_NAME))
+			.map_err(|e| format!("Failed to read library mod: {}", e))?;
+		let mut library_mod: LibraryMod = serde_json::from_str(&json)
+			.map_err(|e| format!("Failed to parse library mod: {}", e))?;
+
+		library_mod.versions = Vec::new();
+		if let Ok(entries) = std::fs::read_dir(path.join("versions")) {
+			for entry in entries.flatten() {
+				if entry.path().is_dir() {
+					library_mod
+						.versions
+						.push(entry.file_name().to_string_lossy().to_string());
+				}
+			}
+		}
+		library_mod
+			.versions
+			.sort_by(|a, b| compare_version_identifiers(a, b));
+
+		return Ok(library_mod);
+	}
+
+	fn save(&self, path: &PathBuf) -> Result<(), String> {
+		let json = serde_json::to_string(self)
+			.map_err(|e| format!("Failed to serialize library mod: {}", e))?;
+
+		return file_controller::save_file(path.join(LIBRARY_MOD_FILE_NAME), json.as_bytes())
+			.map_err(|e| format!("Failed to save library mod: {}", e));
+	}
+}
+
+impl GameInstance {
+	// Mods of different games are kept apart
+	pub fn get_game_library_path(&self, library_path: &PathBuf) -> PathBuf {
+		return library_path.join(format!("{:?}", self.config.game_identifier));
+	}
+
+	pub fn list_library_mods(&self, library_path: &PathBuf) -> Result<Vec<LibraryMod>, String> {
+		let game_library_path = self.get_game_library_path(library_path);
+		if !game_library_path.is_dir() {
+			return Ok(Vec::new());
+		}
+
+		let mut library_mods = Vec::new();
+		for entry in std::fs::read_dir(&game_library_path).map_err(|e| e.to_string())? {
+			let path = entry.map_err(|e| e.to_string())?.path();
+			if !path.join(LIBRARY_MOD_FILE_NAME).exists() {
+				continue;
+			}
+
+			match LibraryMod::load(&path) {
+				Ok(library_mod) => library_mods.push(library_mod),
+				Err(e) => println!("Skipping library mod {:?}: {}", path, e),
+			}
+		}
+
+		library_mods.sort_by(|a, b| a.name.cmp(&b.name));
+
+		return Ok(library_mods);
+	}
+
+	// Move the versions of a mod to the library, the mod then points to them
+	pub fn move_mod_to_library(
+		&mut self,
+		mod_name: String,
+		library_path: &PathBuf,
+	) -> Result<InstanceMod, String> {
+		let library_mod_path = self.get_game_library_path(library_path).join(&mod_name);
+
+		let instance_mod = match self.get_mod_by_name(mod_name.clone()) {
+			Some(instance_mod) => instance_mod,
+			None => return Err(format!("Mod {} not found", mod_name)),
+		};
+
+		if instance_mod.name == "base"
+			|| instance_mod.name == "overwrite"
+			|| instance_mod.is_separator()
+		{
+			return Err(format!("\"{}\" can't be added to the library", mod_name));
+		}
+		if instance_mod.is_from_library() {
+			return Err(format!("\"{}\" already is in the library", mod_name));
+		}
+
+		// The same mod may be in the library already, from another instance
+		for identifier in instance_mod.version_identifiers() {
+			if library_mod_path.join("versions").join(&identifier).exists() {
+				return Err(format!(
+					"Version {} of \"{}\" already is in the library",
+					identifier, mod_name
+				));
+			}
+		}
+
+		let mut moved_identifiers: Vec<String> = Vec::new();
+		for identifier in instance_mod.version_identifiers() {
+			let moved = file_controller::move_file(
+				instance_mod.get_version_absolute_path(identifier.clone()),
+				library_mod_path.join("versions").join(&identifier),
+			);
+			if let Err(e) = moved {
+				// Put back what was moved, the mod keeps all its versions
+				for moved_identifier in moved_identifiers {
+					if let Err(e) = file_controller::move_file(
+						library_mod_path.join("versions").join(&moved_identifier),
+						instance_mod.get_version_absolute_path(moved_identifier.clone()),
+					) {
+						println!(
+							"Failed to move version {} back from the library: {}",
+							moved_identifier, e
+						);
+					}
+				}
+				let _ = file_controller::delete_folder_if_empty(library_mod_path.join("versions"));
+				let _ = file_controller::delete_folder_if_empty(library_mod_path.clone());
+
+				return Err(format!(
+					"Failed to move version {} to the library (is it on the same filesystem?): {}",
+					identifier, e
+				));
+			}
+			moved_identifiers.push(identifier);
+		}
+		let _ = file_controller::delete_folder_if_empty(instance_mod.get_versions_path());
+
+		if !library_mod_path.join(LIBRARY_MOD_FILE_NAME).exists() {
+			LibraryMod {
+				name: mod_name.clone(),
+				info: instance_mod.info.clone(),
+				versions: Vec::new(),
+			}
+			.save(&library_mod_path)?;
+		}
+
+		instance_mod.library_path = Some(library_mod_path);
+		instance_mod.reconcile_versions()?;
+		instance_mod.save()?;
+		let instance_mod = instance_mod.clone();
+
+		self.refresh_file_index(vec![mod_name])?;
+
+		return Ok(instance_mod);
+	}
+
+	// Use a mod of the library in this instance, its latest version gets selected
+	pub fn add_mod_from_library(
+		&mut self,
+		mod_name: String,
+		library_path: &PathBuf,
+	) -> Result<InstanceMod, String> {
+		let library_mod_path = self.get_game_library_path(library_path).join(&mod_name);
+		let library_mod = LibraryMod::load(&library_mod_path)?;
+
+		let mod_path = self.get_mods_absolute_path().join(&mod_name);
+		if mod_path.exists() {
+			return Err(format!("Mod \"{}\" already exists", mod_name));
+		}
+
+		let mut instance_mod = InstanceMod::new(
+			self.get_mods_absolute_path(),
+			mod_name.clone(),
+			None,
+			library_mod.info,
+		)?;
+		instance_mod.library_path = Some(library_mod_path);
+		instance_mod.reconcile_versions()?;
+		instance_mod.save()?;
+
+		self.load_mods()?;
+
+		return Ok(instance_mod);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::instances::instance_mod::InstanceModVersion;
+	use crate::test_utils::test_instance;
+
+	#[test]
+	fn shares_mods_between_instances() {
+		let dir = tempfile::tempdir().unwrap();
+		let library_path = dir.path().join("library");
+		let mut play = test_instance(&dir.path().join("play"), "library");
+		let mut test = test_instance(&dir.path().join("test"), "library");
+
+		let instance_mod = play
+			.create_mod_version(
+				String::from("Skyui"),
+				String::from("1.0"),
+				ModInfo::default(),
+			)
+			.unwrap();
+		let version_path = instance_mod.get_selected_version_absolute_path();
+		std::fs::write(version_path.join("skyui.esp"), "1.0").unwrap();
+		play.create_mod_version(
+			String::from("Skyui"),
+			String::from("2.0"),
+			ModInfo::default(),
+		)
+		.unwrap();
+		play.load_mods().unwrap();
+
+		let shared = play
+			.move_mod_to_library(String::from("Skyui"), &library_path)
+			.unwrap();
+		assert!(shared.is_from_library());
+		assert!(shared
+			.get_version_absolute_path(String::from("1.0"))
+			.join("skyui.esp")
+			.starts_with(&library_path));
+		assert!(!play
+			.get_mods_absolute_path()
+			.join("Skyui/versions")
+			.exists());
+
+		let library_mods = test.list_library_mods(&library_path).unwrap();
+		assert_eq!(library_mods.len(), 1);
+		assert_eq!(library_mods[0].versions, vec!["1.0", "2.0"]);
+
+		// Selected version is per instance, the latest one comes first
+		let added = test
+			.add_mod_from_library(String::from("Skyui"), &library_path)
+			.unwrap();
+		assert_eq!(added.selected_version_identifier, "2.0");
+		test.set_mod_active_version(String::from("Skyui"), String::from("1.0"))
+			.unwrap();
+		play.load_mods().unwrap();
+		test.load_mods().unwrap();
+		let play_mod = play.get_mod_by_name(String::from("Skyui")).unwrap().clone();
+		let test_mod = test.get_mod_by_name(String::from("Skyui")).unwrap().clone();
+		assert_eq!(play_mod.selected_version_identifier, "2.0");
+		assert_eq!(test_mod.selected_version_identifier, "1.0");
+		assert_eq!(
+			std::fs::read_to_string(
+				test_mod
+					.get_selected_version_absolute_path()
+					.join("skyui.esp")
+			)
+			.unwrap(),
+			"1.0"
+		);
+
+		// Library versions are not deleted from an instance
+		assert!(test
+			.delete_mod_version(String::from("Skyui"), Some(String::from("1.0")))
+			.is_err());
+
+		// Removing the mod from an instance keeps it in the library
+		test.delete_mod_version(String::from("Skyui"), None)
+			.unwrap();
+		assert!(play_mod
+			.get_version_absolute_path(String::from("1.0"))
+			.exists());
+	}
+
+	#[test]
+	fn puts_versions_back_when_moving_fails() {
+		let dir = tempfile::tempdir().unwrap();
+		let library_path = dir.path().join("library");
+		let mut play = test_instance(&dir.path().join("play"), "library");
+
+		for identifier in ["1.0", "2.0"] {
+			play.create_mod_version(
+				String::from("Skyui"),
+				String::from(identifier),
+				ModInfo::default(),
+			)
+			.unwrap();
+		}
+		play.load_mods().unwrap();
+
+		// A version without its folder can't be moved
+		play.get_mod_by_name(String::from("Skyui"))
+			.unwrap()
+			.versions
+			.push(InstanceModVersion::new(String::from("3.0")));
+
+		assert!(play
+			.move_mod_to_library(String::from("Skyui"), &library_path)
+			.is_err());
+
+		let instance_mod = play.get_mod_by_name(String::from("Skyui")).unwrap();
+		assert!(!instance_mod.is_from_library());
+		assert!(instance_mod
+			.get_version_absolute_path(String::from("1.0"))
+			.is_dir());
+		assert!(instance_mod
+			.get_version_absolute_path(String::from("2.0"))
+			.is_dir());
+		assert!(!play
+			.get_game_library_path(&library_path)
+			.join("Skyui")
+			.exists());
+	}
+}
@@ -2,8 +2,12 @@ use crate::controllers::file_controller::{self, FileStructureSegment};
 use crate::controllers::plugin_controller::{self, BethesdaPlugin};
 use crate::deployer::vfs;
 use crate::deployer::vfs::base_vfs::{BaseVFS, VFSMountConfig, VFSMountPaths};
+use crate::deployer::vfs::namespace::{self, NamespaceRun};
 use crate::deployer::vfs::union_fs_fuse::UnionFSFuse;
 use crate::mods::downloader;
+use crate::mods::nexus::collection::InstanceCollection;
+use crate::mods::nexus::identify::IdentifyDownloadResult;
+use crate::mods::store::{self, FileStore, StoreFilesReport};
 use crate::state::config::vfs_config::{VFSConfig, VFSImplementation};
 use crate::state::ApplicationState;
 use base64::engine::general_purpose;
@@ -26,9 +30,25 @@ use std::{ffi::OsString, path::PathBuf};
 use tokio::sync::Mutex;
 // use crate::deployer::vfs
 
-use self::instance_mod::InstanceMod;
+use self::categories::ModCategory;
+use self::conflicts::FileConflictRule;
+use self::file_index::FileIndex;
+use self::instance_mod::{InstanceMod, ModVersionsDiff, ModVersionsReconciliation};
+use self::targets::DeploymentTarget;
 
+pub mod build;
+pub mod categories;
+pub mod conflicts;
+pub mod file_index;
 pub mod instance_mod;
+pub mod library;
+pub mod modlist;
+pub mod overwrite;
+pub mod retention;
+pub mod root_deployment;
+pub mod search;
+pub mod targets;
+pub mod views;
 
 pub fn default_true() -> bool {
 	true
@@ -55,6 +75,22 @@ impl GameIdentifier {
 	fn default() -> Self {
 		GameIdentifier::Generic
 	}
+
+	// Game "domain name" used by the NexusMods API
+	pub fn nexus_game_domain(&self) -> Option<String> {
+		let domain = match self {
+			GameIdentifier::Generic => return None,
+			GameIdentifier::Oblivion => "oblivion",
+			GameIdentifier::Morrowind => "morrowind",
+			GameIdentifier::Skyrim => "skyrim",
+			GameIdentifier::SkyrimSE => "skyrimspecialedition",
+			GameIdentifier::Fallout3 => "fallout3",
+			GameIdentifier::FalloutNV => "newvegas",
+			GameIdentifier::Fallout4 => "fallout4",
+		};
+
+		return Some(domain.to_string());
+	}
 }
 
 #[taurpc::ipc_type]
@@ -72,6 +108,9 @@ pub struct InstanceExecutable {
 	pub use_compability: bool,
 	#[serde(default = "default_true")]
 	pub use_proton_tricks: bool,
+	// Mod receiving the files written while it runs, instead of overwrite
+	#[serde(default)]
+	pub output_mod: Option<String>,
 }
 
 #[taurpc::ipc_type]
@@ -92,6 +131,11 @@ pub struct GameInstanceConfig {
 	pub folding_config: CaseFoldingConfig,
 	#[serde(default)]
 	pub downloads_config: DownloadsConfig,
+	#[serde(default)]
+	pub versions_retention: VersionsRetentionConfig,
+	// Folders deployed besides mods, saves and settings
+	#[serde(default)]
+	pub deployment_targets: Vec<DeploymentTarget>,
 }
 
 #[taurpc::ipc_type]
@@ -127,6 +171,25 @@ impl Default for DownloadsConfig {
 	}
 }
 
+// Old mod versions that are deleted after an update
+// A version is kept when any rule keeps it, no rule set keeps everything
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct VersionsRetentionConfig {
+	// Keep the last N installed versions
+	#[serde(default)]
+	pub keep_last: Option<u32>,
+	// Keep versions installed less than N days ago
+	#[serde(default)]
+	pub keep_days: Option<u32>,
+}
+
+impl VersionsRetentionConfig {
+	pub fn is_enabled(&self) -> bool {
+		return self.keep_last.is_some() || self.keep_days.is_some();
+	}
+}
+
 #[taurpc::ipc_type]
 #[derive(Debug)]
 pub struct GameInstanceInternalPaths {
@@ -164,9 +227,24 @@ pub struct GameInstance {
 	pub mods_indexes: HashMap<String, u32>,
 	#[serde(default)]
 	pub mods_errors: HashMap<String, String>,
+	// Version changes found on disk the last time mods were loaded
+	#[serde(default)]
+	pub mods_versions_changes: Vec<ModVersionsReconciliation>,
 
 	#[serde(default)]
 	pub downloads: Vec<downloader::Download>,
+	// Imported collections, and the progress of their installs
+	#[serde(default)]
+	pub collections: Vec<InstanceCollection>,
+	// Per-file overrides of the mods order
+	#[serde(default)]
+	pub conflict_rules: Vec<FileConflictRule>,
+	// Categories mods can be assigned to ("ModInfo::categories")
+	#[serde(default)]
+	pub categories: Vec<ModCategory>,
+	// Files of every mod, saved apart ("file_index.json")
+	#[serde(skip)]
+	pub file_index: FileIndex,
 	// Plugins
 	// #[serde(default)]
 	// pub plugins: HashMap<String, Vec<BethesdaPlugin>>,
@@ -266,14 +344,21 @@ impl GameInstance {
 				game_identifier: GameIdentifier::default(),
 				folding_config: CaseFoldingConfig::default(),
 				downloads_config: DownloadsConfig::default(),
+				versions_retention: VersionsRetentionConfig::default(),
+				deployment_targets: Vec::new(),
 			},
 			// name,
 			// paths,
 			mods: Vec::new(),
 			mods_errors: HashMap::new(),
+			mods_versions_changes: Vec::new(),
 			// executables: Vec::new(),
 			mods_indexes: HashMap::new(),
 			downloads: Vec::new(),
+			collections: Vec::new(),
+			conflict_rules: Vec::new(),
+			categories: Vec::new(),
+			file_index: FileIndex::default(),
 			// plugins: HashMap::new(),
 			// override_config: None,
 			// vfs_config: None,
@@ -298,6 +383,7 @@ impl GameInstance {
 
 		let mut instance_clone = self.clone();
 		instance_clone.mods_errors = HashMap::new();
+		instance_clone.mods_versions_changes = vec![];
 		instance_clone.mods = vec![];
 
 		// Deserialize the game instance to a value
@@ -448,6 +534,95 @@ impl GameInstance {
 		Ok(instance_mod)
 	}
 
+	// Separators are added right above overwrite, then moved like mods
+	pub fn create_separator(&mut self, name: String, color: String) -> Result<InstanceMod, String> {
+		if name.len() == 0 || name.contains('/') {
+			return Err("Invalid separator name".to_string());
+		}
+
+		if name == "base" || name == "overwrite" || self.get_mod_by_name(name.clone()).is_some() {
+			return Err(format!("\"{}\" already exists", name));
+		}
+
+		let separator = InstanceMod::new_separator(self.get_mods_absolute_path(), name, color)?;
+
+		let overwrite_index = self.mods.len().saturating_sub(1);
+		self.mods.insert(overwrite_index, separator.clone());
+		self.save()?;
+
+		return Ok(separator);
+	}
+
+	pub fn update_separator(
+		&mut self,
+		name: String,
+		color: Option<String>,
+		collapsed: Option<bool>,
+	) -> Result<InstanceMod, String> {
+		let separator = match self.get_mod_by_name(name.clone()) {
+			Some(instance_mod) if instance_mod.is_separator() => instance_mod,
+			_ => {
+				return Err(format!("Separator {} not found", name));
+			}
+		};
+
+		let mut separator_info = separator.separator.clone().unwrap_or_default();
+		if let Some(color) = color {
+			separator_info.color = color;
+		}
+		if let Some(collapsed) = collapsed {
+			separator_info.collapsed = collapsed;
+		}
+		separator.separator = Some(separator_info);
+		separator.save()?;
+
+		return Ok(separator.clone());
+	}
+
+	// Indexes of a separator and the mods in its group (up to the next separator)
+	pub fn get_separator_group_indexes(&self, name: String) -> Result<Vec<u32>, String> {
+		let separator_index = self
+			.mods
+			.iter()
+			.position(|instance_mod| instance_mod.name == name && instance_mod.is_separator())
+			.ok_or(format!("Separator {} not found", name))?;
+
+		let mut indexes = vec![separator_index as u32];
+		for (index, instance_mod) in self.mods.iter().enumerate().skip(separator_index + 1) {
+			if instance_mod.is_separator() || instance_mod.name == "overwrite" {
+				break;
+			}
+			indexes.push(index as u32);
+		}
+
+		return Ok(indexes);
+	}
+
+	pub fn set_separator_group_enabled(
+		&mut self,
+		name: String,
+		enabled: bool,
+	) -> Result<(), String> {
+		let indexes = self.get_separator_group_indexes(name)?;
+
+		// The separator itself is not a mod
+		for index in indexes.iter().skip(1) {
+			self.mods[*index as usize].set_enabled(enabled)?;
+		}
+
+		return Ok(());
+	}
+
+	pub fn move_separator_group(
+		&mut self,
+		name: String,
+		target_index: u32,
+	) -> Result<Vec<u32>, String> {
+		let indexes = self.get_separator_group_indexes(name)?;
+
+		return self.move_mods_by_indexes(indexes, target_index);
+	}
+
 	pub fn create_mod_version(
 		&mut self,
 		name: String,
@@ -470,6 +645,8 @@ impl GameInstance {
 		};
 
 		// Update info
+		let mut info = info;
+		info.keep_user_data(&instance_mod.info);
 		instance_mod.info = info;
 
 		// Add version and save
@@ -490,7 +667,44 @@ impl GameInstance {
 			}
 		};
 
-		return instance_mod.set_active_version(version);
+		instance_mod.set_active_version(version)?;
+
+		return self.refresh_file_index(vec![]);
+	}
+
+	pub fn update_mod_version_info(
+		&mut self,
+		mod_name: String,
+		version: String,
+		upstream_version: Option<String>,
+		notes: Option<String>,
+	) -> Result<InstanceMod, String> {
+		let instance_mod = match self.get_mod_by_name(mod_name.clone()) {
+			Some(mod_instance) => mod_instance,
+			None => {
+				return Err(format!("Mod {} not found", mod_name));
+			}
+		};
+
+		instance_mod.update_version_info(version, upstream_version, notes)?;
+
+		return Ok(instance_mod.clone());
+	}
+
+	pub fn diff_mod_versions(
+		&mut self,
+		mod_name: String,
+		from_version: String,
+		to_version: String,
+	) -> Result<ModVersionsDiff, String> {
+		let instance_mod = match self.get_mod_by_name(mod_name.clone()) {
+			Some(mod_instance) => mod_instance,
+			None => {
+				return Err(format!("Mod {} not found", mod_name));
+			}
+		};
+
+		return instance_mod.diff_versions(from_version, to_version);
 	}
 
 	pub fn set_mod_enabled(&mut self, mod_name: String, enabled: bool) -> Result<(), String> {
@@ -504,6 +718,131 @@ impl GameInstance {
 		return instance_mod.set_enabled(enabled);
 	}
 
+	pub fn hide_mod_file(&mut self, mod_name: String, path: String) -> Result<(), String> {
+		let instance_mod = match self.get_mod_by_name(mod_name.clone()) {
+			Some(mod_instance) => mod_instance,
+			None => {
+				return Err(format!("Mod {} not found", mod_name));
+			}
+		};
+
+		instance_mod.hide_file(path)?;
+
+		return self.refresh_file_index(vec![mod_name]);
+	}
+
+	pub fn unhide_mod_file(&mut self, mod_name: String, path: String) -> Result<(), String> {
+		let instance_mod = match self.get_mod_by_name(mod_name.clone()) {
+			Some(mod_instance) => mod_instance,
+			None => {
+				return Err(format!("Mod {} not found", mod_name));
+			}
+		};
+
+		instance_mod.unhide_file(path)?;
+
+		return self.refresh_file_index(vec![mod_name]);
+	}
+
+	// Replaces any rule about the same file and pair of mods
+	pub fn add_conflict_rule(&mut self, rule: FileConflictRule) -> Result<(), String> {
+		for mod_name in [&rule.winner, &rule.loser] {
+			if !self
+				.mods
+				.iter()
+				.any(|instance_mod| &instance_mod.name == mod_name)
+			{
+				return Err(format!("Mod {} not found", mod_name));
+			}
+		}
+
+		self.conflict_rules
+			.retain(|existing_rule| !existing_rule.overlaps(&rule));
+		self.conflict_rules.push(rule);
+
+		return self.save();
+	}
+
+	pub fn remove_conflict_rule(&mut self, rule: FileConflictRule) -> Result<(), String> {
+		let rules_count = self.conflict_rules.len();
+		self.conflict_rules
+			.retain(|existing_rule| existing_rule != &rule);
+
+		if self.conflict_rules.len() == rules_count {
+			return Err(String::from("Conflict rule not found"));
+		}
+
+		return self.save();
+	}
+
+	pub fn get_conflicts_shim_absolute_path(&self) -> PathBuf {
+		return self.instance_absolute_path().join(".vfs_shim");
+	}
+
+	// Whether executables write to the mod, stored files can't be written to
+	fn is_output_mod(&self, mod_name: &str) -> bool {
+		return self
+			.config
+			.executables
+			.iter()
+			.any(|executable| executable.output_mod.as_deref() == Some(mod_name));
+	}
+
+	// Move the files of every version of the given mods (all when empty)
+	// into the deduplicating store, output mods are left out
+	pub fn store_mods_files(
+		&self,
+		store: &FileStore,
+		mod_names: Vec<String>,
+	) -> Result<StoreFilesReport, String> {
+		let mut report = StoreFilesReport::default();
+		for instance_mod in self.mods.iter() {
+			if instance_mod.name == "base"
+				|| instance_mod.name == "overwrite"
+				|| instance_mod.is_separator()
+				|| self.is_output_mod(&instance_mod.name)
+			{
+				continue;
+			}
+			if mod_names.len() > 0 && !mod_names.contains(&instance_mod.name) {
+				continue;
+			}
+
+			for identifier in instance_mod.version_identifiers() {
+				let version_path = instance_mod.get_version_absolute_path(identifier);
+				report.add(store.store_folder(&version_path)?);
+			}
+		}
+
+		return Ok(report);
+	}
+
+	// Move the files of a single version into the deduplicating store, e.g. once installed
+	pub fn store_mod_version_files(
+		&self,
+		store: &FileStore,
+		mod_name: String,
+		version: String,
+	) -> Result<StoreFilesReport, String> {
+		let instance_mod = self
+			.mods
+			.iter()
+			.find(|instance_mod| instance_mod.name == mod_name)
+			.ok_or(format!("Mod {} not found", mod_name))?;
+		if !instance_mod.has_version(version.clone()) {
+			return Err(format!("Version {} not found", version));
+		}
+
+		let mut report = StoreFilesReport::default();
+		if self.is_output_mod(&mod_name) {
+			return Ok(report);
+		}
+
+		report.add(store.store_folder(&instance_mod.get_version_absolute_path(version))?);
+
+		return Ok(report);
+	}
+
 	pub fn load_mods(&mut self) -> Result<Vec<InstanceMod>, String> {
 		let mods_path = self.get_mods_absolute_path();
 
@@ -513,6 +852,7 @@ impl GameInstance {
 
 		let mut mods = vec![];
 		let mut errors: HashMap<String, String> = HashMap::new();
+		let mut versions_changes: Vec<ModVersionsReconciliation> = Vec::new();
 
 		for entry in std::fs::read_dir(mods_path).map_err(|e| e.to_string())? {
 			let entry = entry.map_err(|e| e.to_string())?;
@@ -544,7 +884,18 @@ impl GameInstance {
 
 			// Attempt to load the mod from path
 			match InstanceMod::load_from_path(path.clone()) {
-				Ok(instanceMod) => {
+				Ok(mut instanceMod) => {
+					// Sync versions with the folders on disk
+					match instanceMod.reconcile_versions() {
+						Ok(reconciliation) if reconciliation.has_changes() => {
+							versions_changes.push(reconciliation);
+						}
+						Ok(_) => {}
+						Err(err) => {
+							errors.insert(instanceMod.name.clone(), err);
+						}
+					}
+
 					mods.push(instanceMod);
 				}
 				Err(err) => {
@@ -575,6 +926,9 @@ impl GameInstance {
 				versions: Vec::new(),
 				selected_version_identifier: String::from("0.0.0"),
 				info: ModInfo::default(),
+				hidden_files: Vec::new(),
+				separator: None,
+				library_path: None,
 			},
 		);
 
@@ -588,6 +942,9 @@ impl GameInstance {
 				versions: Vec::new(),
 				selected_version_identifier: String::from("0.0.0"),
 				info: ModInfo::default(),
+				hidden_files: Vec::new(),
+				separator: None,
+				library_path: None,
 			},
 		);
 
@@ -599,6 +956,12 @@ impl GameInstance {
 		// Set mods in instance
 		self.mods = mods.clone();
 		self.mods_errors = errors.clone();
+		self.mods_versions_changes = versions_changes;
+
+		// A broken index must not prevent using the instance
+		if let Err(err) = self.refresh_file_index(vec![]) {
+			println!("Failed to refresh the file index: {}", err);
+		}
 
 		// Rebuild the mod order
 		self.rebuild_mods_order()?;
@@ -606,6 +969,60 @@ impl GameInstance {
 		Ok(mods)
 	}
 
+	// Folder of a mod, from its name or a "mods_errors" key (either a folder name or a path)
+	fn mod_folder_path(&self, folder: &str) -> Result<PathBuf, String> {
+		let mods_path = self.get_mods_absolute_path();
+		let folder_path = match Path::new(folder).is_absolute() {
+			true => PathBuf::from(folder),
+			false => mods_path.join(folder),
+		};
+
+		// ".." has no file name, non UTF-8 names can't be mods
+		let folder_name = match folder_path.file_name().and_then(|name| name.to_str()) {
+			Some(folder_name) => folder_name,
+			None => return Err(format!("\"{}\" is not a mod folder", folder)),
+		};
+
+		let is_mod_folder = folder_path.parent() == Some(mods_path.as_path())
+			&& folder_path.is_dir()
+			&& !vec!["overwrite", "base"].contains(&folder_name);
+		if !is_mod_folder {
+			return Err(format!("\"{}\" is not a mod folder", folder));
+		}
+
+		return Ok(folder_path);
+	}
+
+	// Turn a folder without mod.json (see "mods_errors") into a mod,
+	// it is added at the end of the mod order
+	pub fn adopt_orphan_mod(
+		&mut self,
+		folder: String,
+		version: Option<String>,
+	) -> Result<InstanceMod, String> {
+		let folder_path = self.mod_folder_path(&folder)?;
+
+		let instance_mod =
+			InstanceMod::adopt_folder(folder_path, version.unwrap_or(String::from("1.0")))?;
+
+		self.load_mods()?;
+		self.save()?;
+
+		return Ok(instance_mod);
+	}
+
+	// Restore a mod whose mod.json can't be read from its backup
+	pub fn recover_mod_from_backup(&mut self, folder: String) -> Result<InstanceMod, String> {
+		let folder_path = self.mod_folder_path(&folder)?;
+
+		let instance_mod = InstanceMod::recover_from_backup(folder_path)?;
+
+		self.load_mods()?;
+		self.save()?;
+
+		return Ok(instance_mod);
+	}
+
 	pub fn open_mod_folder(&self, mod_name: String) -> Result<(), String> {
 		let mods_path = self.get_mods_absolute_path();
 		let mod_path = match mod_name == "base" {
@@ -774,7 +1191,7 @@ impl GameInstance {
 		return self
 			.mods
 			.iter_mut()
-			.filter(|mod_instance| mod_instance.enabled)
+			.filter(|mod_instance| mod_instance.enabled && !mod_instance.is_separator())
 			.collect();
 	}
 
@@ -804,6 +1221,8 @@ impl GameInstance {
 		// Rebuild mod order
 		self.rebuild_mods_order()?;
 
+		self.refresh_file_index(vec![])?;
+
 		// Save instance
 		return self.save();
 	}
@@ -832,7 +1251,7 @@ impl GameInstance {
 			}
 		}
 
-		return Ok(());
+		return self.refresh_file_index(vec![mod_name]);
 	}
 
 	// --------------------
@@ -881,6 +1300,8 @@ impl GameInstance {
 	// --------------------
 
 	pub fn update_config(&mut self, config: GameInstanceConfig) -> Result<(), String> {
+		targets::validate_deployment_targets(&config.deployment_targets)?;
+
 		self.config = config;
 
 		self.save()?;
@@ -938,6 +1359,123 @@ impl GameInstance {
 		return Ok(());
 	}
 
+	// Archives in the downloads folder that are not being downloaded,
+	// including the ones that were copied there manually
+	pub fn list_downloaded_archives(&self) -> Result<Vec<(String, PathBuf)>, String> {
+		let downloads_path = self.get_downloads_absolute_path();
+		if !downloads_path.exists() {
+			return Ok(Vec::new());
+		}
+
+		let mut archives = Vec::new();
+		for entry in std::fs::read_dir(&downloads_path).map_err(|e| e.to_string())? {
+			let entry = entry.map_err(|e| e.to_string())?;
+			if !entry.path().is_file() {
+				continue;
+			}
+
+			let file_name = entry.file_name().to_string_lossy().to_string();
+
+			// Skip downloads in progress and their chunks
+			let in_progress = self.downloads.iter().any(|d| {
+				d.status != downloader::DownloadStatus::Downloaded && d.owns_file(&file_name)
+			});
+			if in_progress {
+				continue;
+			}
+
+			archives.push((file_name, entry.path()));
+		}
+
+		archives.sort_by(|a, b| a.0.cmp(&b.0));
+
+		return Ok(archives);
+	}
+
+	// Download a mod version was installed from, matched by hash first
+	// since the archive may have been renamed
+	pub fn find_version_download(
+		&mut self,
+		mod_name: String,
+		version: String,
+	) -> Option<downloader::Download> {
+		let source = self
+			.get_mod_by_name(mod_name)?
+			.get_version(&version)?
+			.source
+			.clone()?;
+
+		let downloaded = self
+			.downloads
+			.iter()
+			.filter(|download| download.status == downloader::DownloadStatus::Downloaded);
+
+		let by_md5 = downloaded.clone().find(|download| {
+			return source.archive_md5.is_some()
+				&& download.md5.as_ref().map(|md5| md5.to_lowercase()) == source.archive_md5;
+		});
+
+		return by_md5
+			.or(downloaded
+				.clone()
+				.find(|download| download.file_name == source.archive_file_name))
+			.cloned();
+	}
+
+	pub fn set_download_installed(
+		&mut self,
+		file_name: String,
+		installed: bool,
+	) -> Result<(), String> {
+		let mut found = false;
+		for download in self.downloads.iter_mut() {
+			if download.file_name == file_name {
+				download.installed = installed;
+				found = true;
+			}
+		}
+
+		if !found {
+			return Err(format!("Download {} not found", file_name));
+		}
+
+		return self.save();
+	}
+
+	// Attach the result of an MD5 lookup to its download,
+	// archives without a download entry get one
+	pub fn apply_identified_download(&mut self, result: &IdentifyDownloadResult) {
+		let index = match self
+			.downloads
+			.iter()
+			.position(|d| d.file_name == result.file_name)
+		{
+			Some(index) => index,
+			None => {
+				if result.md5.is_none() {
+					return;
+				}
+
+				let archive_path = self.get_downloads_absolute_path().join(&result.file_name);
+				self.downloads
+					.push(downloader::Download::from_existing_file(&archive_path));
+				self.downloads.len() - 1
+			}
+		};
+
+		let download = &mut self.downloads[index];
+		if result.md5.is_some() {
+			download.md5 = result.md5.clone();
+		}
+		if let Some(identified) = &result.nexus_data {
+			match download.nexus_data.as_mut() {
+				Some(nexus_data) => nexus_data.merge_identified(identified),
+				None => download.nexus_data = Some(identified.clone()),
+			}
+		}
+		download.pending_update = true;
+	}
+
 	// --------------------
 	// Executables
 	// --------------------
@@ -1085,7 +1623,7 @@ impl GameInstance {
 		return Some(base64);
 	}
 
-	pub fn run_executable(&self, executable: InstanceExecutable) -> Result<Child, String> {
+	fn get_executable_command(&self, executable: InstanceExecutable) -> Result<String, String> {
 		let mut main_command;
 
 		if executable.command.is_some() {
@@ -1103,7 +1641,11 @@ impl GameInstance {
 			None => {}
 		};
 
-		let main_command_parsed = self.parse_string_variables(main_command);
+		return Ok(self.parse_string_variables(main_command));
+	}
+
+	pub fn run_executable(&self, executable: InstanceExecutable) -> Result<Child, String> {
+		let main_command_parsed = self.get_executable_command(executable)?;
 
 		let mut binding = std::process::Command::new("sh");
 		let process_command = binding.arg("-c").arg(main_command_parsed.clone());
@@ -1116,43 +1658,202 @@ impl GameInstance {
 		};
 	}
 
+	// Run the executable in its own mount namespace, with the VFS mounted only there.
+	// Runs can't write to the same folders as the ones still running.
+	pub fn run_executable_isolated(
+		&self,
+		executable: InstanceExecutable,
+		fallback_vfs_config: VFSConfig,
+		running_runs: &Vec<NamespaceRun>,
+	) -> Result<(Child, NamespaceRun), String> {
+		let vfs_config = self.get_vfs_config(fallback_vfs_config);
+		let mount_configs =
+			self.get_vfs_mount_configs(&vfs_config, executable.output_mod.clone(), true)?;
+		let main_command_parsed = self.get_executable_command(executable)?;
+
+		let run = NamespaceRun::new(&mount_configs);
+		for running_run in running_runs.iter() {
+			if let Some(upper_dir) = run.shared_upper_dir(running_run) {
+				return Err(format!(
+					"A running executable already writes to {:?}, stop it first",
+					upper_dir
+				));
+			}
+		}
+
+		let mut process_command =
+			namespace::namespace_command(&mount_configs, &run, &main_command_parsed)?;
+
+		println!(
+			"Running executable in a mount namespace: {}",
+			main_command_parsed
+		);
+
+		return match process_command.spawn() {
+			Ok(child) => Ok((child, run)),
+			Err(err) => {
+				run.delete_workdirs();
+				Err(format!("Failed to run unshare: {}", err.to_string()))
+			}
+		};
+	}
+
 	// --------------------
 	// Virtual File-System
 	// --------------------
 
-	pub fn mount_vfs(
-		&self,
-		fallback_vfs_config: VFSConfig,
-	) -> Result<Vec<Box<dyn BaseVFS>>, String> {
-		let vfs_config = match self.config.vfs_config.clone() {
+	// Create the output mod of an executable if needed, its selected version
+	// becomes the writable layer of the mods VFS. Files it shares with the store
+	// get their own copy, writing to them would change every version linking them.
+	pub fn prepare_output_mod(&mut self, mod_name: String) -> Result<PathBuf, String> {
+		if mod_name == "base" || mod_name == "overwrite" {
+			return Err(format!("\"{}\" can't be an output mod", mod_name));
+		}
+
+		if self.get_mod_by_name(mod_name.clone()).is_none() {
+			self.create_mod_version(mod_name.clone(), String::from("1.0"), ModInfo::default())?;
+			self.load_mods()?;
+		}
+
+		let output_path = self.get_output_mod_path(&mod_name)?;
+		let detached_count = store::detach_folder(&output_path)?;
+		if detached_count > 0 {
+			println!("Copied {} stored files of {}", detached_count, mod_name);
+		}
+
+		return Ok(output_path);
+	}
+
+	fn get_output_mod_path(&self, mod_name: &str) -> Result<PathBuf, String> {
+		let instance_mod = self
+			.mods
+			.iter()
+			.find(|instance_mod| instance_mod.name == mod_name)
+			.ok_or(format!("Mod {} not found", mod_name))?;
+
+		if instance_mod.is_separator() {
+			return Err(format!("\"{}\" can't be an output mod", mod_name));
+		}
+		// Other instances would get the files too
+		if instance_mod.is_from_library() {
+			return Err(format!("\"{}\" is in the library", mod_name));
+		}
+
+		return Ok(instance_mod.get_selected_version_absolute_path());
+	}
+
+	// Once the VFS is unmounted, take the files written to the output mod into account
+	pub fn refresh_output_mod(&mut self, mod_name: String) -> Result<(), String> {
+		if let Some(instance_mod) = self.get_mod_by_name(mod_name.clone()) {
+			instance_mod.refresh_version_stats(instance_mod.selected_version_identifier.clone())?;
+			instance_mod.save()?;
+		}
+
+		return self.refresh_file_index(vec![mod_name]);
+	}
+
+	pub fn get_vfs_config(&self, fallback_vfs_config: VFSConfig) -> VFSConfig {
+		return match self.config.vfs_config.clone() {
 			Some(instance_vfs_config) => instance_vfs_config,
 			None => fallback_vfs_config,
 		};
+	}
 
-		let mut return_vfs_vec: Vec<Box<dyn BaseVFS>> = Vec::new();
-
-		// First, mount mods
+	// Where files written through the VFS end up: the output mod, or overwrite
+	fn get_writable_path(&self, output_mod: &Option<String>) -> Result<PathBuf, String> {
+		return match output_mod {
+			Some(mod_name) => self.get_output_mod_path(mod_name),
+			None => Ok(self.parse_path_variables(self.overwrite_relative_path())),
+		};
+	}
 
-		// Get mods, filter by enabled
-		let filtered_mods: Vec<&InstanceMod> = self
+	// Enabled mods, in order, the output mod is the writable layer instead
+	fn get_deployed_mods(&self, output_mod: &Option<String>) -> Vec<&InstanceMod> {
+		return self
 			.mods
 			.iter()
 			.filter(|mod_source| {
 				mod_source.enabled
-					&& mod_source.versions.len() > 0
+					&& !mod_source.is_separator()
+					&& mod_source.has_selected_version()
 					&& !vec!["overwrite", "base"].contains(&mod_source.name.as_str())
+					&& Some(&mod_source.name) != output_mod.as_ref()
 			})
 			.collect();
+	}
 
-		let mods_sources = filtered_mods
-			.iter()
-			.map(|instance_mod| instance_mod.get_selected_version_absolute_path().clone())
-			.collect();
+	pub fn mount_vfs(
+		&self,
+		fallback_vfs_config: VFSConfig,
+		output_mod: Option<String>,
+	) -> Result<Vec<Box<dyn BaseVFS>>, String> {
+		let vfs_config = self.get_vfs_config(fallback_vfs_config);
+		let mount_configs = self.get_vfs_mount_configs(&vfs_config, output_mod.clone(), false)?;
+
+		// "root" folders are copied into the game folder, undone on unmount
+		let writable_path = self.get_writable_path(&output_mod)?;
+		if let Err(e) = self.deploy_root_files(&self.get_deployed_mods(&output_mod), &writable_path)
+		{
+			let _ = self.undeploy_root_files();
+			return Err(e);
+		}
+
+		let mut return_vfs_vec: Vec<Box<dyn BaseVFS>> = Vec::new();
+		for mount_config in mount_configs {
+			let mount_name = mount_config.mount_name.clone();
+			match self.mount_vfs_sub(vfs_config.implementation, mount_config) {
+				Ok(vfs) => return_vfs_vec.push(vfs),
+				Err(e) => {
+					// Nothing is left mounted, it would not be in the state to be unmounted
+					for vfs in return_vfs_vec.iter().rev() {
+						if let Err(e) = vfs.unmount() {
+							println!("Failed to unmount sub-vfs: {}", e);
+						}
+					}
+					if let Err(e) = self.undeploy_root_files() {
+						println!("Failed to undeploy root files: {}", e);
+					}
+
+					return Err(format!("Failed to mount sub-vfs ({}): {}", mount_name, e));
+				}
+			}
+		}
+
+		return Ok(return_vfs_vec);
+	}
+
+	// What to mount, in order. "root" folders are only mounted over the game folder
+	// in a mount namespace, globally they are linked (see "deploy_root_files").
+	pub fn get_vfs_mount_configs(
+		&self,
+		vfs_config: &VFSConfig,
+		output_mod: Option<String>,
+		mount_root: bool,
+	) -> Result<Vec<VFSMountConfig>, String> {
+		let mut mount_configs: Vec<VFSMountConfig> = Vec::new();
+
+		// First, mods
+		let filtered_mods = self.get_deployed_mods(&output_mod);
+
+		// Without the folders deployed elsewhere ("root", deployment targets)
+		let mut mods_sources = self.get_mods_layer_sources(&filtered_mods)?;
+
+		// Files whose winner was overridden go in a layer above every mod
+		let conflict_overrides =
+			conflicts::resolve_conflict_overrides(&filtered_mods, &self.conflict_rules)?;
+		let shim_path = self.get_conflicts_shim_absolute_path();
+		conflicts::build_shim_layer(&shim_path, &conflict_overrides)?;
+		if !conflict_overrides.is_empty() {
+			mods_sources.push(shim_path);
+		}
+
+		// The output mod is the writable layer, so it is above every other mod
+		let mods_overwrite = self.get_writable_path(&output_mod)?;
 
 		let mods_mount_paths = VFSMountPaths {
 			target: self.parse_path_variables(self.config.paths.deployment.mods.clone()),
 			sources: mods_sources,
-			overwrite: self.parse_path_variables(self.overwrite_relative_path()),
+			overwrite: mods_overwrite.clone(),
 			// TODO: Define workpath as a variable in VFS Config
 			workdir: self
 				.instance_absolute_path()
@@ -1160,11 +1861,26 @@ impl GameInstance {
 				.join("mods"),
 		};
 
-		let vfs_mods = self
-			.mount_vfs_sub("mods", vfs_config.clone(), mods_mount_paths, true)
-			.map_err(|e| format!("Failed to mount sub-vfs ({}): {}", "mods", e.to_string()))?;
+		// The game folder contains the mods one, so it goes first
+		if mount_root {
+			let root_target = DeploymentTarget {
+				name: String::from("root"),
+				path: self.config.paths.game.clone(),
+				source_folder: String::from(root_deployment::ROOT_FOLDER_NAME),
+			};
+			if let Some(root_mount_paths) =
+				self.deployment_target_mount_paths(&root_target, &filtered_mods, &mods_overwrite)?
+			{
+				mount_configs.push(self.vfs_mount_config(
+					"root",
+					vfs_config,
+					root_mount_paths,
+					true,
+				));
+			}
+		}
 
-		return_vfs_vec.push(vfs_mods);
+		mount_configs.push(self.vfs_mount_config("mods", vfs_config, mods_mount_paths, true));
 
 		// Mount Saves
 		match &self.config.paths.deployment.saves {
@@ -1196,13 +1912,12 @@ impl GameInstance {
 						.join("saves"),
 				};
 
-				let vfs_saves = self
-					.mount_vfs_sub("saves", vfs_config.clone(), saves_mount_paths, false)
-					.map_err(|e| {
-						format!("Failed to mount sub-vfs ({}): {}", "saves", e.to_string())
-					})?;
-
-				return_vfs_vec.push(vfs_saves);
+				mount_configs.push(self.vfs_mount_config(
+					"saves",
+					vfs_config,
+					saves_mount_paths,
+					false,
+				));
 			}
 			None => {}
 		}
@@ -1237,41 +1952,79 @@ impl GameInstance {
 						.join("settings"),
 				};
 
-				let vfs_settings = self
-					.mount_vfs_sub("settings", vfs_config.clone(), settings_mount_paths, false)
-					.map_err(|e| {
-						format!(
-							"Failed to mount sub-vfs ({}): {}",
-							"settings",
-							e.to_string()
-						)
-					})?;
-
-				return_vfs_vec.push(vfs_settings);
+				mount_configs.push(self.vfs_mount_config(
+					"settings",
+					vfs_config,
+					settings_mount_paths,
+					false,
+				));
 			}
 			None => {}
 		}
 
-		return Ok(return_vfs_vec);
+		// Mount extra deployment targets, from the source folder of each mod
+		targets::validate_deployment_targets(&self.config.deployment_targets)?;
+		for target in self.config.deployment_targets.iter() {
+			let target_mount_paths = match self.deployment_target_mount_paths(
+				target,
+				&filtered_mods,
+				&mods_overwrite,
+			)? {
+				Some(target_mount_paths) => target_mount_paths,
+				None => continue,
+			};
+
+			// Targets like "SKSE/Plugins" may not exist until a mod uses them
+			if !target_mount_paths.target.exists() {
+				file_controller::create_folder(&target_mount_paths.target).map_err(|e| {
+					format!(
+						"Failed to create deployment target \"{}\": {}",
+						target.name,
+						e.to_string()
+					)
+				})?;
+			}
+			if !target_mount_paths.target.is_dir() {
+				return Err(format!(
+					"Deployment target \"{}\" is not a directory",
+					target.name
+				));
+			}
+
+			mount_configs.push(self.vfs_mount_config(
+				&target.name,
+				vfs_config,
+				target_mount_paths,
+				true,
+			));
+		}
+
+		return Ok(mount_configs);
 	}
 
-	fn mount_vfs_sub(
+	fn vfs_mount_config(
 		&self,
 		name: &str,
-		vfs_config: VFSConfig,
+		vfs_config: &VFSConfig,
 		mount_paths: VFSMountPaths,
 		should_overlay_target: bool,
-	) -> Result<Box<dyn BaseVFS>, String> {
+	) -> VFSMountConfig {
 		let mount_name = format!("{}{}", self.config.name.clone(), name);
 
-		let vfs_mount_config = VFSMountConfig {
+		return VFSMountConfig {
 			mount_name,
-			command: vfs_config.command,
+			command: vfs_config.command.clone(),
 			paths: mount_paths,
 			should_overlay_target,
 		};
+	}
 
-		let vfs_implementation = match vfs_config.implementation {
+	fn mount_vfs_sub(
+		&self,
+		implementation: VFSImplementation,
+		vfs_mount_config: VFSMountConfig,
+	) -> Result<Box<dyn BaseVFS>, String> {
+		let vfs_implementation = match implementation {
 			VFSImplementation::UnionFSFuse => UnionFSFuse {
 				config: vfs_mount_config,
 			},
@@ -1294,3 +2047,88 @@ impl GameInstance {
 	// 	};
 	// }
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{add_mod_with_files, test_instance};
+
+	fn mod_names(instance: &GameInstance) -> Vec<String> {
+		return instance.mods.iter().map(|m| m.name.clone()).collect();
+	}
+
+	#[test]
+	fn moves_and_toggles_separator_groups() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(&dir.path().join("groups"), "groups");
+		add_mod_with_files(&mut instance, "Skyui", "1.0", vec![]);
+		instance
+			.create_separator("Textures".to_string(), "#ff0000".to_string())
+			.unwrap();
+		add_mod_with_files(&mut instance, "Sky", "1.0", vec![]);
+		add_mod_with_files(&mut instance, "Trees", "1.0", vec![]);
+		assert!(instance
+			.create_separator("Skyui".to_string(), "#00ff00".to_string())
+			.is_err());
+
+		// Separators keep their place when mods are reloaded
+		assert_eq!(
+			mod_names(&instance),
+			vec!["base", "Skyui", "Textures", "Sky", "Trees", "overwrite"]
+		);
+		assert_eq!(
+			instance
+				.get_separator_group_indexes("Textures".to_string())
+				.unwrap(),
+			vec![2, 3, 4]
+		);
+
+		instance
+			.set_separator_group_enabled("Textures".to_string(), false)
+			.unwrap();
+		assert!(instance.mods[1].enabled);
+		assert!(!instance.mods[3].enabled && !instance.mods[4].enabled);
+
+		let new_indexes = instance
+			.move_separator_group("Textures".to_string(), 1)
+			.unwrap();
+		assert_eq!(new_indexes, vec![1, 2, 3]);
+		assert_eq!(
+			mod_names(&instance),
+			vec!["base", "Textures", "Sky", "Trees", "Skyui", "overwrite"]
+		);
+
+		// Separators are not deployed and can't get versions
+		assert!(instance
+			.create_mod_version(
+				"Textures".to_string(),
+				"1.0".to_string(),
+				ModInfo::default()
+			)
+			.is_err());
+		let names: Vec<String> = instance
+			.get_enabled_mods()
+			.iter()
+			.map(|m| m.name.clone())
+			.collect();
+		assert_eq!(names, vec!["base", "Skyui", "overwrite"]);
+
+		// Recreated from a modlist
+		instance
+			.update_separator("Textures".to_string(), None, Some(true))
+			.unwrap();
+		let manifest = instance.export_modlist().unwrap();
+		assert!(manifest.mods[0].archive.is_none());
+
+		let mut target = test_instance(&dir.path().join("target"), "target");
+		add_mod_with_files(&mut target, "Sky", "1.0", vec![]);
+		target.apply_modlist(&manifest).unwrap();
+		assert_eq!(
+			mod_names(&target),
+			vec!["base", "Textures", "Sky", "overwrite"]
+		);
+		let separator = target.mods[1].separator.clone().unwrap();
+		assert_eq!(separator.color, "#ff0000");
+		assert!(separator.collapsed);
+	}
+}
@@ -0,0 +1,714 @@
+use file_integrity::hash_file;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::instance_mod::{InstanceMod, ModInfo, ModSeparator};
+use super::{GameIdentifier, GameInstance};
+use crate::controllers::file_controller;
+use crate::mods::downloader::{self, Download, DownloadNexusData, DownloadStatus};
+use crate::state::config::nexusmods_config::{NMSchemeParameters, NexusModsConfig};
+
+// Bumped whenever the manifest changes in a non backwards-compatible way
+pub const MODLIST_FORMAT_VERSION: u32 = 1;
+
+const PLUGINS_FILE_NAME: &str = "plugins.txt";
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct ModlistNexusIds {
+	pub game_domain: String,
+	pub mod_id: String,
+	pub file_id: String,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct ModlistArchive {
+	pub file_name: String,
+	#[serde(default)]
+	pub md5: Option<String>,
+	#[serde(default)]
+	pub size: Option<String>,
+	// Direct download url, if it is not a NexusMods file
+	#[serde(default)]
+	pub url: Option<String>,
+	#[serde(default)]
+	pub nexus: Option<ModlistNexusIds>,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct ModlistMod {
+	pub name: String,
+	pub enabled: bool,
+	pub selected_version: String,
+	#[serde(default)]
+	pub info: ModInfo,
+	#[serde(default)]
+	pub archive: Option<ModlistArchive>,
+	// Separators are recreated on import, they have no files
+	#[serde(default)]
+	pub separator: Option<ModSeparator>,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct ModlistPlugin {
+	pub name: String,
+	pub enabled: bool,
+}
+
+// Portable description of an instance setup, shared between machines
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct ModlistManifest {
+	pub format_version: u32,
+	pub name: String,
+	pub game_identifier: GameIdentifier,
+	#[serde(default)]
+	pub exported_at: Option<String>,
+	// In mod order, without "base" and "overwrite"
+	#[serde(default)]
+	pub mods: Vec<ModlistMod>,
+	// In load order
+	#[serde(default)]
+	pub plugins: Vec<ModlistPlugin>,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct ModlistMissingArchive {
+	pub mod_name: String,
+	pub file_name: Option<String>,
+	pub queued: bool,
+	pub error: Option<String>,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct ModlistImportReport {
+	// Installed mods whose order/enabled/version were restored
+	pub applied_mods: Vec<String>,
+	// Mods of the list that are not installed, import again once installed
+	pub missing_mods: Vec<String>,
+	// Installed mods without the version selected in the list
+	pub missing_versions: Vec<String>,
+	pub missing_archives: Vec<ModlistMissingArchive>,
+	pub plugins_written: bool,
+}
+
+enum ModlistFormat {
+	Json,
+	Toml,
+}
+
+impl ModlistFormat {
+	fn from_path(path: &PathBuf) -> Self {
+		let is_toml = path
+			.extension()
+			.is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+
+		return match is_toml {
+			true => ModlistFormat::Toml,
+			false => ModlistFormat::Json,
+		};
+	}
+}
+
+impl ModlistManifest {
+	pub fn parse(content: &str, path: &PathBuf) -> Result<Self, String> {
+		let manifest: ModlistManifest = match ModlistFormat::from_path(path) {
+			ModlistFormat::Json => serde_json::from_str(content)
+				.map_err(|e| format!("Failed to parse modlist: {}", e))?,
+			ModlistFormat::Toml => {
+				toml::from_str(content).map_err(|e| format!("Failed to parse modlist: {}", e))?
+			}
+		};
+
+		if manifest.format_version > MODLIST_FORMAT_VERSION {
+			return Err(format!(
+				"Modlist format version {} is not supported, please update",
+				manifest.format_version
+			));
+		}
+
+		return Ok(manifest);
+	}
+
+	pub fn load(path: &PathBuf) -> Result<Self, String> {
+		let content = file_controller::read_file(path.clone())
+			.map_err(|e| format!("Failed to read modlist: {}", e))?;
+
+		return Self::parse(&content, path);
+	}
+
+	// Saved as TOML when the path ends with ".toml", JSON otherwise
+	pub fn save(&self, path: &PathBuf) -> Result<(), String> {
+		let content = match ModlistFormat::from_path(path) {
+			ModlistFormat::Json => serde_json::to_string_pretty(self)
+				.map_err(|e| format!("Failed to serialize modlist: {}", e))?,
+			ModlistFormat::Toml => toml::to_string_pretty(self)
+				.map_err(|e| format!("Failed to serialize modlist: {}", e))?,
+		};
+
+		return file_controller::save_file(path.clone(), content.as_bytes())
+			.map_err(|e| format!("Failed to save modlist: {}", e));
+	}
+}
+
+// ------------------------------
+// plugins.txt
+// ------------------------------
+
+// Newer games list every plugin, active ones are prefixed with "*"
+// Older games only list the active plugins
+fn uses_asterisk_format(game_identifier: GameIdentifier) -> bool {
+	return match game_identifier {
+		GameIdentifier::SkyrimSE | GameIdentifier::Fallout4 => true,
+		_ => false,
+	};
+}
+
+fn supports_plugins_file(game_identifier: GameIdentifier) -> bool {
+	return match game_identifier {
+		// Morrowind keeps its plugins in Morrowind.ini
+		GameIdentifier::Generic | GameIdentifier::Morrowind => false,
+		_ => true,
+	};
+}
+
+pub fn parse_plugins_file(content: &str, game_identifier: GameIdentifier) -> Vec<ModlistPlugin> {
+	let asterisk_format = uses_asterisk_format(game_identifier);
+
+	return content
+		.lines()
+		.map(|line| line.trim())
+		.filter(|line| !line.is_empty() && !line.starts_with('#'))
+		.map(|line| match asterisk_format {
+			true => ModlistPlugin {
+				name: line.trim_start_matches('*').to_string(),
+				enabled: line.starts_with('*'),
+			},
+			false => ModlistPlugin {
+				name: line.to_string(),
+				enabled: true,
+			},
+		})
+		.collect();
+}
+
+pub fn format_plugins_file(
+	plugins: &Vec<ModlistPlugin>,
+	game_identifier: GameIdentifier,
+) -> String {
+	let asterisk_format = uses_asterisk_format(game_identifier);
+
+	let mut content = String::new();
+	for plugin in plugins {
+		match (asterisk_format, plugin.enabled) {
+			(true, true) => content.push_str(&format!("*{}\n", plugin.name)),
+			(true, false) | (false, true) => content.push_str(&format!("{}\n", plugin.name)),
+			(false, false) => {}
+		}
+	}
+
+	return content;
+}
+
+// ------------------------------
+// Archives
+// ------------------------------
+
+impl ModlistArchive {
+	fn from_download(download: &Download, downloads_path: &PathBuf) -> Self {
+		// Hash archives that were never verified, the list is useless without it
+		let md5 = download.md5.clone().or_else(|| {
+			let archive_path = downloads_path.join(&download.file_name);
+			match archive_path.is_file() {
+				true => Some(hash_file(archive_path.to_string_lossy().to_string()).md5_hash),
+				false => None,
+			}
+		});
+
+		return Self {
+			file_name: download.file_name.clone(),
+			md5: md5.map(|md5| md5.to_lowercase()),
+			size: Some(download.size_total.clone()),
+			url: match download.nexus_data.is_some() || download.url.is_empty() {
+				true => None,
+				false => Some(download.url.clone()),
+			},
+			nexus: download
+				.nexus_data
+				.as_ref()
+				.map(|nexus_data| ModlistNexusIds {
+					game_domain: nexus_data.game_domain.clone(),
+					mod_id: nexus_data.mod_id.clone(),
+					file_id: nexus_data.file_id.clone(),
+				}),
+		};
+	}
+
+	pub fn matches_download(&self, download: &Download) -> bool {
+		if let (Some(md5), Some(download_md5)) = (&self.md5, &download.md5) {
+			return md5.eq_ignore_ascii_case(download_md5);
+		}
+
+		if let (Some(nexus), Some(nexus_data)) = (&self.nexus, &download.nexus_data) {
+			return nexus.mod_id == nexus_data.mod_id && nexus.file_id == nexus_data.file_id;
+		}
+
+		return self.file_name == download.file_name;
+	}
+
+	// Build the download for this archive, if it can be fetched without the user
+	pub async fn create_download(&self, nexus: &NexusModsConfig) -> Result<Download, String> {
+		let mut download = Download {
+			file_name: self.file_name.clone(),
+			status: DownloadStatus::Queued,
+			size_total: String::from("0"),
+			size_downloaded: String::from("0"),
+			url: String::new(),
+			mirrors: Vec::new(),
+			md5: self.md5.clone(),
+			error: None,
+			downloader: None,
+			pending_update: false,
+			added_at: downloader::default_date(),
+			completed_at: None,
+			nexus_data: None,
+			installed: false,
+		};
+
+		if let Some(nexus_ids) = &self.nexus {
+			let nexus_download = nexus
+				.convert_nmm_request_to_url(NMSchemeParameters {
+					game_domain: nexus_ids.game_domain.clone(),
+					mod_id: nexus_ids.mod_id.clone(),
+					file_id: nexus_ids.file_id.clone(),
+					key: None,
+					expires: None,
+				})
+				.await
+				.map_err(|e| format!("Download it from NexusMods: {}", e))?;
+
+			download.set_nexus_download_url(&nexus_download);
+			download.nexus_data = Some(DownloadNexusData {
+				mod_id: nexus_ids.mod_id.clone(),
+				file_id: nexus_ids.file_id.clone(),
+				game_domain: nexus_ids.game_domain.clone(),
+				..Default::default()
+			});
+
+			return Ok(download);
+		}
+
+		return match &self.url {
+			Some(url) if !url.is_empty() => {
+				download.url = url.clone();
+				Ok(download)
+			}
+			_ => Err("No NexusMods file or url to download it from".to_string()),
+		};
+	}
+}
+
+// Queue the downloads of the missing archives that can be fetched
+pub async fn queue_missing_archives(
+	nexus: &NexusModsConfig,
+	manifest: &ModlistManifest,
+	report: &mut ModlistImportReport,
+) -> Vec<Download> {
+	let mut downloads = Vec::new();
+
+	for missing_archive in report.missing_archives.iter_mut() {
+		if missing_archive.queued {
+			continue;
+		}
+
+		let archive = manifest
+			.mods
+			.iter()
+			.find(|modlist_mod| modlist_mod.name == missing_archive.mod_name)
+			.and_then(|modlist_mod| modlist_mod.archive.as_ref());
+
+		let archive = match archive {
+			Some(archive) => archive,
+			None => continue,
+		};
+
+		match archive.create_download(nexus).await {
+			Ok(download) => {
+				missing_archive.queued = true;
+				missing_archive.error = None;
+				downloads.push(download);
+			}
+			Err(e) => {
+				missing_archive.error = Some(e);
+			}
+		}
+	}
+
+	return downloads;
+}
+
+// ------------------------------
+// Export / Import
+// ------------------------------
+
+impl GameInstance {
+	pub fn get_plugins_file_path(&self) -> PathBuf {
+		return self
+			.parse_path_variables(self.config.paths.internal.settings.clone())
+			.join(PLUGINS_FILE_NAME);
+	}
+
+	// Archive a mod was installed from
+	// Nexus metadata or the archive name are the only link we have
+	fn find_mod_archive(&self, instance_mod: &InstanceMod) -> Option<&Download> {
+		// Archive the selected version was installed from, when known
+		let from_source = instance_mod
+			.get_version(&instance_mod.selected_version_identifier)
+			.and_then(|version| version.source.as_ref())
+			.and_then(|source| {
+				return self.downloads.iter().find(|download| {
+					download.status == DownloadStatus::Downloaded
+						&& (download.file_name == source.archive_file_name
+							|| (source.archive_md5.is_some()
+								&& download.md5.as_ref().map(|md5| md5.to_lowercase())
+									== source.archive_md5))
+				});
+			});
+		if from_source.is_some() {
+			return from_source;
+		}
+
+		let from_collection = self
+			.collections
+			.iter()
+			.flat_map(|collection| collection.mods.iter())
+			.find(|collection_mod| collection_mod.name == instance_mod.name)
+			.and_then(|collection_mod| collection_mod.download_file_name.clone());
+
+		let mod_name = instance_mod.name.to_lowercase();
+		return self.downloads.iter().find(|download| {
+			if download.status != DownloadStatus::Downloaded {
+				return false;
+			}
+
+			if from_collection.as_ref() == Some(&download.file_name) {
+				return true;
+			}
+
+			let same_nexus_name = download
+				.nexus_data
+				.as_ref()
+				.and_then(|nexus_data| nexus_data.mod_name.as_ref())
+				.is_some_and(|name| name.to_lowercase() == mod_name);
+
+			let file_stem = PathBuf::from(&download.file_name)
+				.file_stem()
+				.map(|stem| stem.to_string_lossy().to_lowercase());
+
+			return same_nexus_name || file_stem == Some(mod_name.clone());
+		});
+	}
+
+	pub fn export_modlist(&self) -> Result<ModlistManifest, String> {
+		let downloads_path = self.get_downloads_absolute_path();
+
+		let mods = self
+			.mods
+			.iter()
+			.filter(|instance_mod| instance_mod.name != "base" && instance_mod.name != "overwrite")
+			.map(|instance_mod| ModlistMod {
+				name: instance_mod.name.clone(),
+				enabled: instance_mod.enabled,
+				selected_version: instance_mod.selected_version_identifier.clone(),
+				info: instance_mod.info.clone(),
+				archive: match instance_mod.is_separator() {
+					true => None,
+					false => self
+						.find_mod_archive(instance_mod)
+						.map(|download| ModlistArchive::from_download(download, &downloads_path)),
+				},
+				separator: instance_mod.separator.clone(),
+			})
+			.collect();
+
+		let plugins_file_path = self.get_plugins_file_path();
+		let plugins = match plugins_file_path.is_file() {
+			true => {
+				let content = file_controller::read_file(plugins_file_path)
+					.map_err(|e| format!("Failed to read plugins file: {}", e))?;
+				parse_plugins_file(&content, self.config.game_identifier)
+			}
+			false => Vec::new(),
+		};
+
+		return Ok(ModlistManifest {
+			format_version: MODLIST_FORMAT_VERSION,
+			name: self.config.name.clone(),
+			game_identifier: self.config.game_identifier,
+			exported_at: Some(downloader::default_date()),
+			mods,
+			plugins,
+		});
+	}
+
+	// Restore the order, enabled state and versions of the installed mods,
+	// and the plugin load order. Missing archives are reported, not fetched.
+	pub fn apply_modlist(
+		&mut self,
+		manifest: &ModlistManifest,
+	) -> Result<ModlistImportReport, String> {
+		if manifest.game_identifier != self.config.game_identifier {
+			return Err(format!(
+				"Modlist is for {:?}, this instance is {:?}",
+				manifest.game_identifier, self.config.game_identifier
+			));
+		}
+
+		let mut report = ModlistImportReport::default();
+
+		for modlist_mod in manifest.mods.iter() {
+			if let Some(separator) = &modlist_mod.separator {
+				let is_separator = match self.get_mod_by_name(modlist_mod.name.clone()) {
+					Some(instance_mod) => instance_mod.is_separator(),
+					None => {
+						self.create_separator(modlist_mod.name.clone(), separator.color.clone())?;
+						true
+					}
+				};
+
+				// A mod with the same name is installed
+				if !is_separator {
+					report.missing_mods.push(modlist_mod.name.clone());
+					continue;
+				}
+
+				self.update_separator(
+					modlist_mod.name.clone(),
+					Some(separator.color.clone()),
+					Some(separator.collapsed),
+				)?;
+				report.applied_mods.push(modlist_mod.name.clone());
+				continue;
+			}
+
+			let instance_mod = match self.get_mod_by_name(modlist_mod.name.clone()) {
+				Some(instance_mod) => instance_mod,
+				None => {
+					report.missing_mods.push(modlist_mod.name.clone());
+					continue;
+				}
+			};
+
+			instance_mod.enabled = modlist_mod.enabled;
+			if instance_mod.info.is_empty() {
+				instance_mod.info = modlist_mod.info.clone();
+			}
+
+			if instance_mod.has_version(modlist_mod.selected_version.clone()) {
+				instance_mod.selected_version_identifier = modlist_mod.selected_version.clone();
+			} else {
+				report.missing_versions.push(modlist_mod.name.clone());
+			}
+
+			instance_mod.save()?;
+			report.applied_mods.push(modlist_mod.name.clone());
+		}
+
+		// Archives needed for what is not installed
+		for modlist_mod in manifest.mods.iter() {
+			let is_missing = report.missing_mods.contains(&modlist_mod.name)
+				|| report.missing_versions.contains(&modlist_mod.name);
+			if !is_missing {
+				continue;
+			}
+
+			let archive = modlist_mod.archive.as_ref();
+			let download = archive.and_then(|archive| {
+				self.downloads
+					.iter()
+					.find(|download| archive.matches_download(download))
+			});
+			if download.is_some_and(|download| download.status == DownloadStatus::Downloaded) {
+				continue;
+			}
+
+			report.missing_archives.push(ModlistMissingArchive {
+				mod_name: modlist_mod.name.clone(),
+				file_name: archive.map(|archive| archive.file_name.clone()),
+				// Already being downloaded
+				queued: download.is_some_and(|download| download.status != DownloadStatus::Failed),
+				error: match archive {
+					Some(_) => None,
+					None => Some("The modlist does not reference an archive".to_string()),
+				},
+			});
+		}
+
+		// Mod order, mods that are not in the list keep their order after the listed ones
+		let manifest_indexes: HashMap<&String, usize> = manifest
+			.mods
+			.iter()
+			.enumerate()
+			.map(|(index, modlist_mod)| (&modlist_mod.name, index))
+			.collect();
+		self.mods.sort_by_key(|instance_mod| {
+			*manifest_indexes
+				.get(&instance_mod.name)
+				.unwrap_or(&usize::MAX)
+		});
+		self.reseat_static_mods();
+		self.rebuild_mods_order()?;
+		self.refresh_file_index(vec![])?;
+
+		// Plugin load order
+		if !manifest.plugins.is_empty() && supports_plugins_file(self.config.game_identifier) {
+			let content = format_plugins_file(&manifest.plugins, self.config.game_identifier);
+			file_controller::save_file_with_backup(
+				self.get_plugins_file_path(),
+				content.as_bytes(),
+			)
+			.map_err(|e| format!("Failed to save plugins file: {}", e))?;
+			report.plugins_written = true;
+		}
+
+		self.save()?;
+
+		return Ok(report);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{add_mod_with_files, test_instance};
+
+	fn mod_names(instance: &GameInstance) -> Vec<String> {
+		return instance.mods.iter().map(|m| m.name.clone()).collect();
+	}
+
+	#[test]
+	fn plugins_file_round_trip() {
+		let content =
+			"# This file is used by the game\n*Unofficial Patch.esp\nDisabled.esp\n*Mod.esp\n";
+
+		let plugins = parse_plugins_file(content, GameIdentifier::SkyrimSE);
+		assert_eq!(plugins.len(), 3);
+		assert_eq!(plugins[0].name, "Unofficial Patch.esp");
+		assert!(plugins[0].enabled);
+		assert!(!plugins[1].enabled);
+
+		assert_eq!(
+			format_plugins_file(&plugins, GameIdentifier::SkyrimSE),
+			"*Unofficial Patch.esp\nDisabled.esp\n*Mod.esp\n"
+		);
+
+		// Older games only list active plugins
+		assert_eq!(
+			format_plugins_file(&plugins, GameIdentifier::FalloutNV),
+			"Unofficial Patch.esp\nMod.esp\n"
+		);
+	}
+
+	#[test]
+	fn rejects_newer_formats() {
+		let json = r#"{"format_version": 99, "name": "Test", "game_identifier": "SkyrimSE"}"#;
+
+		assert!(ModlistManifest::parse(json, &PathBuf::from("list.json")).is_err());
+	}
+
+	#[test]
+	fn exports_and_imports_modlist() {
+		let dir = tempfile::tempdir().unwrap();
+
+		// Source machine
+		let mut source = test_instance(&dir.path().join("source"), "source");
+		source.config.game_identifier = GameIdentifier::SkyrimSE;
+		add_mod_with_files(&mut source, "Skyui", "1.0", vec![]);
+		add_mod_with_files(&mut source, "Skyui", "2.0", vec![]);
+		add_mod_with_files(&mut source, "Patch", "1.0", vec![]);
+		add_mod_with_files(&mut source, "Textures", "1.0", vec![]);
+		source.set_mod_enabled("Patch".to_string(), false).unwrap();
+		source.move_mod_by_name("Textures".to_string(), 1).unwrap();
+
+		let mut download =
+			Download::from_existing_file(&source.get_downloads_absolute_path().join("textures.7z"));
+		download.md5 = Some("ABCDEF".to_string());
+		download.nexus_data = Some(DownloadNexusData {
+			mod_id: "1".to_string(),
+			file_id: "2".to_string(),
+			game_domain: "skyrimspecialedition".to_string(),
+			mod_name: Some("Textures".to_string()),
+			..Default::default()
+		});
+		source.downloads.push(download);
+
+		std::fs::create_dir_all(source.get_plugins_file_path().parent().unwrap()).unwrap();
+		std::fs::write(source.get_plugins_file_path(), "*Skyui.esp\nPatch.esp\n").unwrap();
+
+		let modlist_path = dir.path().join("modlist.toml");
+		source
+			.export_modlist()
+			.unwrap()
+			.save(&modlist_path)
+			.unwrap();
+
+		// Other machine, different order and versions, "Textures" is not installed
+		let mut target = test_instance(&dir.path().join("target"), "target");
+		target.config.game_identifier = GameIdentifier::SkyrimSE;
+		add_mod_with_files(&mut target, "Patch", "1.0", vec![]);
+		add_mod_with_files(&mut target, "Skyui", "1.0", vec![]);
+
+		let manifest = ModlistManifest::load(&modlist_path).unwrap();
+		assert_eq!(manifest.format_version, MODLIST_FORMAT_VERSION);
+		assert_eq!(manifest.mods[0].name, "Textures");
+		assert_eq!(
+			manifest.mods[0]
+				.archive
+				.as_ref()
+				.unwrap()
+				.nexus
+				.as_ref()
+				.unwrap()
+				.file_id,
+			"2"
+		);
+
+		let report = target.apply_modlist(&manifest).unwrap();
+		assert_eq!(report.applied_mods, vec!["Skyui", "Patch"]);
+		assert_eq!(report.missing_mods, vec!["Textures"]);
+		assert_eq!(report.missing_versions, vec!["Skyui"]);
+		assert_eq!(report.missing_archives.len(), 2);
+		assert_eq!(
+			report.missing_archives[0].file_name,
+			Some("textures.7z".to_string())
+		);
+		// No archive was found for Skyui on the source machine
+		assert_eq!(report.missing_archives[1].mod_name, "Skyui");
+		assert!(report.missing_archives[1].error.is_some());
+		assert!(report.plugins_written);
+
+		assert_eq!(
+			mod_names(&target),
+			vec!["base", "Skyui", "Patch", "overwrite"]
+		);
+		assert_eq!(target.mods_indexes.get("Skyui"), Some(&1));
+		assert!(!target.get_mod_by_name("Patch".to_string()).unwrap().enabled);
+
+		// The order survives a reload from disk
+		target.load_mods().unwrap();
+		assert_eq!(
+			mod_names(&target),
+			vec!["base", "Skyui", "Patch", "overwrite"]
+		);
+
+		assert_eq!(
+			std::fs::read_to_string(target.get_plugins_file_path()).unwrap(),
+			"*Skyui.esp\nPatch.esp\n"
+		);
+	}
+}
@@ -0,0 +1,313 @@
+use std::path::PathBuf;
+
+use crate::controllers::file_controller;
+
+use super::instance_mod::{normalize_mod_file_path, InstanceMod, ModInfo};
+use super::GameInstance;
+
+// File written to "overwrite" by the game or a tool
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct OverwriteFile {
+	// Relative to the overwrite folder, as found on disk
+	pub path: String,
+	// Bytes
+	pub size: String,
+}
+
+impl GameInstance {
+	fn get_overwrite_path(&self) -> PathBuf {
+		return self.parse_path_variables(self.overwrite_relative_path());
+	}
+
+	pub fn list_overwrite_files(&self) -> Result<Vec<OverwriteFile>, String> {
+		let overwrite_path = self.get_overwrite_path();
+		if !overwrite_path.is_dir() {
+			return Ok(Vec::new());
+		}
+
+		let mut files = Vec::new();
+		for file in file_controller::list_files_recursively_flattened(overwrite_path.clone())
+			.map_err(|e| format!("Failed to list overwrite files: {}", e.to_string()))?
+		{
+			let file = PathBuf::from(file);
+			let path = match file.strip_prefix(&overwrite_path) {
+				Ok(path) => path.to_string_lossy().to_string(),
+				Err(_) => continue,
+			};
+			let size = std::fs::metadata(&file)
+				.map(|metadata| metadata.len())
+				.unwrap_or(0);
+
+			files.push(OverwriteFile {
+				path,
+				size: size.to_string(),
+			});
+		}
+
+		files.sort_by(|a, b| a.path.cmp(&b.path));
+
+		return Ok(files);
+	}
+
+	// Overwrite files matching the given paths (files or folders), all of them when empty
+	fn select_overwrite_files(&self, paths: &Vec<String>) -> Result<Vec<String>, String> {
+		let files = self.list_overwrite_files()?;
+		let paths = paths
+			.iter()
+			.map(|path| normalize_mod_file_path(path))
+			.collect::<Vec<String>>();
+
+		let selected = files
+			.into_iter()
+			.map(|file| file.path)
+			.filter(|file| {
+				let file = normalize_mod_file_path(file);
+				return paths.is_empty()
+					|| paths
+						.iter()
+						.any(|path| file == *path || file.starts_with(&format!("{}/", path)));
+			})
+			.collect::<Vec<String>>();
+
+		if selected.is_empty() {
+			return Err(String::from("No matching files in overwrite"));
+		}
+
+		return Ok(selected);
+	}
+
+	// Move overwrite files into a folder, with the casing of the deployment folder.
+	// Existing files are replaced, the overwrite ones are the most recent.
+	fn move_overwrite_files(
+		&self,
+		files: &Vec<String>,
+		destination: &PathBuf,
+	) -> Result<(), String> {
+		let overwrite_path = self.get_overwrite_path();
+		let deployment_file_structure = self.get_mods_deployment_file_structure()?;
+
+		for file in files {
+			let case_folded_path =
+				file_controller::case_fold_to_structure(&deployment_file_structure, file);
+
+			file_controller::move_file(
+				overwrite_path.join(file),
+				destination.join(case_folded_path),
+			)
+			.map_err(|e| format!("Failed to move \"{}\": {}", file, e.to_string()))?;
+
+			// Drop the folders left empty
+			let mut parent = overwrite_path
+				.join(file)
+				.parent()
+				.map(|path| path.to_path_buf());
+			while let Some(folder) = parent {
+				if folder == overwrite_path
+					|| file_controller::delete_folder_if_empty(folder.clone()).is_err()
+				{
+					break;
+				}
+				parent = folder.parent().map(|path| path.to_path_buf());
+			}
+		}
+
+		return Ok(());
+	}
+
+	// Turn overwrite files into a new mod
+	pub fn create_mod_from_overwrite(
+		&mut self,
+		mod_name: String,
+		version: String,
+		paths: Vec<String>,
+	) -> Result<InstanceMod, String> {
+		if mod_name == "base"
+			|| mod_name == "overwrite"
+			|| self.get_mod_by_name(mod_name.clone()).is_some()
+		{
+			return Err(format!("Mod \"{}\" already exists", mod_name));
+		}
+
+		let files = self.select_overwrite_files(&paths)?;
+
+		let mut instance_mod =
+			self.create_mod_version(mod_name.clone(), version.clone(), ModInfo::default())?;
+		self.move_overwrite_files(&files, &instance_mod.get_selected_version_absolute_path())?;
+
+		instance_mod.refresh_version_stats(version)?;
+		instance_mod.save()?;
+
+		self.load_mods()?;
+		self.refresh_file_index(vec![mod_name])?;
+
+		return Ok(instance_mod);
+	}
+
+	// Move overwrite files into the selected version of a mod
+	pub fn move_overwrite_to_mod(
+		&mut self,
+		mod_name: String,
+		paths: Vec<String>,
+	) -> Result<(), String> {
+		let destination = match self.get_mod_by_name(mod_name.clone()) {
+			Some(instance_mod) => {
+				if instance_mod.name == "base"
+					|| instance_mod.name == "overwrite"
+					|| instance_mod.is_separator()
+				{
+					return Err(format!("Files can't be moved to \"{}\"", mod_name));
+				}
+				// Other instances would get the files too
+				if instance_mod.is_from_library() {
+					return Err(format!("\"{}\" is in the library", mod_name));
+				}
+
+				instance_mod.get_selected_version_absolute_path()
+			}
+			None => return Err(format!("Mod {} not found", mod_name)),
+		};
+
+		let files = self.select_overwrite_files(&paths)?;
+		self.move_overwrite_files(&files, &destination)?;
+
+		if let Some(instance_mod) = self.get_mod_by_name(mod_name.clone()) {
+			instance_mod.refresh_version_stats(instance_mod.selected_version_identifier.clone())?;
+			instance_mod.save()?;
+		}
+
+		return self.refresh_file_index(vec![mod_name]);
+	}
+
+	// Delete everything in overwrite, the folder itself is kept
+	pub fn clear_overwrite(&mut self) -> Result<(), String> {
+		let overwrite_path = self.get_overwrite_path();
+
+		for entry in file_controller::list_entries_absolute_path(overwrite_path.clone())
+			.map_err(|e| format!("Failed to list overwrite: {}", e.to_string()))?
+		{
+			if entry.is_dir() {
+				file_controller::delete_folder_safe(entry.clone(), overwrite_path.clone())
+			} else {
+				std::fs::remove_file(&entry)
+			}
+			.map_err(|e| format!("Failed to delete {:?}: {}", entry, e.to_string()))?;
+		}
+
+		return self.refresh_file_index(Vec::new());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::config::vfs_config::VFSConfig;
+	use crate::test_utils::{add_mod_with_files, test_instance};
+
+	// Files written by tools, the deployment folder has its own casing
+	fn write_overwrite_files(instance: &GameInstance) {
+		std::fs::create_dir_all(instance.config.paths.root.join("game/Data/SKSE/Plugins")).unwrap();
+
+		let overwrite_path = instance.get_overwrite_path();
+		std::fs::create_dir_all(overwrite_path.join("skse/plugins")).unwrap();
+		std::fs::create_dir_all(overwrite_path.join("Logs")).unwrap();
+		std::fs::write(overwrite_path.join("skse/plugins/a.ini"), "a").unwrap();
+		std::fs::write(overwrite_path.join("skse/plugins/b.ini"), "b").unwrap();
+		std::fs::write(overwrite_path.join("Logs/tool.log"), "log").unwrap();
+	}
+
+	#[test]
+	fn creates_mod_from_overwrite() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "overwrite");
+		write_overwrite_files(&instance);
+
+		let files = instance.list_overwrite_files().unwrap();
+		assert_eq!(files.len(), 3);
+		assert_eq!(files[0].path, "Logs/tool.log");
+		assert_eq!(files[0].size, "3");
+
+		let instance_mod = instance
+			.create_mod_from_overwrite(
+				String::from("Plugin configs"),
+				String::from("1.0"),
+				vec![String::from("SKSE/plugins")],
+			)
+			.unwrap();
+
+		// Deployment casing is kept, unknown folders are lowercased
+		let version_path = instance_mod.get_selected_version_absolute_path();
+		assert!(version_path.join("SKSE/Plugins/a.ini").is_file());
+		assert!(version_path.join("SKSE/Plugins/b.ini").is_file());
+		assert_eq!(instance_mod.versions[0].files_count, 2);
+		assert!(instance
+			.get_mod_by_name(String::from("Plugin configs"))
+			.is_some());
+
+		let files = instance.list_overwrite_files().unwrap();
+		assert_eq!(files.len(), 1);
+		assert!(!instance.get_overwrite_path().join("skse").exists());
+
+		// Moved into the selected version of an existing mod
+		instance
+			.move_overwrite_to_mod(String::from("Plugin configs"), Vec::new())
+			.unwrap();
+		assert!(version_path.join("logs/tool.log").is_file());
+		assert!(instance.list_overwrite_files().unwrap().is_empty());
+		assert_eq!(
+			instance
+				.find_file_providers(String::from("logs/tool.log"))
+				.unwrap()[0]
+				.mod_name,
+			"Plugin configs"
+		);
+
+		assert!(instance
+			.create_mod_from_overwrite(String::from("Empty"), String::from("1.0"), Vec::new())
+			.is_err());
+	}
+
+	#[test]
+	fn clears_overwrite() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "overwrite");
+		write_overwrite_files(&instance);
+
+		instance.clear_overwrite().unwrap();
+		assert!(instance.get_overwrite_path().is_dir());
+		assert!(instance.list_overwrite_files().unwrap().is_empty());
+	}
+
+	#[test]
+	fn output_mod_replaces_overwrite() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "overwrite");
+		write_overwrite_files(&instance);
+		add_mod_with_files(&mut instance, "Skyui", "1.0", vec![]);
+
+		let output_path = instance
+			.prepare_output_mod(String::from("Tool output"))
+			.unwrap();
+
+		let mount_configs = instance
+			.get_vfs_mount_configs(&VFSConfig::new(), Some(String::from("Tool output")), false)
+			.unwrap();
+		let mods_config = mount_configs
+			.iter()
+			.find(|config| config.paths.target == dir.path().join("game/Data"))
+			.unwrap();
+
+		// Written files go to the output mod, which is not a layer below it
+		assert_eq!(mods_config.paths.overwrite, output_path);
+		assert!(!mods_config
+			.paths
+			.sources
+			.iter()
+			.any(|source| source.starts_with(&output_path)));
+		assert!(mods_config
+			.paths
+			.sources
+			.iter()
+			.any(|source| source.ends_with("Skyui/versions/1.0")));
+	}
+}
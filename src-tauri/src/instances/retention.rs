@@ -0,0 +1,348 @@
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::controllers::file_controller;
+
+use super::instance_mod::{InstanceMod, InstanceModVersion};
+use super::{GameInstance, VersionsRetentionConfig};
+
+static SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct PrunableModVersion {
+	pub mod_name: String,
+	pub identifier: String,
+	// Bytes
+	pub size: String,
+	// Bytes freed by deleting it
+	pub reclaimable_size: String,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct VersionsCleanupReport {
+	// Whether the versions were only listed, not deleted
+	pub dry_run: bool,
+	pub versions: Vec<PrunableModVersion>,
+	// Bytes freed (or to be freed) by deleting them
+	pub reclaimable_size: String,
+}
+
+// Bytes deleting a folder frees, files still linked elsewhere (file store) are left out
+fn reclaimable_folder_size(path: PathBuf) -> u64 {
+	if !path.is_dir() {
+		return 0;
+	}
+
+	return file_controller::list_files_recursively_flattened(path)
+		.unwrap_or_default()
+		.iter()
+		.filter_map(|file| std::fs::metadata(file).ok())
+		.filter(|metadata| metadata.nlink() <= 1)
+		.map(|metadata| metadata.len())
+		.sum();
+}
+
+fn now_seconds() -> u64 {
+	return SystemTime::now()
+		.duration_since(SystemTime::UNIX_EPOCH)
+		.map(|duration| duration.as_secs())
+		.unwrap_or(0);
+}
+
+impl InstanceMod {
+	pub fn set_version_pinned(
+		&mut self,
+		version_identifier: String,
+		pinned: bool,
+	) -> Result<(), String> {
+		let version = self
+			.get_version_mut(&version_identifier)
+			.ok_or(format!("Version {} not found", version_identifier))?;
+		version.pinned = pinned;
+
+		return self.save();
+	}
+
+	// Versions the policy does not keep, oldest first
+	// The selected version and pinned ones are always kept
+	pub fn get_prunable_versions(&self, policy: &VersionsRetentionConfig, now: u64) -> Vec<String> {
+		// Library versions may be used by other instances
+		if !policy.is_enabled()
+			|| self.name == "base"
+			|| self.name == "overwrite"
+			|| self.is_from_library()
+		{
+			return Vec::new();
+		}
+
+		// Versions are listed in the order they were found, not installed
+		let mut versions: Vec<&InstanceModVersion> = self.versions.iter().collect();
+		versions.sort_by(|a, b| a.compare_install_order(b));
+
+		let versions_count = versions.len();
+		return versions
+			.into_iter()
+			.enumerate()
+			.filter(|(index, version)| {
+				if version.pinned || version.identifier == self.selected_version_identifier {
+					return false;
+				}
+
+				let is_last = policy
+					.keep_last
+					.is_some_and(|keep_last| versions_count - index <= keep_last as usize);
+
+				// Versions without an install date are considered old
+				let is_recent = policy.keep_days.is_some_and(|keep_days| {
+					return version
+						.installed_at
+						.as_ref()
+						.and_then(|installed_at| installed_at.parse::<u64>().ok())
+						.is_some_and(|installed_at| {
+							now.saturating_sub(installed_at) < keep_days as u64 * SECONDS_PER_DAY
+						});
+				});
+
+				return !is_last && !is_recent;
+			})
+			.map(|(_, version)| version.identifier.clone())
+			.collect();
+	}
+
+	pub fn prune_versions(
+		&mut self,
+		policy: &VersionsRetentionConfig,
+		dry_run: bool,
+	) -> Result<Vec<PrunableModVersion>, String> {
+		let mut pruned = Vec::new();
+		for identifier in self.get_prunable_versions(policy, now_seconds()) {
+			// Files may have been changed by hand since it was installed
+			self.refresh_version_stats(identifier.clone())?;
+			let size = self
+				.get_version(&identifier)
+				.map(|version| version.size.clone())
+				.unwrap_or_default();
+			let reclaimable_size =
+				reclaimable_folder_size(self.get_version_absolute_path(identifier.clone()))
+					.to_string();
+
+			if !dry_run {
+				self.delete_version(identifier.clone())?;
+			}
+
+			pruned.push(PrunableModVersion {
+				mod_name: self.name.clone(),
+				identifier,
+				size,
+				reclaimable_size,
+			});
+		}
+
+		return Ok(pruned);
+	}
+}
+
+impl GameInstance {
+	pub fn set_mod_version_pinned(
+		&mut self,
+		mod_name: String,
+		version: String,
+		pinned: bool,
+	) -> Result<(), String> {
+		let instance_mod = match self.get_mod_by_name(mod_name.clone()) {
+			Some(mod_instance) => mod_instance,
+			None => {
+				return Err(format!("Mod {} not found", mod_name));
+			}
+		};
+
+		return instance_mod.set_version_pinned(version, pinned);
+	}
+
+	// Apply the retention policy to every mod, or only report what it would delete
+	pub fn clean_up_versions(&mut self, dry_run: bool) -> Result<VersionsCleanupReport, String> {
+		let policy = self.config.versions_retention.clone();
+
+		let mut report = VersionsCleanupReport {
+			dry_run,
+			..Default::default()
+		};
+		let mut pruned_mods = Vec::new();
+		for instance_mod in self.mods.iter_mut() {
+			let pruned = instance_mod.prune_versions(&policy, dry_run)?;
+			if pruned.len() > 0 {
+				pruned_mods.push(instance_mod.name.clone());
+			}
+			report.versions.extend(pruned);
+		}
+
+		report.reclaimable_size = report
+			.versions
+			.iter()
+			.filter_map(|version| version.reclaimable_size.parse::<u64>().ok())
+			.sum::<u64>()
+			.to_string();
+
+		if !dry_run && pruned_mods.len() > 0 {
+			self.refresh_file_index(pruned_mods)?;
+		}
+
+		return Ok(report);
+	}
+
+	// Called once a new version of a mod is installed
+	pub fn apply_versions_retention(
+		&mut self,
+		mod_name: String,
+	) -> Result<Vec<PrunableModVersion>, String> {
+		let policy = self.config.versions_retention.clone();
+
+		let pruned = match self.get_mod_by_name(mod_name.clone()) {
+			Some(instance_mod) => instance_mod.prune_versions(&policy, false)?,
+			None => return Ok(Vec::new()),
+		};
+
+		if pruned.len() > 0 {
+			println!("Deleted old versions of \"{}\": {:?}", mod_name, pruned);
+			self.refresh_file_index(vec![mod_name])?;
+		}
+
+		return Ok(pruned);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{add_mod_with_files, test_instance};
+
+	fn test_mod<'a>(instance: &'a mut GameInstance, versions: Vec<&str>) -> &'a mut InstanceMod {
+		for version in versions {
+			add_mod_with_files(instance, "Skyui", version, vec!["skyui.esp"]);
+		}
+
+		return instance.get_mod_by_name(String::from("Skyui")).unwrap();
+	}
+
+	#[test]
+	fn keeps_last_versions() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "retention");
+		let instance_mod = test_mod(&mut instance, vec!["1.0", "2.0", "3.0", "4.0"]);
+		instance_mod
+			.set_active_version(String::from("1.0"))
+			.unwrap();
+		instance_mod
+			.set_version_pinned(String::from("2.0"), true)
+			.unwrap();
+
+		let policy = VersionsRetentionConfig {
+			keep_last: Some(1),
+			keep_days: None,
+		};
+
+		// Selected and pinned versions are kept
+		let report = instance_mod.prune_versions(&policy, true).unwrap();
+		assert_eq!(report.len(), 1);
+		assert_eq!(report[0].identifier, "3.0");
+		assert_eq!(report[0].size, "5");
+		assert_eq!(instance_mod.versions.len(), 4);
+
+		instance_mod.prune_versions(&policy, false).unwrap();
+		assert_eq!(
+			instance_mod.version_identifiers(),
+			vec!["1.0", "2.0", "4.0"]
+		);
+		assert!(!instance_mod.get_versions_path().join("3.0").exists());
+
+		// Disabled policy
+		let policy = VersionsRetentionConfig::default();
+		assert!(instance_mod
+			.prune_versions(&policy, false)
+			.unwrap()
+			.is_empty());
+	}
+
+	#[test]
+	fn keeps_recent_versions() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "retention");
+		let instance_mod = test_mod(&mut instance, vec!["1.0", "2.0", "3.0"]);
+
+		let now = now_seconds();
+		let old = (now - 10 * SECONDS_PER_DAY).to_string();
+		instance_mod.get_version_mut("1.0").unwrap().installed_at = Some(old);
+		instance_mod.get_version_mut("2.0").unwrap().installed_at = None;
+
+		let policy = VersionsRetentionConfig {
+			keep_last: None,
+			keep_days: Some(7),
+		};
+		assert_eq!(
+			instance_mod.get_prunable_versions(&policy, now),
+			vec!["2.0", "1.0"]
+		);
+
+		// Either rule keeps a version, the last installed are 1.0 and 3.0
+		let policy = VersionsRetentionConfig {
+			keep_last: Some(2),
+			keep_days: Some(7),
+		};
+		assert_eq!(
+			instance_mod.get_prunable_versions(&policy, now),
+			vec!["2.0"]
+		);
+	}
+
+	#[test]
+	fn keeps_last_installed_versions() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "retention");
+		// Found on disk in another order than installed
+		let instance_mod = test_mod(&mut instance, vec!["3.0", "1.0", "2.0"]);
+		instance_mod
+			.set_active_version(String::from("3.0"))
+			.unwrap();
+		for (identifier, installed_at) in [
+			("1.0", "1700000100"),
+			("2.0", "1700000200"),
+			("3.0", "1700000300"),
+		] {
+			instance_mod
+				.get_version_mut(identifier)
+				.unwrap()
+				.installed_at = Some(installed_at.to_string());
+		}
+
+		let policy = VersionsRetentionConfig {
+			keep_last: Some(2),
+			keep_days: None,
+		};
+		assert_eq!(
+			instance_mod.get_prunable_versions(&policy, now_seconds()),
+			vec!["1.0"]
+		);
+	}
+
+	#[test]
+	fn leaves_stored_files_out_of_reclaimable_size() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "retention");
+		let instance_mod = test_mod(&mut instance, vec!["1.0", "2.0"]);
+		let version_path = instance_mod.get_version_absolute_path(String::from("1.0"));
+		std::fs::write(version_path.join("stored.bsa"), "stored").unwrap();
+		std::fs::hard_link(version_path.join("stored.bsa"), dir.path().join("object")).unwrap();
+
+		let policy = VersionsRetentionConfig {
+			keep_last: Some(1),
+			keep_days: None,
+		};
+		let report = instance_mod.prune_versions(&policy, true).unwrap();
+		assert_eq!(report[0].identifier, "1.0");
+		assert_eq!(report[0].size, "11");
+		assert_eq!(report[0].reclaimable_size, "5");
+	}
+}
@@ -0,0 +1,460 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use super::instance_mod::{normalize_mod_file_path, same_file_content, InstanceMod};
+use super::GameInstance;
+use crate::controllers::file_controller;
+
+// Folder of a mod version deployed to the game folder (MO2 convention), case folded
+pub static ROOT_FOLDER_NAME: &str = "root";
+static ROOT_DEPLOYMENT_FILE_NAME: &str = "root_deployment.json";
+
+// What was put in the game folder, so it can be undone on unmount.
+// Paths are relative to the game folder.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RootDeployment {
+	pub files: Vec<RootDeployedFile>,
+	// Folders created for the files, removed if empty afterwards
+	pub folders: Vec<String>,
+	// Writable layer of the mount, edited files are moved to its "root" folder
+	#[serde(default)]
+	pub writable_path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RootDeployedFile {
+	pub path: String,
+	// Whether a game file was moved away to make room for it
+	pub backed_up: bool,
+	// File it is a copy of, to tell whether it was edited
+	#[serde(default)]
+	pub source: Option<PathBuf>,
+}
+
+// Use the casing of the existing folders and files (e.g. "D3D11.dll" for "d3d11.dll")
+fn match_existing_casing(base: &PathBuf, relative_path: &str) -> String {
+	let mut current = base.clone();
+	let mut segments = Vec::new();
+
+	for segment in relative_path.split('/') {
+		let existing = std::fs::read_dir(&current).ok().and_then(|entries| {
+			return entries
+				.flatten()
+				.map(|entry| entry.file_name().to_string_lossy().to_string())
+				.find(|name| name.to_lowercase() == segment.to_lowercase());
+		});
+		let segment = existing.unwrap_or(segment.to_string());
+
+		current = current.join(&segment);
+		segments.push(segment);
+	}
+
+	return segments.join("/");
+}
+
+impl GameInstance {
+	fn get_root_deployment_path(&self) -> PathBuf {
+		return self
+			.instance_absolute_path()
+			.join(ROOT_DEPLOYMENT_FILE_NAME);
+	}
+
+	fn get_root_backup_path(&self) -> PathBuf {
+		return self.instance_absolute_path().join(".root_backup");
+	}
+
+	fn load_root_deployment(&self) -> Result<RootDeployment, String> {
+		let path = self.get_root_deployment_path();
+		if !path.exists() {
+			return Ok(RootDeployment::default());
+		}
+
+		let json = file_controller::read_file(path)
+			.map_err(|e| format!("Failed to read root deployment: {}", e.to_string()))?;
+
+		return serde_json::from_str(&json)
+			.map_err(|e| format!("Failed to parse root deployment: {}", e.to_string()));
+	}
+
+	fn save_root_deployment(&self, root_deployment: &RootDeployment) -> Result<(), String> {
+		let json = serde_json::to_string(root_deployment)
+			.map_err(|e| format!("Failed to serialize root deployment: {}", e.to_string()))?;
+
+		return file_controller::save_file(self.get_root_deployment_path(), json.as_bytes())
+			.map_err(|e| format!("Failed to save root deployment: {}", e.to_string()));
+	}
+
+	// Files of the "root" folders, the last mod providing a file wins.
+	// Files edited during previous runs, kept in the writable layer, win over every mod.
+	fn collect_root_files(
+		&self,
+		mods: &Vec<&InstanceMod>,
+		writable_path: &PathBuf,
+	) -> Result<BTreeMap<String, PathBuf>, String> {
+		let mut root_files = BTreeMap::new();
+
+		for instance_mod in mods {
+			let version_path = instance_mod.get_selected_version_absolute_path();
+
+			// Hidden files are left out
+			for file in instance_mod.list_deployed_files()? {
+				let normalized_path = normalize_mod_file_path(&file);
+				let relative_path =
+					match normalized_path.strip_prefix(&format!("{}/", ROOT_FOLDER_NAME)) {
+						Some(relative_path) => relative_path.to_string(),
+						None => continue,
+					};
+				root_files.insert(relative_path, version_path.join(&file));
+			}
+		}
+
+		let writable_root_path = writable_path.join(ROOT_FOLDER_NAME);
+		if writable_root_path.is_dir() {
+			let files =
+				file_controller::list_files_recursively_flattened(writable_root_path.clone())
+					.map_err(|e| format!("Failed to list edited root files: {}", e.to_string()))?;
+			for file in files.into_iter().map(PathBuf::from) {
+				if let Ok(relative_path) = file.strip_prefix(&writable_root_path) {
+					let relative_path = normalize_mod_file_path(&relative_path.to_string_lossy());
+					root_files.insert(relative_path, file.clone());
+				}
+			}
+		}
+
+		return Ok(root_files);
+	}
+
+	// Copy the "root" files of the mods into the game folder. Copies, not links,
+	// as tools edit some of them in place (e.g. "enbseries.ini"), the edits are
+	// moved to the writable layer on undeploy.
+	// Game files in the way are backed up, a previous deployment is undone first.
+	pub fn deploy_root_files(
+		&self,
+		mods: &Vec<&InstanceMod>,
+		writable_path: &PathBuf,
+	) -> Result<(), String> {
+		self.undeploy_root_files()?;
+
+		let root_files = self.collect_root_files(mods, writable_path)?;
+		if root_files.is_empty() {
+			return Ok(());
+		}
+
+		let game_path = self.parse_path_variables(self.config.paths.game.clone());
+		let backup_path = self.get_root_backup_path();
+		let mut root_deployment = RootDeployment {
+			writable_path: Some(writable_path.clone()),
+			..Default::default()
+		};
+
+		for (relative_path, source) in root_files.iter() {
+			let relative_path = match_existing_casing(&game_path, relative_path);
+			let target = game_path.join(&relative_path);
+
+			if target.is_dir() {
+				return Err(format!(
+					"\"{}\" is a folder in the game folder",
+					relative_path
+				));
+			}
+
+			// Remember the folders we create
+			let mut missing_folders = Vec::new();
+			let mut parent = target.parent().map(|path| path.to_path_buf());
+			while let Some(folder) = parent {
+				if folder.exists() || !folder.starts_with(&game_path) {
+					break;
+				}
+				if let Ok(folder_path) = folder.strip_prefix(&game_path) {
+					missing_folders.push(folder_path.to_string_lossy().to_string());
+				}
+				parent = folder.parent().map(|path| path.to_path_buf());
+			}
+			missing_folders.reverse();
+			root_deployment.folders.extend(missing_folders);
+
+			let backed_up = target.exists();
+			root_deployment.files.push(RootDeployedFile {
+				path: relative_path.clone(),
+				backed_up,
+				source: Some(source.clone()),
+			});
+
+			// Recorded before touching anything, so a crash halfway can be undone
+			self.save_root_deployment(&root_deployment)?;
+
+			if backed_up {
+				file_controller::move_file(target.clone(), backup_path.join(&relative_path))
+					.map_err(|e| format!("Failed to back up \"{}\": {}", relative_path, e))?;
+			}
+
+			file_controller::create_folder(&target.parent().unwrap().to_path_buf())
+				.map_err(|e| format!("Failed to create folder: {}", e.to_string()))?;
+			std::fs::copy(source, &target)
+				.map_err(|e| format!("Failed to deploy \"{}\": {}", relative_path, e))?;
+		}
+
+		return Ok(());
+	}
+
+	// Deployed file changed since it was copied, by a tool or the user
+	fn is_root_file_edited(file: &RootDeployedFile, target: &PathBuf) -> bool {
+		return match &file.source {
+			Some(source) => target.is_file() && !same_file_content(source, target),
+			None => false,
+		};
+	}
+
+	// Remove the deployed "root" files and put the game files back.
+	// Edited files are kept in the "root" folder of the writable layer.
+	// What can't be restored stays recorded, to be retried on the next unmount.
+	pub fn undeploy_root_files(&self) -> Result<(), String> {
+		let root_deployment = self.load_root_deployment()?;
+
+		let game_path = self.parse_path_variables(self.config.paths.game.clone());
+		let backup_path = self.get_root_backup_path();
+
+		let mut failed_files = Vec::new();
+		let mut errors = Vec::new();
+		for file in root_deployment.files.iter().rev() {
+			let target = game_path.join(&file.path);
+			let backup = backup_path.join(&file.path);
+
+			// Without a backup, a backed up game file was never moved and is still in place
+			let is_deployed = !file.backed_up || backup.exists();
+			let kept = match &root_deployment.writable_path {
+				Some(writable_path) if is_deployed && Self::is_root_file_edited(file, &target) => {
+					file_controller::move_file(
+						target.clone(),
+						writable_path
+							.join(ROOT_FOLDER_NAME)
+							.join(normalize_mod_file_path(&file.path)),
+					)
+				}
+				_ => Ok(()),
+			};
+
+			let restored = if let Err(e) = kept {
+				Err(e)
+			} else if !file.backed_up {
+				file_controller::delete_file_if_exists(target)
+			} else if backup.exists() {
+				// Replaces the deployed file
+				file_controller::move_file(backup.clone(), target)
+			} else {
+				// Recorded but never moved, the game file is still in place
+				Ok(())
+			};
+
+			match restored {
+				Ok(_) => {
+					// Drop the backup folders left empty
+					let mut parent = backup.parent().map(|path| path.to_path_buf());
+					while let Some(folder) = parent {
+						if !folder.starts_with(&backup_path)
+							|| file_controller::delete_folder_if_empty(folder.clone()).is_err()
+						{
+							break;
+						}
+						parent = folder.parent().map(|path| path.to_path_buf());
+					}
+				}
+				Err(e) => {
+					errors.push(format!("Failed to restore \"{}\": {}", file.path, e));
+					failed_files.insert(0, file.clone());
+				}
+			}
+		}
+
+		for folder in root_deployment.folders.iter().rev() {
+			let _ = file_controller::delete_folder_if_empty(game_path.join(folder));
+		}
+
+		if !errors.is_empty() {
+			self.save_root_deployment(&RootDeployment {
+				files: failed_files,
+				folders: root_deployment.folders.clone(),
+				writable_path: root_deployment.writable_path.clone(),
+			})?;
+
+			return Err(errors.join("\n"));
+		}
+
+		// Anything left in the backup folder is not ours to delete
+		if backup_path.exists() && file_controller::delete_folder_if_empty(backup_path).is_err() {
+			println!("Root backup folder is not empty, it was kept");
+		}
+
+		return file_controller::delete_file_if_exists(self.get_root_deployment_path())
+			.map_err(|e| format!("Failed to delete root deployment: {}", e.to_string()));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_utils::{add_mod_with_files, test_instance};
+
+	#[test]
+	fn deploys_and_restores_root_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_path_buf();
+		let mut instance = test_instance(&root, "root");
+		let game_path = root.join("game");
+		let overwrite_path = instance.overwrite_relative_path();
+		std::fs::create_dir_all(game_path.join("Data")).unwrap();
+		std::fs::write(game_path.join("D3D11.dll"), "game").unwrap();
+
+		let mut mods = vec![
+			add_mod_with_files(
+				&mut instance,
+				"Skse",
+				"1.0",
+				vec!["root/skse64_loader.exe", "root/d3d11.dll"],
+			),
+			add_mod_with_files(
+				&mut instance,
+				"Enb",
+				"1.0",
+				vec![
+					"root/d3d11.dll",
+					"root/enbseries/effect.txt",
+					"root/enblocal.ini",
+				],
+			),
+		];
+		mods[1].hidden_files = vec![String::from("root/enblocal.ini")];
+
+		instance
+			.deploy_root_files(&mods.iter().collect(), &overwrite_path)
+			.unwrap();
+
+		// Hidden files stay in the mod
+		assert!(!game_path.join("enblocal.ini").exists());
+
+		// The last mod wins, existing casing is kept
+		assert_eq!(
+			std::fs::read_to_string(game_path.join("D3D11.dll")).unwrap(),
+			"Enb"
+		);
+		assert!(game_path.join("skse64_loader.exe").is_file());
+		assert!(game_path.join("enbseries/effect.txt").is_file());
+		assert!(!game_path.join("d3d11.dll").exists());
+
+		// Deploying again starts from the original game folder
+		instance
+			.deploy_root_files(&mods[..1].iter().collect(), &overwrite_path)
+			.unwrap();
+		assert!(!game_path.join("enbseries").exists());
+
+		instance.undeploy_root_files().unwrap();
+		assert_eq!(
+			std::fs::read_to_string(game_path.join("D3D11.dll")).unwrap(),
+			"game"
+		);
+		assert!(!game_path.join("skse64_loader.exe").exists());
+		assert!(game_path.join("Data").is_dir());
+		assert!(!instance.get_root_backup_path().exists());
+		assert!(!instance.get_root_deployment_path().exists());
+
+		// Deployed files are copies, editing them leaves the mod alone
+		instance
+			.deploy_root_files(&mods.iter().collect(), &overwrite_path)
+			.unwrap();
+		std::fs::write(game_path.join("D3D11.dll"), "edited").unwrap();
+		assert_eq!(
+			std::fs::read_to_string(
+				mods[1]
+					.get_selected_version_absolute_path()
+					.join(ROOT_FOLDER_NAME)
+					.join("d3d11.dll")
+			)
+			.unwrap(),
+			"Enb"
+		);
+
+		// Backups missing from the record are kept
+		std::fs::write(instance.get_root_backup_path().join("unknown.dll"), "").unwrap();
+		instance.undeploy_root_files().unwrap();
+		assert_eq!(
+			std::fs::read_to_string(game_path.join("D3D11.dll")).unwrap(),
+			"game"
+		);
+		assert!(instance
+			.get_root_backup_path()
+			.join("unknown.dll")
+			.is_file());
+
+		// The edit was kept in overwrite, it is deployed over the mods next time
+		let edited_path = overwrite_path.join(ROOT_FOLDER_NAME).join("d3d11.dll");
+		assert_eq!(std::fs::read_to_string(&edited_path).unwrap(), "edited");
+		assert!(!overwrite_path
+			.join(ROOT_FOLDER_NAME)
+			.join("skse64_loader.exe")
+			.exists());
+		std::fs::remove_file(instance.get_root_backup_path().join("unknown.dll")).unwrap();
+		instance
+			.deploy_root_files(&mods.iter().collect(), &overwrite_path)
+			.unwrap();
+		assert_eq!(
+			std::fs::read_to_string(game_path.join("D3D11.dll")).unwrap(),
+			"edited"
+		);
+		instance.undeploy_root_files().unwrap();
+		assert_eq!(std::fs::read_to_string(&edited_path).unwrap(), "edited");
+		assert_eq!(
+			std::fs::read_to_string(game_path.join("D3D11.dll")).unwrap(),
+			"game"
+		);
+	}
+
+	#[test]
+	fn undoes_interrupted_root_deployment() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_path_buf();
+		let instance = test_instance(&root, "root");
+		let game_path = root.join("game");
+		std::fs::create_dir_all(&game_path).unwrap();
+		std::fs::write(game_path.join("a.dll"), "game a").unwrap();
+		std::fs::write(game_path.join("b.dll"), "game b").unwrap();
+
+		// Crashed after backing up "a.dll", before moving "b.dll"
+		std::fs::create_dir_all(instance.get_root_backup_path()).unwrap();
+		std::fs::rename(
+			game_path.join("a.dll"),
+			instance.get_root_backup_path().join("a.dll"),
+		)
+		.unwrap();
+		std::fs::write(game_path.join("a.dll"), "mod a").unwrap();
+		instance
+			.save_root_deployment(&RootDeployment {
+				files: vec![
+					RootDeployedFile {
+						path: String::from("a.dll"),
+						backed_up: true,
+						source: None,
+					},
+					RootDeployedFile {
+						path: String::from("b.dll"),
+						backed_up: true,
+						source: None,
+					},
+				],
+				folders: Vec::new(),
+				writable_path: None,
+			})
+			.unwrap();
+
+		instance.undeploy_root_files().unwrap();
+		assert_eq!(
+			std::fs::read_to_string(game_path.join("a.dll")).unwrap(),
+			"game a"
+		);
+		assert_eq!(
+			std::fs::read_to_string(game_path.join("b.dll")).unwrap(),
+			"game b"
+		);
+		assert!(!instance.get_root_backup_path().exists());
+	}
+}
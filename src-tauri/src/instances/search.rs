@@ -0,0 +1,299 @@
+use super::instance_mod::InstanceMod;
+use super::GameInstance;
+
+// Every set filter must match, text filters are case insensitive
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct ModSearchQuery {
+	// Part of the name, author, a tag or a category
+	#[serde(default)]
+	pub text: Option<String>,
+	#[serde(default)]
+	pub author: Option<String>,
+	// Mods must have all of them
+	#[serde(default)]
+	pub tags: Vec<String>,
+	#[serde(default)]
+	pub category: Option<String>,
+	#[serde(default)]
+	pub enabled: Option<bool>,
+	// Part of the path of a file in the selected version
+	#[serde(default)]
+	pub file_name: Option<String>,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct ModSearchResult {
+	// Index in "GameInstance::mods"
+	pub index: u32,
+	pub name: String,
+	// Files matching "file_name"
+	pub matched_files: Vec<String>,
+}
+
+fn contains_lowercase(haystack: &str, needle: &str) -> bool {
+	return haystack.to_lowercase().contains(needle);
+}
+
+impl ModSearchQuery {
+	fn matches_info(&self, instance_mod: &InstanceMod) -> bool {
+		let info = &instance_mod.info;
+
+		if let Some(enabled) = self.enabled {
+			if instance_mod.enabled != enabled {
+				return false;
+			}
+		}
+
+		if let Some(category) = &self.category {
+			if !info
+				.categories
+				.iter()
+				.any(|c| c.eq_ignore_ascii_case(category))
+			{
+				return false;
+			}
+		}
+
+		for tag in self.tags.iter() {
+			if !info.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+				return false;
+			}
+		}
+
+		if let Some(author) = &self.author {
+			let author = author.to_lowercase();
+			if !info
+				.author
+				.as_ref()
+				.is_some_and(|a| contains_lowercase(a, &author))
+			{
+				return false;
+			}
+		}
+
+		if let Some(text) = &self.text {
+			let text = text.to_lowercase();
+			let matches_text = contains_lowercase(&instance_mod.name, &text)
+				|| info
+					.author
+					.as_ref()
+					.is_some_and(|a| contains_lowercase(a, &text))
+				|| info.tags.iter().any(|t| contains_lowercase(t, &text))
+				|| info.categories.iter().any(|c| contains_lowercase(c, &text));
+			if !matches_text {
+				return false;
+			}
+		}
+
+		return true;
+	}
+}
+
+impl GameInstance {
+	// Search the mods (not "base", "overwrite" or separators), in mod order
+	pub fn search_mods(&mut self, query: &ModSearchQuery) -> Result<Vec<ModSearchResult>, String> {
+		let file_name = query
+			.file_name
+			.as_ref()
+			.map(|file_name| file_name.trim().replace('\\', "/").to_lowercase())
+			.filter(|file_name| !file_name.is_empty());
+
+		if file_name.is_some() {
+			self.refresh_file_index(vec![])?;
+		}
+
+		let mut results: Vec<ModSearchResult> = Vec::new();
+
+		for (index, instance_mod) in self.mods.iter().enumerate() {
+			if instance_mod.name == "base"
+				|| instance_mod.name == "overwrite"
+				|| instance_mod.is_separator()
+				|| !query.matches_info(instance_mod)
+			{
+				continue;
+			}
+
+			let matched_files: Vec<String> = match &file_name {
+				Some(file_name) => self
+					.file_index
+					.get_mod_files(&instance_mod.name)
+					.unwrap_or(&Vec::new())
+					.iter()
+					.filter(|file| file.contains(file_name.as_str()))
+					.cloned()
+					.collect(),
+				None => Vec::new(),
+			};
+
+			if file_name.is_some() && matched_files.is_empty() {
+				continue;
+			}
+
+			results.push(ModSearchResult {
+				index: index as u32,
+				name: instance_mod.name.clone(),
+				matched_files,
+			});
+		}
+
+		return Ok(results);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::instances::categories::ModCategory;
+	use crate::instances::instance_mod::ModInfo;
+	use crate::test_utils::{add_mod_with_files, test_instance};
+
+	fn add_mod(instance: &mut GameInstance, name: &str, author: &str, files: Vec<&str>) {
+		let mut info = add_mod_with_files(instance, name, "1.0", files).info;
+		info.author = Some(author.to_string());
+		instance.update_mod_info(name.to_string(), info).unwrap();
+	}
+
+	fn search(instance: &mut GameInstance, query: ModSearchQuery) -> Vec<String> {
+		return instance
+			.search_mods(&query)
+			.unwrap()
+			.into_iter()
+			.map(|result| result.name)
+			.collect();
+	}
+
+	#[test]
+	fn searches_mods() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "search");
+		add_mod(&mut instance, "SkyUI", "schlangster", vec!["skyui_se.esp"]);
+		add_mod(
+			&mut instance,
+			"Sky Textures",
+			"someone",
+			vec!["textures/sky.dds"],
+		);
+		add_mod(&mut instance, "Patch", "someone", vec!["patch.esp"]);
+
+		instance
+			.set_categories(vec![ModCategory {
+				name: String::from("Visuals"),
+				nexus_category_ids: vec![29],
+			}])
+			.unwrap();
+		let mut info = instance.mods[2].info.clone();
+		info.categories = vec![String::from("Visuals")];
+		info.tags = vec![String::from(" 4k "), String::from("4K"), String::from("")];
+		instance
+			.update_mod_info(String::from("Sky Textures"), info)
+			.unwrap();
+		assert_eq!(instance.mods[2].info.tags, vec!["4k"]);
+		assert_eq!(instance.get_mods_tags(), vec!["4k"]);
+		instance
+			.set_mod_enabled(String::from("Patch"), false)
+			.unwrap();
+
+		assert_eq!(search(&mut instance, ModSearchQuery::default()).len(), 3);
+
+		let query = ModSearchQuery {
+			text: Some(String::from("SKY")),
+			..Default::default()
+		};
+		assert_eq!(search(&mut instance, query), vec!["SkyUI", "Sky Textures"]);
+
+		let query = ModSearchQuery {
+			author: Some(String::from("some")),
+			enabled: Some(true),
+			..Default::default()
+		};
+		assert_eq!(search(&mut instance, query), vec!["Sky Textures"]);
+
+		let query = ModSearchQuery {
+			category: Some(String::from("visuals")),
+			tags: vec![String::from("4K")],
+			..Default::default()
+		};
+		assert_eq!(search(&mut instance, query), vec!["Sky Textures"]);
+
+		let query = ModSearchQuery {
+			file_name: Some(String::from(".ESP")),
+			..Default::default()
+		};
+		let results = instance.search_mods(&query).unwrap();
+		assert_eq!(results.len(), 2);
+		assert_eq!(results[1].name, "Patch");
+		assert_eq!(results[1].index, 3);
+		assert_eq!(results[1].matched_files, vec!["patch.esp"]);
+
+		// Hidden files are not searched
+		instance
+			.hide_mod_file(String::from("Patch"), String::from("patch.esp"))
+			.unwrap();
+		let results = instance.search_mods(&query).unwrap();
+		assert_eq!(results.len(), 1);
+	}
+
+	#[test]
+	fn keeps_user_data_and_categories() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "search");
+		add_mod(&mut instance, "SkyUI", "schlangster", vec![]);
+
+		instance
+			.set_categories(vec![
+				ModCategory {
+					name: String::from("UI"),
+					nexus_category_ids: vec![42],
+				},
+				ModCategory {
+					name: String::from("Visuals"),
+					nexus_category_ids: vec![29, 42],
+				},
+			])
+			.unwrap();
+		assert_eq!(
+			instance.get_categories_for_nexus_id(42),
+			vec!["UI", "Visuals"]
+		);
+
+		let mut info = instance.mods[1].info.clone();
+		info.categories = vec![String::from("Missing")];
+		assert!(instance
+			.update_mod_info(String::from("SkyUI"), info.clone())
+			.is_err());
+
+		info.categories = vec![String::from("UI"), String::from("Visuals")];
+		info.notes = Some(String::from("Needs SKSE"));
+		info.color = Some(String::from("  "));
+		instance
+			.update_mod_info(String::from("SkyUI"), info)
+			.unwrap();
+		assert_eq!(instance.mods[1].info.color, None);
+
+		// A new version keeps what the user set
+		instance
+			.create_mod_version(
+				String::from("SkyUI"),
+				String::from("2.0"),
+				ModInfo::default(),
+			)
+			.unwrap();
+		instance.load_mods().unwrap();
+		assert_eq!(
+			instance.mods[1].info.notes,
+			Some(String::from("Needs SKSE"))
+		);
+
+		// Removed categories are removed from mods
+		instance
+			.set_categories(vec![ModCategory {
+				name: String::from("UI"),
+				nexus_category_ids: vec![],
+			}])
+			.unwrap();
+		instance.load_mods().unwrap();
+		assert_eq!(instance.mods[1].info.categories, vec!["UI"]);
+	}
+}
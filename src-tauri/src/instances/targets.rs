@@ -0,0 +1,202 @@
+use std::path::PathBuf;
+
+use crate::deployer::vfs::base_vfs::VFSMountPaths;
+
+use super::instance_mod::{normalize_mod_file_path, InstanceMod};
+use super::root_deployment::ROOT_FOLDER_NAME;
+use super::GameInstance;
+
+// Names of the built-in sub-VFS mounts
+static RESERVED_TARGET_NAMES: [&str; 4] = ["mods", "saves", "settings", ROOT_FOLDER_NAME];
+
+// Extra folder files get deployed to, besides mods/saves/settings.
+// Mods ship its files in "source_folder" (e.g. "Plugins" for a folder outside Data),
+// which is kept out of the mods deployment folder.
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct DeploymentTarget {
+	pub name: String,
+	// Can be absolute or use variables
+	pub path: PathBuf,
+	// Relative to each mod version
+	pub source_folder: String,
+}
+
+impl DeploymentTarget {
+	// Mod files are case folded when installed
+	pub fn normalized_source_folder(&self) -> String {
+		return normalize_mod_file_path(&self.source_folder);
+	}
+}
+
+pub fn validate_deployment_targets(targets: &Vec<DeploymentTarget>) -> Result<(), String> {
+	for (index, target) in targets.iter().enumerate() {
+		// The name is used as a folder name for the VFS workdir
+		if target.name.is_empty()
+			|| RESERVED_TARGET_NAMES.contains(&target.name.as_str())
+			|| target.name.contains(['/', '\\'])
+			|| target.name.contains("..")
+		{
+			return Err(format!(
+				"Invalid deployment target name \"{}\"",
+				target.name
+			));
+		}
+
+		let source_folder = target.normalized_source_folder();
+		// "root" is deployed to the game folder already
+		if source_folder.is_empty()
+			|| source_folder == ROOT_FOLDER_NAME
+			|| source_folder.split('/').any(|segment| segment == "..")
+		{
+			return Err(format!(
+				"Invalid source folder \"{}\" for deployment target \"{}\"",
+				target.source_folder, target.name
+			));
+		}
+
+		for other in targets.iter().skip(index + 1) {
+			if other.name == target.name {
+				return Err(format!("Duplicated deployment target \"{}\"", target.name));
+			}
+			if other.normalized_source_folder() == source_folder {
+				return Err(format!(
+					"Deployment targets \"{}\" and \"{}\" use the same source folder",
+					target.name, other.name
+				));
+			}
+		}
+	}
+
+	return Ok(());
+}
+
+impl GameInstance {
+	// Mount paths of a deployment target, None when no mod ships files for it.
+	// Written files go to the source folder of the writable layer (overwrite or output mod).
+	pub fn deployment_target_mount_paths(
+		&self,
+		target: &DeploymentTarget,
+		mods: &Vec<&InstanceMod>,
+		writable_path: &PathBuf,
+	) -> Result<Option<VFSMountPaths>, String> {
+		let source_folder = target.normalized_source_folder();
+
+		let mut sources = Vec::new();
+		for instance_mod in mods {
+			if let Some(source) = self.get_target_layer_source(instance_mod, &source_folder)? {
+				sources.push(source);
+			}
+		}
+
+		if sources.is_empty() {
+			return Ok(None);
+		}
+
+		return Ok(Some(VFSMountPaths {
+			target: self.parse_path_variables(target.path.clone()),
+			sources,
+			overwrite: writable_path.join(&source_folder),
+			workdir: self
+				.instance_absolute_path()
+				.join(".vfs_workdir")
+				.join("targets")
+				.join(&target.name),
+		}));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::config::vfs_config::VFSConfig;
+	use crate::test_utils::{add_mod_with_files, test_instance};
+
+	fn target(name: &str, source_folder: &str) -> DeploymentTarget {
+		return DeploymentTarget {
+			name: name.to_string(),
+			path: PathBuf::from("$game"),
+			source_folder: source_folder.to_string(),
+		};
+	}
+
+	#[test]
+	fn validates_targets() {
+		assert!(validate_deployment_targets(&vec![
+			target("plugins", "Plugins"),
+			target("skse", "_targets/SKSE"),
+		])
+		.is_ok());
+
+		assert!(validate_deployment_targets(&vec![target("mods", "Plugins")]).is_err());
+		assert!(validate_deployment_targets(&vec![target("root", "Root")]).is_err());
+		assert!(validate_deployment_targets(&vec![target("root", "Plugins")]).is_err());
+		assert!(validate_deployment_targets(&vec![target("../plugins", "Plugins")]).is_err());
+		assert!(validate_deployment_targets(&vec![target("a\\b", "Plugins")]).is_err());
+		assert!(validate_deployment_targets(&vec![target("plugins", "")]).is_err());
+		assert!(validate_deployment_targets(&vec![target("plugins", "../Plugins")]).is_err());
+		assert!(validate_deployment_targets(&vec![
+			target("plugins", "Plugins"),
+			target("other", "plugins/"),
+		])
+		.is_err());
+	}
+
+	#[test]
+	fn collects_target_sources() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_path_buf();
+		let mut instance = test_instance(&root, "targets");
+
+		let mods = vec![
+			add_mod_with_files(&mut instance, "Skse", "1.0", vec!["plugins/skse.dll"]),
+			add_mod_with_files(&mut instance, "Skyui", "1.0", vec![]),
+		];
+		let plugins_path = mods[0].get_selected_version_absolute_path().join("plugins");
+
+		let writable_path = root.join("mods/overwrite");
+		let mount_paths = instance
+			.deployment_target_mount_paths(
+				&target("plugins", "Plugins"),
+				&mods.iter().collect(),
+				&writable_path,
+			)
+			.unwrap()
+			.unwrap();
+		assert_eq!(mount_paths.target, root.join("game"));
+		assert_eq!(mount_paths.sources, vec![plugins_path]);
+		assert_eq!(mount_paths.overwrite, writable_path.join("plugins"));
+
+		// No mod ships files for it
+		assert!(instance
+			.deployment_target_mount_paths(
+				&target("skse", "SKSE"),
+				&mods.iter().collect(),
+				&writable_path,
+			)
+			.unwrap()
+			.is_none());
+	}
+
+	#[test]
+	fn creates_missing_target_folders() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_path_buf();
+		let mut instance = test_instance(&root, "targets");
+		instance.config.deployment_targets = vec![DeploymentTarget {
+			name: String::from("skse"),
+			path: PathBuf::from("$game/SKSE/Plugins"),
+			source_folder: String::from("SKSE"),
+		}];
+
+		add_mod_with_files(&mut instance, "Skse", "1.0", vec!["skse/plugin.dll"]);
+
+		let mount_configs = instance
+			.get_vfs_mount_configs(&VFSConfig::new(), None, false)
+			.unwrap();
+		assert!(mount_configs
+			.iter()
+			.any(|config| config.paths.target == root.join("game/SKSE/Plugins")));
+		assert!(root.join("game/SKSE/Plugins").is_dir());
+	}
+}
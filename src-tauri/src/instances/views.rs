@@ -0,0 +1,214 @@
+use std::path::{Path, PathBuf};
+
+use super::instance_mod::{normalize_mod_file_path, InstanceMod};
+use super::root_deployment::ROOT_FOLDER_NAME;
+use super::GameInstance;
+
+// Whether a file (relative to the version folder) is inside one of the folders
+fn is_in_folders(file: &str, folders: &Vec<String>) -> bool {
+	let file = normalize_mod_file_path(file);
+
+	return folders
+		.iter()
+		.any(|folder| file == *folder || file.starts_with(&format!("{}/", folder)));
+}
+
+impl GameInstance {
+	fn get_views_absolute_path(&self) -> PathBuf {
+		return self.instance_absolute_path().join(".vfs_views");
+	}
+
+	// Folders of a version deployed somewhere else than the mods deployment folder
+	fn get_excluded_mod_folders(&self) -> Vec<String> {
+		let mut folders = vec![String::from(ROOT_FOLDER_NAME)];
+		for target in self.config.deployment_targets.iter() {
+			folders.push(target.normalized_source_folder());
+		}
+
+		return folders;
+	}
+
+	// Link (or copy) the files of a version into a view, "prefix" is left out of their path
+	fn build_view(
+		&self,
+		instance_mod: &InstanceMod,
+		view_path: &PathBuf,
+		files: &Vec<String>,
+		prefix: &str,
+	) -> Result<(), String> {
+		let version_path = instance_mod.get_selected_version_absolute_path();
+		// Files are case folded, the prefix may not match their case
+		let prefix_depth = Path::new(prefix).components().count();
+
+		for file in files.iter() {
+			let relative_path: PathBuf = Path::new(file).components().skip(prefix_depth).collect();
+			let destination = view_path.join(relative_path);
+			if let Some(parent) = destination.parent() {
+				std::fs::create_dir_all(parent)
+					.map_err(|e| format!("Failed to create mod view: {}", e.to_string()))?;
+			}
+
+			// Hardlink when possible, mods and instance usually share a filesystem
+			if std::fs::hard_link(version_path.join(file), &destination).is_err() {
+				std::fs::copy(version_path.join(file), &destination).map_err(|e| {
+					format!(
+						"Failed to copy \"{}\" into the view of \"{}\": {}",
+						file,
+						instance_mod.name,
+						e.to_string()
+					)
+				})?;
+			}
+		}
+		std::fs::create_dir_all(view_path)
+			.map_err(|e| format!("Failed to create mod view: {}", e.to_string()))?;
+
+		return Ok(());
+	}
+
+	// Folders to layer in the mods deployment folder, one per mod. A version with files
+	// that must not end up there (deployed elsewhere, or hidden) is layered through a view:
+	// a folder linking the others. Views of the previous deployment are cleared.
+	pub fn get_mods_layer_sources(&self, mods: &Vec<&InstanceMod>) -> Result<Vec<PathBuf>, String> {
+		let views_path = self.get_views_absolute_path();
+		if views_path.exists() {
+			std::fs::remove_dir_all(&views_path)
+				.map_err(|e| format!("Failed to clear mod views: {}", e.to_string()))?;
+		}
+
+		let excluded_folders = self.get_excluded_mod_folders();
+		let mut sources = Vec::new();
+
+		for instance_mod in mods {
+			let version_files_count = instance_mod.list_selected_version_files()?.len();
+			let files: Vec<String> = instance_mod
+				.list_deployed_files()?
+				.into_iter()
+				.filter(|file| !is_in_folders(file, &excluded_folders))
+				.collect();
+
+			if files.len() == version_files_count {
+				sources.push(instance_mod.get_selected_version_absolute_path());
+				continue;
+			}
+
+			let view_path = views_path.join("mods").join(&instance_mod.name);
+			self.build_view(instance_mod, &view_path, &files, "")?;
+			sources.push(view_path);
+		}
+
+		return Ok(sources);
+	}
+
+	// Folder to layer for a deployment target, None when the mod ships nothing for it.
+	// Its hidden files are left out through a view, like for the mods deployment folder.
+	pub fn get_target_layer_source(
+		&self,
+		instance_mod: &InstanceMod,
+		source_folder: &str,
+	) -> Result<Option<PathBuf>, String> {
+		let folder_path = instance_mod
+			.get_selected_version_absolute_path()
+			.join(source_folder);
+		if !folder_path.is_dir() {
+			return Ok(None);
+		}
+
+		let folders = vec![source_folder.to_string()];
+		let folder_files_count = instance_mod
+			.list_selected_version_files()?
+			.iter()
+			.filter(|file| is_in_folders(file, &folders))
+			.count();
+		let files: Vec<String> = instance_mod
+			.list_deployed_files()?
+			.into_iter()
+			.filter(|file| is_in_folders(file, &folders))
+			.collect();
+
+		if files.len() == folder_files_count {
+			return Ok(Some(folder_path));
+		}
+
+		let view_path = self
+			.get_views_absolute_path()
+			.join("targets")
+			.join(source_folder)
+			.join(&instance_mod.name);
+		self.build_view(instance_mod, &view_path, &files, source_folder)?;
+
+		return Ok(Some(view_path));
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::instances::targets::DeploymentTarget;
+	use crate::test_utils::{add_mod_with_files, test_instance};
+
+	#[test]
+	fn excludes_folders_deployed_elsewhere() {
+		let dir = tempfile::tempdir().unwrap();
+		let root = dir.path().to_path_buf();
+		let mut instance = test_instance(&root, "views");
+		instance.config.deployment_targets = vec![DeploymentTarget {
+			name: String::from("plugins"),
+			path: PathBuf::from("$game/Plugins"),
+			source_folder: String::from("Plugins"),
+		}];
+
+		let mut mods = Vec::new();
+		for (name, files) in [
+			("Skse", vec!["root/skse64_loader.exe", "scripts/a.pex"]),
+			("Skyui", vec!["skyui.esp"]),
+			(
+				"Tool",
+				vec!["plugins/tool.dll", "plugins/old.dll", "rootless.esp"],
+			),
+			("Textures", vec!["sky.dds", "readme.txt"]),
+		] {
+			mods.push(add_mod_with_files(&mut instance, name, "1.0", files));
+		}
+
+		mods[2].hidden_files = vec![String::from("plugins/old.dll")];
+		mods[3].hidden_files = vec![String::from("readme.txt")];
+
+		let sources = instance
+			.get_mods_layer_sources(&mods.iter().collect())
+			.unwrap();
+
+		// Mods without such folders are layered as is
+		assert_eq!(sources[1], mods[1].get_selected_version_absolute_path());
+
+		let views_path = instance.get_views_absolute_path().join("mods");
+		assert_eq!(sources[0], views_path.join("Skse"));
+		assert!(sources[0].join("scripts/a.pex").is_file());
+		assert!(!sources[0].join("root").exists());
+		assert_eq!(sources[2], views_path.join("Tool"));
+		assert!(sources[2].join("rootless.esp").is_file());
+		assert!(!sources[2].join("plugins").exists());
+
+		// Hidden files are not deployed either
+		assert_eq!(sources[3], views_path.join("Textures"));
+		assert!(sources[3].join("sky.dds").is_file());
+		assert!(!sources[3].join("readme.txt").exists());
+
+		let target_source = instance
+			.get_target_layer_source(&mods[2], "plugins")
+			.unwrap()
+			.unwrap();
+		assert!(target_source.join("tool.dll").is_file());
+		assert!(!target_source.join("old.dll").exists());
+		assert_eq!(
+			instance.get_target_layer_source(&mods[0], "root").unwrap(),
+			Some(mods[0].get_selected_version_absolute_path().join("root"))
+		);
+		assert_eq!(
+			instance
+				.get_target_layer_source(&mods[1], "plugins")
+				.unwrap(),
+			None
+		);
+	}
+}
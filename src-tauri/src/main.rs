@@ -8,11 +8,25 @@ use controllers::file_controller::{self, FileStructureSegment};
 use controllers::plugin_controller::BethesdaPlugin;
 use core::panic;
 use futures::Future;
-use instances::instance_mod::{InstanceMod, ModInfo};
+use instances::build::{BuildInstallReport, BuildManifest};
+use instances::categories::ModCategory;
+use instances::conflicts::FileConflictRule;
+use instances::file_index::FileProvider;
+use instances::instance_mod::{InstanceMod, ModInfo, ModVersionsDiff, ModVersionsReconciliation};
+use instances::library::LibraryMod;
+use instances::modlist::{ModlistImportReport, ModlistManifest};
+use instances::overwrite::OverwriteFile;
+use instances::retention::VersionsCleanupReport;
+use instances::search::{ModSearchQuery, ModSearchResult};
 use instances::{GameInstance, GameInstanceConfig, GameInstancePaths, InstanceExecutable};
 use mods::downloader::{Download, DownloadNexusData};
+use mods::installer::{self, InstallMod};
 use mods::ipc::{self, IPCClient, IPCPayload, IPCServer};
+use mods::nexus::collection::{CollectionManifest, InstanceCollection};
+use mods::nexus::identify::IdentifyDownloadResult;
+use mods::store::{FileStore, StoreFilesReport, StoreGarbageReport, StoreIntegrityReport};
 use serde::{Deserialize, Serialize};
+use state::config::nexusmods_config::RateLimit;
 use state::{config, default_instances_path, root_config_path, AvailableInstancesResponse};
 use std::borrow::BorrowMut;
 use std::collections::HashMap;
@@ -38,23 +52,11 @@ pub mod deployer;
 pub mod instances;
 pub mod mods;
 pub mod state;
+#[cfg(test)]
+mod test_utils;
 
 pub type MutexState = Arc<Mutex<state::ApplicationState>>;
 
-#[taurpc::ipc_type]
-struct InstallModFile {
-	source: String,
-	destination: String,
-}
-
-#[taurpc::ipc_type]
-struct InstallMod {
-	name: String,
-	version: String,
-	info: ModInfo,
-	files: Vec<InstallModFile>,
-}
-
 #[taurpc::ipc_type]
 struct InstallerPayload {
 	file_name: String,
@@ -88,6 +90,10 @@ trait Api {
 	async fn get_config_path() -> PathBuf;
 	async fn update_application_config(config: config::ApplicationConfig) -> bool;
 
+	// Deduplicating file store
+	async fn collect_store_garbage() -> Result<StoreGarbageReport, String>;
+	async fn check_store_integrity() -> Result<StoreIntegrityReport, String>;
+
 	// Frontend config state
 	async fn update_frontend_config(config: state::FrontendConfig) -> bool;
 
@@ -120,6 +126,20 @@ impl Api for ApiImpl {
 		return state::root_config_path();
 	}
 
+	async fn collect_store_garbage(self) -> Result<StoreGarbageReport, String> {
+		let state = self.state.lock().await;
+		let store = FileStore::new(state.application_config.file_store.store_path());
+
+		return store.collect_garbage();
+	}
+
+	async fn check_store_integrity(self) -> Result<StoreIntegrityReport, String> {
+		let state = self.state.lock().await;
+		let store = FileStore::new(state.application_config.file_store.store_path());
+
+		return store.check_integrity();
+	}
+
 	async fn update_frontend_config(self, config: state::FrontendConfig) -> bool {
 		let mut state = self.state.lock().await;
 		state.frontend_config = config;
@@ -130,22 +150,52 @@ impl Api for ApiImpl {
 	async fn update_application_config(self, config: config::ApplicationConfig) -> bool {
 		let mut state = self.state.lock().await;
 
-		// If the API key has changed, validate it
-		if state.application_config.nexusmods.api_key != config.nexusmods.api_key {
-			state.application_config.nexusmods.api_key = config.nexusmods.api_key.clone();
-			state.application_config.nexusmods.user_data = None;
+		// Keep using the shared NexusMods client (it is not sent to the frontend)
+		// The frontend only gets a redacted API key, keep the real one too
+		let previous_nexusmods = state.application_config.nexusmods.clone();
+		let new_api_key = config.nexusmods.api_key.clone();
 
-			// Validate API key
-			let _ = state.application_config.nexusmods.validate_api_key().await;
-			// match response {
-			// 	Ok(_) => {}
-			// 	Err(e) => {
-			// 		tauri::dialog::message(e).run();
-			// 	}
-			// }
+		state.application_config = config;
+		state.application_config.nexusmods.client = previous_nexusmods.client;
+		state.application_config.nexusmods.api_key = previous_nexusmods.api_key.clone();
+		state.application_config.nexusmods.user_data = previous_nexusmods.user_data;
+
+		// If the API key has changed, store and validate it
+		let api_key_changed = match &new_api_key {
+			Some(api_key) => {
+				!state::secrets::is_redacted(api_key)
+					&& Some(api_key) != previous_nexusmods.api_key.as_ref()
+			}
+			None => previous_nexusmods.api_key.is_some(),
+		};
+
+		if api_key_changed {
+			let secrets = state::secrets::SecretStore::new();
+			match state
+				.application_config
+				.nexusmods
+				.set_api_key(new_api_key, &secrets)
+			{
+				Ok(_) => {
+					// Validate API key, requests may wait for the rate limit
+					let mut nexus_config = state.application_config.nexusmods.clone();
+					state.save().unwrap();
+					drop(state);
+
+					let _ = nexus_config.validate_api_key().await;
+
+					state = self.state.lock().await;
+					state
+						.application_config
+						.nexusmods
+						.apply_validation(&nexus_config);
+				}
+				Err(e) => {
+					println!("Failed to store NexusMods API key: {}", e);
+				}
+			}
 		}
 
-		state.application_config = config;
 		state.save().unwrap();
 		return true;
 	}
@@ -194,11 +244,26 @@ trait ApiInstances {
 	// Mods
 	async fn create_empty_mod(name: String) -> Result<InstanceMod, String>;
 	async fn reload_mods() -> Result<(), String>;
+	async fn rescan_mod_versions() -> Result<Vec<ModVersionsReconciliation>, String>;
+	async fn adopt_orphan_mod(
+		folder: String,
+		version: Option<String>,
+	) -> Result<InstanceMod, String>;
+	async fn recover_mod_from_backup(folder: String) -> Result<InstanceMod, String>;
 	async fn open_mod_folder(mod_name: String) -> Result<(), String>;
 	async fn move_mod_by_index(mod_index: u32, target_index: u32) -> Result<(), String>;
 	async fn move_mods_by_indexes(indexes: Vec<u32>, target_index: u32)
 		-> Result<Vec<u32>, String>;
 	async fn move_mod_by_name(mod_name: String, target_index: u32) -> Result<(), String>;
+	// Separators
+	async fn create_separator(name: String, color: String) -> Result<InstanceMod, String>;
+	async fn update_separator(
+		name: String,
+		color: Option<String>,
+		collapsed: Option<bool>,
+	) -> Result<InstanceMod, String>;
+	async fn set_separator_group_enabled(name: String, enabled: bool) -> Result<(), String>;
+	async fn move_separator_group(name: String, target_index: u32) -> Result<Vec<u32>, String>;
 	async fn delete_mod_version(
 		mod_name: String,
 		mod_version: Option<String>,
@@ -206,6 +271,61 @@ trait ApiInstances {
 	async fn delete_mod(mod_name: String) -> Result<(), String>;
 	async fn set_mod_enabled(mod_name: String, enabled: bool) -> Result<(), String>;
 	async fn set_mod_active_version(mod_name: String, mod_version: String) -> Result<(), String>;
+	async fn hide_mod_file(mod_name: String, path: String) -> Result<(), String>;
+	async fn unhide_mod_file(mod_name: String, path: String) -> Result<(), String>;
+	async fn reinstall_mod_version(mod_name: String, version: String) -> Result<(), String>;
+	async fn store_mods_files(mod_names: Vec<String>) -> Result<StoreFilesReport, String>;
+	async fn update_mod_version_info(
+		mod_name: String,
+		version: String,
+		upstream_version: Option<String>,
+		notes: Option<String>,
+	) -> Result<InstanceMod, String>;
+	async fn diff_mod_versions(
+		mod_name: String,
+		from_version: String,
+		to_version: String,
+	) -> Result<ModVersionsDiff, String>;
+	async fn set_mod_version_pinned(
+		mod_name: String,
+		version: String,
+		pinned: bool,
+	) -> Result<(), String>;
+	async fn clean_up_versions(dry_run: bool) -> Result<VersionsCleanupReport, String>;
+	// Mod library
+	async fn list_library_mods() -> Result<Vec<LibraryMod>, String>;
+	async fn move_mod_to_library(mod_name: String) -> Result<InstanceMod, String>;
+	async fn add_mod_from_library(mod_name: String) -> Result<InstanceMod, String>;
+	// Overwrite
+	async fn list_overwrite_files() -> Result<Vec<OverwriteFile>, String>;
+	async fn create_mod_from_overwrite(
+		mod_name: String,
+		version: String,
+		paths: Vec<String>,
+	) -> Result<InstanceMod, String>;
+	async fn move_overwrite_to_mod(mod_name: String, paths: Vec<String>) -> Result<(), String>;
+	async fn clear_overwrite() -> Result<(), String>;
+	// Categories, tags and search
+	async fn set_categories(categories: Vec<ModCategory>) -> Result<(), String>;
+	async fn update_mod_info(mod_name: String, info: ModInfo) -> Result<InstanceMod, String>;
+	async fn get_mods_tags() -> Result<Vec<String>, String>;
+	async fn search_mods(query: ModSearchQuery) -> Result<Vec<ModSearchResult>, String>;
+	// Files index
+	async fn find_file_providers(path: String) -> Result<Vec<FileProvider>, String>;
+	async fn rebuild_file_index() -> Result<(), String>;
+	// Conflicts
+	async fn get_conflict_rules() -> Result<Vec<FileConflictRule>, String>;
+	async fn add_conflict_rule(path: String, winner: String, loser: String) -> Result<(), String>;
+	async fn remove_conflict_rule(
+		path: String,
+		winner: String,
+		loser: String,
+	) -> Result<(), String>;
+	// Modlists
+	async fn export_modlist(path: String) -> Result<(), String>;
+	async fn import_modlist(path: String) -> Result<ModlistImportReport, String>;
+	async fn compile_build(path: String) -> Result<(), String>;
+	async fn install_build(path: String) -> Result<BuildInstallReport, String>;
 	// async fn update_vfs_config(vfs_config: Option<config::vfs_config::VFSConfig>) -> Result<(), String>;
 	// async fn validate_config(config: GameInstanceConfig) -> Result<(), Vec<String>>;
 
@@ -342,6 +462,48 @@ impl ApiInstances for ApiInstancesStateImpl {
 		}
 	}
 
+	async fn rescan_mod_versions(self) -> Result<Vec<ModVersionsReconciliation>, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		// Loading the mods syncs their versions with the disk
+		selected_instance.load_mods()?;
+		let changes = selected_instance.mods_versions_changes.clone();
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(changes);
+	}
+
+	async fn adopt_orphan_mod(
+		self,
+		folder: String,
+		version: Option<String>,
+	) -> Result<InstanceMod, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		let instance_mod = selected_instance.adopt_orphan_mod(folder, version)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(instance_mod);
+	}
+
+	async fn recover_mod_from_backup(self, folder: String) -> Result<InstanceMod, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		let instance_mod = selected_instance.recover_mod_from_backup(folder)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(instance_mod);
+	}
+
 	async fn open_mod_folder(self, mod_name: String) -> Result<(), String> {
 		let state = self.state.lock().await;
 
@@ -394,44 +556,418 @@ impl ApiInstances for ApiInstancesStateImpl {
 		}
 	}
 
-	async fn move_mod_by_name(self, mod_name: String, target_index: u32) -> Result<(), String> {
-		let mut state = self.state.lock().await;
+	async fn move_mod_by_name(self, mod_name: String, target_index: u32) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+
+		match &mut state.selected_instance {
+			Some(instance) => {
+				instance.move_mod_by_name(mod_name, target_index)?;
+
+				// Update state
+				state.trigger_on_state_changed()?;
+				return Ok(());
+			}
+			None => {
+				return Err("No instance selected".to_string());
+			}
+		}
+	}
+
+	// Separators
+
+	async fn create_separator(self, name: String, color: String) -> Result<InstanceMod, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		let separator = selected_instance.create_separator(name, color)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(separator);
+	}
+
+	async fn update_separator(
+		self,
+		name: String,
+		color: Option<String>,
+		collapsed: Option<bool>,
+	) -> Result<InstanceMod, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		let separator = selected_instance.update_separator(name, color, collapsed)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(separator);
+	}
+
+	async fn set_separator_group_enabled(self, name: String, enabled: bool) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		selected_instance.set_separator_group_enabled(name, enabled)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn move_separator_group(
+		self,
+		name: String,
+		target_index: u32,
+	) -> Result<Vec<u32>, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		let new_indexes = selected_instance.move_separator_group(name, target_index)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(new_indexes);
+	}
+
+	async fn delete_mod(self, mod_name: String) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		selected_instance.delete_mod(mod_name)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn delete_mod_version(
+		self,
+		mod_name: String,
+		mod_version: Option<String>,
+	) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		selected_instance.delete_mod_version(mod_name, mod_version)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn set_mod_enabled(self, mod_name: String, enabled: bool) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		// Set active version
+		selected_instance.set_mod_enabled(mod_name, enabled)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn set_mod_active_version(
+		self,
+		mod_name: String,
+		mod_version: String,
+	) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		// Set active version
+		selected_instance.set_mod_active_version(mod_name, mod_version)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn hide_mod_file(self, mod_name: String, path: String) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		selected_instance.hide_mod_file(mod_name, path)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn unhide_mod_file(self, mod_name: String, path: String) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		selected_instance.unhide_mod_file(mod_name, path)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn reinstall_mod_version(self, mod_name: String, version: String) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		installer::reinstall_version(selected_instance, mod_name, version)?;
+
+		// Load mods
+		selected_instance.load_mods()?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn store_mods_files(self, mod_names: Vec<String>) -> Result<StoreFilesReport, String> {
+		let mut state = self.state.lock().await;
+		let store = FileStore::new(state.application_config.file_store.store_path());
+		let selected_instance = state.selected_instance_or_fail();
+
+		return selected_instance.store_mods_files(&store, mod_names);
+	}
+
+	async fn update_mod_version_info(
+		self,
+		mod_name: String,
+		version: String,
+		upstream_version: Option<String>,
+		notes: Option<String>,
+	) -> Result<InstanceMod, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		let instance_mod = selected_instance.update_mod_version_info(
+			mod_name,
+			version,
+			upstream_version,
+			notes,
+		)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(instance_mod);
+	}
+
+	async fn diff_mod_versions(
+		self,
+		mod_name: String,
+		from_version: String,
+		to_version: String,
+	) -> Result<ModVersionsDiff, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		return selected_instance.diff_mod_versions(mod_name, from_version, to_version);
+	}
+
+	async fn set_mod_version_pinned(
+		self,
+		mod_name: String,
+		version: String,
+		pinned: bool,
+	) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		selected_instance.set_mod_version_pinned(mod_name, version, pinned)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn clean_up_versions(self, dry_run: bool) -> Result<VersionsCleanupReport, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		let report = selected_instance.clean_up_versions(dry_run)?;
+
+		// Update state
+		if !dry_run {
+			state.trigger_on_state_changed()?;
+		}
+
+		return Ok(report);
+	}
+
+	// Mod library
+
+	async fn list_library_mods(self) -> Result<Vec<LibraryMod>, String> {
+		let mut state = self.state.lock().await;
+		let library_path = state.application_config.mod_library.library_path();
+		let selected_instance = state.selected_instance_or_fail();
+
+		return selected_instance.list_library_mods(&library_path);
+	}
+
+	async fn move_mod_to_library(self, mod_name: String) -> Result<InstanceMod, String> {
+		let mut state = self.state.lock().await;
+		let library_path = state.application_config.mod_library.library_path();
+		let selected_instance = state.selected_instance_or_fail();
+
+		let instance_mod = selected_instance.move_mod_to_library(mod_name, &library_path)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(instance_mod);
+	}
+
+	async fn add_mod_from_library(self, mod_name: String) -> Result<InstanceMod, String> {
+		let mut state = self.state.lock().await;
+		let library_path = state.application_config.mod_library.library_path();
+		let selected_instance = state.selected_instance_or_fail();
+
+		let instance_mod = selected_instance.add_mod_from_library(mod_name, &library_path)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(instance_mod);
+	}
+
+	// Overwrite
+
+	async fn list_overwrite_files(self) -> Result<Vec<OverwriteFile>, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		return selected_instance.list_overwrite_files();
+	}
+
+	async fn create_mod_from_overwrite(
+		self,
+		mod_name: String,
+		version: String,
+		paths: Vec<String>,
+	) -> Result<InstanceMod, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		let instance_mod = selected_instance.create_mod_from_overwrite(mod_name, version, paths)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(instance_mod);
+	}
+
+	async fn move_overwrite_to_mod(
+		self,
+		mod_name: String,
+		paths: Vec<String>,
+	) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		selected_instance.move_overwrite_to_mod(mod_name, paths)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn clear_overwrite(self) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		selected_instance.clear_overwrite()?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	// Categories, tags and search
+
+	async fn set_categories(self, categories: Vec<ModCategory>) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		selected_instance.set_categories(categories)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
+	async fn update_mod_info(self, mod_name: String, info: ModInfo) -> Result<InstanceMod, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		let instance_mod = selected_instance.update_mod_info(mod_name, info)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(instance_mod);
+	}
+
+	async fn get_mods_tags(self) -> Result<Vec<String>, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		return Ok(selected_instance.get_mods_tags());
+	}
+
+	async fn search_mods(self, query: ModSearchQuery) -> Result<Vec<ModSearchResult>, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		return selected_instance.search_mods(&query);
+	}
+
+	// Files index
 
-		match &mut state.selected_instance {
-			Some(instance) => {
-				instance.move_mod_by_name(mod_name, target_index)?;
+	async fn find_file_providers(self, path: String) -> Result<Vec<FileProvider>, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
 
-				// Update state
-				state.trigger_on_state_changed()?;
-				return Ok(());
-			}
-			None => {
-				return Err("No instance selected".to_string());
-			}
-		}
+		return selected_instance.find_file_providers(path);
 	}
 
-	async fn delete_mod(self, mod_name: String) -> Result<(), String> {
+	async fn rebuild_file_index(self) -> Result<(), String> {
 		let mut state = self.state.lock().await;
 		let selected_instance = state.selected_instance_or_fail();
 
-		selected_instance.delete_mod(mod_name)?;
+		return selected_instance.rebuild_file_index();
+	}
 
-		// Update state
-		state.trigger_on_state_changed()?;
+	// Conflicts
 
-		return Ok(());
+	async fn get_conflict_rules(self) -> Result<Vec<FileConflictRule>, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		return Ok(selected_instance.conflict_rules.clone());
 	}
 
-	async fn delete_mod_version(
+	async fn add_conflict_rule(
 		self,
-		mod_name: String,
-		mod_version: Option<String>,
+		path: String,
+		winner: String,
+		loser: String,
 	) -> Result<(), String> {
 		let mut state = self.state.lock().await;
 		let selected_instance = state.selected_instance_or_fail();
 
-		selected_instance.delete_mod_version(mod_name, mod_version)?;
+		selected_instance.add_conflict_rule(FileConflictRule::new(path, winner, loser)?)?;
 
 		// Update state
 		state.trigger_on_state_changed()?;
@@ -439,12 +975,16 @@ impl ApiInstances for ApiInstancesStateImpl {
 		return Ok(());
 	}
 
-	async fn set_mod_enabled(self, mod_name: String, enabled: bool) -> Result<(), String> {
+	async fn remove_conflict_rule(
+		self,
+		path: String,
+		winner: String,
+		loser: String,
+	) -> Result<(), String> {
 		let mut state = self.state.lock().await;
 		let selected_instance = state.selected_instance_or_fail();
 
-		// Set active version
-		selected_instance.set_mod_enabled(mod_name, enabled)?;
+		selected_instance.remove_conflict_rule(FileConflictRule::new(path, winner, loser)?)?;
 
 		// Update state
 		state.trigger_on_state_changed()?;
@@ -452,23 +992,108 @@ impl ApiInstances for ApiInstancesStateImpl {
 		return Ok(());
 	}
 
-	async fn set_mod_active_version(
-		self,
-		mod_name: String,
-		mod_version: String,
-	) -> Result<(), String> {
+	// Modlists
+
+	async fn export_modlist(self, path: String) -> Result<(), String> {
 		let mut state = self.state.lock().await;
 		let selected_instance = state.selected_instance_or_fail();
 
-		// Set active version
-		selected_instance.set_mod_active_version(mod_name, mod_version)?;
+		let manifest = selected_instance.export_modlist()?;
+		manifest.save(&PathBuf::from(path))?;
 
-		// Update state
+		return Ok(());
+	}
+
+	async fn import_modlist(self, path: String) -> Result<ModlistImportReport, String> {
+		let manifest = ModlistManifest::load(&PathBuf::from(path))?;
+
+		let mut state = self.state.lock().await;
+		let mut report = state.selected_instance_or_fail().apply_modlist(&manifest)?;
 		state.trigger_on_state_changed()?;
 
+		// Don't hold the state while requesting download links
+		let nexus_config = state.application_config.nexusmods.clone();
+		drop(state);
+
+		let downloads =
+			instances::modlist::queue_missing_archives(&nexus_config, &manifest, &mut report).await;
+
+		if downloads.len() > 0 {
+			let mut state = self.state.lock().await;
+			let selected_instance = state.selected_instance_or_fail();
+			selected_instance.downloads.extend(downloads);
+			selected_instance.save()?;
+
+			selected_instance
+				.start_downloads(self.state.clone())
+				.await?;
+
+			state.trigger_on_state_changed()?;
+		}
+
+		return Ok(report);
+	}
+
+	async fn compile_build(self, path: String) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let compilation = state
+			.selected_instance_or_fail()
+			.prepare_build_compilation()?;
+		drop(state);
+
+		// Extracting and hashing archives takes a while, don't block everything else
+		let build = compilation.compile()?;
+
+		let mut state = self.state.lock().await;
+		state
+			.selected_instance_or_fail()
+			.remember_archive_hashes(&build.archives)?;
+		drop(state);
+
+		build.save(&PathBuf::from(path))?;
+
 		return Ok(());
 	}
 
+	async fn install_build(self, path: String) -> Result<BuildInstallReport, String> {
+		let build = BuildManifest::load(&PathBuf::from(path))?;
+
+		let mut state = self.state.lock().await;
+		let report = state.selected_instance_or_fail().install_build(&build)?;
+		state.trigger_on_state_changed()?;
+
+		if report.missing_archives.is_empty() {
+			return Ok(report);
+		}
+
+		// Queue what can be fetched, the build can be installed once they are downloaded
+		let nexus_config = state.application_config.nexusmods.clone();
+		drop(state);
+
+		let mut downloads = Vec::new();
+		for archive in report.missing_archives.iter() {
+			match archive.create_download(&nexus_config).await {
+				Ok(download) => downloads.push(download),
+				Err(e) => println!("Can't queue {}: {}", archive.file_name, e),
+			}
+		}
+
+		if downloads.len() > 0 {
+			let mut state = self.state.lock().await;
+			let selected_instance = state.selected_instance_or_fail();
+			selected_instance.downloads.extend(downloads);
+			selected_instance.save()?;
+
+			selected_instance
+				.start_downloads(self.state.clone())
+				.await?;
+
+			state.trigger_on_state_changed()?;
+		}
+
+		return Ok(report);
+	}
+
 	// Executables
 
 	async fn set_executables(self, executables: Vec<InstanceExecutable>) -> Result<(), String> {
@@ -490,17 +1115,22 @@ impl ApiInstances for ApiInstancesStateImpl {
 	async fn run_executable(self, executable: InstanceExecutable) -> Result<(), String> {
 		let mut state = self.state.lock().await;
 
-		// Mount VFS, if needed
-		if !state.is_vfs_mounted {
-			state.mount_vfs()?
-		}
-
-		// Run executable
-		let selected_instance = state.selected_instance_or_fail();
-		let child_process = selected_instance.run_executable(executable.clone())?;
+		let (child_process, namespace_run) = if state.is_vfs_isolated() {
+			// Run executable, the VFS is mounted in its own mount namespace
+			let (child_process, namespace_run) =
+				state.run_executable_isolated(executable.clone())?;
+			(child_process, Some(namespace_run))
+		} else {
+			// Mount VFS, if needed, with the output mod of the executable as writable layer
+			state.mount_vfs_for_output(executable.output_mod.clone())?;
+
+			// Run executable
+			let selected_instance = state.selected_instance_or_fail();
+			(selected_instance.run_executable(executable.clone())?, None)
+		};
 
 		// Update running executables state
-		state.running_executables_add(executable.name, child_process);
+		state.running_executables_add(executable.name, child_process, namespace_run);
 
 		// Save state
 		state.save()?;
@@ -592,26 +1222,96 @@ impl ApiInstances for ApiInstancesStateImpl {
 #[derive(Clone)]
 struct ApiNexusModsStateImpl {
 	state: MutexState,
+	// Cancels the SSO login that is currently waiting for the user, if any
+	sso_cancel: Arc<Mutex<Option<oneshot::Sender<()>>>>,
 }
-#[taurpc::procedures(path = "nexusmods")]
+#[taurpc::procedures(path = "nexusmods", event_trigger = ApiNexusModsEventTrigger)]
 trait ApiNexusMods {
 	async fn validate_user() -> Result<(), String>;
+	async fn sso_login() -> Result<(), String>;
+	async fn cancel_sso_login() -> Result<(), String>;
+
+	#[taurpc(event)]
+	async fn on_rate_limit_update(rate_limit: RateLimit);
 }
 
 #[taurpc::resolvers]
 impl ApiNexusMods for ApiNexusModsStateImpl {
 	async fn validate_user(self) -> Result<(), String> {
-		let mut state = self.state.lock().await;
+		// Don't hold the state while the request waits for the rate limit
+		let mut nexus_config = self.state.lock().await.application_config.nexusmods.clone();
 
-		let response = match state.application_config.nexusmods.validate_api_key().await {
+		let response = match nexus_config.validate_api_key().await {
 			Ok(_) => Ok(()),
 			Err(e) => Err(e),
 		};
 
+		let mut state = self.state.lock().await;
+		state
+			.application_config
+			.nexusmods
+			.apply_validation(&nexus_config);
 		state.save()?;
 
 		return response;
 	}
+
+	async fn sso_login(self) -> Result<(), String> {
+		// Replacing a previous sender cancels the login that was still pending
+		let (cancel_tx, cancel_rx) = oneshot::channel();
+		*self.sso_cancel.lock().await = Some(cancel_tx);
+
+		// Don't hold the state while waiting for the user to authorize us
+		let api_key = mods::nexus::sso::NexusSSO::new()
+			.request_api_key(
+				|url| open::that(url).map_err(|e| format!("Failed to open browser: {}", e)),
+				cancel_rx,
+			)
+			.await;
+
+		// Forget our sender, unless another login already replaced it
+		let mut sso_cancel = self.sso_cancel.lock().await;
+		if sso_cancel.as_ref().is_some_and(|tx| tx.is_closed()) {
+			*sso_cancel = None;
+		}
+		drop(sso_cancel);
+
+		let api_key = api_key?;
+
+		let mut state = self.state.lock().await;
+		let secrets = state::secrets::SecretStore::new();
+		state
+			.application_config
+			.nexusmods
+			.set_api_key(Some(api_key), &secrets)?;
+		state.save()?;
+		let mut nexus_config = state.application_config.nexusmods.clone();
+		drop(state);
+
+		let response = nexus_config.validate_api_key().await;
+
+		let mut state = self.state.lock().await;
+		state
+			.application_config
+			.nexusmods
+			.apply_validation(&nexus_config);
+		state.save()?;
+		state.trigger_on_state_changed()?;
+
+		return response.map(|_| ());
+	}
+
+	async fn cancel_sso_login(self) -> Result<(), String> {
+		match self.sso_cancel.lock().await.take() {
+			Some(cancel_tx) => {
+				let _ = cancel_tx.send(());
+				return Ok(());
+			}
+			None => {
+				return Err("No NexusMods login in progress".to_string());
+			}
+		}
+	}
 }
 
 // ------------------------------
@@ -627,6 +1327,11 @@ trait ApiDownloads {
 	async fn download_urls(url: Vec<String>) -> Result<(), String>;
 	async fn resume_downloads() -> Result<(), String>;
 	async fn delete_downloads(filenames: Vec<String>) -> Result<(), String>;
+	async fn find_version_download(
+		mod_name: String,
+		version: String,
+	) -> Result<Option<Download>, String>;
+	async fn set_download_installed(filename: String, installed: bool) -> Result<(), String>;
 	async fn open_download_in_filemanager(filename: String) -> Result<(), String>;
 	async fn open_extracted_folder(extracted_file: String) -> Result<(), String>;
 	async fn install_file(app_handle: tauri::AppHandle, filename: String) -> Result<(), String>;
@@ -640,11 +1345,73 @@ trait ApiDownloads {
 		extracted_file: String,
 		install_mod: InstallMod,
 	) -> Result<(), String>;
+	async fn identify_download(filename: String) -> Result<IdentifyDownloadResult, String>;
+	async fn identify_all_downloads() -> Result<Vec<IdentifyDownloadResult>, String>;
+	async fn import_collection(manifest_path: String) -> Result<InstanceCollection, String>;
+	async fn select_collection_optional_mods(
+		collection_name: String,
+		mod_names: Vec<String>,
+	) -> Result<InstanceCollection, String>;
 
 	#[taurpc(event)]
 	async fn on_downloads_update(downloads: Vec<Download>);
 }
 
+// Look up archives on NexusMods by their MD5 hash
+// Every downloaded archive is checked when "file_names" is None
+async fn identify_downloads(
+	state_mutex: MutexState,
+	file_names: Option<Vec<String>>,
+) -> Result<Vec<IdentifyDownloadResult>, String> {
+	let state = state_mutex.lock().await;
+
+	let selected_instance = match &state.selected_instance {
+		Some(instance) => instance,
+		None => return Err("No instance selected".to_string()),
+	};
+
+	let game_domain = selected_instance
+		.config
+		.game_identifier
+		.nexus_game_domain()
+		.ok_or("This game is not supported by NexusMods")?;
+
+	let mut archives = selected_instance.list_downloaded_archives()?;
+	match file_names {
+		Some(file_names) => {
+			archives.retain(|(file_name, _)| file_names.contains(file_name));
+			if archives.is_empty() {
+				return Err("Download not found".to_string());
+			}
+		}
+		// In bulk, only the downloads not known on NexusMods yet
+		None => archives.retain(|(file_name, _)| {
+			!selected_instance
+				.downloads
+				.iter()
+				.any(|d| &d.file_name == file_name && d.nexus_data.is_some())
+		}),
+	}
+
+	// Don't hold the state while hashing and querying NexusMods
+	let nexus_config = state.application_config.nexusmods.clone();
+	drop(state);
+
+	let results =
+		mods::nexus::identify::identify_archives(&nexus_config, &game_domain, archives).await;
+
+	let mut state = state_mutex.lock().await;
+	let selected_instance = state.selected_instance_or_fail();
+	for result in results.iter() {
+		selected_instance.apply_identified_download(result);
+	}
+	selected_instance.save()?;
+
+	state.trigger_on_state_changed()?;
+
+	return Ok(results);
+}
+
 #[taurpc::resolvers]
 impl ApiDownloads for ApiDownloadsStateImpl {
 	async fn download_urls(self, urls: Vec<String>) -> Result<(), String> {
@@ -656,6 +1423,7 @@ impl ApiDownloads for ApiDownloadsStateImpl {
 			let mut parsed_download = Download {
 				file_name: format!("temp-{}", rand::random::<u32>()),
 				url: url.clone(),
+				mirrors: Vec::new(),
 				md5: None,
 				// is_initialized: false,
 				status: mods::downloader::DownloadStatus::Queued,
@@ -667,6 +1435,7 @@ impl ApiDownloads for ApiDownloadsStateImpl {
 				added_at: mods::downloader::default_date(),
 				completed_at: None,
 				nexus_data: None,
+				installed: false,
 			};
 
 			// Handle nxm links
@@ -686,12 +1455,16 @@ impl ApiDownloads for ApiDownloadsStateImpl {
 					continue;
 				}
 
-				parsed_download.url = parsed_nexus_download.url.clone();
+				parsed_download.set_nexus_download_url(&parsed_nexus_download);
 				parsed_download.file_name = parsed_nexus_download.filename.clone();
 				parsed_download.md5 = parsed_nexus_download.md5.clone();
 				parsed_download.nexus_data = Some(DownloadNexusData {
 					mod_id: parsed_nexus_download.file_request.mod_id,
 					file_id: parsed_nexus_download.file_request.file_id,
+					game_domain: parsed_nexus_download.file_request.game_domain,
+					key: parsed_nexus_download.file_request.key,
+					expires: parsed_nexus_download.file_request.expires,
+					..Default::default()
 				});
 			}
 
@@ -808,6 +1581,29 @@ impl ApiDownloads for ApiDownloadsStateImpl {
 		return Ok(());
 	}
 
+	async fn find_version_download(
+		self,
+		mod_name: String,
+		version: String,
+	) -> Result<Option<Download>, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		return Ok(selected_instance.find_version_download(mod_name, version));
+	}
+
+	async fn set_download_installed(self, filename: String, installed: bool) -> Result<(), String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+
+		selected_instance.set_download_installed(filename, installed)?;
+
+		// Update state
+		state.trigger_on_state_changed()?;
+
+		return Ok(());
+	}
+
 	async fn open_download_in_filemanager(self, filename: String) -> Result<(), String> {
 		let mut state = self.state.lock().await;
 
@@ -925,73 +1721,12 @@ impl ApiDownloads for ApiDownloadsStateImpl {
 		// Append _unpacked to the filename
 		unpacked_filename.push_str("_unpacked");
 
-		// Extracted path
-		let extracted_path_absolute: PathBuf = downloads_absolute_path
-			.join(PathBuf::from("extracted"))
-			.join(PathBuf::from(unpacked_filename.clone()));
-
-		// Delete pre-extracted files, if any
-		if extracted_path_absolute.clone().exists() {
-			println!(
-				"Deleting pre-existing extracted folder: {:?}",
-				extracted_path_absolute
-			);
-			file_controller::delete_folder_safe(
-				extracted_path_absolute.clone(),
-				downloads_absolute_path,
-			)
-			.map_err(|e| {
-				format!(
-					"Failed to delete existing extracted folder: {}",
-					e.to_string()
-				)
-			})?;
-		}
-
-		println!("Extracting download archive: {:?}", download_absolute_path);
-
-		// Extract the archive
-		crate::controllers::file_controller::extract_archive(
+		let extracted_path_absolute = installer::extract_download(
+			&downloads_absolute_path,
 			download_absolute_path,
-			extracted_path_absolute.clone(),
+			&unpacked_filename,
 		)?;
 
-		// Check if we have a sub-root
-		let entries =
-			match file_controller::list_entries_absolute_path(extracted_path_absolute.clone()) {
-				Ok(entries) => entries,
-				Err(e) => {
-					return Err(format!("Failed to list entries: {}", e.to_string()));
-				}
-			};
-
-		// If we have a sub-root
-		if entries.len() == 1 {
-			// We have a sub-root
-			// Move the sub-root to the root
-			let sub_root = entries[0].clone();
-			if sub_root.is_dir() {
-				println!("Moving sub-root to root: {:?}", sub_root);
-
-				// Move sub-root to main root
-				file_controller::move_folder(sub_root.clone(), extracted_path_absolute.clone())?;
-
-				// Delete old sub-root
-				// file_controller::delete_folder_safe(sub_root, extracted_path.clone())
-				// 	.map_err(|e| format!("Failed to delete sub-root: {}", e.to_string()))?;
-			}
-		};
-
-		// Case-fold entire extracted path
-		match crate::controllers::file_controller::case_fold_folder_recursive(
-			extracted_path_absolute.clone(),
-		) {
-			Ok(_) => {}
-			Err(e) => {
-				return Err(format!("Failed to case-fold folder: {}", e.to_string()));
-			}
-		}
-
 		// Return extracted path
 		return Ok(UnpackedFileResponse {
 			relative_folder: unpacked_filename,
@@ -1071,97 +1806,157 @@ impl ApiDownloads for ApiDownloadsStateImpl {
 		install_mod: InstallMod,
 	) -> Result<(), String> {
 		let mut state = self.state.lock().await;
+		let file_store = state.application_config.file_store.get_store();
 		let selected_instance = state.selected_instance_or_fail();
 
 		let downloads_absolute_path = selected_instance.get_downloads_absolute_path();
 
 		// Extracted path
-		let extracted_path: PathBuf = downloads_absolute_path
-			.join(PathBuf::from("extracted"))
-			.join(PathBuf::from(unpacked_filename.clone()));
+		let extracted_path =
+			installer::extracted_path(&downloads_absolute_path, &unpacked_filename);
+
+		let installed_mod =
+			installer::install_extracted(selected_instance, extracted_path.clone(), install_mod)?;
+
+		// Deduplicate the new files, the install is done either way
+		if let Some(file_store) = file_store {
+			let stored = selected_instance.store_mod_version_files(
+				&file_store,
+				installed_mod.name,
+				installed_mod.selected_version_identifier,
+			);
+			if let Err(e) = stored {
+				println!("Failed to add the mod to the store: {}", e);
+			}
+		}
 
-		// Create mod
-		let mod_instance = selected_instance.create_mod_version(
-			install_mod.name,
-			install_mod.version,
-			install_mod.info,
-		)?;
+		// Delete extracted files
+		file_controller::delete_folder_safe(extracted_path.clone(), downloads_absolute_path)
+			.map_err(|e| format!("Failed to delete extracted files: {}", e.to_string()))?;
+
+		// Load mods
+		selected_instance.load_mods()?;
+
+		// Trigger on state changed
+		state.trigger_on_state_changed()?;
 
-		// Get version absolute path
-		let version_absolute_path = mod_instance.get_selected_version_absolute_path();
+		return Ok(());
+	}
 
-		// Get deployment file structure, so we can check folder/file casing
-		let deployment_file_structure = selected_instance.get_mods_deployment_file_structure()?;
+	async fn identify_download(self, filename: String) -> Result<IdentifyDownloadResult, String> {
+		let mut results = identify_downloads(self.state.clone(), Some(vec![filename])).await?;
 
-		// Move files
-		for file in install_mod.files {
-			let file_source = file.source.replace("\\", "/").to_lowercase();
-			let mut case_folded_file_destination =
-				file.destination.replace("\\", "/").to_lowercase();
+		return Ok(results.remove(0));
+	}
 
-			// Check if the file is in the deployment file structure
-			let mut found_case_folded_path: Option<String> = None;
-			for file_structure in deployment_file_structure.clone() {
-				let split_path_str = case_folded_file_destination
-					.split("/")
-					.collect::<Vec<&str>>();
+	async fn identify_all_downloads(self) -> Result<Vec<IdentifyDownloadResult>, String> {
+		return identify_downloads(self.state.clone(), None).await;
+	}
 
-				// Convert split_path from Vec<&str> to Vec<String>
-				let split_path = split_path_str
-					.iter()
-					.map(|s| s.to_string())
-					.collect::<Vec<String>>();
+	async fn import_collection(self, manifest_path: String) -> Result<InstanceCollection, String> {
+		let manifest = CollectionManifest::load(&PathBuf::from(manifest_path))?;
+		let mut collection = InstanceCollection::from_manifest(manifest);
 
-				match file_structure.case_fold_path(split_path) {
-					Some(case_folded_path) => {
-						found_case_folded_path = Some(case_folded_path);
-						break;
-					}
-					None => continue,
-				}
-			}
+		let state = self.state.lock().await;
+		let selected_instance = match &state.selected_instance {
+			Some(instance) => instance,
+			None => return Err("No instance selected".to_string()),
+		};
 
-			// If a path was found matching deployment casing, use it instead
-			if found_case_folded_path.is_some() {
-				case_folded_file_destination = found_case_folded_path.unwrap();
-			}
+		if selected_instance
+			.collections
+			.iter()
+			.any(|c| c.name == collection.name)
+		{
+			return Err(format!(
+				"Collection \"{}\" is already imported",
+				collection.name
+			));
+		}
 
-			let source_file_absolute_path =
-				file_controller::join_paths(extracted_path.clone(), PathBuf::from(file_source));
-			let destination_file_absolute_path = file_controller::join_paths(
-				version_absolute_path.clone(),
-				PathBuf::from(case_folded_file_destination),
-			);
+		// Don't hold the state while requesting download links
+		let nexus_config = state.application_config.nexusmods.clone();
+		let existing_downloads = selected_instance.downloads.clone();
+		drop(state);
+
+		let downloads = mods::nexus::collection::queue_collection_downloads(
+			&nexus_config,
+			&mut collection,
+			&existing_downloads,
+		)
+		.await;
+
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+		selected_instance.downloads.extend(downloads);
+		selected_instance.collections.push(collection.clone());
+		selected_instance.save()?;
+
+		selected_instance
+			.start_downloads(self.state.clone())
+			.await?;
+
+		state.trigger_on_state_changed()?;
+
+		return Ok(collection);
+	}
+
+	async fn select_collection_optional_mods(
+		self,
+		collection_name: String,
+		mod_names: Vec<String>,
+	) -> Result<InstanceCollection, String> {
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+		let collection = selected_instance
+			.collections
+			.iter_mut()
+			.find(|c| c.name == collection_name)
+			.ok_or(format!("Collection \"{}\" not found", collection_name))?;
+		collection.select_optional_mods(&mod_names)?;
+
+		// Don't hold the state while requesting download links
+		let mut queued_collection = collection.clone();
+		let existing_downloads = selected_instance.downloads.clone();
+		let nexus_config = state.application_config.nexusmods.clone();
+		drop(state);
+
+		let downloads = mods::nexus::collection::queue_collection_downloads(
+			&nexus_config,
+			&mut queued_collection,
+			&existing_downloads,
+		)
+		.await;
 
-			// Move file/folder
-			// Check if we are moving a file
-			if source_file_absolute_path.is_file() {
-				file_controller::move_file(
-					source_file_absolute_path,
-					destination_file_absolute_path,
-				)
-				.map_err(|e| format!("Failed to move file: {}", e.to_string()))?;
-			} else {
-				file_controller::copy_recursive(
-					source_file_absolute_path,
-					destination_file_absolute_path,
-					true,
-				)
-				.map_err(|e| format!("Failed to copy with hardlinks folder: {}", e.to_string()))?;
+		let mut state = self.state.lock().await;
+		let selected_instance = state.selected_instance_or_fail();
+		let collection = selected_instance
+			.collections
+			.iter_mut()
+			.find(|c| c.name == collection_name)
+			.ok_or(format!("Collection \"{}\" not found", collection_name))?;
+		collection.merge_queued_mods(&queued_collection);
+		let collection = collection.clone();
+
+		// Skip archives queued meanwhile
+		for download in downloads {
+			if !selected_instance
+				.downloads
+				.iter()
+				.any(|d| d.file_name == download.file_name)
+			{
+				selected_instance.downloads.push(download);
 			}
 		}
+		selected_instance.save()?;
 
-		// Delete extracted files
-		file_controller::delete_folder_safe(extracted_path.clone(), downloads_absolute_path)
-			.map_err(|e| format!("Failed to delete extracted files: {}", e.to_string()))?;
-
-		// Load mods
-		selected_instance.load_mods()?;
+		selected_instance
+			.start_downloads(self.state.clone())
+			.await?;
 
-		// Trigger on state changed
 		state.trigger_on_state_changed()?;
 
-		return Ok(());
+		return Ok(collection);
 	}
 }
 
@@ -1189,6 +1984,7 @@ async fn main() {
 		.merge(
 			ApiNexusModsStateImpl {
 				state: stateMutex.clone(),
+				sso_cancel: Arc::new(Mutex::new(None)),
 			}
 			.into_handler(),
 		)
@@ -1205,10 +2001,16 @@ async fn main() {
 		let events_trigger = ApiEventTrigger::new(app_handle.clone());
 		let downloads_events_trigger: ApiDownloadsEventTrigger =
 			ApiDownloadsEventTrigger::new(app_handle.clone());
+		let nexusmods_events_trigger: ApiNexusModsEventTrigger =
+			ApiNexusModsEventTrigger::new(app_handle.clone());
 
 		// Set event trigger
 		let mut state = stateMutex.lock().await;
-		state.set_events_triggers(events_trigger, downloads_events_trigger);
+		state.set_events_triggers(
+			events_trigger,
+			downloads_events_trigger,
+			nexusmods_events_trigger,
+		);
 
 		drop(state);
 
@@ -1221,6 +2023,20 @@ async fn main() {
 			// -------------------------------
 			state.check_running_executables();
 
+			// -------------------------------
+			// Forward NexusMods quota changes
+			// -------------------------------
+			if state.application_config.nexusmods.client.take_pending_update() {
+				let rate_limit = state.application_config.nexusmods.client.rate_limit();
+				state.application_config.nexusmods.rate_limit = rate_limit.clone();
+
+				let _ = state
+					.nexusmods_event_trigger
+					.as_ref()
+					.unwrap()
+					.on_rate_limit_update(rate_limit);
+			}
+
 			if state.selected_instance.is_some() {
 				let selected_instance = state.selected_instance.as_mut().unwrap();
 
@@ -1257,6 +2073,25 @@ async fn main() {
 				}
 			}
 
+			// ---------------------------------------------
+			// Install collection mods whose archive is ready
+			// ---------------------------------------------
+			let has_pending_installs = state.selected_instance.as_ref().is_some_and(|instance| {
+				instance
+					.collections
+					.iter()
+					.any(|collection| collection.has_pending_installs())
+			});
+			if has_pending_installs
+				&& !state
+					.collection_install_running
+					.load(std::sync::atomic::Ordering::SeqCst)
+			{
+				tokio::spawn(mods::nexus::collection::process_collection_installs(
+					stateMutex.clone(),
+				));
+			}
+
 			// Drop the state mutex, so it can be used somewhere else
 			drop(state);
 
@@ -1,7 +1,7 @@
 use events::{DownloaderEvent, DownloaderEventHandler};
 use futures::stream::StreamExt;
 use reqwest::header::{HeaderMap, HeaderValue, RANGE};
-use reqwest::Client;
+use reqwest::{Client, StatusCode};
 use serde::{Deserialize, Serialize};
 use specta::Type;
 use std::fs::OpenOptions;
@@ -18,6 +18,8 @@ use tokio::sync::Mutex;
 
 use crate::controllers::file_controller;
 use crate::mods::downloader::events::DownloaderChunkEventHandler;
+use crate::mods::nexus;
+use crate::state::config::nexusmods_config::{NMDownloadUrl, NMSchemeParameters};
 use crate::state::ApplicationState;
 use crate::ApiDownloadsEventTrigger;
 use file_integrity::hash_file;
@@ -47,10 +49,97 @@ pub enum DownloadStatus {
 }
 
 #[taurpc::ipc_type]
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct DownloadNexusData {
 	pub mod_id: String,
 	pub file_id: String,
+	// Needed to request a fresh download link once the current one expires
+	#[serde(default)]
+	pub game_domain: String,
+	#[serde(default)]
+	pub key: Option<String>,
+	#[serde(default)]
+	pub expires: Option<String>,
+	// Mod/file details, filled when the archive is identified by its hash
+	#[serde(default)]
+	pub mod_name: Option<String>,
+	#[serde(default)]
+	pub file_title: Option<String>,
+	#[serde(default)]
+	pub version: Option<String>,
+	#[serde(default)]
+	pub author: Option<String>,
+	#[serde(default)]
+	pub category_name: Option<String>,
+	// Category of the mod on NexusMods
+	#[serde(default)]
+	pub category_id: Option<u32>,
+}
+
+impl DownloadNexusData {
+	pub fn to_file_request(&self) -> NMSchemeParameters {
+		return NMSchemeParameters {
+			game_domain: self.game_domain.clone(),
+			mod_id: self.mod_id.clone(),
+			file_id: self.file_id.clone(),
+			key: self.key.clone(),
+			expires: self.expires.clone(),
+		};
+	}
+
+	// Add what identifying the archive found, the nxm key is kept for the same file
+	pub fn merge_identified(&mut self, identified: &DownloadNexusData) {
+		if self.mod_id != identified.mod_id || self.file_id != identified.file_id {
+			self.key = None;
+			self.expires = None;
+		}
+		self.mod_id = identified.mod_id.clone();
+		self.file_id = identified.file_id.clone();
+		self.game_domain = identified.game_domain.clone();
+
+		let details = [
+			(&mut self.mod_name, &identified.mod_name),
+			(&mut self.file_title, &identified.file_title),
+			(&mut self.version, &identified.version),
+			(&mut self.author, &identified.author),
+			(&mut self.category_name, &identified.category_name),
+		];
+		for (detail, identified_detail) in details {
+			if identified_detail.is_some() {
+				*detail = identified_detail.clone();
+			}
+		}
+		if identified.category_id.is_some() {
+			self.category_id = identified.category_id;
+		}
+	}
+}
+
+// Returned by the downloader when every mirror rejects the link (403/410)
+#[derive(Debug)]
+pub struct DownloadLinkExpired;
+
+impl std::fmt::Display for DownloadLinkExpired {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "Download link has expired")
+	}
+}
+
+impl std::error::Error for DownloadLinkExpired {}
+
+fn is_expired_link_status(status: StatusCode) -> bool {
+	return status == StatusCode::FORBIDDEN || status == StatusCode::GONE;
+}
+
+fn is_expired_link_error(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+	if error.downcast_ref::<DownloadLinkExpired>().is_some() {
+		return true;
+	}
+
+	return match error.downcast_ref::<reqwest::Error>() {
+		Some(error) => error.status().map_or(false, is_expired_link_status),
+		None => false,
+	};
 }
 
 #[taurpc::ipc_type]
@@ -61,6 +150,9 @@ pub struct Download {
 	pub size_total: String,
 	pub size_downloaded: String,
 	pub url: String,
+	// Alternative urls (CDNs) for the same file, tried in order when "url" fails
+	#[serde(default)]
+	pub mirrors: Vec<String>,
 	pub md5: Option<String>,
 	// pub is_initialized: bool,
 	// We dont want to either send this to the front
@@ -76,9 +168,45 @@ pub struct Download {
 	pub completed_at: Option<String>,
 	// Nexus Data
 	pub nexus_data: Option<DownloadNexusData>,
+	// Set when a mod version is installed from it, or by the user
+	#[serde(default)]
+	pub installed: bool,
 }
 
 impl Download {
+	// Whether a file of the downloads folder is this download, or one of its chunks
+	pub fn owns_file(&self, file_name: &str) -> bool {
+		if file_name == self.file_name {
+			return true;
+		}
+
+		return file_name
+			.strip_prefix(&format!("{}.part", self.file_name))
+			.is_some_and(|index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()));
+	}
+
+	// Entry for an archive that already is in the downloads folder
+	pub fn from_existing_file(path: &Path) -> Self {
+		let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+
+		return Self {
+			file_name: path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+			status: DownloadStatus::Downloaded,
+			size_total: size.to_string(),
+			size_downloaded: size.to_string(),
+			url: String::new(),
+			mirrors: Vec::new(),
+			md5: None,
+			error: None,
+			downloader: None,
+			pending_update: false,
+			added_at: default_date(),
+			completed_at: Some(default_date()),
+			nexus_data: None,
+			installed: false,
+		};
+	}
+
 	pub fn start(
 		&mut self,
 		state_mutex: Arc<Mutex<ApplicationState>>,
@@ -90,23 +218,105 @@ impl Download {
 		// Create a new event handler
 		let event_handler = Arc::new(Mutex::new(DownloaderEventHandler::new()));
 
-		let file_path = downloads_path.join(&self.file_name);
-
-		// Create a new downloader
-		let downloader = Downloader::new(
-			self.url.clone(),
-			file_path.into_os_string().into_string().unwrap(),
-			event_handler.clone(),
-			self.md5.clone(),
-			num_threads,
-		);
+		let file_path = downloads_path
+			.join(&self.file_name)
+			.into_os_string()
+			.into_string()
+			.unwrap();
+
+		// Primary url first, then the remaining mirrors
+		let mut urls = vec![self.url.clone()];
+		for mirror in self.mirrors.iter() {
+			if !urls.contains(mirror) {
+				urls.push(mirror.clone());
+			}
+		}
 
 		// Clear the error field
 		self.error = None;
 
 		// Start the download
+		let md5 = self.md5.clone();
+		let file_name = self.file_name.clone();
+		let file_request = self.nexus_data.as_ref().map(|d| d.to_file_request());
+		let downloader_state_mutex = state_mutex.clone();
+		let downloader_event_handler = event_handler.clone();
 		let download_handle = tokio::spawn(async move {
-			downloader.start().await.unwrap();
+			let mut urls = urls;
+			let mut refreshed_link = false;
+
+			loop {
+				// NexusMods links expire, request a fresh one instead of trying a dead link
+				if !refreshed_link
+					&& file_request.is_some()
+					&& nexus::is_download_link_expired(&urls[0])
+				{
+					refreshed_link = true;
+					match refresh_nexus_download_link(
+						downloader_state_mutex.clone(),
+						file_name.clone(),
+						file_request.clone().unwrap(),
+					)
+					.await
+					{
+						Ok(fresh_urls) => urls = fresh_urls,
+						Err(e) => {
+							println!("Failed to refresh download link: {}", e);
+							let _ = downloader_event_handler
+								.lock()
+								.await
+								.send_event(DownloaderEvent::Failed { error: e })
+								.await;
+							break;
+						}
+					}
+				}
+
+				let downloader = Downloader::new(
+					urls[0].clone(),
+					file_path.clone(),
+					downloader_event_handler.clone(),
+					md5.clone(),
+					num_threads,
+				)
+				.with_mirrors(urls[1..].to_vec());
+
+				let error = match downloader.start().await {
+					Ok(_) => break,
+					Err(error) => error,
+				};
+				let mut error_message = error.to_string();
+
+				// Retry once with a fresh link, if this is a NexusMods download
+				if !refreshed_link && file_request.is_some() && is_expired_link_error(&*error) {
+					refreshed_link = true;
+					match refresh_nexus_download_link(
+						downloader_state_mutex.clone(),
+						file_name.clone(),
+						file_request.clone().unwrap(),
+					)
+					.await
+					{
+						Ok(fresh_urls) => {
+							urls = fresh_urls;
+							continue;
+						}
+						Err(e) => {
+							println!("Failed to refresh download link: {}", e);
+							error_message = e;
+						}
+					}
+				}
+
+				let _ = downloader_event_handler
+					.lock()
+					.await
+					.send_event(DownloaderEvent::Failed {
+						error: error_message,
+					})
+					.await;
+				break;
+			}
 		});
 
 		// Handle events
@@ -145,6 +355,11 @@ impl Download {
 		// return Ok(());
 	}
 
+	pub fn set_nexus_download_url(&mut self, nexus_download: &NMDownloadUrl) {
+		self.url = nexus_download.url.clone();
+		self.mirrors = nexus_download.mirrors.clone();
+	}
+
 	pub fn handle_event(&mut self, event: DownloaderEvent) {
 		match event {
 			DownloaderEvent::Progress { downloaded, total } => {
@@ -219,6 +434,54 @@ impl Download {
 	}
 }
 
+// Request a new NexusMods download link and store it in the download
+// Returns the new urls, ordered by preference
+async fn refresh_nexus_download_link(
+	state_mutex: Arc<Mutex<ApplicationState>>,
+	file_name: String,
+	file_request: NMSchemeParameters,
+) -> Result<Vec<String>, String> {
+	println!("Requesting a fresh download link for: {}", file_name);
+
+	if nexus::is_nxm_key_expired(&file_request) {
+		return Err(format!(
+			"The NexusMods link of \"{}\" has expired, re-open the nxm link to download it",
+			file_name
+		));
+	}
+
+	// Do not hold the state while waiting for NexusMods
+	let nexusmods_config = state_mutex
+		.lock()
+		.await
+		.application_config
+		.nexusmods
+		.clone();
+	let nexus_download = nexusmods_config
+		.convert_nmm_request_to_url(file_request)
+		.await?;
+
+	let mut state = state_mutex.lock().await;
+	match state.selected_instance.as_mut() {
+		Some(instance) => {
+			match instance
+				.downloads
+				.iter_mut()
+				.find(|d| d.file_name == file_name)
+			{
+				Some(download) => {
+					download.set_nexus_download_url(&nexus_download);
+					download.pending_update = true;
+				}
+				None => {}
+			}
+		}
+		None => {}
+	}
+
+	return Ok(nexus_download.mirrors);
+}
+
 #[derive(Debug)]
 pub struct DownloaderSize {
 	pub total: u64,
@@ -229,6 +492,7 @@ pub struct DownloaderSize {
 pub struct Downloader {
 	client: Client,                                    // HTTP client for making requests
 	url: String,                                       // URL of the file to download
+	mirrors: Vec<String>,                              // Fallback URLs, tried in order
 	file_path: String,                                 // Path to save the downloaded file
 	md5: Option<String>,                               // MD5 hash of the file
 	event_handler: Arc<Mutex<DownloaderEventHandler>>, // Event handler for sending events
@@ -249,6 +513,7 @@ impl Downloader {
 		Downloader {
 			client: Client::new(), // Initialize the HTTP client
 			url,
+			mirrors: Vec::new(),
 			file_path,
 			event_handler,
 			md5,
@@ -261,6 +526,19 @@ impl Downloader {
 		}
 	}
 
+	// Set the urls to fall back to when the main url fails
+	pub fn with_mirrors(mut self, mirrors: Vec<String>) -> Self {
+		self.mirrors = mirrors;
+		self
+	}
+
+	// Main url first, followed by the mirrors
+	fn urls(&self) -> Vec<String> {
+		let mut urls = vec![self.url.clone()];
+		urls.extend(self.mirrors.iter().cloned());
+		urls
+	}
+
 	// Pause the download
 	// pub fn pause(&self) {
 	// 	self.paused.store(true, Ordering::SeqCst);
@@ -289,7 +567,37 @@ impl Downloader {
 		let parent_dir = Path::new(&self.file_path).parent().unwrap();
 		tokio::fs::create_dir_all(parent_dir).await?;
 
-		let head_request = self.client.head(&self.url).send().await?;
+		// Find the first mirror that answers
+		let urls = self.urls();
+		let mut head_request: Option<reqwest::Response> = None;
+		let mut first_mirror = 0;
+		let mut all_expired = true;
+		for (index, url) in urls.iter().enumerate() {
+			match self.client.head(url).send().await {
+				Ok(response) if response.status().is_success() => {
+					head_request = Some(response);
+					first_mirror = index;
+					break;
+				}
+				Ok(response) => {
+					println!("Mirror returned {}: {}", response.status(), url);
+					all_expired = all_expired && is_expired_link_status(response.status());
+				}
+				Err(e) => {
+					println!("Mirror failed: {} -> {}", url, e);
+					all_expired = false;
+				}
+			}
+		}
+
+		let head_request = match head_request {
+			Some(head_request) => head_request,
+			None if all_expired => return Err(Box::new(DownloadLinkExpired)),
+			None => return Err("Every download mirror failed".into()),
+		};
+
+		// Mirrors that failed to answer are not worth retrying
+		let urls: Vec<String> = urls[first_mirror..].to_vec();
 
 		// Get the total size of the file
 		let content_length_header = match head_request.headers().get("content-length") {
@@ -341,13 +649,13 @@ impl Downloader {
 			let start = i as u64 * chunk_size;
 			// Calculate the end byte of the chunk
 			let end = if i == final_threads - 1 {
-				total_size
+				total_size - 1
 			} else {
 				(i as u64 + 1) * chunk_size - 1
 			};
 
-			// Clone the URL for the task
-			let url = self.url.clone();
+			// Clone the URLs for the task
+			let urls = urls.clone();
 			// Create a unique file path for the chunk
 			let file_path = format!("{}.part{}", self.file_path, i);
 			// Clone the HTTP client for the task
@@ -406,24 +714,57 @@ impl Downloader {
 
 			// Spawn and add the task to the tasks vector
 			tasks.push(tokio::spawn(async move {
-				// Download the chunk
-				println!("Downloading chunk {} -> {} - {}", i, start, end);
-				let _ = download_chunk(
-					url,
-					file_path,
-					client,
-					start,
-					end,
-					chunk_event_handler_clone.clone(),
-					supports_range,
-				)
-				.await;
-				println!("Downloaded chunk {} -> {} - {}", i, start, end);
+				let mut last_error: Option<Box<dyn std::error::Error + Send + Sync>> = None;
+
+				// Try each mirror in order, already downloaded bytes are kept in the part file
+				for url in urls {
+					println!("Downloading chunk {} -> {} - {} ({})", i, start, end, url);
+					match download_chunk(
+						url.clone(),
+						file_path.clone(),
+						client.clone(),
+						start,
+						end,
+						chunk_event_handler_clone.clone(),
+						supports_range,
+					)
+					.await
+					{
+						Ok(_) => {
+							println!("Downloaded chunk {} -> {} - {}", i, start, end);
+							return Ok(());
+						}
+						Err(e) => {
+							println!("Chunk {} failed on mirror {}: {}", i, url, e);
+							last_error = Some(e);
+						}
+					}
+				}
+
+				let error = last_error.unwrap_or_else(|| "No download mirrors available".into());
+				let _ = chunk_event_handler_clone
+					.lock()
+					.await
+					.send_event(DownloaderChunkEvent::Failed {
+						error: error.to_string(),
+					})
+					.await;
+
+				return Err(error);
 			}));
 		}
 
 		// Wait for all download tasks to complete
-		futures::future::try_join_all(tasks).await?;
+		let results = futures::future::try_join_all(tasks).await?;
+		for result in results {
+			match result {
+				Ok(_) => {}
+				Err(error) if is_expired_link_error(&*error) => {
+					return Err(Box::new(DownloadLinkExpired));
+				}
+				Err(error) => return Err(error),
+			}
+		}
 
 		self.event_handler
 			.lock()
@@ -545,11 +886,22 @@ async fn download_chunk(
 		}
 	}
 
-	// Send the HTTP request
-	let response = client.get(&url).headers(headers).send().await?;
 	// Calculate the total size of the chunk
 	let total_size = end - start + 1;
 
+	// Nothing left to download for this chunk (Ex. resuming on another mirror)
+	if supports_range && downloaded >= total_size {
+		return Ok(());
+	}
+
+	// Send the HTTP request
+	let response = client
+		.get(&url)
+		.headers(headers)
+		.send()
+		.await?
+		.error_for_status()?;
+
 	// Get the response body as a stream
 	let mut stream = response.bytes_stream();
 	let mut file = if downloaded > 0 {
@@ -576,8 +928,14 @@ async fn download_chunk(
 		// 	event_handler.lock().await.send_event(DownloaderEvent::Resumed).await?;
 		// }
 
-		// Get the chunk
-		let chunk = chunk?;
+		// Get the chunk, keeping what we got so far if the transfer breaks
+		let chunk = match chunk {
+			Ok(chunk) => chunk,
+			Err(e) => {
+				file.flush().await?;
+				return Err(e.into());
+			}
+		};
 		// Write the chunk to the file
 		file.write_all(&chunk).await?;
 		// Update the downloaded size
@@ -843,6 +1201,77 @@ mod tests {
 		// Shutdown the server
 		server_handle.abort();
 
+		Ok(())
+	}
+	#[tokio::test]
+	async fn test_mirror_failover() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let file_data: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+		// The first mirror answers HEAD requests, but fails every transfer
+		let broken_data_len = file_data.len();
+		let broken_route = warp::path!("broken" / "file").and(warp::method()).map(
+			move |method: warp::http::Method| {
+				let status = match method {
+					warp::http::Method::HEAD => 200,
+					_ => 500,
+				};
+				Response::builder()
+					.status(status)
+					.header(ACCEPT_RANGES, "bytes")
+					.header(CONTENT_LENGTH, broken_data_len)
+					.body(Body::empty())
+					.unwrap()
+			},
+		);
+
+		// The second mirror serves the file, with byte ranges
+		let good_data = file_data.clone();
+		let good_route = warp::path!("good" / "file")
+			.and(warp::header::optional::<String>("range"))
+			.map(move |range: Option<String>| {
+				let (start, end) = match range {
+					Some(range) => {
+						let range = range.trim_start_matches("bytes=");
+						let (start, end) = range.split_once('-').unwrap();
+						(
+							start.parse::<usize>().unwrap(),
+							std::cmp::min(end.parse::<usize>().unwrap(), good_data.len() - 1),
+						)
+					}
+					None => (0, good_data.len() - 1),
+				};
+				Response::builder()
+					.header(ACCEPT_RANGES, "bytes")
+					.header(CONTENT_LENGTH, end - start + 1)
+					.body(Body::from(good_data[start..=end].to_vec()))
+					.unwrap()
+			});
+
+		let (addr, server) =
+			warp::serve(broken_route.or(good_route)).bind_ephemeral(([127, 0, 0, 1], 0));
+		tokio::spawn(server);
+
+		let temp_dir = tempdir()?;
+		let file_path = temp_dir.path().join("mirrored_file");
+
+		let downloader = Downloader::new(
+			format!("http://{}/broken/file", addr),
+			file_path.to_str().unwrap().to_string(),
+			Arc::new(Mutex::new(DownloaderEventHandler::new())),
+			None,
+			2,
+		)
+		.with_mirrors(vec![format!("http://{}/good/file", addr)]);
+
+		timeout(Duration::from_secs(30), downloader.start()).await??;
+
+		let mut downloaded_data = Vec::new();
+		File::open(file_path)
+			.await?
+			.read_to_end(&mut downloaded_data)
+			.await?;
+		assert_eq!(downloaded_data, file_data);
+
 		Ok(())
 	}
 }
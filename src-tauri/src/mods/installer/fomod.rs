@@ -0,0 +1,388 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::InstallModFile;
+
+// Selected FOMOD options, as recorded by Nexus Collections
+// Steps -> Groups -> Choices, matched by name
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct FomodChoices {
+	#[serde(default)]
+	pub options: Vec<FomodStepChoices>,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct FomodStepChoices {
+	pub name: String,
+	#[serde(default)]
+	pub groups: Vec<FomodGroupChoices>,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct FomodGroupChoices {
+	pub name: String,
+	#[serde(default)]
+	pub choices: Vec<FomodChoice>,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct FomodChoice {
+	pub name: String,
+	// Index of the option inside its group, used when names are ambiguous
+	#[serde(default)]
+	pub idx: Option<u32>,
+}
+
+struct FomodFile {
+	source: String,
+	destination: String,
+	priority: i32,
+}
+
+// Extracted archives are case-folded, so is the installer path
+pub fn module_config_path(extracted_path: &PathBuf) -> PathBuf {
+	return extracted_path.join("fomod").join("moduleconfig.xml");
+}
+
+pub fn has_fomod(extracted_path: &PathBuf) -> bool {
+	return module_config_path(extracted_path).is_file();
+}
+
+// FOMOD files are often UTF-16 encoded
+fn decode_xml(bytes: &[u8]) -> Result<String, String> {
+	let decode_utf16 = |bytes: &[u8], little_endian: bool| {
+		let units = bytes
+			.chunks_exact(2)
+			.map(|pair| match little_endian {
+				true => u16::from_le_bytes([pair[0], pair[1]]),
+				false => u16::from_be_bytes([pair[0], pair[1]]),
+			})
+			.collect::<Vec<u16>>();
+
+		String::from_utf16(&units).map_err(|e| format!("Invalid UTF-16 installer: {}", e))
+	};
+
+	if bytes.starts_with(&[0xFF, 0xFE]) {
+		return decode_utf16(&bytes[2..], true);
+	}
+	if bytes.starts_with(&[0xFE, 0xFF]) {
+		return decode_utf16(&bytes[2..], false);
+	}
+
+	let text = String::from_utf8_lossy(bytes).to_string();
+	return Ok(text.trim_start_matches('\u{feff}').to_string());
+}
+
+fn parse_files(node: roxmltree::Node) -> Vec<FomodFile> {
+	let mut files = Vec::new();
+
+	for child in node.children().filter(|n| n.is_element()) {
+		let is_folder = match child.tag_name().name() {
+			"file" => false,
+			"folder" => true,
+			_ => continue,
+		};
+
+		let source = child
+			.attribute("source")
+			.unwrap_or("")
+			.replace("\\", "/")
+			.to_lowercase();
+		// Without destination, files and folders keep their path.
+		// Only an empty one puts them at the mod root, files keeping their name.
+		let destination = match child.attribute("destination") {
+			Some("") if !is_folder => source.rsplit('/').next().unwrap_or("").to_string(),
+			Some(destination) => destination.replace("\\", "/").to_lowercase(),
+			None => source.clone(),
+		};
+
+		files.push(FomodFile {
+			source,
+			destination,
+			priority: child
+				.attribute("priority")
+				.and_then(|p| p.trim().parse::<i32>().ok())
+				.unwrap_or(0),
+		});
+	}
+
+	return files;
+}
+
+fn child_element<'a, 'input>(
+	node: roxmltree::Node<'a, 'input>,
+	name: &str,
+) -> Option<roxmltree::Node<'a, 'input>> {
+	return node
+		.children()
+		.find(|n| n.is_element() && n.tag_name().name() == name);
+}
+
+fn child_elements<'a, 'input>(
+	node: roxmltree::Node<'a, 'input>,
+	name: &'a str,
+) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> + 'a {
+	return node
+		.children()
+		.filter(move |n| n.is_element() && n.tag_name().name() == name);
+}
+
+fn eq_name(a: Option<&str>, b: &str) -> bool {
+	return a.is_some_and(|a| a.trim().eq_ignore_ascii_case(b.trim()));
+}
+
+// Only flag dependencies can be evaluated here,
+// other kinds (files, game version...) are considered met
+fn dependencies_met(node: roxmltree::Node, flags: &HashMap<String, String>) -> bool {
+	let is_or = node.attribute("operator") == Some("Or");
+
+	let mut results = node
+		.children()
+		.filter(|n| n.is_element())
+		.map(|dependency| match dependency.tag_name().name() {
+			"flagDependency" => {
+				let flag = dependency.attribute("flag").unwrap_or("");
+				let value = dependency.attribute("value").unwrap_or("");
+				return flags.get(flag).map(|v| v.as_str()).unwrap_or("") == value;
+			}
+			"dependencies" => dependencies_met(dependency, flags),
+			_ => true,
+		});
+
+	return match is_or {
+		true => results.any(|met| met),
+		false => results.all(|met| met),
+	};
+}
+
+// Resolve the files to install from the archive installer and the recorded choices
+pub fn install_files_from_choices(
+	extracted_path: &PathBuf,
+	choices: &FomodChoices,
+) -> Result<Vec<InstallModFile>, String> {
+	let bytes = std::fs::read(module_config_path(extracted_path))
+		.map_err(|e| format!("Failed to read FOMOD installer: {}", e))?;
+	let xml = decode_xml(&bytes)?;
+	let document = roxmltree::Document::parse(&xml)
+		.map_err(|e| format!("Failed to parse FOMOD installer: {}", e))?;
+	let config = document.root_element();
+
+	let mut files: Vec<FomodFile> = Vec::new();
+	let mut flags: HashMap<String, String> = HashMap::new();
+
+	if let Some(required) = child_element(config, "requiredInstallFiles") {
+		files.extend(parse_files(required));
+	}
+
+	let steps = child_element(config, "installSteps")
+		.map(|steps| child_elements(steps, "installStep").collect::<Vec<_>>())
+		.unwrap_or_default();
+
+	for step_choices in choices.options.iter() {
+		let step = steps
+			.iter()
+			.find(|step| eq_name(step.attribute("name"), &step_choices.name))
+			.ok_or(format!("FOMOD step not found: {}", step_choices.name))?;
+
+		let groups = child_element(*step, "optionalFileGroups")
+			.map(|groups| child_elements(groups, "group").collect::<Vec<_>>())
+			.unwrap_or_default();
+
+		for group_choices in step_choices.groups.iter() {
+			let group = groups
+				.iter()
+				.find(|group| eq_name(group.attribute("name"), &group_choices.name))
+				.ok_or(format!("FOMOD group not found: {}", group_choices.name))?;
+
+			let plugins = child_element(*group, "plugins")
+				.map(|plugins| child_elements(plugins, "plugin").collect::<Vec<_>>())
+				.unwrap_or_default();
+
+			for choice in group_choices.choices.iter() {
+				let by_index = choice
+					.idx
+					.and_then(|idx| plugins.get(idx as usize))
+					.filter(|plugin| eq_name(plugin.attribute("name"), &choice.name));
+
+				let plugin = by_index
+					.or_else(|| {
+						plugins
+							.iter()
+							.find(|plugin| eq_name(plugin.attribute("name"), &choice.name))
+					})
+					.ok_or(format!("FOMOD option not found: {}", choice.name))?;
+
+				if let Some(plugin_files) = child_element(*plugin, "files") {
+					files.extend(parse_files(plugin_files));
+				}
+
+				if let Some(condition_flags) = child_element(*plugin, "conditionFlags") {
+					for flag in child_elements(condition_flags, "flag") {
+						flags.insert(
+							flag.attribute("name").unwrap_or("").to_string(),
+							flag.text().unwrap_or("").to_string(),
+						);
+					}
+				}
+			}
+		}
+	}
+
+	// Files depending on the flags set by the selected options
+	if let Some(conditional) = child_element(config, "conditionalFileInstalls") {
+		let patterns = child_element(conditional, "patterns")
+			.map(|patterns| child_elements(patterns, "pattern").collect::<Vec<_>>())
+			.unwrap_or_default();
+
+		for pattern in patterns {
+			let met = match child_element(pattern, "dependencies") {
+				Some(dependencies) => dependencies_met(dependencies, &flags),
+				None => true,
+			};
+
+			if met {
+				if let Some(pattern_files) = child_element(pattern, "files") {
+					files.extend(parse_files(pattern_files));
+				}
+			}
+		}
+	}
+
+	// Lower priority first, higher priority files overwrite them
+	files.sort_by_key(|file| file.priority);
+
+	return Ok(files
+		.into_iter()
+		.map(|file| InstallModFile {
+			source: file.source,
+			destination: file.destination,
+		})
+		.collect());
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MODULE_CONFIG: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<config>
+	<moduleName>Test Mod</moduleName>
+	<requiredInstallFiles>
+		<folder source="Core" destination="" />
+		<folder source="Docs" />
+	</requiredInstallFiles>
+	<installSteps order="Explicit">
+		<installStep name="Options">
+			<optionalFileGroups order="Explicit">
+				<group name="Textures" type="SelectExactlyOne">
+					<plugins order="Explicit">
+						<plugin name="1K">
+							<files><folder source="Textures\1K" destination="textures" /></files>
+						</plugin>
+						<plugin name="2K">
+							<files><folder source="Textures\2K" destination="textures" /></files>
+							<conditionFlags><flag name="hd">On</flag></conditionFlags>
+						</plugin>
+					</plugins>
+				</group>
+			</optionalFileGroups>
+		</installStep>
+	</installSteps>
+	<conditionalFileInstalls>
+		<patterns>
+			<pattern>
+				<dependencies operator="And"><flagDependency flag="hd" value="On" /></dependencies>
+				<files><file source="Patches\HD.esp" priority="1" /></files>
+			</pattern>
+		</patterns>
+	</conditionalFileInstalls>
+</config>"#;
+
+	fn choices(option: &str) -> FomodChoices {
+		return FomodChoices {
+			options: vec![FomodStepChoices {
+				name: String::from("Options"),
+				groups: vec![FomodGroupChoices {
+					name: String::from("Textures"),
+					choices: vec![FomodChoice {
+						name: option.to_string(),
+						idx: None,
+					}],
+				}],
+			}],
+		};
+	}
+
+	fn write_module_config(dir: &tempfile::TempDir, bytes: &[u8]) -> PathBuf {
+		let extracted_path = dir.path().to_path_buf();
+		std::fs::create_dir_all(extracted_path.join("fomod")).unwrap();
+		std::fs::write(module_config_path(&extracted_path), bytes).unwrap();
+		return extracted_path;
+	}
+
+	fn as_pairs(files: Vec<InstallModFile>) -> Vec<(String, String)> {
+		return files
+			.into_iter()
+			.map(|file| (file.source, file.destination))
+			.collect();
+	}
+
+	#[test]
+	fn applies_recorded_choices() {
+		let dir = tempfile::tempdir().unwrap();
+		let extracted_path = write_module_config(&dir, MODULE_CONFIG.as_bytes());
+
+		let files = install_files_from_choices(&extracted_path, &choices("1K")).unwrap();
+		assert_eq!(
+			as_pairs(files),
+			vec![
+				(String::from("core"), String::from("")),
+				(String::from("docs"), String::from("docs")),
+				(String::from("textures/1k"), String::from("textures")),
+			]
+		);
+
+		// The flag set by "2K" enables the conditional install
+		let files = install_files_from_choices(&extracted_path, &choices("2K")).unwrap();
+		assert_eq!(
+			as_pairs(files),
+			vec![
+				(String::from("core"), String::from("")),
+				(String::from("docs"), String::from("docs")),
+				(String::from("textures/2k"), String::from("textures")),
+				(
+					String::from("patches/hd.esp"),
+					String::from("patches/hd.esp")
+				),
+			]
+		);
+	}
+
+	#[test]
+	fn reads_utf16_installers() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut bytes = vec![0xFF, 0xFE];
+		for unit in MODULE_CONFIG.replace("UTF-8", "UTF-16").encode_utf16() {
+			bytes.extend(unit.to_le_bytes());
+		}
+		let extracted_path = write_module_config(&dir, &bytes);
+
+		let files = install_files_from_choices(&extracted_path, &choices("1K")).unwrap();
+		assert_eq!(files.len(), 3);
+	}
+
+	#[test]
+	fn fails_on_unknown_options() {
+		let dir = tempfile::tempdir().unwrap();
+		let extracted_path = write_module_config(&dir, MODULE_CONFIG.as_bytes());
+
+		assert_eq!(
+			install_files_from_choices(&extracted_path, &choices("8K")).err(),
+			Some(String::from("FOMOD option not found: 8K"))
+		);
+	}
+}
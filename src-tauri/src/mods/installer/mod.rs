@@ -0,0 +1,643 @@
+use std::path::PathBuf;
+
+use file_integrity::hash_file;
+
+use crate::controllers::file_controller;
+use crate::instances::instance_mod::{
+	normalize_mod_file_path, InstanceMod, InstanceModVersionSource, ModInfo,
+};
+use crate::instances::modlist::ModlistNexusIds;
+use crate::instances::GameInstance;
+
+pub mod fomod;
+
+// Loaders of the script extenders, found at the top of their archives
+static SCRIPT_EXTENDER_LOADERS: [&str; 8] = [
+	"skse_loader.exe",
+	"skse64_loader.exe",
+	"sksevr_loader.exe",
+	"f4se_loader.exe",
+	"f4sevr_loader.exe",
+	"obse_loader.exe",
+	"nvse_loader.exe",
+	"fose_loader.exe",
+];
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct InstallModFile {
+	pub source: String,
+	pub destination: String,
+}
+
+#[taurpc::ipc_type]
+pub struct InstallMod {
+	pub name: String,
+	pub version: String,
+	pub info: ModInfo,
+	pub files: Vec<InstallModFile>,
+}
+
+// Path where an archive from the downloads folder gets extracted
+pub fn extracted_path(downloads_path: &PathBuf, unpacked_filename: &str) -> PathBuf {
+	return downloads_path
+		.join(PathBuf::from("extracted"))
+		.join(PathBuf::from(unpacked_filename));
+}
+
+// Name of the archive an extracted folder comes from
+pub fn archive_file_name(extracted_path: &PathBuf) -> Option<String> {
+	return extracted_path
+		.file_name()
+		.and_then(|file_name| file_name.to_str())
+		.and_then(|file_name| file_name.strip_suffix("_unpacked"))
+		.map(|file_name| file_name.to_string());
+}
+
+// Extract an archive, flatten its sub-root (if any) and case-fold it
+pub fn extract_download(
+	downloads_path: &PathBuf,
+	archive_path: PathBuf,
+	unpacked_filename: &str,
+) -> Result<PathBuf, String> {
+	let extracted_path_absolute = extracted_path(downloads_path, unpacked_filename);
+
+	// Delete pre-extracted files, if any
+	if extracted_path_absolute.clone().exists() {
+		println!(
+			"Deleting pre-existing extracted folder: {:?}",
+			extracted_path_absolute
+		);
+		file_controller::delete_folder_safe(
+			extracted_path_absolute.clone(),
+			downloads_path.clone(),
+		)
+		.map_err(|e| {
+			format!(
+				"Failed to delete existing extracted folder: {}",
+				e.to_string()
+			)
+		})?;
+	}
+
+	println!("Extracting download archive: {:?}", archive_path);
+
+	// Extract the archive
+	file_controller::extract_archive(archive_path, extracted_path_absolute.clone())?;
+
+	// Check if we have a sub-root
+	let entries = match file_controller::list_entries_absolute_path(extracted_path_absolute.clone())
+	{
+		Ok(entries) => entries,
+		Err(e) => {
+			return Err(format!("Failed to list entries: {}", e.to_string()));
+		}
+	};
+
+	// If we have a sub-root
+	if entries.len() == 1 {
+		// We have a sub-root
+		// Move the sub-root to the root
+		let sub_root = entries[0].clone();
+		if sub_root.is_dir() {
+			println!("Moving sub-root to root: {:?}", sub_root);
+
+			// Move sub-root to main root
+			file_controller::move_folder(sub_root.clone(), extracted_path_absolute.clone())?;
+		}
+	};
+
+	// Case-fold entire extracted path
+	match file_controller::case_fold_folder_recursive(extracted_path_absolute.clone()) {
+		Ok(_) => {}
+		Err(e) => {
+			return Err(format!("Failed to case-fold folder: {}", e.to_string()));
+		}
+	}
+
+	return Ok(extracted_path_absolute);
+}
+
+// Install the given files from an extracted archive as a new mod version,
+// then delete the old versions the retention policy does not keep
+pub fn install_extracted(
+	instance: &mut GameInstance,
+	extracted_path: PathBuf,
+	install_mod: InstallMod,
+) -> Result<InstanceMod, String> {
+	let installed_mod = install_version(instance, extracted_path, install_mod)?;
+
+	// The install itself succeeded, so a failed cleanup is only reported
+	if let Err(e) = instance.apply_versions_retention(installed_mod.name.clone()) {
+		println!("Failed to delete old versions: {}", e);
+	}
+
+	return Ok(instance
+		.get_mod_by_name(installed_mod.name.clone())
+		.map(|instance_mod| instance_mod.clone())
+		.unwrap_or(installed_mod));
+}
+
+// Move the files to install (folders are hardlinked) into a version folder
+// Destinations are matched against the deployment casing
+fn install_version_files(
+	instance: &GameInstance,
+	extracted_path: &PathBuf,
+	files: Vec<InstallModFile>,
+	version_path: &PathBuf,
+) -> Result<(), String> {
+	// Get deployment file structure, so we can check folder/file casing
+	let deployment_file_structure = instance.get_mods_deployment_file_structure()?;
+
+	// Move files
+	for file in files {
+		let file_source = file.source.replace("\\", "/").to_lowercase();
+		// Use the casing of the deployment folder when the path exists there
+		let case_folded_file_destination =
+			file_controller::case_fold_to_structure(&deployment_file_structure, &file.destination);
+
+		let source_file_absolute_path =
+			file_controller::join_paths(extracted_path.clone(), PathBuf::from(file_source));
+		let destination_file_absolute_path = file_controller::join_paths(
+			version_path.clone(),
+			PathBuf::from(case_folded_file_destination),
+		);
+
+		// Move file/folder
+		// Check if we are moving a file
+		if source_file_absolute_path.is_file() {
+			file_controller::move_file(source_file_absolute_path, destination_file_absolute_path)
+				.map_err(|e| format!("Failed to move file: {}", e.to_string()))?;
+		} else {
+			file_controller::copy_recursive(
+				source_file_absolute_path,
+				destination_file_absolute_path,
+				true,
+			)
+			.map_err(|e| format!("Failed to copy with hardlinks folder: {}", e.to_string()))?;
+		}
+	}
+
+	return Ok(());
+}
+
+// Create the version, then install the files into it
+fn install_version(
+	instance: &mut GameInstance,
+	extracted_path: PathBuf,
+	mut install_mod: InstallMod,
+) -> Result<InstanceMod, String> {
+	let archive_file_name = archive_file_name(&extracted_path);
+
+	// Use the NexusMods category of the archive, unless categories were picked
+	if install_mod.info.categories.is_empty() {
+		if let Some(archive_file_name) = &archive_file_name {
+			install_mod.info.categories = instance.get_archive_nexus_categories(archive_file_name);
+		}
+	}
+
+	install_mod.files = script_extender_files(&extracted_path, install_mod.files);
+
+	// Kept to be able to reinstall the version later on
+	let installed_files = install_mod.files.clone();
+	let installed_version = install_mod.version.clone();
+
+	// Create mod
+	let mut mod_instance =
+		instance.create_mod_version(install_mod.name, install_mod.version, install_mod.info)?;
+
+	install_version_files(
+		instance,
+		&extracted_path,
+		install_mod.files,
+		&mod_instance.get_selected_version_absolute_path(),
+	)?;
+
+	// Link the version to the archive it comes from
+	let mut source = None;
+	let mut upstream_version = None;
+	if let Some(archive_file_name) = archive_file_name {
+		let download = instance
+			.downloads
+			.iter()
+			.find(|download| download.file_name == archive_file_name);
+		upstream_version = download
+			.and_then(|download| download.nexus_data.as_ref())
+			.and_then(|nexus_data| nexus_data.version.clone());
+
+		if download.is_some() {
+			instance.set_download_installed(archive_file_name.clone(), true)?;
+		}
+		source = Some(version_source(instance, archive_file_name, installed_files));
+	}
+
+	let instance_mod = match instance.get_mod_by_name(mod_instance.name.clone()) {
+		Some(instance_mod) => instance_mod,
+		None => &mut mod_instance,
+	};
+	instance_mod.refresh_version_stats(installed_version.clone())?;
+	if let Some(version) = instance_mod.get_version_mut(&installed_version) {
+		version.source = source;
+		version.upstream_version = upstream_version;
+	}
+	instance_mod.save()?;
+	mod_instance = instance_mod.clone();
+
+	// Files of an existing version may have changed
+	instance.refresh_file_index(vec![mod_instance.name.clone()])?;
+
+	return Ok(mod_instance);
+}
+
+fn version_source(
+	instance: &GameInstance,
+	archive_file_name: String,
+	files: Vec<InstallModFile>,
+) -> InstanceModVersionSource {
+	let download = instance
+		.downloads
+		.iter()
+		.find(|download| download.file_name == archive_file_name);
+
+	// Hash archives that were not downloaded by us
+	let archive_path = instance
+		.get_downloads_absolute_path()
+		.join(&archive_file_name);
+	let archive_md5 = match download.and_then(|download| download.md5.clone()) {
+		Some(md5) => Some(md5.to_lowercase()),
+		None if archive_path.is_file() => Some(
+			hash_file(archive_path.to_string_lossy().to_string())
+				.md5_hash
+				.to_lowercase(),
+		),
+		None => None,
+	};
+
+	let nexus = download
+		.and_then(|download| download.nexus_data.as_ref())
+		.map(|nexus_data| ModlistNexusIds {
+			game_domain: nexus_data.game_domain.clone(),
+			mod_id: nexus_data.mod_id.clone(),
+			file_id: nexus_data.file_id.clone(),
+		});
+
+	return InstanceModVersionSource {
+		archive_file_name,
+		archive_md5,
+		nexus,
+		files,
+	};
+}
+
+// Extract the archive of a version again and re-run its install with the same files.
+// The files are installed apart, the version is only replaced once they all are.
+pub fn reinstall_version(
+	instance: &mut GameInstance,
+	mod_name: String,
+	version: String,
+) -> Result<InstanceMod, String> {
+	let instance_mod = instance
+		.get_mod_by_name(mod_name.clone())
+		.ok_or(format!("Mod {} not found", mod_name))?;
+
+	let source = instance_mod
+		.get_version(&version)
+		.ok_or(format!("Version {} not found", version))?
+		.source
+		.clone()
+		.ok_or(format!(
+			"No source archive is known for version {} of {}",
+			version, mod_name
+		))?;
+	let staged_path = instance_mod.get_staged_version_path(&version);
+	let staging_path = staged_path.parent().unwrap().to_path_buf();
+
+	let download = instance
+		.find_version_download(mod_name.clone(), version.clone())
+		.ok_or(format!(
+			"Archive {} is not in the downloads anymore",
+			source.archive_file_name
+		))?;
+
+	let downloads_path = instance.get_downloads_absolute_path();
+	let extracted_path = extract_download(
+		&downloads_path,
+		downloads_path.join(&download.file_name),
+		&format!("{}_unpacked", download.file_name),
+	)?;
+
+	// Left over by an interrupted reinstall
+	if staged_path.exists() {
+		file_controller::delete_folder_safe(staged_path.clone(), staging_path.clone())
+			.map_err(|e| format!("Failed to clear staged version: {}", e))?;
+	}
+
+	let files = script_extender_files(&extracted_path, source.files);
+	let install_result = file_controller::create_folder(&staged_path)
+		.map_err(|e| format!("Failed to create staged version: {}", e))
+		.and_then(|_| install_version_files(instance, &extracted_path, files, &staged_path))
+		.and_then(|_| {
+			instance
+				.get_mod_by_name(mod_name.clone())
+				.ok_or(format!("Mod {} not found", mod_name))?
+				.replace_version_folder(&version)
+		});
+
+	// Delete extracted files, and the staged ones if the install failed
+	let _ = file_controller::delete_folder_safe(extracted_path, downloads_path);
+	if install_result.is_err() && staged_path.exists() {
+		let _ = file_controller::delete_folder_safe(staged_path, staging_path);
+	}
+	install_result?;
+
+	// The version itself is kept, with what the user set on it
+	let instance_mod = instance
+		.get_mod_by_name(mod_name.clone())
+		.ok_or(format!("Mod {} not found", mod_name))?;
+	instance_mod.refresh_version_stats(version)?;
+	instance_mod.save()?;
+	let reinstalled_mod = instance_mod.clone();
+
+	instance.refresh_file_index(vec![mod_name])?;
+
+	return Ok(reinstalled_mod);
+}
+
+// Files to install when there is no installer (nor choices) for an archive
+// Everything is installed, a top "data" folder (next to docs) is used as the mod root
+pub fn default_install_files(extracted_path: &PathBuf) -> Vec<InstallModFile> {
+	let entries =
+		file_controller::list_entries_absolute_path(extracted_path.clone()).unwrap_or_default();
+
+	let has_data_folder = extracted_path.join("data").is_dir();
+	let folders_count = entries.iter().filter(|entry| entry.is_dir()).count();
+
+	if has_data_folder && folders_count == 1 {
+		return vec![InstallModFile {
+			source: String::from("data"),
+			destination: String::new(),
+		}];
+	}
+
+	return vec![InstallModFile {
+		source: String::new(),
+		destination: String::new(),
+	}];
+}
+
+// Script extender archives ship the loaders next to a "Data" folder.
+// Unless the files were mapped by hand, loaders (and their DLLs) go to the
+// game folder through "root", "Data" to the mod root, and sources/docs are left out.
+pub fn script_extender_files(
+	extracted_path: &PathBuf,
+	files: Vec<InstallModFile>,
+) -> Vec<InstallModFile> {
+	// The defaults, or every file at its own place
+	let normalize_files = |files: &Vec<InstallModFile>| {
+		return files
+			.iter()
+			.map(|file| {
+				(
+					normalize_mod_file_path(&file.source),
+					normalize_mod_file_path(&file.destination),
+				)
+			})
+			.collect::<Vec<(String, String)>>();
+	};
+	let is_unmapped = normalize_files(&files)
+		== normalize_files(&default_install_files(extracted_path))
+		|| files.iter().all(|file| {
+			return normalize_mod_file_path(&file.source)
+				== normalize_mod_file_path(&file.destination);
+		});
+	let entries =
+		file_controller::list_entries_absolute_path(extracted_path.clone()).unwrap_or_default();
+	let file_names: Vec<String> = entries
+		.iter()
+		.filter_map(|entry| entry.file_name())
+		.map(|file_name| file_name.to_string_lossy().to_lowercase())
+		.collect();
+	let is_script_extender = file_names
+		.iter()
+		.any(|file_name| SCRIPT_EXTENDER_LOADERS.contains(&file_name.as_str()));
+
+	if !is_unmapped || !is_script_extender {
+		return files;
+	}
+
+	let mut mapped_files = Vec::new();
+	for (entry, file_name) in entries.iter().zip(file_names) {
+		if entry.is_dir() && file_name == "data" {
+			mapped_files.push(InstallModFile {
+				source: file_name,
+				destination: String::new(),
+			});
+		} else if entry.is_file() && (file_name.ends_with(".exe") || file_name.ends_with(".dll")) {
+			mapped_files.push(InstallModFile {
+				destination: format!("root/{}", file_name),
+				source: file_name,
+			});
+		}
+	}
+
+	return mapped_files;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mods::downloader::Download;
+	use crate::test_utils::{tar_archive, test_instance};
+
+	#[test]
+	fn records_version_source() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "installer");
+
+		std::fs::create_dir_all(instance.get_deployment_mods_absolute_path()).unwrap();
+		let downloads_path = instance.get_downloads_absolute_path();
+		std::fs::create_dir_all(&downloads_path).unwrap();
+		let archive_path = downloads_path.join("mod-a.zip");
+		std::fs::write(&archive_path, "archive").unwrap();
+		instance
+			.downloads
+			.push(Download::from_existing_file(&archive_path));
+
+		let extracted_path = extracted_path(&downloads_path, "mod-a.zip_unpacked");
+		std::fs::create_dir_all(extracted_path.join("data")).unwrap();
+		std::fs::write(extracted_path.join("data/a.esp"), "a").unwrap();
+
+		let files = default_install_files(&extracted_path);
+		install_extracted(
+			&mut instance,
+			extracted_path,
+			InstallMod {
+				name: String::from("mod-a"),
+				version: String::from("1.0"),
+				info: ModInfo::default(),
+				files: files.clone(),
+			},
+		)
+		.unwrap();
+		instance.load_mods().unwrap();
+
+		let instance_mod = instance.get_mod_by_name(String::from("mod-a")).unwrap();
+		let version = instance_mod.get_version("1.0").unwrap();
+		assert_eq!(version.files_count, 1);
+		assert_eq!(version.size, "1");
+		let source = version.source.as_ref().unwrap();
+		assert_eq!(source.archive_file_name, "mod-a.zip");
+		assert_eq!(
+			source.archive_md5,
+			Some(
+				hash_file(archive_path.to_string_lossy().to_string())
+					.md5_hash
+					.to_lowercase()
+			)
+		);
+		assert_eq!(source.files.len(), files.len());
+		assert_eq!(source.files[0].source, "data");
+
+		assert!(instance.downloads[0].installed);
+		let download = instance
+			.find_version_download(String::from("mod-a"), String::from("1.0"))
+			.unwrap();
+		assert_eq!(download.file_name, "mod-a.zip");
+
+		// Deleting the version forgets its source
+		instance
+			.delete_mod_version(String::from("mod-a"), Some(String::from("1.0")))
+			.unwrap();
+		assert!(instance
+			.find_version_download(String::from("mod-a"), String::from("1.0"))
+			.is_none());
+	}
+
+	#[test]
+	fn splits_script_extender_archives() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "installer");
+		std::fs::create_dir_all(instance.get_deployment_mods_absolute_path()).unwrap();
+
+		let extracted_path = dir.path().join("skse64_2_02_06");
+		std::fs::create_dir_all(extracted_path.join("data/scripts")).unwrap();
+		std::fs::create_dir_all(extracted_path.join("src")).unwrap();
+		for file in [
+			"skse64_loader.exe",
+			"skse64_1_6_1170.dll",
+			"skse64_readme.txt",
+			"data/scripts/game.pex",
+			"src/main.cpp",
+		] {
+			std::fs::write(extracted_path.join(file), file).unwrap();
+		}
+
+		let installed_mod = install_extracted(
+			&mut instance,
+			extracted_path.clone(),
+			InstallMod {
+				name: String::from("SKSE"),
+				version: String::from("2.2.6"),
+				info: ModInfo::default(),
+				files: default_install_files(&extracted_path),
+			},
+		)
+		.unwrap();
+
+		let version_path = installed_mod.get_selected_version_absolute_path();
+		assert!(version_path.join("root/skse64_loader.exe").is_file());
+		assert!(version_path.join("root/skse64_1_6_1170.dll").is_file());
+		assert!(version_path.join("scripts/game.pex").is_file());
+		assert!(!version_path.join("src").exists());
+		assert!(!version_path.join("skse64_readme.txt").exists());
+
+		// Without sources, "data" is the only folder and the defaults map it to the mod root
+		let extracted_path = dir.path().join("skse64_2_02_06_nosrc");
+		std::fs::create_dir_all(extracted_path.join("data/scripts")).unwrap();
+		std::fs::write(extracted_path.join("skse64_loader.exe"), "").unwrap();
+		let files = script_extender_files(&extracted_path, default_install_files(&extracted_path));
+		assert!(files
+			.iter()
+			.any(|file| file.destination == "root/skse64_loader.exe"));
+		assert!(files
+			.iter()
+			.any(|file| file.source == "data" && file.destination.is_empty()));
+
+		// Files mapped by hand are kept as is
+		let files = vec![InstallModFile {
+			source: String::from("data"),
+			destination: String::from("skse"),
+		}];
+		assert_eq!(
+			script_extender_files(&extracted_path, files)[0].destination,
+			"skse"
+		);
+	}
+
+	#[test]
+	fn reinstalls_versions_apart() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "installer");
+		std::fs::create_dir_all(instance.get_deployment_mods_absolute_path()).unwrap();
+
+		let downloads_path = instance.get_downloads_absolute_path();
+		std::fs::create_dir_all(&downloads_path).unwrap();
+		let archive_path = downloads_path.join("mod-a.tar");
+		std::fs::write(
+			&archive_path,
+			tar_archive(vec![("data/a.esp", "a"), ("data/b.esp", "b")]),
+		)
+		.unwrap();
+		instance
+			.downloads
+			.push(Download::from_existing_file(&archive_path));
+
+		let extracted_path =
+			extract_download(&downloads_path, archive_path, "mod-a.tar_unpacked").unwrap();
+		let files = default_install_files(&extracted_path);
+		install_extracted(
+			&mut instance,
+			extracted_path,
+			InstallMod {
+				name: String::from("mod-a"),
+				version: String::from("1.0"),
+				info: ModInfo::default(),
+				files,
+			},
+		)
+		.unwrap();
+		instance.load_mods().unwrap();
+
+		let instance_mod = instance.get_mod_by_name(String::from("mod-a")).unwrap();
+		instance_mod.get_version_mut("1.0").unwrap().notes = Some(String::from("kept"));
+		let version_path = instance_mod.get_version_absolute_path(String::from("1.0"));
+		let staged_path = instance_mod.get_staged_version_path("1.0");
+		std::fs::write(version_path.join("a.esp"), "changed").unwrap();
+		std::fs::remove_file(version_path.join("b.esp")).unwrap();
+
+		let reinstalled_mod =
+			reinstall_version(&mut instance, String::from("mod-a"), String::from("1.0")).unwrap();
+		assert_eq!(
+			std::fs::read_to_string(version_path.join("a.esp")).unwrap(),
+			"a"
+		);
+		assert!(version_path.join("b.esp").is_file());
+		let version = reinstalled_mod.get_version("1.0").unwrap();
+		assert_eq!(version.notes, Some(String::from("kept")));
+		assert_eq!(version.files_count, 2);
+		assert!(!staged_path.exists());
+
+		// A failed reinstall leaves the version as it was
+		std::fs::write(version_path.join("a.esp"), "changed").unwrap();
+		std::fs::remove_dir_all(instance.get_deployment_mods_absolute_path()).unwrap();
+		assert!(
+			reinstall_version(&mut instance, String::from("mod-a"), String::from("1.0")).is_err()
+		);
+		assert_eq!(
+			std::fs::read_to_string(version_path.join("a.esp")).unwrap(),
+			"changed"
+		);
+		assert!(version_path.join("b.esp").is_file());
+		assert!(!staged_path.exists());
+	}
+}
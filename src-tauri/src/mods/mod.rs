@@ -1,2 +1,5 @@
 pub mod downloader;
+pub mod installer;
 pub mod ipc;
+pub mod nexus;
+pub mod store;
@@ -0,0 +1,1120 @@
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+use crate::controllers::file_controller;
+use crate::instances::conflicts::FileConflictRule;
+use crate::instances::instance_mod::ModInfo;
+use crate::instances::GameInstance;
+use crate::mods::downloader::{self, Download, DownloadNexusData, DownloadStatus};
+use crate::mods::installer::{self, fomod, InstallMod};
+use crate::state::config::nexusmods_config::{NMSchemeParameters, NexusModsConfig};
+use crate::MutexState;
+
+// ------------------------------
+// collection.json
+// ------------------------------
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionManifest {
+	pub info: CollectionManifestInfo,
+	#[serde(default)]
+	pub mods: Vec<CollectionManifestMod>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionManifestInfo {
+	pub name: String,
+	#[serde(default)]
+	pub author: Option<String>,
+	#[serde(default)]
+	pub description: Option<String>,
+	#[serde(default)]
+	pub install_instructions: Option<String>,
+	pub domain_name: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionManifestMod {
+	pub name: String,
+	#[serde(default)]
+	pub version: Option<String>,
+	#[serde(default)]
+	pub optional: bool,
+	#[serde(default)]
+	pub domain_name: Option<String>,
+	#[serde(default)]
+	pub author: Option<String>,
+	pub source: CollectionManifestSource,
+	#[serde(default)]
+	pub hashes: Vec<CollectionFileHash>,
+	#[serde(default)]
+	pub choices: Option<fomod::FomodChoices>,
+	#[serde(default)]
+	pub file_overrides: Vec<String>,
+	#[serde(default)]
+	pub instructions: Option<String>,
+	#[serde(default)]
+	pub phase: u32,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionManifestSource {
+	#[serde(rename = "type")]
+	pub source_type: String,
+	#[serde(default)]
+	pub mod_id: Option<u64>,
+	#[serde(default)]
+	pub file_id: Option<u64>,
+	#[serde(default)]
+	pub md5: Option<String>,
+	#[serde(default)]
+	pub file_size: Option<u64>,
+	#[serde(default)]
+	pub logical_filename: Option<String>,
+	#[serde(default)]
+	pub url: Option<String>,
+}
+
+impl CollectionManifest {
+	pub fn parse(json: &str) -> Result<Self, String> {
+		return serde_json::from_str(json)
+			.map_err(|e| format!("Failed to parse collection manifest: {}", e));
+	}
+
+	pub fn load(path: &PathBuf) -> Result<Self, String> {
+		let json = file_controller::read_file(path.clone())
+			.map_err(|e| format!("Failed to read collection manifest: {}", e))?;
+
+		return Self::parse(&json);
+	}
+}
+
+// ------------------------------
+// Instance collections
+// ------------------------------
+
+#[derive(Type, Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum CollectionModStatus {
+	// Waiting for its archive (queued, or to be downloaded manually)
+	Pending,
+	Queued,
+	Installed,
+	Failed,
+	// Optional mods not picked by the user
+	Skipped,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct CollectionFileHash {
+	pub path: String,
+	pub md5: String,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct CollectionModSource {
+	pub source_type: String,
+	pub game_domain: String,
+	pub mod_id: Option<String>,
+	pub file_id: Option<String>,
+	pub md5: Option<String>,
+	pub url: Option<String>,
+	pub logical_filename: Option<String>,
+}
+
+// Everything needed to install one mod of a collection
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct CollectionModInstall {
+	pub name: String,
+	pub version: Option<String>,
+	pub author: Option<String>,
+	pub optional: bool,
+	pub source: CollectionModSource,
+	// Install instructions
+	pub choices: Option<fomod::FomodChoices>,
+	pub hashes: Vec<CollectionFileHash>,
+	pub file_overrides: Vec<String>,
+	pub instructions: Option<String>,
+	// Progress
+	pub download_file_name: Option<String>,
+	pub status: CollectionModStatus,
+	pub error: Option<String>,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug)]
+pub struct InstanceCollection {
+	pub name: String,
+	pub author: Option<String>,
+	pub game_domain: String,
+	pub install_instructions: Option<String>,
+	// In install order
+	pub mods: Vec<CollectionModInstall>,
+}
+
+impl InstanceCollection {
+	pub fn from_manifest(manifest: CollectionManifest) -> Self {
+		let game_domain = manifest.info.domain_name.clone();
+
+		// Install order is the phase, then the order of the manifest
+		let mut manifest_mods = manifest.mods;
+		manifest_mods.sort_by_key(|manifest_mod| manifest_mod.phase);
+
+		let mods = manifest_mods
+			.into_iter()
+			.map(|manifest_mod| CollectionModInstall {
+				name: manifest_mod.name,
+				version: manifest_mod.version.filter(|v| !v.is_empty()),
+				author: manifest_mod.author,
+				optional: manifest_mod.optional,
+				source: CollectionModSource {
+					source_type: manifest_mod.source.source_type,
+					game_domain: manifest_mod.domain_name.unwrap_or(game_domain.clone()),
+					mod_id: manifest_mod.source.mod_id.map(|id| id.to_string()),
+					file_id: manifest_mod.source.file_id.map(|id| id.to_string()),
+					md5: manifest_mod.source.md5.map(|md5| md5.to_lowercase()),
+					url: manifest_mod.source.url,
+					logical_filename: manifest_mod.source.logical_filename,
+				},
+				choices: manifest_mod.choices,
+				hashes: manifest_mod.hashes,
+				file_overrides: manifest_mod.file_overrides,
+				instructions: manifest_mod.instructions,
+				download_file_name: None,
+				status: match manifest_mod.optional {
+					true => CollectionModStatus::Skipped,
+					false => CollectionModStatus::Pending,
+				},
+				error: None,
+			})
+			.collect();
+
+		return Self {
+			name: manifest.info.name,
+			author: manifest.info.author,
+			game_domain,
+			install_instructions: manifest.info.install_instructions,
+			mods,
+		};
+	}
+
+	// Next mod whose archive is ready, installs follow the collection order:
+	// a mod waiting for its archive blocks the ones after it
+	pub fn next_install(&mut self, downloads: &Vec<Download>) -> Option<usize> {
+		for (mod_index, collection_mod) in self.mods.iter_mut().enumerate() {
+			match collection_mod.status {
+				CollectionModStatus::Pending | CollectionModStatus::Queued => {}
+				_ => continue,
+			}
+
+			let download = downloads
+				.iter()
+				.find(|download| collection_mod.matches_download(download));
+
+			match download {
+				Some(download) if download.status == DownloadStatus::Downloaded => {
+					collection_mod.download_file_name = Some(download.file_name.clone());
+					return Some(mod_index);
+				}
+				Some(download) if download.status == DownloadStatus::Failed => {
+					collection_mod.status = CollectionModStatus::Failed;
+					collection_mod.error = Some(format!(
+						"Download failed: {}",
+						download.error.clone().unwrap_or_default()
+					));
+				}
+				_ => return None,
+			}
+		}
+
+		return None;
+	}
+
+	pub fn has_pending_installs(&self) -> bool {
+		return self.mods.iter().any(|collection_mod| {
+			collection_mod.status == CollectionModStatus::Pending
+				|| collection_mod.status == CollectionModStatus::Queued
+		});
+	}
+
+	// Opt in the listed optional mods, the other optional mods not installed yet are skipped
+	pub fn select_optional_mods(&mut self, mod_names: &Vec<String>) -> Result<(), String> {
+		for mod_name in mod_names.iter() {
+			match self
+				.mods
+				.iter()
+				.find(|collection_mod| &collection_mod.name == mod_name)
+			{
+				Some(collection_mod) if collection_mod.optional => {}
+				Some(_) => return Err(format!("Mod \"{}\" is not optional", mod_name)),
+				None => return Err(format!("Mod \"{}\" is not in the collection", mod_name)),
+			}
+		}
+
+		for collection_mod in self.mods.iter_mut() {
+			if !collection_mod.optional {
+				continue;
+			}
+
+			let selected = mod_names.contains(&collection_mod.name);
+			match collection_mod.status {
+				CollectionModStatus::Skipped if selected => {
+					collection_mod.status = CollectionModStatus::Pending;
+				}
+				CollectionModStatus::Pending | CollectionModStatus::Queued if !selected => {
+					collection_mod.status = CollectionModStatus::Skipped;
+					collection_mod.error = None;
+				}
+				_ => {}
+			}
+		}
+
+		return Ok(());
+	}
+
+	// Keep what got queued on a copy of this collection, while the state was unlocked
+	pub fn merge_queued_mods(&mut self, queued: &InstanceCollection) {
+		for collection_mod in self.mods.iter_mut() {
+			if collection_mod.status != CollectionModStatus::Pending {
+				continue;
+			}
+
+			if let Some(queued_mod) = queued
+				.mods
+				.iter()
+				.find(|queued_mod| queued_mod.name == collection_mod.name)
+			{
+				collection_mod.status = queued_mod.status.clone();
+				collection_mod.download_file_name = queued_mod.download_file_name.clone();
+				collection_mod.error = queued_mod.error.clone();
+			}
+		}
+	}
+}
+
+impl CollectionModInstall {
+	// Whether the download is the archive this mod needs
+	// Archives downloaded by hand (nxm links) are matched by their ids or hash
+	pub fn matches_download(&self, download: &Download) -> bool {
+		if self.download_file_name.as_ref() == Some(&download.file_name) {
+			return true;
+		}
+
+		if let (Some(nexus_data), Some(mod_id), Some(file_id)) = (
+			&download.nexus_data,
+			&self.source.mod_id,
+			&self.source.file_id,
+		) {
+			if nexus_data.mod_id == *mod_id && nexus_data.file_id == *file_id {
+				return true;
+			}
+		}
+
+		return match (&download.md5, &self.source.md5) {
+			(Some(download_md5), Some(md5)) => download_md5.eq_ignore_ascii_case(md5),
+			_ => false,
+		};
+	}
+
+	fn file_name_from_url(url: &str) -> Option<String> {
+		let parsed_url = url::Url::parse(url).ok()?;
+		let file_name = parsed_url.path_segments()?.last()?.to_string();
+		let file_name = urlencoding::decode(&file_name).ok()?.to_string();
+
+		return match file_name.is_empty() {
+			true => None,
+			false => Some(file_name),
+		};
+	}
+
+	fn new_download(&self, url: String, file_name: String) -> Download {
+		return Download {
+			file_name,
+			status: DownloadStatus::Queued,
+			size_total: String::from("0"),
+			size_downloaded: String::from("0"),
+			url,
+			mirrors: Vec::new(),
+			md5: self.source.md5.clone(),
+			error: None,
+			downloader: None,
+			pending_update: false,
+			added_at: downloader::default_date(),
+			completed_at: None,
+			nexus_data: None,
+			installed: false,
+		};
+	}
+
+	// Build the download for this mod, if it can be fetched without the user
+	pub async fn create_download(&self, nexus: &NexusModsConfig) -> Result<Download, String> {
+		match self.source.source_type.as_str() {
+			"nexus" => {
+				let (mod_id, file_id) = match (&self.source.mod_id, &self.source.file_id) {
+					(Some(mod_id), Some(file_id)) => (mod_id.clone(), file_id.clone()),
+					_ => return Err("Missing NexusMods mod or file id".to_string()),
+				};
+
+				let nexus_download = nexus
+					.convert_nmm_request_to_url(NMSchemeParameters {
+						game_domain: self.source.game_domain.clone(),
+						mod_id: mod_id.clone(),
+						file_id: file_id.clone(),
+						key: None,
+						expires: None,
+					})
+					.await
+					.map_err(|e| format!("Download it from NexusMods: {}", e))?;
+
+				let mut download =
+					self.new_download(nexus_download.url.clone(), nexus_download.filename.clone());
+				download.set_nexus_download_url(&nexus_download);
+				download.nexus_data = Some(DownloadNexusData {
+					mod_id,
+					file_id,
+					game_domain: self.source.game_domain.clone(),
+					mod_name: Some(self.name.clone()),
+					version: self.version.clone(),
+					author: self.author.clone(),
+					..Default::default()
+				});
+
+				return Ok(download);
+			}
+			"direct" | "browse" => {
+				let url = match &self.source.url {
+					Some(url) if !url.is_empty() => url.clone(),
+					_ => return Err("Missing download url".to_string()),
+				};
+
+				let file_name = match self.source.logical_filename.clone() {
+					Some(file_name) if !file_name.is_empty() => file_name,
+					_ => Self::file_name_from_url(&url).unwrap_or(format!("{}.archive", self.name)),
+				};
+
+				return Ok(self.new_download(url, file_name));
+			}
+			source_type => {
+				return Err(format!("Download it manually (\"{}\" source)", source_type));
+			}
+		}
+	}
+}
+
+// Queue the downloads of every mod still waiting for its archive
+// Mods that can't be fetched automatically keep waiting, with the reason as error
+pub async fn queue_collection_downloads(
+	nexus: &NexusModsConfig,
+	collection: &mut InstanceCollection,
+	existing_downloads: &Vec<Download>,
+) -> Vec<Download> {
+	let mut downloads: Vec<Download> = Vec::new();
+
+	for collection_mod in collection.mods.iter_mut() {
+		if collection_mod.status != CollectionModStatus::Pending {
+			continue;
+		}
+
+		// Already downloaded (or being downloaded)
+		let existing = existing_downloads
+			.iter()
+			.chain(downloads.iter())
+			.find(|download| collection_mod.matches_download(download));
+		if let Some(existing) = existing {
+			collection_mod.download_file_name = Some(existing.file_name.clone());
+			collection_mod.status = CollectionModStatus::Queued;
+			collection_mod.error = None;
+			continue;
+		}
+
+		match collection_mod.create_download(nexus).await {
+			Ok(download) => {
+				collection_mod.download_file_name = Some(download.file_name.clone());
+				collection_mod.status = CollectionModStatus::Queued;
+				collection_mod.error = None;
+				downloads.push(download);
+			}
+			Err(e) => {
+				println!(
+					"Collection mod \"{}\" can't be queued: {}",
+					collection_mod.name, e
+				);
+				collection_mod.error = Some(e);
+			}
+		}
+	}
+
+	return downloads;
+}
+
+// Next (collection, mod) to install
+pub fn next_collection_install(instance: &mut GameInstance) -> Option<(usize, usize)> {
+	for (collection_index, collection) in instance.collections.iter_mut().enumerate() {
+		if let Some(mod_index) = collection.next_install(&instance.downloads) {
+			return Some((collection_index, mod_index));
+		}
+	}
+
+	return None;
+}
+
+// Compare the installed files against the hashes of the collection
+fn verify_installed_hashes(
+	version_path: &PathBuf,
+	hashes: &Vec<CollectionFileHash>,
+) -> Vec<String> {
+	let mut mismatches = Vec::new();
+
+	for file_hash in hashes.iter() {
+		let relative_path = file_hash.path.replace("\\", "/");
+		let mut file_path =
+			file_controller::join_paths(version_path.clone(), PathBuf::from(relative_path.clone()));
+
+		// Installed files are case-folded
+		if !file_path.exists() {
+			file_path = file_controller::join_paths(
+				version_path.clone(),
+				PathBuf::from(relative_path.to_lowercase()),
+			);
+		}
+
+		if !file_path.is_file() {
+			mismatches.push(format!("{} (missing)", file_hash.path));
+			continue;
+		}
+
+		let hash = file_integrity::hash_file(file_path.to_string_lossy().to_string());
+		if !hash.md5_hash.eq_ignore_ascii_case(&file_hash.md5) {
+			mismatches.push(file_hash.path.clone());
+		}
+	}
+
+	return mismatches;
+}
+
+fn install_files(
+	collection_mod: &CollectionModInstall,
+	extracted_path: &PathBuf,
+) -> Result<Vec<installer::InstallModFile>, String> {
+	if fomod::has_fomod(extracted_path) {
+		return match &collection_mod.choices {
+			Some(choices) => fomod::install_files_from_choices(extracted_path, choices),
+			None => Err("The archive has an installer but no choices were recorded".to_string()),
+		};
+	}
+
+	return Ok(installer::default_install_files(extracted_path));
+}
+
+// Version a collection mod gets installed as
+fn collection_mod_version(collection_mod: &CollectionModInstall, file_name: &str) -> String {
+	return collection_mod.version.clone().unwrap_or(
+		collection_mod
+			.source
+			.file_id
+			.clone()
+			.unwrap_or(file_name.to_string()),
+	);
+}
+
+// Extract the archive of a collection mod and pick its files, no instance needed
+fn extract_collection_mod(
+	downloads_path: &PathBuf,
+	collection_mod: &CollectionModInstall,
+) -> Result<(PathBuf, Vec<installer::InstallModFile>), String> {
+	let file_name = collection_mod
+		.download_file_name
+		.clone()
+		.ok_or("Collection mod has no archive")?;
+
+	let extracted_path = installer::extract_download(
+		downloads_path,
+		downloads_path.join(&file_name),
+		&format!("{}_unpacked", file_name),
+	)?;
+
+	return match install_files(collection_mod, &extracted_path) {
+		Ok(files) => Ok((extracted_path, files)),
+		Err(e) => {
+			let _ = file_controller::delete_folder_safe(extracted_path, downloads_path.clone());
+			Err(e)
+		}
+	};
+}
+
+// Install the extracted archive of a collection mod, returns the installed version folder
+fn install_collection_mod(
+	instance: &mut GameInstance,
+	collection_mod: &CollectionModInstall,
+	extracted_path: &PathBuf,
+	files: Vec<installer::InstallModFile>,
+) -> Result<PathBuf, String> {
+	let file_name = collection_mod
+		.download_file_name
+		.clone()
+		.ok_or("Collection mod has no archive")?;
+
+	let installed_mod = installer::install_extracted(
+		instance,
+		extracted_path.clone(),
+		InstallMod {
+			name: collection_mod.name.clone(),
+			version: collection_mod_version(collection_mod, &file_name),
+			info: ModInfo {
+				author: collection_mod.author.clone(),
+				website: None,
+				description: collection_mod.instructions.clone(),
+				..Default::default()
+			},
+			files,
+		},
+	)?;
+	instance.load_mods()?;
+
+	return Ok(installed_mod.get_selected_version_absolute_path());
+}
+
+// Files the installed mods of a collection must deploy, whatever the mods order.
+// They win over every other mod providing them, unless a rule already exists for the pair.
+fn apply_file_overrides(
+	instance: &mut GameInstance,
+	collection_index: usize,
+) -> Result<(), String> {
+	let overrides: Vec<(String, String)> = instance.collections[collection_index]
+		.mods
+		.iter()
+		.filter(|collection_mod| collection_mod.status == CollectionModStatus::Installed)
+		.flat_map(|collection_mod| {
+			collection_mod
+				.file_overrides
+				.iter()
+				.map(|path| (collection_mod.name.clone(), path.replace("\\", "/")))
+		})
+		.collect();
+	if overrides.len() == 0 {
+		return Ok(());
+	}
+
+	let rules_count = instance.conflict_rules.len();
+	for (mod_name, path) in overrides {
+		for provider in instance.find_file_providers(path.clone())? {
+			if provider.mod_name == mod_name {
+				continue;
+			}
+
+			let rule = FileConflictRule::new(path.clone(), mod_name.clone(), provider.mod_name)?;
+			if !instance
+				.conflict_rules
+				.iter()
+				.any(|existing_rule| existing_rule.overlaps(&rule))
+			{
+				instance.conflict_rules.push(rule);
+			}
+		}
+	}
+
+	if instance.conflict_rules.len() != rules_count {
+		return instance.save();
+	}
+
+	return Ok(());
+}
+
+// (collection, mod) indexes of a collection mod, found again after the state was unlocked
+fn find_collection_mod(
+	instance: &GameInstance,
+	collection_name: &str,
+	mod_name: &str,
+) -> Option<(usize, usize)> {
+	let collection_index = instance
+		.collections
+		.iter()
+		.position(|collection| collection.name == collection_name)?;
+	let mod_index = instance.collections[collection_index]
+		.mods
+		.iter()
+		.position(|collection_mod| collection_mod.name == mod_name)?;
+
+	return Some((collection_index, mod_index));
+}
+
+// What the next install needs, taken while the state is locked
+struct CollectionInstallJob {
+	instance_name: String,
+	collection_name: String,
+	collection_mod: CollectionModInstall,
+	downloads_path: PathBuf,
+}
+
+// Install every collection mod whose archive is ready, one at a time and in order.
+// The state is only locked to pick the next mod and to install its extracted files:
+// extracting and hashing archives would block everything else.
+pub async fn process_collection_installs(state_mutex: MutexState) {
+	let running = state_mutex.lock().await.collection_install_running.clone();
+	if running.swap(true, Ordering::SeqCst) {
+		return;
+	}
+
+	loop {
+		let mut state = state_mutex.lock().await;
+		let instance = match state.selected_instance.as_mut() {
+			Some(instance) => instance,
+			None => break,
+		};
+
+		let (collection_index, mod_index) = match next_collection_install(instance) {
+			Some(next) => next,
+			None => break,
+		};
+		let collection_mod = instance.collections[collection_index].mods[mod_index].clone();
+		let file_name = collection_mod
+			.download_file_name
+			.clone()
+			.unwrap_or_default();
+
+		// Installed by a previous attempt (or by hand)
+		let version = collection_mod_version(&collection_mod, &file_name);
+		let is_installed = instance
+			.get_mod_by_name(collection_mod.name.clone())
+			.is_some_and(|instance_mod| instance_mod.has_version(version));
+		if is_installed {
+			instance.collections[collection_index].mods[mod_index].status =
+				CollectionModStatus::Installed;
+			let _ = instance.save();
+			let _ = state.trigger_on_state_changed();
+			continue;
+		}
+
+		let job = CollectionInstallJob {
+			instance_name: instance.config.name.clone(),
+			collection_name: instance.collections[collection_index].name.clone(),
+			collection_mod,
+			downloads_path: instance.get_downloads_absolute_path(),
+		};
+		drop(state);
+
+		let result = process_collection_install(&state_mutex, &job).await;
+
+		let mut state = state_mutex.lock().await;
+		let file_store = state.application_config.file_store.get_store();
+		let instance = match state.selected_instance.as_mut() {
+			Some(instance) if instance.config.name == job.instance_name => instance,
+			_ => break,
+		};
+		let (collection_index, mod_index) =
+			match find_collection_mod(instance, &job.collection_name, &job.collection_mod.name) {
+				Some(indexes) => indexes,
+				None => continue,
+			};
+
+		if let (Ok(_), Some(file_store)) = (&result, &file_store) {
+			let version = collection_mod_version(&job.collection_mod, &file_name);
+			if let Err(e) = instance.store_mod_version_files(
+				file_store,
+				job.collection_mod.name.clone(),
+				version,
+			) {
+				println!("Failed to add the mod to the store: {}", e);
+			}
+		}
+
+		let collection_mod = &mut instance.collections[collection_index].mods[mod_index];
+		match result {
+			Ok(mismatches) => {
+				println!("Collection mod installed: {}", collection_mod.name);
+				collection_mod.status = CollectionModStatus::Installed;
+				if mismatches.len() > 0 {
+					collection_mod.error = Some(format!(
+						"Files do not match the collection: {}",
+						mismatches.join(", ")
+					));
+				}
+
+				if let Err(e) = apply_file_overrides(instance, collection_index) {
+					println!("Failed to apply the collection file overrides: {}", e);
+				}
+			}
+			Err(e) => {
+				println!(
+					"Failed to install collection mod {}: {}",
+					collection_mod.name, e
+				);
+				collection_mod.status = CollectionModStatus::Failed;
+				collection_mod.error = Some(e);
+			}
+		}
+
+		let _ = instance.save();
+		let _ = state.trigger_on_state_changed();
+	}
+
+	running.store(false, Ordering::SeqCst);
+}
+
+// Extract, install and verify one collection mod, returns the files not matching
+// the collection hashes
+async fn process_collection_install(
+	state_mutex: &MutexState,
+	job: &CollectionInstallJob,
+) -> Result<Vec<String>, String> {
+	let (extracted_path, files) = extract_collection_mod(&job.downloads_path, &job.collection_mod)?;
+
+	let mut state = state_mutex.lock().await;
+	let installed = match state.selected_instance.as_mut() {
+		Some(instance) if instance.config.name == job.instance_name => {
+			install_collection_mod(instance, &job.collection_mod, &extracted_path, files)
+		}
+		_ => Err(String::from("The instance is not selected anymore")),
+	};
+	drop(state);
+
+	// Delete extracted files
+	let _ = file_controller::delete_folder_safe(extracted_path, job.downloads_path.clone());
+
+	return Ok(verify_installed_hashes(
+		&installed?,
+		&job.collection_mod.hashes,
+	));
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mods::nexus::NexusClient;
+	use crate::state::ApplicationState;
+	use crate::test_utils::{add_mod_with_files, tar_archive, test_instance};
+	use std::sync::Arc;
+	use std::time::Duration;
+	use tokio::sync::Mutex;
+	use warp::Filter;
+
+	const MANIFEST: &str = r#"{
+		"info": {
+			"name": "Test Collection",
+			"author": "tester",
+			"installInstructions": "Run the game once first",
+			"domainName": "skyrimspecialedition"
+		},
+		"mods": [
+			{
+				"name": "Patch",
+				"version": "1.0",
+				"optional": false,
+				"phase": 1,
+				"source": { "type": "direct", "url": "http://localhost/files/Patch%20Files.zip" }
+			},
+			{
+				"name": "SkyUI",
+				"version": "5.2SE",
+				"optional": false,
+				"source": { "type": "nexus", "modId": 12604, "fileId": 35407, "md5": "ABCDEF" },
+				"choices": { "type": "fomod", "options": [] },
+				"hashes": [{ "path": "Interface\\skyui.swf", "md5": "abc" }]
+			},
+			{
+				"name": "Extra",
+				"optional": true,
+				"source": { "type": "nexus", "modId": 1, "fileId": 2 }
+			},
+			{
+				"name": "Bundled",
+				"optional": false,
+				"source": { "type": "bundle" }
+			}
+		]
+	}"#;
+
+	// Local stand-in for the NexusMods download link endpoint
+	async fn start_nexus_server() -> String {
+		let route = warp::path!(
+			"v1" / "games"
+				/ "skyrimspecialedition"
+				/ "mods" / "12604"
+				/ "files" / "35407"
+				/ "download_link.json"
+		)
+		.map(|| {
+			return warp::reply::json(&serde_json::json!([
+				{"name": "Nexus CDN", "short_name": "Nexus CDN", "URI": "http://localhost/SkyUI_5_2_SE.7z"}
+			]));
+		});
+
+		let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+		tokio::spawn(server);
+
+		return format!("http://{}", addr);
+	}
+
+	fn test_collection() -> InstanceCollection {
+		return InstanceCollection::from_manifest(CollectionManifest::parse(MANIFEST).unwrap());
+	}
+
+	#[test]
+	fn parses_manifest_in_install_order() {
+		let collection = test_collection();
+
+		assert_eq!(collection.name, "Test Collection");
+		assert_eq!(collection.game_domain, "skyrimspecialedition");
+
+		// Phase 0 first, then the manifest order
+		let names: Vec<&str> = collection.mods.iter().map(|m| m.name.as_str()).collect();
+		assert_eq!(names, vec!["SkyUI", "Extra", "Bundled", "Patch"]);
+
+		let skyui = &collection.mods[0];
+		assert_eq!(skyui.source.mod_id, Some("12604".to_string()));
+		assert_eq!(skyui.source.md5, Some("abcdef".to_string()));
+		assert_eq!(skyui.source.game_domain, "skyrimspecialedition");
+		assert!(skyui.choices.is_some());
+		assert_eq!(skyui.hashes.len(), 1);
+
+		assert_eq!(collection.mods[1].status, CollectionModStatus::Skipped);
+		assert_eq!(collection.mods[2].status, CollectionModStatus::Pending);
+	}
+
+	#[tokio::test]
+	async fn queues_fetchable_downloads() {
+		let mut config = NexusModsConfig::new();
+		config.api_key = Some("test-key".to_string());
+		config.client = NexusClient::with_base_url(start_nexus_server().await);
+
+		let mut collection = test_collection();
+		let downloads = queue_collection_downloads(&config, &mut collection, &Vec::new()).await;
+
+		assert_eq!(downloads.len(), 2);
+		assert_eq!(downloads[0].file_name, "SkyUI_5_2_SE.7z");
+		assert_eq!(downloads[0].md5, Some("abcdef".to_string()));
+		assert_eq!(downloads[0].nexus_data.as_ref().unwrap().file_id, "35407");
+		assert_eq!(downloads[1].file_name, "Patch Files.zip");
+
+		assert_eq!(collection.mods[0].status, CollectionModStatus::Queued);
+		assert_eq!(collection.mods[1].status, CollectionModStatus::Skipped);
+		assert_eq!(collection.mods[3].status, CollectionModStatus::Queued);
+
+		// Can't be fetched, waits for the user
+		let bundled = &collection.mods[2];
+		assert_eq!(bundled.status, CollectionModStatus::Pending);
+		assert!(bundled.error.is_some());
+	}
+
+	#[test]
+	fn installs_follow_collection_order() {
+		let mut collection = test_collection();
+
+		// Archive downloaded by hand, matched by its hash
+		let mut skyui_download = Download::from_existing_file(std::path::Path::new("skyui.7z"));
+		skyui_download.md5 = Some("ABCDEF".to_string());
+
+		let mut patch_download = Download::from_existing_file(std::path::Path::new("patch.zip"));
+		collection.mods[3].download_file_name = Some("patch.zip".to_string());
+
+		let mut downloads = vec![patch_download.clone()];
+
+		// SkyUI comes first, nothing to install yet
+		assert_eq!(collection.next_install(&downloads), None);
+
+		downloads.push(skyui_download);
+		assert_eq!(collection.next_install(&downloads), Some(0));
+		assert_eq!(
+			collection.mods[0].download_file_name,
+			Some("skyui.7z".to_string())
+		);
+		collection.mods[0].status = CollectionModStatus::Installed;
+
+		// "Bundled" has no archive, it blocks the patch
+		assert_eq!(collection.next_install(&downloads), None);
+		collection.mods[2].status = CollectionModStatus::Failed;
+		assert_eq!(collection.next_install(&downloads), Some(3));
+
+		// Failed downloads fail their mod
+		collection.mods[3].status = CollectionModStatus::Queued;
+		patch_download.status = DownloadStatus::Failed;
+		downloads[0] = patch_download;
+		assert_eq!(collection.next_install(&downloads), None);
+		assert_eq!(collection.mods[3].status, CollectionModStatus::Failed);
+	}
+
+	#[test]
+	fn optional_mods_are_picked_by_the_user() {
+		let mut collection = test_collection();
+
+		assert!(collection
+			.select_optional_mods(&vec!["SkyUI".to_string()])
+			.is_err());
+		assert!(collection
+			.select_optional_mods(&vec!["Unknown".to_string()])
+			.is_err());
+
+		collection
+			.select_optional_mods(&vec!["Extra".to_string()])
+			.unwrap();
+		assert_eq!(collection.mods[1].status, CollectionModStatus::Pending);
+
+		// Queued on a copy, while the state was unlocked
+		let mut queued = collection.clone();
+		queued.mods[1].status = CollectionModStatus::Queued;
+		queued.mods[1].download_file_name = Some("extra.7z".to_string());
+		collection.merge_queued_mods(&queued);
+		assert_eq!(collection.mods[1].status, CollectionModStatus::Queued);
+		assert_eq!(
+			collection.mods[1].download_file_name,
+			Some("extra.7z".to_string())
+		);
+
+		collection.select_optional_mods(&Vec::new()).unwrap();
+		assert_eq!(collection.mods[1].status, CollectionModStatus::Skipped);
+		assert_eq!(collection.mods[0].status, CollectionModStatus::Pending);
+	}
+
+	async fn start_archive_server(archive: Vec<u8>) -> String {
+		let get_archive = archive.clone();
+		let get_route = warp::get()
+			.and(warp::path!("files" / "Patch.tar"))
+			.map(move || {
+				return warp::http::Response::builder()
+					.header("Content-Length", get_archive.len())
+					.body(get_archive.clone());
+			});
+		let head_route = warp::head()
+			.and(warp::path!("files" / "Patch.tar"))
+			.map(move || {
+				return warp::http::Response::builder()
+					.header("Content-Length", archive.len())
+					.body(Vec::new());
+			});
+
+		let (addr, server) =
+			warp::serve(get_route.or(head_route)).bind_ephemeral(([127, 0, 0, 1], 0));
+		tokio::spawn(server);
+
+		return format!("http://{}", addr);
+	}
+
+	#[test]
+	fn reports_missing_and_changed_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let version_path = dir.path().join("version");
+		std::fs::create_dir_all(version_path.join("textures")).unwrap();
+		std::fs::write(version_path.join("textures/sky.dds"), "sky").unwrap();
+		std::fs::write(version_path.join("patch.esp"), "changed").unwrap();
+
+		let hash = |path: &str, content: &str| {
+			let file_path = dir.path().join("hashed");
+			std::fs::write(&file_path, content).unwrap();
+			return CollectionFileHash {
+				path: path.to_string(),
+				md5: file_integrity::hash_file(file_path.to_string_lossy().to_string()).md5_hash,
+			};
+		};
+		let mismatches = verify_installed_hashes(
+			&version_path,
+			&vec![
+				hash("Textures\\Sky.dds", "sky"),
+				hash("Patch.esp", "patch"),
+				hash("Missing.esp", "missing"),
+			],
+		);
+		assert_eq!(mismatches, vec!["Patch.esp", "Missing.esp (missing)"]);
+	}
+
+	#[tokio::test]
+	async fn installs_downloaded_collection_mods() {
+		let dir = tempfile::tempdir().unwrap();
+		let url = start_archive_server(tar_archive(vec![
+			("Patch.esp", "patch"),
+			("textures/sky.dds", "sky"),
+		]))
+		.await;
+
+		// Already installed, provides the file the collection overrides
+		let mut instance = test_instance(dir.path(), "collection");
+		std::fs::create_dir_all(instance.get_deployment_mods_absolute_path()).unwrap();
+		add_mod_with_files(&mut instance, "Base", "1.0", vec!["patch.esp"]);
+
+		let hashed_file = dir.path().join("sky.dds");
+		std::fs::write(&hashed_file, "sky").unwrap();
+		let sky_md5 = file_integrity::hash_file(hashed_file.to_string_lossy().to_string()).md5_hash;
+
+		let manifest = serde_json::json!({
+			"info": { "name": "Overrides", "domainName": "skyrimspecialedition" },
+			"mods": [{
+				"name": "Patch",
+				"version": "1.0",
+				"source": { "type": "direct", "url": format!("{}/files/Patch.tar", url) },
+				"hashes": [{ "path": "textures\\sky.dds", "md5": sky_md5 }],
+				"fileOverrides": ["Patch.esp"]
+			}]
+		});
+		let mut collection = InstanceCollection::from_manifest(
+			CollectionManifest::parse(&manifest.to_string()).unwrap(),
+		);
+		let downloads =
+			queue_collection_downloads(&NexusModsConfig::new(), &mut collection, &Vec::new()).await;
+		instance.downloads.extend(downloads);
+		instance.collections.push(collection);
+
+		let state_mutex: MutexState = Arc::new(Mutex::new(ApplicationState::new()));
+		{
+			let mut state = state_mutex.lock().await;
+			state.selected_instance = Some(instance);
+			state
+				.selected_instance_or_fail()
+				.start_downloads(state_mutex.clone())
+				.await
+				.unwrap();
+		}
+
+		tokio::time::timeout(Duration::from_secs(10), async {
+			loop {
+				let status = state_mutex
+					.lock()
+					.await
+					.selected_instance_or_fail()
+					.downloads[0]
+					.status
+					.clone();
+				if status == DownloadStatus::Downloaded {
+					break;
+				}
+				tokio::time::sleep(Duration::from_millis(20)).await;
+			}
+		})
+		.await
+		.expect("The archive was not downloaded");
+
+		process_collection_installs(state_mutex.clone()).await;
+
+		let mut state = state_mutex.lock().await;
+		let instance = state.selected_instance_or_fail();
+		let collection_mod = &instance.collections[0].mods[0];
+		assert_eq!(collection_mod.status, CollectionModStatus::Installed);
+		assert_eq!(collection_mod.error, None);
+
+		let version_path = instance
+			.get_mod_by_name(String::from("Patch"))
+			.unwrap()
+			.get_selected_version_absolute_path();
+		assert_eq!(
+			std::fs::read_to_string(version_path.join("patch.esp")).unwrap(),
+			"patch"
+		);
+
+		// The collection file wins over the mod installed before
+		assert_eq!(
+			instance.conflict_rules,
+			vec![FileConflictRule::new(
+				String::from("Patch.esp"),
+				String::from("Patch"),
+				String::from("Base")
+			)
+			.unwrap()]
+		);
+	}
+}
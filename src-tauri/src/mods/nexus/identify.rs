@@ -0,0 +1,277 @@
+use file_integrity::hash_file;
+use std::path::PathBuf;
+
+use crate::mods::downloader::DownloadNexusData;
+use crate::state::config::nexusmods_config::{NMMd5SearchResult, NexusModsConfig};
+
+#[taurpc::ipc_type]
+pub struct IdentifyDownloadResult {
+	pub file_name: String,
+	pub md5: Option<String>,
+	pub nexus_data: Option<DownloadNexusData>,
+	pub error: Option<String>,
+}
+
+// Hashing big archives takes a while, keep it off the async workers
+pub async fn hash_archive(path: PathBuf) -> Result<String, String> {
+	if !path.is_file() {
+		return Err(format!("Archive not found: {}", path.display()));
+	}
+
+	let path_string = path.to_string_lossy().to_string();
+	let file_hash = tokio::task::spawn_blocking(move || hash_file(path_string))
+		.await
+		.map_err(|e| format!("Failed to hash archive: {}", e))?;
+
+	return Ok(file_hash.md5_hash.to_lowercase());
+}
+
+// Pick the result for our exact file, the same archive can be uploaded to several mods
+fn best_md5_match(results: Vec<NMMd5SearchResult>, md5: &str) -> Option<NMMd5SearchResult> {
+	let exact_match = results.iter().position(|result| {
+		result
+			.file_details
+			.md5
+			.as_ref()
+			.is_some_and(|file_md5| file_md5.eq_ignore_ascii_case(md5))
+	});
+
+	return match exact_match {
+		Some(index) => results.into_iter().nth(index),
+		None => results.into_iter().next(),
+	};
+}
+
+fn to_nexus_data(game_domain: &str, result: NMMd5SearchResult) -> DownloadNexusData {
+	return DownloadNexusData {
+		mod_id: result.mod_info.mod_id.to_string(),
+		file_id: result.file_details.file_id.to_string(),
+		game_domain: game_domain.to_string(),
+		mod_name: result.mod_info.name,
+		file_title: result.file_details.name,
+		version: result.file_details.version.or(result.mod_info.version),
+		author: result.mod_info.author,
+		category_name: result.file_details.category_name,
+		category_id: result.mod_info.category_id,
+		..Default::default()
+	};
+}
+
+// Hash an archive and look it up on NexusMods
+pub async fn identify_archive(
+	nexus: &NexusModsConfig,
+	game_domain: &str,
+	file_name: String,
+	path: PathBuf,
+) -> IdentifyDownloadResult {
+	let mut result = IdentifyDownloadResult {
+		file_name,
+		md5: None,
+		nexus_data: None,
+		error: None,
+	};
+
+	let md5 = match hash_archive(path).await {
+		Ok(md5) => md5,
+		Err(e) => {
+			result.error = Some(e);
+			return result;
+		}
+	};
+	result.md5 = Some(md5.clone());
+
+	match nexus.search_md5(game_domain, &md5).await {
+		Ok(results) => {
+			result.nexus_data =
+				best_md5_match(results, &md5).map(|found| to_nexus_data(game_domain, found));
+		}
+		Err(e) => {
+			result.error = Some(e);
+		}
+	}
+
+	return result;
+}
+
+// Identify several archives, one after the other (requests are queued anyway)
+pub async fn identify_archives(
+	nexus: &NexusModsConfig,
+	game_domain: &str,
+	archives: Vec<(String, PathBuf)>,
+) -> Vec<IdentifyDownloadResult> {
+	let mut results = Vec::new();
+
+	for (file_name, path) in archives {
+		println!("Identifying archive: {}", file_name);
+		results.push(identify_archive(nexus, game_domain, file_name, path).await);
+	}
+
+	return results;
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mods::downloader::{Download, DownloadStatus};
+	use crate::mods::nexus::NexusClient;
+	use crate::test_utils::test_instance;
+	use warp::Filter;
+
+	// md5("hello")
+	const HELLO_MD5: &str = "5d41402abc4b2a76b9719d911017c592";
+
+	// Local stand-in for the NexusMods md5 search endpoint
+	async fn start_nexus_server() -> String {
+		let route = warp::path!("v1" / "games" / String / "mods" / "md5_search" / String).map(
+			|game_domain: String, file: String| {
+				if game_domain != "skyrimspecialedition" || file != format!("{}.json", HELLO_MD5) {
+					return warp::reply::with_status(
+						warp::reply::json(&serde_json::json!({"error": "File hash not found"})),
+						warp::http::StatusCode::NOT_FOUND,
+					);
+				}
+
+				return warp::reply::with_status(
+					warp::reply::json(&serde_json::json!([
+						{
+							"mod": {"mod_id": 1, "name": "Other upload"},
+							"file_details": {"file_id": 10, "md5": "ffffffffffffffffffffffffffffffff"}
+						},
+						{
+							"mod": {"mod_id": 12604, "name": "SkyUI", "author": "schlangster", "version": "5.2SE"},
+							"file_details": {
+								"file_id": 35407,
+								"name": "SkyUI",
+								"version": "5.2SE",
+								"category_name": "MAIN",
+								"file_name": "SkyUI_5_2_SE-12604-5-2SE.7z",
+								"md5": HELLO_MD5
+							}
+						}
+					])),
+					warp::http::StatusCode::OK,
+				);
+			},
+		);
+
+		let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+		tokio::spawn(server);
+
+		return format!("http://{}", addr);
+	}
+
+	fn test_config(base_url: String) -> NexusModsConfig {
+		let mut config = NexusModsConfig::new();
+		config.api_key = Some("test-key".to_string());
+		config.client = NexusClient::with_base_url(base_url);
+		return config;
+	}
+
+	#[tokio::test]
+	async fn identifies_archive_by_md5() {
+		let config = test_config(start_nexus_server().await);
+		let dir = tempfile::tempdir().unwrap();
+		let archive_path = dir.path().join("skyui.7z");
+		std::fs::write(&archive_path, "hello").unwrap();
+
+		let result = identify_archive(
+			&config,
+			"skyrimspecialedition",
+			"skyui.7z".to_string(),
+			archive_path,
+		)
+		.await;
+
+		assert_eq!(result.error, None);
+		assert_eq!(result.md5, Some(HELLO_MD5.to_string()));
+
+		let nexus_data = result.nexus_data.unwrap();
+		assert_eq!(nexus_data.mod_id, "12604");
+		assert_eq!(nexus_data.file_id, "35407");
+		assert_eq!(nexus_data.game_domain, "skyrimspecialedition");
+		assert_eq!(nexus_data.mod_name, Some("SkyUI".to_string()));
+		assert_eq!(nexus_data.author, Some("schlangster".to_string()));
+		assert_eq!(nexus_data.category_name, Some("MAIN".to_string()));
+	}
+
+	#[tokio::test]
+	async fn identifies_archives_in_bulk() {
+		let config = test_config(start_nexus_server().await);
+		let dir = tempfile::tempdir().unwrap();
+		std::fs::write(dir.path().join("known.7z"), "hello").unwrap();
+		std::fs::write(dir.path().join("unknown.zip"), "not on nexus").unwrap();
+
+		let results = identify_archives(
+			&config,
+			"skyrimspecialedition",
+			vec![
+				("known.7z".to_string(), dir.path().join("known.7z")),
+				("unknown.zip".to_string(), dir.path().join("unknown.zip")),
+				("missing.rar".to_string(), dir.path().join("missing.rar")),
+			],
+		)
+		.await;
+
+		assert_eq!(results.len(), 3);
+
+		assert_eq!(results[0].nexus_data.as_ref().unwrap().file_id, "35407");
+
+		// Unknown hashes are not errors
+		assert_eq!(results[1].error, None);
+		assert!(results[1].md5.is_some());
+		assert!(results[1].nexus_data.is_none());
+
+		assert!(results[2].error.is_some());
+		assert!(results[2].nexus_data.is_none());
+	}
+
+	#[test]
+	fn keeps_download_details_when_applying_results() {
+		let dir = tempfile::tempdir().unwrap();
+		let mut instance = test_instance(dir.path(), "identify");
+		let downloads_path = instance.get_downloads_absolute_path();
+		std::fs::create_dir_all(&downloads_path).unwrap();
+		for file_name in ["SkyUI", "SkyUI.part0", "SkyUI_5_2.7z"] {
+			std::fs::write(downloads_path.join(file_name), "hello").unwrap();
+		}
+
+		// A failed download only hides its own files
+		let mut failed = Download::from_existing_file(&downloads_path.join("SkyUI"));
+		failed.status = DownloadStatus::Failed;
+		instance.downloads.push(failed);
+		let archives = instance.list_downloaded_archives().unwrap();
+		assert_eq!(archives.len(), 1);
+		assert_eq!(archives[0].0, "SkyUI_5_2.7z");
+
+		let mut download = Download::from_existing_file(&downloads_path.join("SkyUI_5_2.7z"));
+		download.nexus_data = Some(DownloadNexusData {
+			mod_id: String::from("12604"),
+			file_id: String::from("35407"),
+			key: Some(String::from("nxm-key")),
+			expires: Some(String::from("1700000000")),
+			category_id: Some(3),
+			..Default::default()
+		});
+		instance.downloads.push(download);
+
+		instance.apply_identified_download(&IdentifyDownloadResult {
+			file_name: String::from("SkyUI_5_2.7z"),
+			md5: Some(HELLO_MD5.to_string()),
+			nexus_data: Some(DownloadNexusData {
+				mod_id: String::from("12604"),
+				file_id: String::from("35407"),
+				game_domain: String::from("skyrimspecialedition"),
+				mod_name: Some(String::from("SkyUI")),
+				..Default::default()
+			}),
+			error: None,
+		});
+
+		let nexus_data = instance.downloads[1].nexus_data.clone().unwrap();
+		assert_eq!(nexus_data.key, Some(String::from("nxm-key")));
+		assert_eq!(nexus_data.expires, Some(String::from("1700000000")));
+		assert_eq!(nexus_data.category_id, Some(3));
+		assert_eq!(nexus_data.mod_name, Some(String::from("SkyUI")));
+		assert_eq!(nexus_data.game_domain, "skyrimspecialedition");
+	}
+}
@@ -0,0 +1,444 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, Response, StatusCode};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+
+use crate::state::config::nexusmods_config::{NMSchemeParameters, RateLimit};
+
+pub mod collection;
+pub mod identify;
+pub mod sso;
+
+pub const NEXUS_API_BASE_URL: &str = "https://api.nexusmods.com";
+
+// Once a quota window has this many requests left, we start spacing requests out
+const LOW_QUOTA_THRESHOLD: u32 = 10;
+// Longest we are willing to hold a request in the queue, anything longer is an error
+const MAX_SCHEDULING_WAIT: Duration = Duration::from_secs(60);
+// How many times a request is retried after receiving a 429
+const MAX_RATE_LIMITED_RETRIES: u32 = 3;
+// Used when a 429 response does not include a (valid) Retry-After header
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+struct NexusQueueState {
+	// Earliest moment the next request may be sent (set by Retry-After)
+	retry_after: Option<SystemTime>,
+}
+
+// Shared NexusMods API client
+// Requests are sent one at a time, in the order they were queued,
+// and are delayed whenever the API quota is running low.
+#[derive(Clone, Debug)]
+pub struct NexusClient {
+	http: Client,
+	base_url: String,
+	queue: Arc<Mutex<NexusQueueState>>,
+	rate_limit: Arc<std::sync::Mutex<RateLimit>>,
+	pending_update: Arc<AtomicBool>,
+}
+
+impl Default for NexusClient {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl NexusClient {
+	pub fn new() -> Self {
+		return Self::with_base_url(NEXUS_API_BASE_URL.to_string());
+	}
+
+	pub fn with_base_url(base_url: String) -> Self {
+		return Self {
+			http: Client::new(),
+			base_url: base_url.trim_end_matches('/').to_string(),
+			queue: Arc::new(Mutex::new(NexusQueueState::default())),
+			rate_limit: Arc::new(std::sync::Mutex::new(RateLimit::default())),
+			pending_update: Arc::new(AtomicBool::new(false)),
+		};
+	}
+
+	pub fn base_url(&self) -> String {
+		return self.base_url.clone();
+	}
+
+	pub fn http_client(&self) -> Client {
+		return self.http.clone();
+	}
+
+	pub fn rate_limit(&self) -> RateLimit {
+		return self.rate_limit.lock().unwrap().clone();
+	}
+
+	// Returns true (once) if the rate limit changed since the last call
+	pub fn take_pending_update(&self) -> bool {
+		return self.pending_update.swap(false, Ordering::SeqCst);
+	}
+
+	fn update_rate_limit(&self, headers: &HeaderMap) {
+		match parse_rate_limit_headers(headers) {
+			Some(rate_limit) => {
+				*self.rate_limit.lock().unwrap() = rate_limit;
+				self.pending_update.store(true, Ordering::SeqCst);
+			}
+			None => {}
+		}
+	}
+
+	// Send a GET request to the given API path (Ex. "/v1/users/validate.json")
+	pub async fn get(&self, path: &str, api_key: &str) -> Result<Response, String> {
+		let url = format!("{}{}", self.base_url, path);
+
+		// Holding the queue guard for the whole request makes requests run one by one
+		let mut queue = self.queue.lock().await;
+
+		for _ in 0..=MAX_RATE_LIMITED_RETRIES {
+			// Wait for our turn, if the quota requires it
+			match compute_request_delay(&self.rate_limit(), queue.retry_after, SystemTime::now()) {
+				Some(delay) => {
+					if delay > MAX_SCHEDULING_WAIT {
+						return Err(format!(
+							"NexusMods API rate limit reached, try again in {} seconds",
+							delay.as_secs()
+						));
+					}
+
+					println!("Delaying NexusMods request by {:?}: {}", delay, path);
+					tokio::time::sleep(delay).await;
+				}
+				None => {}
+			}
+
+			queue.retry_after = None;
+
+			let response = self
+				.http
+				.get(&url)
+				.header("apikey", api_key)
+				.send()
+				.await
+				.map_err(|e| format!("Failed to send request: {}", e))?;
+
+			self.update_rate_limit(response.headers());
+
+			if response.status() != StatusCode::TOO_MANY_REQUESTS {
+				return Ok(response);
+			}
+
+			let retry_after = parse_retry_after(response.headers(), SystemTime::now())
+				.unwrap_or(DEFAULT_RETRY_AFTER);
+			println!(
+				"NexusMods API returned 429, retrying after {:?}",
+				retry_after
+			);
+			queue.retry_after = Some(SystemTime::now() + retry_after);
+		}
+
+		return Err("NexusMods API rate limit reached, too many retries".to_string());
+	}
+}
+
+fn parse_header_u32(headers: &HeaderMap, name: &str) -> Option<u32> {
+	return headers
+		.get(name)
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| v.trim().parse::<u32>().ok());
+}
+
+fn parse_header_timestamp(headers: &HeaderMap, name: &str) -> Option<String> {
+	// 2024-05-28 20:38:34 +0000
+	return headers
+		.get(name)
+		.and_then(|v| v.to_str().ok())
+		.and_then(|v| DateTime::parse_from_str(v.trim(), "%Y-%m-%d %H:%M:%S%.3f %z").ok())
+		.map(|v| v.with_timezone(&Utc).timestamp().to_string());
+}
+
+// Extract the "X-RL-*" headers, malformed values are ignored
+pub fn parse_rate_limit_headers(headers: &HeaderMap) -> Option<RateLimit> {
+	let rate_limit = RateLimit {
+		hourly_limit: parse_header_u32(headers, "X-RL-Hourly-Limit"),
+		hourly_remaining: parse_header_u32(headers, "X-RL-Hourly-Remaining"),
+		hourly_reset_timestamp: parse_header_timestamp(headers, "X-RL-Hourly-Reset"),
+		daily_limit: parse_header_u32(headers, "X-RL-Daily-Limit"),
+		daily_remaining: parse_header_u32(headers, "X-RL-Daily-Remaining"),
+		daily_reset_timestamp: parse_header_timestamp(headers, "X-RL-Daily-Reset"),
+	};
+
+	if rate_limit.hourly_remaining.is_none() && rate_limit.daily_remaining.is_none() {
+		return None;
+	}
+
+	return Some(rate_limit);
+}
+
+// NexusMods CDN links carry an "expires" (unix timestamp) query parameter
+pub fn is_download_link_expired(url: &str) -> bool {
+	let parsed_url = match url::Url::parse(url) {
+		Ok(parsed_url) => parsed_url,
+		Err(_) => return false,
+	};
+
+	let expires = parsed_url
+		.query_pairs()
+		.find(|(key, _)| key == "expires")
+		.and_then(|(_, value)| value.parse::<u64>().ok());
+
+	return match expires {
+		Some(expires) => SystemTime::UNIX_EPOCH + Duration::from_secs(expires) <= SystemTime::now(),
+		None => false,
+	};
+}
+
+// The key of an nxm link (non premium users) expires too, only a new nxm link has a valid one
+pub fn is_nxm_key_expired(file_request: &NMSchemeParameters) -> bool {
+	if file_request.key.is_none() {
+		return false;
+	}
+
+	return file_request
+		.expires
+		.as_ref()
+		.and_then(|expires| expires.parse::<u64>().ok())
+		.is_some_and(|expires| {
+			SystemTime::UNIX_EPOCH + Duration::from_secs(expires) <= SystemTime::now()
+		});
+}
+
+// Retry-After can either be a number of seconds or an HTTP date
+fn parse_retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+	let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+	if let Ok(seconds) = value.parse::<u64>() {
+		return Some(Duration::from_secs(seconds));
+	}
+
+	let date = DateTime::parse_from_rfc2822(value).ok()?;
+	let date: SystemTime = date.with_timezone(&Utc).into();
+
+	return Some(date.duration_since(now).unwrap_or(Duration::ZERO));
+}
+
+fn duration_until_timestamp(timestamp: &Option<String>, now: SystemTime) -> Option<Duration> {
+	let timestamp = timestamp.as_ref()?.parse::<u64>().ok()?;
+	let reset_at = SystemTime::UNIX_EPOCH + Duration::from_secs(timestamp);
+
+	return Some(reset_at.duration_since(now).unwrap_or(Duration::ZERO));
+}
+
+// How long the next request has to wait, if at all
+pub fn compute_request_delay(
+	rate_limit: &RateLimit,
+	retry_after: Option<SystemTime>,
+	now: SystemTime,
+) -> Option<Duration> {
+	// A 429 always wins
+	if let Some(retry_after) = retry_after {
+		if let Ok(delay) = retry_after.duration_since(now) {
+			return Some(delay);
+		}
+	}
+
+	// Running out of the daily quota only means NexusMods switches to the hourly one,
+	// so the hourly window is the only one worth throttling on
+	let remaining = rate_limit.hourly_remaining?;
+	let reset_timestamp = &rate_limit.hourly_reset_timestamp;
+
+	if remaining > LOW_QUOTA_THRESHOLD {
+		return None;
+	}
+
+	let until_reset = duration_until_timestamp(reset_timestamp, now)?;
+
+	// Quota is exhausted, wait for the window to reset
+	if remaining == 0 {
+		return Some(until_reset);
+	}
+
+	// Quota is low, spread the remaining requests over what is left of the window
+	let delay = until_reset / (remaining + 1);
+	if delay.is_zero() {
+		return None;
+	}
+
+	return Some(delay);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use reqwest::header::HeaderValue;
+	use std::sync::atomic::AtomicUsize;
+	use warp::Filter;
+
+	fn timestamp_in(now: SystemTime, seconds: u64) -> Option<String> {
+		let at = now + Duration::from_secs(seconds);
+		Some(
+			at.duration_since(SystemTime::UNIX_EPOCH)
+				.unwrap()
+				.as_secs()
+				.to_string(),
+		)
+	}
+
+	#[test]
+	fn malformed_rate_limit_headers_are_ignored() {
+		let mut headers = HeaderMap::new();
+		headers.insert("X-RL-Hourly-Limit", HeaderValue::from_static("100"));
+		headers.insert(
+			"X-RL-Hourly-Remaining",
+			HeaderValue::from_static("not-a-number"),
+		);
+		headers.insert("X-RL-Hourly-Reset", HeaderValue::from_static("yesterday"));
+		headers.insert("X-RL-Daily-Remaining", HeaderValue::from_static("2400"));
+		headers.insert(
+			"X-RL-Daily-Reset",
+			HeaderValue::from_static("2024-05-28 20:38:34 +0000"),
+		);
+
+		let rate_limit = parse_rate_limit_headers(&headers).unwrap();
+		assert_eq!(rate_limit.hourly_limit, Some(100));
+		assert_eq!(rate_limit.hourly_remaining, None);
+		assert_eq!(rate_limit.hourly_reset_timestamp, None);
+		assert_eq!(rate_limit.daily_remaining, Some(2400));
+		assert_eq!(
+			rate_limit.daily_reset_timestamp,
+			Some(String::from("1716928714"))
+		);
+
+		// No remaining headers at all means nothing to update
+		assert!(parse_rate_limit_headers(&HeaderMap::new()).is_none());
+	}
+
+	#[test]
+	fn request_delay_follows_quota() {
+		let now = SystemTime::now();
+
+		// Plenty of quota left
+		let rate_limit = RateLimit {
+			daily_remaining: Some(2000),
+			daily_reset_timestamp: timestamp_in(now, 3600),
+			..Default::default()
+		};
+		assert_eq!(compute_request_delay(&rate_limit, None, now), None);
+
+		// Low daily quota is not throttled on
+		let rate_limit = RateLimit {
+			daily_remaining: Some(3),
+			daily_reset_timestamp: timestamp_in(now, 80000),
+			hourly_remaining: Some(80),
+			hourly_reset_timestamp: timestamp_in(now, 600),
+			..Default::default()
+		};
+		assert_eq!(compute_request_delay(&rate_limit, None, now), None);
+
+		// Daily quota exhausted, hourly is fine
+		let rate_limit = RateLimit {
+			daily_remaining: Some(0),
+			hourly_remaining: Some(80),
+			hourly_reset_timestamp: timestamp_in(now, 600),
+			..Default::default()
+		};
+		assert_eq!(compute_request_delay(&rate_limit, None, now), None);
+
+		// Both exhausted, wait for the hourly reset
+		let rate_limit = RateLimit {
+			daily_remaining: Some(0),
+			hourly_remaining: Some(0),
+			hourly_reset_timestamp: timestamp_in(now, 600),
+			..Default::default()
+		};
+		let delay = compute_request_delay(&rate_limit, None, now).unwrap();
+		assert!(delay > Duration::from_secs(598) && delay <= Duration::from_secs(600));
+
+		// Low quota, requests get spread out
+		let rate_limit = RateLimit {
+			daily_remaining: Some(0),
+			hourly_remaining: Some(9),
+			hourly_reset_timestamp: timestamp_in(now, 100),
+			..Default::default()
+		};
+		let delay = compute_request_delay(&rate_limit, None, now).unwrap();
+		assert!(delay > Duration::from_secs(9) && delay <= Duration::from_secs(10));
+
+		// Retry-After takes precedence
+		let delay = compute_request_delay(
+			&RateLimit::default(),
+			Some(now + Duration::from_secs(30)),
+			now,
+		);
+		assert_eq!(delay, Some(Duration::from_secs(30)));
+	}
+
+	#[test]
+	fn download_link_expiry_is_read_from_url() {
+		assert!(is_download_link_expired(
+			"https://cf-files.nexusmods.com/cdn/1704/file.7z?md5=abc&expires=1686009809&user_id=1"
+		));
+		assert!(!is_download_link_expired(
+			"https://cf-files.nexusmods.com/cdn/1704/file.7z?md5=abc&expires=99999999999"
+		));
+		assert!(!is_download_link_expired("https://example.com/file.7z"));
+	}
+
+	#[test]
+	fn nxm_key_expiry_is_read_from_request() {
+		let mut file_request = NMSchemeParameters {
+			game_domain: String::from("skyrimspecialedition"),
+			mod_id: String::from("1"),
+			file_id: String::from("2"),
+			key: Some(String::from("key")),
+			expires: Some(String::from("1686009809")),
+		};
+		assert!(is_nxm_key_expired(&file_request));
+
+		file_request.expires = Some(String::from("99999999999"));
+		assert!(!is_nxm_key_expired(&file_request));
+
+		// Premium users don't need a key
+		file_request.key = None;
+		file_request.expires = Some(String::from("1686009809"));
+		assert!(!is_nxm_key_expired(&file_request));
+	}
+
+	#[tokio::test]
+	async fn client_retries_after_429() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+		let hits = Arc::new(AtomicUsize::new(0));
+		let hits_clone = hits.clone();
+
+		let route = warp::path!("v1" / "users" / "validate.json")
+			.and(warp::header::<String>("apikey"))
+			.map(move |_apikey: String| {
+				// First request gets rate limited
+				if hits_clone.fetch_add(1, Ordering::SeqCst) == 0 {
+					return warp::http::Response::builder()
+						.status(429)
+						.header("Retry-After", "1")
+						.body(String::new());
+				}
+
+				warp::http::Response::builder()
+					.header("X-RL-Hourly-Remaining", "99")
+					.header("X-RL-Daily-Remaining", "2499")
+					.body(String::from("{}"))
+			});
+
+		let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+		tokio::spawn(server);
+
+		let client = NexusClient::with_base_url(format!("http://{}", addr));
+		let response = client.get("/v1/users/validate.json", "key").await?;
+
+		assert!(response.status().is_success());
+		assert_eq!(hits.load(Ordering::SeqCst), 2);
+		assert_eq!(client.rate_limit().daily_remaining, Some(2499));
+		assert!(client.take_pending_update());
+		assert!(!client.take_pending_update());
+
+		Ok(())
+	}
+}
@@ -0,0 +1,287 @@
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::Message;
+
+pub const NEXUS_SSO_URL: &str = "wss://sso.nexusmods.com";
+pub const NEXUS_SSO_BROWSER_URL: &str = "https://www.nexusmods.com/sso";
+// Application slug, as registered with NexusMods
+pub const NEXUS_SSO_APPLICATION: &str = "rusty-mod-manager";
+// How long we wait for the user to authorize the application
+pub const NEXUS_SSO_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Serialize)]
+struct SSORequest {
+	id: String,
+	token: Option<String>,
+	protocol: u32,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct SSOResponseData {
+	#[serde(default)]
+	connection_token: Option<String>,
+	#[serde(default)]
+	api_key: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SSOResponse {
+	success: bool,
+	#[serde(default)]
+	data: Option<SSOResponseData>,
+	#[serde(default)]
+	error: Option<String>,
+}
+
+pub struct NexusSSO {
+	pub sso_url: String,
+	pub browser_url: String,
+	pub application: String,
+	pub timeout: Duration,
+}
+
+impl Default for NexusSSO {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl NexusSSO {
+	pub fn new() -> Self {
+		return Self {
+			sso_url: NEXUS_SSO_URL.to_string(),
+			browser_url: NEXUS_SSO_BROWSER_URL.to_string(),
+			application: NEXUS_SSO_APPLICATION.to_string(),
+			timeout: NEXUS_SSO_TIMEOUT,
+		};
+	}
+
+	// Url the user has to visit in order to authorize the application
+	pub fn authorization_url(&self, id: &str) -> String {
+		return format!(
+			"{}?id={}&application={}",
+			self.browser_url, id, self.application
+		);
+	}
+
+	// Run the SSO handshake and wait for the API key
+	// 1) Connect to the SSO websocket and send our (random) id
+	// 2) Open the authorization page for that id
+	// 3) Wait until NexusMods sends the API key through the socket
+	pub async fn request_api_key<F>(
+		&self,
+		open_browser: F,
+		cancel: oneshot::Receiver<()>,
+	) -> Result<String, String>
+	where
+		F: FnOnce(String) -> Result<(), String>,
+	{
+		let id = uuid::Uuid::new_v4().to_string();
+
+		let handshake = self.handshake(id, open_browser);
+
+		tokio::select! {
+			result = handshake => result,
+			_ = tokio::time::sleep(self.timeout) => {
+				Err("Timed out waiting for the NexusMods authorization".to_string())
+			}
+			_ = cancel => Err("NexusMods login was cancelled".to_string()),
+		}
+	}
+
+	async fn handshake<F>(&self, id: String, open_browser: F) -> Result<String, String>
+	where
+		F: FnOnce(String) -> Result<(), String>,
+	{
+		let (mut socket, _) = tokio_tungstenite::connect_async(self.sso_url.as_str())
+			.await
+			.map_err(|e| format!("Failed to connect to NexusMods SSO: {}", e))?;
+
+		let request = serde_json::to_string(&SSORequest {
+			id: id.clone(),
+			token: None,
+			protocol: 2,
+		})
+		.map_err(|e| format!("Failed to serialize SSO request: {}", e))?;
+
+		socket
+			.send(Message::Text(request))
+			.await
+			.map_err(|e| format!("Failed to send SSO request: {}", e))?;
+
+		let mut browser_opened = false;
+		let mut open_browser = Some(open_browser);
+
+		while let Some(message) = socket.next().await {
+			let message = message.map_err(|e| format!("NexusMods SSO connection failed: {}", e))?;
+
+			let text = match message {
+				Message::Text(text) => text,
+				Message::Close(_) => break,
+				// Ping/pong frames are answered by tungstenite
+				_ => continue,
+			};
+
+			let response: SSOResponse = serde_json::from_str(&text)
+				.map_err(|e| format!("Failed to parse SSO response: {}", e))?;
+
+			if !response.success {
+				return Err(format!(
+					"NexusMods SSO error: {}",
+					response.error.unwrap_or(String::from("Unknown error"))
+				));
+			}
+
+			let data = response.data.unwrap_or_default();
+
+			// We got the API key, we are done
+			if let Some(api_key) = data.api_key {
+				let _ = socket.close(None).await;
+				return Ok(api_key);
+			}
+
+			// The server acknowledged our id, send the user to the authorization page
+			if data.connection_token.is_some() && !browser_opened {
+				browser_opened = true;
+				(open_browser.take().unwrap())(self.authorization_url(&id))?;
+			}
+		}
+
+		return Err("NexusMods SSO connection was closed before receiving the API key".to_string());
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::{Arc, Mutex};
+	use warp::ws::{Message as WsMessage, WebSocket, Ws};
+	use warp::Filter;
+
+	// Local stand-in for the NexusMods SSO server
+	// Answers the id with a connection token, then sends "replies" one by one
+	async fn start_sso_server(replies: Vec<String>) -> String {
+		let route = warp::ws().map(move |ws: Ws| {
+			let replies = replies.clone();
+			ws.on_upgrade(move |mut socket: WebSocket| async move {
+				// Wait for the SSO request
+				let request = match socket.next().await {
+					Some(Ok(request)) => request,
+					_ => return,
+				};
+				let request: serde_json::Value =
+					serde_json::from_str(request.to_str().unwrap()).unwrap();
+				assert_eq!(request["protocol"], 2);
+				assert!(request["id"].as_str().unwrap().len() > 0);
+
+				let _ = socket
+					.send(WsMessage::text(
+						r#"{"success":true,"data":{"connection_token":"token"},"error":null}"#,
+					))
+					.await;
+
+				for reply in replies {
+					let _ = socket.send(WsMessage::text(reply)).await;
+				}
+
+				// Keep the connection open until the client leaves
+				while let Some(Ok(_)) = socket.next().await {}
+			})
+		});
+
+		let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+		tokio::spawn(server);
+
+		return format!("ws://{}", addr);
+	}
+
+	fn test_sso(sso_url: String, timeout: Duration) -> NexusSSO {
+		return NexusSSO {
+			sso_url,
+			browser_url: String::from("http://localhost/sso"),
+			application: String::from("test-app"),
+			timeout,
+		};
+	}
+
+	#[tokio::test]
+	async fn sso_receives_api_key() {
+		let sso_url = start_sso_server(vec![String::from(
+			r#"{"success":true,"data":{"api_key":"my-api-key"},"error":null}"#,
+		)])
+		.await;
+		let sso = test_sso(sso_url, Duration::from_secs(10));
+
+		let opened_url = Arc::new(Mutex::new(None));
+		let opened_url_clone = opened_url.clone();
+		let (_cancel_tx, cancel_rx) = oneshot::channel();
+
+		let api_key = sso
+			.request_api_key(
+				move |url| {
+					*opened_url_clone.lock().unwrap() = Some(url);
+					Ok(())
+				},
+				cancel_rx,
+			)
+			.await;
+
+		assert_eq!(api_key, Ok(String::from("my-api-key")));
+
+		let opened_url = opened_url.lock().unwrap().clone().unwrap();
+		assert!(opened_url.starts_with("http://localhost/sso?id="));
+		assert!(opened_url.ends_with("&application=test-app"));
+	}
+
+	#[tokio::test]
+	async fn sso_reports_server_errors() {
+		let sso_url = start_sso_server(vec![String::from(
+			r#"{"success":false,"data":null,"error":"Invalid id"}"#,
+		)])
+		.await;
+		let sso = test_sso(sso_url, Duration::from_secs(10));
+		let (_cancel_tx, cancel_rx) = oneshot::channel();
+
+		let result = sso.request_api_key(|_| Ok(()), cancel_rx).await;
+
+		assert_eq!(result, Err(String::from("NexusMods SSO error: Invalid id")));
+	}
+
+	#[tokio::test]
+	async fn sso_times_out() {
+		// The server never sends the API key
+		let sso_url = start_sso_server(Vec::new()).await;
+		let sso = test_sso(sso_url, Duration::from_millis(300));
+		let (_cancel_tx, cancel_rx) = oneshot::channel();
+
+		let result = sso.request_api_key(|_| Ok(()), cancel_rx).await;
+
+		assert_eq!(
+			result,
+			Err(String::from(
+				"Timed out waiting for the NexusMods authorization"
+			))
+		);
+	}
+
+	#[tokio::test]
+	async fn sso_can_be_cancelled() {
+		let sso_url = start_sso_server(Vec::new()).await;
+		let sso = test_sso(sso_url, Duration::from_secs(10));
+		let (cancel_tx, cancel_rx) = oneshot::channel();
+
+		let handle = tokio::spawn(async move { sso.request_api_key(|_| Ok(()), cancel_rx).await });
+
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		cancel_tx.send(()).unwrap();
+
+		let result = tokio::time::timeout(Duration::from_secs(5), handle)
+			.await
+			.unwrap()
+			.unwrap();
+
+		assert_eq!(result, Err(String::from("NexusMods login was cancelled")));
+	}
+}
@@ -0,0 +1,351 @@
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::PathBuf;
+
+use file_integrity::hash_file;
+
+use crate::controllers::file_controller;
+use crate::instances::instance_mod::same_file_content;
+
+// Content-addressable store: every unique file is kept once, as
+// "objects/<first 2 chars of its md5>/<md5>", and hardlinked into mod versions.
+// Objects are read-only, since changing one would change it in every version:
+// stored versions are immutable. Being the same inode, the files of a version
+// become read-only too once stored. A version written to (the writable layer of
+// an output mod) gets its own copies first, see "detach_folder".
+// An object is only linked to files with the same bytes, an MD5 collision is not
+// enough to swap the content of a file.
+pub struct FileStore {
+	pub path: PathBuf,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct StoreFilesReport {
+	pub files_count: u32,
+	// Files that were replaced by a link to an existing object
+	pub deduplicated_count: u32,
+	// Bytes no longer used twice
+	pub saved_size: String,
+}
+
+impl StoreFilesReport {
+	pub fn add(&mut self, other: StoreFilesReport) {
+		let saved_size = self.saved_size.parse::<u64>().unwrap_or(0)
+			+ other.saved_size.parse::<u64>().unwrap_or(0);
+
+		self.files_count += other.files_count;
+		self.deduplicated_count += other.deduplicated_count;
+		self.saved_size = saved_size.to_string();
+	}
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct StoreGarbageReport {
+	pub removed_count: u32,
+	pub freed_size: String,
+}
+
+#[taurpc::ipc_type]
+#[derive(Debug, Default)]
+pub struct StoreIntegrityReport {
+	pub checked_count: u32,
+	// Hashes of the objects whose content does not match
+	pub corrupt_objects: Vec<String>,
+}
+
+fn md5_of(path: &PathBuf) -> String {
+	return hash_file(path.to_string_lossy().to_string())
+		.md5_hash
+		.to_lowercase();
+}
+
+fn list_files(root: &PathBuf) -> Result<Vec<PathBuf>, String> {
+	if !root.is_dir() {
+		return Ok(Vec::new());
+	}
+
+	return file_controller::list_files_recursively_flattened(root.clone())
+		.map(|files| files.into_iter().map(PathBuf::from).collect())
+		.map_err(|e| format!("Failed to list files: {}", e));
+}
+
+// Give the linked files of a folder their own writable copy, returns how many
+pub fn detach_folder(folder: &PathBuf) -> Result<u32, String> {
+	let mut detached_count = 0;
+
+	for file in list_files(folder)? {
+		let metadata = std::fs::symlink_metadata(&file).map_err(|e| e.to_string())?;
+		if !metadata.is_file() || metadata.nlink() < 2 {
+			continue;
+		}
+
+		// Swap the link for the copy, without a moment where it is missing
+		let temporary_path = file.with_file_name(format!(
+			".{}.detach",
+			file.file_name().unwrap_or_default().to_string_lossy()
+		));
+		std::fs::copy(&file, &temporary_path)
+			.map_err(|e| format!("Failed to copy {:?}: {}", file, e))?;
+
+		let mut permissions = metadata.permissions();
+		permissions.set_mode(permissions.mode() | 0o200);
+		std::fs::set_permissions(&temporary_path, permissions)
+			.map_err(|e| format!("Failed to make {:?} writable: {}", file, e))?;
+		std::fs::rename(&temporary_path, &file)
+			.map_err(|e| format!("Failed to replace {:?}: {}", file, e))?;
+
+		detached_count += 1;
+	}
+
+	return Ok(detached_count);
+}
+
+impl FileStore {
+	pub fn new(path: PathBuf) -> Self {
+		return Self { path };
+	}
+
+	fn objects_path(&self) -> PathBuf {
+		return self.path.join("objects");
+	}
+
+	fn object_path(&self, md5: &str) -> PathBuf {
+		return self.objects_path().join(&md5[..2]).join(md5);
+	}
+
+	// Move the files of a folder into the store, leaving hardlinks in their place
+	pub fn store_folder(&self, folder: &PathBuf) -> Result<StoreFilesReport, String> {
+		let mut report = StoreFilesReport::default();
+		let mut saved_size: u64 = 0;
+
+		for file in list_files(folder)? {
+			let metadata = std::fs::symlink_metadata(&file).map_err(|e| e.to_string())?;
+			if !metadata.is_file() {
+				continue;
+			}
+			report.files_count += 1;
+
+			let md5 = md5_of(&file);
+			let object_path = self.object_path(&md5);
+
+			match std::fs::metadata(&object_path) {
+				// Already linked
+				Ok(object) if object.ino() == metadata.ino() && object.dev() == metadata.dev() => {
+					continue;
+				}
+				Ok(_) if !same_file_content(&object_path, &file) => {
+					println!(
+						"Not storing {:?}, its hash collides with another file",
+						file
+					);
+					continue;
+				}
+				Ok(_) => {
+					// Swap the file for a link, without a moment where it is missing
+					let temporary_path = file.with_file_name(format!(
+						".{}.store",
+						file.file_name().unwrap_or_default().to_string_lossy()
+					));
+					std::fs::hard_link(&object_path, &temporary_path)
+						.map_err(|e| format!("Failed to link {:?}: {}", file, e))?;
+					std::fs::rename(&temporary_path, &file)
+						.map_err(|e| format!("Failed to replace {:?}: {}", file, e))?;
+
+					report.deduplicated_count += 1;
+					saved_size += metadata.len();
+				}
+				Err(_) => {
+					file_controller::create_folder(&object_path.parent().unwrap().to_path_buf())
+						.map_err(|e| format!("Failed to create store folder: {}", e))?;
+					std::fs::hard_link(&file, &object_path).map_err(|e| {
+						format!(
+							"Failed to add {:?} to the store (is it on the same filesystem?): {}",
+							file, e
+						)
+					})?;
+
+					let mut permissions = metadata.permissions();
+					permissions.set_mode(permissions.mode() & 0o555);
+					std::fs::set_permissions(&object_path, permissions)
+						.map_err(|e| format!("Failed to protect {:?}: {}", object_path, e))?;
+				}
+			}
+		}
+
+		report.saved_size = saved_size.to_string();
+
+		return Ok(report);
+	}
+
+	// Delete the objects no mod version links to anymore
+	pub fn collect_garbage(&self) -> Result<StoreGarbageReport, String> {
+		let mut report = StoreGarbageReport::default();
+		let mut freed_size: u64 = 0;
+
+		for object_path in list_files(&self.objects_path())? {
+			let metadata = std::fs::metadata(&object_path).map_err(|e| e.to_string())?;
+			if metadata.nlink() > 1 {
+				continue;
+			}
+
+			std::fs::remove_file(&object_path)
+				.map_err(|e| format!("Failed to delete {:?}: {}", object_path, e))?;
+			report.removed_count += 1;
+			freed_size += metadata.len();
+
+			// Drop the prefix folder once empty
+			if let Some(parent) = object_path.parent() {
+				let _ = file_controller::delete_folder_if_empty(parent.to_path_buf());
+			}
+		}
+
+		report.freed_size = freed_size.to_string();
+
+		return Ok(report);
+	}
+
+	// Hash every object again, a mismatch means it was changed on disk
+	pub fn check_integrity(&self) -> Result<StoreIntegrityReport, String> {
+		let mut report = StoreIntegrityReport::default();
+
+		for object_path in list_files(&self.objects_path())? {
+			report.checked_count += 1;
+
+			let name = object_path
+				.file_name()
+				.unwrap_or_default()
+				.to_string_lossy()
+				.to_string();
+			if md5_of(&object_path) != name {
+				report.corrupt_objects.push(name);
+			}
+		}
+
+		report.corrupt_objects.sort();
+
+		return Ok(report);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn deduplicates_and_collects_garbage() {
+		let dir = tempfile::tempdir().unwrap();
+		let store = FileStore::new(dir.path().join("store"));
+
+		let version_a = dir.path().join("mod/versions/1.0");
+		let version_b = dir.path().join("mod/versions/2.0");
+		std::fs::create_dir_all(version_a.join("textures")).unwrap();
+		std::fs::create_dir_all(&version_b).unwrap();
+		std::fs::write(version_a.join("textures/sky.dds"), "sky").unwrap();
+		std::fs::write(version_a.join("mod.esp"), "1.0").unwrap();
+		std::fs::write(version_b.join("sky.dds"), "sky").unwrap();
+
+		let report = store.store_folder(&version_a).unwrap();
+		assert_eq!(report.files_count, 2);
+		assert_eq!(report.deduplicated_count, 0);
+
+		let report = store.store_folder(&version_b).unwrap();
+		assert_eq!(report.deduplicated_count, 1);
+		assert_eq!(report.saved_size, "3");
+
+		// Same file on disk, still readable from both versions
+		let a = std::fs::metadata(version_a.join("textures/sky.dds")).unwrap();
+		let b = std::fs::metadata(version_b.join("sky.dds")).unwrap();
+		assert_eq!(a.ino(), b.ino());
+		assert_eq!(a.nlink(), 3);
+		assert_eq!(
+			std::fs::read_to_string(version_b.join("sky.dds")).unwrap(),
+			"sky"
+		);
+
+		// Storing again changes nothing
+		let report = store.store_folder(&version_b).unwrap();
+		assert_eq!(report.deduplicated_count, 0);
+
+		// Objects still linked are kept
+		std::fs::remove_dir_all(&version_a).unwrap();
+		let report = store.collect_garbage().unwrap();
+		assert_eq!(report.removed_count, 1);
+		assert_eq!(report.freed_size, "3");
+		assert!(version_b.join("sky.dds").exists());
+
+		let report = store.check_integrity().unwrap();
+		assert_eq!(report.checked_count, 1);
+		assert!(report.corrupt_objects.is_empty());
+	}
+
+	#[test]
+	fn detaches_stored_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let store = FileStore::new(dir.path().join("store"));
+
+		let version = dir.path().join("output/versions/1.0");
+		std::fs::create_dir_all(&version).unwrap();
+		std::fs::write(version.join("mod.esp"), "mod").unwrap();
+		store.store_folder(&version).unwrap();
+
+		assert_eq!(detach_folder(&version).unwrap(), 1);
+		let file = version.join("mod.esp");
+		assert_eq!(std::fs::metadata(&file).unwrap().nlink(), 1);
+
+		// Written to without touching the object
+		std::fs::write(&file, "changed").unwrap();
+		assert!(store.check_integrity().unwrap().corrupt_objects.is_empty());
+
+		assert_eq!(detach_folder(&version).unwrap(), 0);
+	}
+
+	#[test]
+	fn detects_corrupt_objects() {
+		let dir = tempfile::tempdir().unwrap();
+		let store = FileStore::new(dir.path().join("store"));
+
+		let version = dir.path().join("mod/versions/1.0");
+		std::fs::create_dir_all(&version).unwrap();
+		std::fs::write(version.join("mod.esp"), "mod").unwrap();
+		store.store_folder(&version).unwrap();
+
+		// Changed through another link, despite being read-only
+		let file = version.join("mod.esp");
+		let object_name = md5_of(&file);
+		let mut permissions = std::fs::metadata(&file).unwrap().permissions();
+		permissions.set_mode(0o644);
+		std::fs::set_permissions(&file, permissions).unwrap();
+		std::fs::write(&file, "changed").unwrap();
+
+		let report = store.check_integrity().unwrap();
+		assert_eq!(report.checked_count, 1);
+		assert_eq!(report.corrupt_objects, vec![object_name]);
+	}
+
+	#[test]
+	fn links_only_identical_files() {
+		let dir = tempfile::tempdir().unwrap();
+		let store = FileStore::new(dir.path().join("store"));
+
+		let version = dir.path().join("mod/versions/1.0");
+		std::fs::create_dir_all(&version).unwrap();
+		std::fs::write(version.join("mod.esp"), "mod").unwrap();
+
+		// Stands in for a different file with the same MD5
+		let object_path = store.object_path(&md5_of(&version.join("mod.esp")));
+		std::fs::create_dir_all(object_path.parent().unwrap()).unwrap();
+		std::fs::write(&object_path, "collision").unwrap();
+
+		let report = store.store_folder(&version).unwrap();
+		assert_eq!(report.deduplicated_count, 0);
+		assert_eq!(
+			std::fs::read_to_string(version.join("mod.esp")).unwrap(),
+			"mod"
+		);
+		assert_eq!(
+			std::fs::metadata(version.join("mod.esp")).unwrap().nlink(),
+			1
+		);
+	}
+}
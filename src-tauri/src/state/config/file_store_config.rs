@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use crate::mods::store::FileStore;
+use crate::state::root_config_path;
+
+// Deduplicating store shared by every instance ("mods::store")
+// It must be on the same filesystem as the mods, so files can be hardlinked
+#[derive(Default, Debug)]
+#[taurpc::ipc_type]
+pub struct FileStoreConfig {
+	#[serde(default)]
+	pub enabled: bool,
+	// Defaults to "store" in the config folder
+	#[serde(default)]
+	pub path: Option<PathBuf>,
+}
+
+impl FileStoreConfig {
+	pub fn store_path(&self) -> PathBuf {
+		return self
+			.path
+			.clone()
+			.unwrap_or_else(|| root_config_path().join("store"));
+	}
+
+	// The store to add installed files to, if enabled
+	pub fn get_store(&self) -> Option<FileStore> {
+		if !self.enabled {
+			return None;
+		}
+
+		return Some(FileStore::new(self.store_path()));
+	}
+}
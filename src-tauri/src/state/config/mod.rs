@@ -3,6 +3,8 @@ use nexusmods_config::NexusModsConfig;
 
 pub mod vfs_config;
 pub mod nexusmods_config;
+pub mod file_store_config;
+pub mod mod_library_config;
 
 fn _default_nexusmods_config() -> NexusModsConfig {
 	NexusModsConfig::new()
@@ -25,6 +27,10 @@ pub struct ApplicationConfig {
 	// #[serde(default= "_default_vfs_config")]
 	#[serde(default)]
 	pub default_vfs_config: vfs_config::VFSConfig,
+	#[serde(default)]
+	pub file_store: file_store_config::FileStoreConfig,
+	#[serde(default)]
+	pub mod_library: mod_library_config::ModLibraryConfig,
 }
 
 impl ApplicationConfig {
@@ -33,6 +39,8 @@ impl ApplicationConfig {
 			available_instances_paths: Vec::new(),
 			nexusmods: NexusModsConfig::new(),
 			default_vfs_config: vfs_config::VFSConfig::new(),
+			file_store: Default::default(),
+			mod_library: Default::default(),
 		};
 	}
 }
@@ -0,0 +1,21 @@
+use std::path::PathBuf;
+
+use crate::state::root_config_path;
+
+// Folder with mods shared by several instances ("instances::library")
+#[derive(Default, Debug)]
+#[taurpc::ipc_type]
+pub struct ModLibraryConfig {
+	// Defaults to "library" in the config folder
+	#[serde(default)]
+	pub path: Option<PathBuf>,
+}
+
+impl ModLibraryConfig {
+	pub fn library_path(&self) -> PathBuf {
+		return self
+			.path
+			.clone()
+			.unwrap_or_else(|| root_config_path().join("library"));
+	}
+}
@@ -4,16 +4,20 @@
 
 use std::fmt::Display;
 
-use chrono::{DateTime, Utc};
 use url::{Url, ParseError};
-use reqwest::header;
 use tauri::http::Uri;
 use urlencoding::decode;
+
+use crate::mods::nexus::NexusClient;
+use crate::state::secrets::{
+	is_redacted, redact_optional_secret, redact_secret, SecretStore, NEXUS_API_KEY_SECRET,
+};
 // use time::{format_description, Time};
 
 #[taurpc::ipc_type]
 pub struct NexusModsValidateResponse {
 	user_id: u32,
+	#[serde(serialize_with = "redact_secret")]
 	key: String,
 	name: String,
 	email: String,
@@ -22,7 +26,7 @@ pub struct NexusModsValidateResponse {
 	is_supporter: bool,
 }
 
-#[derive(Default)]
+#[derive(Default, Debug)]
 #[taurpc::ipc_type]
 pub struct RateLimit {
 	#[serde(default = "Option::default")]
@@ -42,12 +46,19 @@ pub struct RateLimit {
 #[derive(Default)]
 #[taurpc::ipc_type]
 pub struct NexusModsConfig {
-	#[serde(default)]
+	// Kept in the secret store, only a placeholder gets serialized
+	#[serde(default, serialize_with = "redact_optional_secret")]
 	pub api_key: Option<String>,
 	#[serde(default)]
 	pub user_data: Option<NexusModsValidateResponse>,
 	#[serde(default)]
 	pub rate_limit: RateLimit,
+	// CDN "short_name" to download from first, falls back to the rest
+	#[serde(default)]
+	pub preferred_cdn: Option<String>,
+	// Shared API client, requests are queued through it
+	#[serde(skip)]
+	pub client: NexusClient,
 }
 
 #[taurpc::ipc_type]
@@ -61,24 +72,58 @@ pub struct NMSchemeParameters {
 
 #[taurpc::ipc_type]
 pub struct NMCDNOptionsResponse {
-	name: String,
-	short_name: String,
-	URI: String,
+	pub name: String,
+	pub short_name: String,
+	pub URI: String,
 }
 
 #[taurpc::ipc_type]
 pub struct NMDownloadUrl {
 	pub url: String,
+	// Every CDN url, ordered by preference (the first one is "url")
+	pub mirrors: Vec<String>,
 	pub filename: String,
 	pub md5: Option<String>,
 	pub file_request: NMSchemeParameters,
 }
 
-fn parse_date_to_unix_timestamp(timestamp: String) -> Option<String> {
-	match DateTime::parse_from_str(timestamp.as_str(), "%Y-%m-%d %H:%M:%S%.3f %z") {
-		Ok(v) => Some(v.with_timezone(&Utc).timestamp().to_string()),
-		Err(_) => None,
-	}
+#[taurpc::ipc_type]
+pub struct NMMd5SearchMod {
+	pub mod_id: u32,
+	#[serde(default)]
+	pub name: Option<String>,
+	#[serde(default)]
+	pub summary: Option<String>,
+	#[serde(default)]
+	pub version: Option<String>,
+	#[serde(default)]
+	pub author: Option<String>,
+	#[serde(default)]
+	pub picture_url: Option<String>,
+	#[serde(default)]
+	pub category_id: Option<u32>,
+}
+
+#[taurpc::ipc_type]
+pub struct NMMd5SearchFile {
+	pub file_id: u32,
+	#[serde(default)]
+	pub name: Option<String>,
+	#[serde(default)]
+	pub version: Option<String>,
+	#[serde(default)]
+	pub category_name: Option<String>,
+	#[serde(default)]
+	pub file_name: Option<String>,
+	#[serde(default)]
+	pub md5: Option<String>,
+}
+
+#[taurpc::ipc_type]
+pub struct NMMd5SearchResult {
+	#[serde(rename = "mod")]
+	pub mod_info: NMMd5SearchMod,
+	pub file_details: NMMd5SearchFile,
 }
 
 impl NexusModsConfig {
@@ -86,60 +131,50 @@ impl NexusModsConfig {
 		return Self {
 			api_key: None,
 			user_data: None,
-			rate_limit: Default::default()
+			rate_limit: Default::default(),
+			preferred_cdn: None,
+			client: NexusClient::new(),
 		};
 	}
 
-	pub fn get_client(&self) -> Result<reqwest::Client, reqwest::Error>{
-		let mut headers = header::HeaderMap::new();
-
-		// Insert API Key
-		match self.user_data {
-			Some(ref user_data) => {
-				headers.insert("apikey", reqwest::header::HeaderValue::from_str(user_data.key.as_str()).unwrap());
-			},
-			None => {
+	// Load the API key from the secret store
+	// Plaintext keys from older state files are moved into it,
+	// returns true when the state has to be saved again to drop them
+	pub fn load_api_key(&mut self, secrets: &SecretStore) -> Result<bool, String> {
+		let has_plaintext_user_key = self
+			.user_data
+			.as_ref()
+			.is_some_and(|user_data| !user_data.key.is_empty() && !is_redacted(&user_data.key));
+
+		match self.api_key.clone() {
+			Some(api_key) if !api_key.is_empty() && !is_redacted(&api_key) => {
+				secrets.set(NEXUS_API_KEY_SECRET, &api_key)?;
+				return Ok(true);
+			}
+			_ => {
+				self.api_key = secrets.get(NEXUS_API_KEY_SECRET)?;
+				return Ok(has_plaintext_user_key);
 			}
 		}
-
-		return reqwest::Client::builder()
-			.default_headers(headers)
-			.build();
 	}
 
-	fn extract_api_rate_limit (&mut self, headers: &reqwest::header::HeaderMap) -> RateLimit {
-		// Hourly limits
-		let rate_hourly_limit = headers.get("X-RL-Hourly-Limit").map(|v| v.to_str().unwrap().parse::<u32>().unwrap());
-		let rate_hourly_remaining = headers.get("X-RL-Hourly-Remaining").map(|v| v.to_str().unwrap().parse::<u32>().unwrap());
-		let rate_hourly_reset_str = headers.get("X-RL-Hourly-Reset").map(|v| String::from(v.to_str().unwrap()));
-		// Daily limits
-		let rate_daily_limit = headers.get("X-RL-Daily-Limit").map(|v| v.to_str().unwrap().parse::<u32>().unwrap());
-		let rate_daily_remaining = headers.get("X-RL-Daily-Remaining").map(|v| v.to_str().unwrap().parse::<u32>().unwrap());
-		let rate_daily_reset_str = headers.get("X-RL-Daily-Reset").map(|v| String::from(v.to_str().unwrap()));
-
-		// 2024-05-28 20:38:34 +0000
-		// %Y-%m-%d %H:%M:%S%.3f %z
-		let hourly_reset_timestamp = match rate_hourly_reset_str {
-			Some(v) => parse_date_to_unix_timestamp(v),
-			None => None,
-		};
-		let daily_reset_timestamp = match rate_daily_reset_str {
-			Some(v) => parse_date_to_unix_timestamp(v),
-			None => None,
-		};
-
-		let rate_limit = RateLimit {
-			hourly_limit: rate_hourly_limit,
-			hourly_remaining: rate_hourly_remaining,
-			hourly_reset_timestamp,
-			daily_limit: rate_daily_limit,
-			daily_remaining: rate_daily_remaining,
-			daily_reset_timestamp,
-		};
+	// Replace (or remove, with None) the API key and persist it in the secret store
+	pub fn set_api_key(
+		&mut self,
+		api_key: Option<String>,
+		secrets: &SecretStore,
+	) -> Result<(), String> {
+		let api_key = api_key.filter(|api_key| !api_key.trim().is_empty());
+
+		match &api_key {
+			Some(api_key) => secrets.set(NEXUS_API_KEY_SECRET, api_key)?,
+			None => secrets.delete(NEXUS_API_KEY_SECRET)?,
+		}
 
-		self.rate_limit = rate_limit.clone();
+		self.api_key = api_key;
+		self.user_data = None;
 
-		return rate_limit;
+		return Ok(());
 	}
 
 	pub async fn validate_api_key(&mut self) -> Result<NexusModsValidateResponse, String> {
@@ -148,17 +183,12 @@ impl NexusModsConfig {
 			return Err("API key is not set".to_string());
 		}
 
-		let url = format!("https://api.nexusmods.com/v1/users/validate.json");
-		let client = reqwest::Client::new();
-		let response = client
-			.get(&url)
-			.header("apikey", self.api_key.clone().unwrap())
-			.send()
-			.await
-			.map_err(|e| format!("Failed to send request: {}", e))?;
+		let response = self.client
+			.get("/v1/users/validate.json", self.api_key.clone().unwrap().as_str())
+			.await?;
 
-		// Extract rate limit
-		self.extract_api_rate_limit(&response.headers());
+		// Keep the latest rate limit
+		self.rate_limit = self.client.rate_limit();
 
 		self.user_data = None;
 
@@ -172,6 +202,47 @@ impl NexusModsConfig {
 		}
 	}
 
+	// Keep what a validation made on a copy found, as requests are not made under the
+	// state lock. Dropped if the API key changed in the meantime.
+	pub fn apply_validation(&mut self, validated: &NexusModsConfig) {
+		if self.api_key != validated.api_key {
+			return;
+		}
+
+		self.user_data = validated.user_data.clone();
+		self.rate_limit = validated.rate_limit.clone();
+	}
+
+	// Find the mod files matching an archive MD5 hash
+	// An unknown hash is not an error, it just has no results
+	pub async fn search_md5(
+		&self,
+		game_domain: &str,
+		md5: &str,
+	) -> Result<Vec<NMMd5SearchResult>, String> {
+		if !self.api_key.is_some() {
+			return Err("API key is not set".to_string());
+		}
+
+		let path = format!("/v1/games/{}/mods/md5_search/{}.json", game_domain, md5);
+		let response = self.client
+			.get(path.as_str(), self.api_key.clone().unwrap().as_str())
+			.await?;
+
+		if response.status() == reqwest::StatusCode::NOT_FOUND {
+			return Ok(Vec::new());
+		}
+
+		if !response.status().is_success() {
+			return Err(format!("Failed to search MD5 hash: {}", response.status()));
+		}
+
+		return response
+			.json::<Vec<NMMd5SearchResult>>()
+			.await
+			.map_err(|e| format!("Failed to parse response: {}", e));
+	}
+
 	pub async fn parse_nxm_uri(&self, url: String) -> Result<NMDownloadUrl, String> {
 		if !self.api_key.is_some() {
 			return Err("API key is not set".to_string());
@@ -250,8 +321,8 @@ impl NexusModsConfig {
 		}
 
 		let file_request_copy = file_request.clone();
-		let url = format!(
-			"https://api.nexusmods.com/v1/games/{}/mods/{}/files/{}/download_link.json?key={}&expires={}",
+		let path = format!(
+			"/v1/games/{}/mods/{}/files/{}/download_link.json?key={}&expires={}",
 			file_request_copy.game_domain,
 			file_request_copy.mod_id,
 			file_request_copy.file_id,
@@ -259,26 +330,29 @@ impl NexusModsConfig {
 			file_request_copy.expires.unwrap_or("".to_string())
 		);
 
-		let client = reqwest::Client::new();
-		let response = client
-			.get(&url)
-			.header("apikey", self.api_key.clone().unwrap())
-			.send()
-			.await
-			.map_err(|e| format!("Failed to send request: {}", e))?;
+		let response = self.client
+			.get(path.as_str(), self.api_key.clone().unwrap().as_str())
+			.await?;
 
 		if response.status().is_success() {
 			let body = response.text().await.expect("Failed to get response body");
-			let parsed_response: Vec<NMCDNOptionsResponse> = serde_json::from_str(&body.to_string())
+			let mut parsed_response: Vec<NMCDNOptionsResponse> = serde_json::from_str(&body.to_string())
 				.map_err(|e| format!("Failed to parse response: {}", e))?;
 
 			if parsed_response.len() <= 0 {
 				return Err("No download links found".to_string());
 			}
-			
-			// TODO: Allow selecting CDN to download from
 
-			let url = parsed_response[0].URI.clone();
+			// Move the preferred CDN to the front, keeping the order of the rest
+			match self.preferred_cdn {
+				Some(ref preferred_cdn) => {
+					parsed_response.sort_by_key(|cdn| cdn.short_name != *preferred_cdn);
+				},
+				None => {}
+			}
+
+			let mirrors: Vec<String> = parsed_response.iter().map(|cdn| cdn.URI.clone()).collect();
+			let url = mirrors[0].clone();
 
 			// Parse the url
 			let parsed_url = Url::parse(&url).map_err(|e: ParseError| format!("Failed to parse URL: {}", e))?;
@@ -297,6 +371,7 @@ impl NexusModsConfig {
 
 			return Ok(NMDownloadUrl{
 				url,
+				mirrors,
 				filename: decode(filename).expect("Failed to decode filename").to_string(),
 				md5: None,
 				file_request,
@@ -306,3 +381,58 @@ impl NexusModsConfig {
 		return Err(format!("Failed to process NMM link: {}", response.status()));
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::state::secrets::EncryptedFileStore;
+
+	fn test_secrets(dir: &tempfile::TempDir) -> SecretStore {
+		return SecretStore::file_only(EncryptedFileStore::new(
+			dir.path().join("secrets.enc"),
+			dir.path().join("secrets.key"),
+		));
+	}
+
+	#[test]
+	fn migrates_plaintext_api_key() {
+		let dir = tempfile::tempdir().unwrap();
+		let secrets = test_secrets(&dir);
+
+		// State written by older versions
+		let mut config: NexusModsConfig =
+			serde_json::from_str(r#"{"api_key":"plaintext-key"}"#).unwrap();
+
+		assert_eq!(config.load_api_key(&secrets), Ok(true));
+		assert_eq!(config.api_key, Some("plaintext-key".to_string()));
+		assert_eq!(
+			secrets.get(NEXUS_API_KEY_SECRET),
+			Ok(Some("plaintext-key".to_string()))
+		);
+
+		// Saving it again does not leak the key
+		let json = serde_json::to_string(&config).unwrap();
+		assert!(!json.contains("plaintext-key"));
+
+		// And the next load picks it up from the secret store
+		let mut config: NexusModsConfig = serde_json::from_str(&json).unwrap();
+		assert_eq!(config.load_api_key(&secrets), Ok(false));
+		assert_eq!(config.api_key, Some("plaintext-key".to_string()));
+	}
+
+	#[test]
+	fn clears_api_key() {
+		let dir = tempfile::tempdir().unwrap();
+		let secrets = test_secrets(&dir);
+		let mut config = NexusModsConfig::new();
+
+		config
+			.set_api_key(Some("my-key".to_string()), &secrets)
+			.unwrap();
+		assert_eq!(secrets.get(NEXUS_API_KEY_SECRET), Ok(Some("my-key".to_string())));
+
+		config.set_api_key(Some("".to_string()), &secrets).unwrap();
+		assert_eq!(config.api_key, None);
+		assert_eq!(secrets.get(NEXUS_API_KEY_SECRET), Ok(None));
+	}
+}
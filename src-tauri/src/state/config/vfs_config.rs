@@ -15,6 +15,9 @@ pub struct VFSConfig {
 	pub implementation: VFSImplementation,
 	#[serde(default)]
 	pub command: Option<String>,
+	// Mount only for the executables run, in their own mount namespace
+	#[serde(default)]
+	pub isolated: bool,
 }
 
 impl VFSConfig {
@@ -22,6 +25,7 @@ impl VFSConfig {
 		return Self {
 			implementation: Default::default(),
 			command: None,
+			isolated: false,
 		};
 	}
 }
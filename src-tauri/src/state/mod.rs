@@ -6,15 +6,15 @@ use std::{
 	fs,
 	path::{Path, PathBuf},
 	process::{Child, Command},
-	sync::{Arc, Mutex},
+	sync::{atomic::AtomicBool, Arc, Mutex},
 };
 
 use crate::{
 	controllers,
-	deployer::vfs::base_vfs::BaseVFS,
+	deployer::vfs::{base_vfs::BaseVFS, namespace::NamespaceRun},
 	instances::{self, GameInstance, InstanceExecutable},
 	mods::downloader,
-	ApiDownloadsEventTrigger, ApiEventTrigger,
+	ApiDownloadsEventTrigger, ApiEventTrigger, ApiNexusModsEventTrigger,
 };
 
 use self::config::ApplicationConfig;
@@ -23,6 +23,7 @@ const CURRENT_CONFIG_VERSION: &str = "0.0.1";
 const ROOT_CONFIG_FOLDER_NAME: &str = "rmm.neilseligmann.com";
 
 pub mod config;
+pub mod secrets;
 
 pub fn root_config_path() -> PathBuf {
 	let mut path = dirs::config_dir().unwrap();
@@ -67,6 +68,16 @@ pub struct InstanceError {
 pub struct RunningExecutable {
 	pub executable_name: String,
 	pub child_process: Child,
+	// Folders of its mount namespace, when isolated
+	pub namespace_run: Option<NamespaceRun>,
+}
+
+// Content of vfs_state.json, written when the VFS is mounted
+#[derive(Serialize, Deserialize)]
+struct MountedVFSRecord {
+	vfs: Vec<Box<dyn BaseVFS>>,
+	#[serde(default)]
+	output_mod: Option<String>,
 }
 
 // pub type RunningExecutablesMutex = Arc<Mutex<Vec<RunningExecutable>>>;
@@ -92,6 +103,9 @@ pub struct ApplicationState {
 	#[serde(skip)]
 	pub download_event_trigger: Option<ApiDownloadsEventTrigger>,
 
+	#[serde(skip)]
+	pub nexusmods_event_trigger: Option<ApiNexusModsEventTrigger>,
+
 	#[serde(default)]
 	pub instances_errors: Vec<InstanceError>,
 
@@ -102,11 +116,24 @@ pub struct ApplicationState {
 	#[serde(default)]
 	pub is_vfs_mounted: bool,
 
+	// Mod used instead of overwrite by the mounted VFS, see "InstanceExecutable::output_mod"
+	#[serde(default)]
+	pub vfs_output_mod: Option<String>,
+
+	// Whether the VFS was mounted before it got remounted on an output mod,
+	// it is put back that way once their executables exit
+	#[serde(skip)]
+	pub vfs_mounted_before_output: bool,
+
 	#[serde(skip)]
 	pub running_executables: Arc<Mutex<Vec<RunningExecutable>>>,
 
 	#[serde(default)]
 	pub running_executables_id: HashMap<String, Vec<u32>>,
+
+	// Set while collection mods are being installed in the background
+	#[serde(skip)]
+	pub collection_install_running: Arc<AtomicBool>,
 }
 
 impl Default for ApplicationState {
@@ -123,12 +150,16 @@ impl ApplicationState {
 			selected_instance: None,
 			event_trigger: None,
 			download_event_trigger: None,
+			nexusmods_event_trigger: None,
 			frontend_config: FrontendConfig::new(),
 			instances_errors: Vec::new(),
 			// mounted_vfs: Vec::new(),
 			is_vfs_mounted: false,
+			vfs_output_mod: None,
+			vfs_mounted_before_output: false,
 			running_executables: Arc::new(Mutex::new(Vec::new())),
 			running_executables_id: HashMap::new(),
+			collection_install_running: Arc::new(AtomicBool::new(false)),
 		};
 	}
 
@@ -136,9 +167,11 @@ impl ApplicationState {
 		&mut self,
 		event_trigger: ApiEventTrigger,
 		download_event_trigger: ApiDownloadsEventTrigger,
+		nexusmods_event_trigger: ApiNexusModsEventTrigger,
 	) {
 		self.event_trigger = Some(event_trigger);
 		self.download_event_trigger = Some(download_event_trigger);
+		self.nexusmods_event_trigger = Some(nexusmods_event_trigger);
 	}
 
 	pub fn load_or_new() -> Result<Self, String> {
@@ -153,6 +186,16 @@ impl ApplicationState {
 		let mut state: Self = serde_json::from_str(&json)
 			.map_err(|e| format!("Failed to parse state json: {}", e.to_string()))?;
 
+		// Secrets are not kept in state.json, older plaintext ones get migrated
+		let secrets = secrets::SecretStore::new();
+		let migrated_secrets = match state.application_config.nexusmods.load_api_key(&secrets) {
+			Ok(migrated) => migrated,
+			Err(e) => {
+				println!("Failed to load NexusMods API key: {}", e);
+				false
+			}
+		};
+
 		// Update mounted vfs state
 		state.fetch_mounted_vfs();
 
@@ -181,6 +224,18 @@ impl ApplicationState {
 			}
 		}
 
+		// Rewrite the state without the plaintext secrets,
+		// the backup of the previous state still has them
+		if migrated_secrets {
+			state.save()?;
+
+			let backup_path = root_config_path().join("state.json.backup");
+			if backup_path.exists() {
+				std::fs::remove_file(&backup_path)
+					.map_err(|e| format!("Failed to delete the state backup: {}", e))?;
+			}
+		}
+
 		Ok(state)
 	}
 
@@ -194,6 +249,7 @@ impl ApplicationState {
 		cloned.selected_instance = None;
 		cloned.running_executables_id = HashMap::new();
 		cloned.is_vfs_mounted = false;
+		cloned.vfs_output_mod = None;
 
 		let json = serde_json::to_string(&cloned).map_err(|e| e.to_string())?;
 
@@ -275,20 +331,36 @@ impl ApplicationState {
 			}
 		};
 
-		let mounted_vfs: Vec<Box<dyn BaseVFS>> =
-			match serde_json::from_str(&vfs_json).map_err(|e| e.to_string()) {
-				Ok(vfs) => vfs,
-				Err(e) => {
-					println!("Failed to load VFS state: {}", e);
-					return Vec::new();
+		// Older versions only saved the list of mounted VFS
+		let record = match serde_json::from_str::<MountedVFSRecord>(&vfs_json) {
+			Ok(record) => Ok(record),
+			Err(_) => serde_json::from_str::<Vec<Box<dyn BaseVFS>>>(&vfs_json).map(|vfs| {
+				MountedVFSRecord {
+					vfs,
+					output_mod: None,
 				}
-			};
+			}),
+		};
+		let record = match record.map_err(|e| e.to_string()) {
+			Ok(record) => record,
+			Err(e) => {
+				println!("Failed to load VFS state: {}", e);
+				return Vec::new();
+			}
+		};
+		let mounted_vfs = record.vfs;
 
 		// Update mounted vfs state
 		// state.mounted_vfs = mounted_vfs;
 		let are_vfs_mounted = mounted_vfs.len() > 0;
-		if are_vfs_mounted != self.is_vfs_mounted {
+		// The output mod only matters while mounted
+		let output_mod = match are_vfs_mounted {
+			true => record.output_mod,
+			false => None,
+		};
+		if are_vfs_mounted != self.is_vfs_mounted || output_mod != self.vfs_output_mod {
 			self.is_vfs_mounted = are_vfs_mounted;
+			self.vfs_output_mod = output_mod;
 			self.save()
 				.expect("Failed to save instance while saving VFS mounted state!");
 		}
@@ -296,13 +368,21 @@ impl ApplicationState {
 		return mounted_vfs;
 	}
 
-	pub fn save_mounted_vfs(&mut self, vfs: Vec<Box<dyn BaseVFS>>) -> Result<(), String> {
+	// The output mod is kept with the mounts, so it is known after a restart
+	pub fn save_mounted_vfs(
+		&mut self,
+		vfs: Vec<Box<dyn BaseVFS>>,
+		output_mod: Option<String>,
+	) -> Result<(), String> {
 		let vfs_state_path = root_config_path().join("vfs_state.json");
-		let vfs_json = serde_json::to_string(&vfs).map_err(|e| e.to_string())?;
+		let is_vfs_mounted = vfs.len() > 0;
+		let record = MountedVFSRecord { vfs, output_mod };
+		let vfs_json = serde_json::to_string(&record).map_err(|e| e.to_string())?;
 		controllers::file_controller::save_file(vfs_state_path, vfs_json.as_bytes())
 			.map_err(|e| e.to_string())?;
 
-		self.is_vfs_mounted = vfs.len() > 0;
+		self.is_vfs_mounted = is_vfs_mounted;
+		self.vfs_output_mod = record.output_mod;
 		self.save()?;
 
 		return Ok(());
@@ -315,6 +395,11 @@ impl ApplicationState {
 	}
 
 	pub fn mount_vfs(&mut self) -> Result<(), String> {
+		return self.mount_vfs_with_output(None);
+	}
+
+	// Mount the VFS with the given mod as writable layer (overwrite if none)
+	pub fn mount_vfs_with_output(&mut self, output_mod: Option<String>) -> Result<(), String> {
 		let existing_vfs = self.fetch_mounted_vfs();
 
 		if existing_vfs.len() > 0 {
@@ -323,17 +408,21 @@ impl ApplicationState {
 
 		let fallback_vfs: config::vfs_config::VFSConfig =
 			self.application_config.default_vfs_config.clone();
-		let selected_instance = &self.selected_instance_or_fail();
+		let selected_instance = self.selected_instance_or_fail();
+
+		if let Some(mod_name) = &output_mod {
+			selected_instance.prepare_output_mod(mod_name.clone())?;
+		}
 
 		// Mount VFS
-		let mounted_vfs = selected_instance.mount_vfs(fallback_vfs)?;
+		let mounted_vfs = selected_instance.mount_vfs(fallback_vfs, output_mod.clone())?;
 
 		// Add newly mounted VFSes to existing list
 		// existing_vfs.append(&mut mounted_vfs);
 		// existing_vfs = mounted_vfs;
 
 		// Save and update state
-		self.save_mounted_vfs(mounted_vfs)?;
+		self.save_mounted_vfs(mounted_vfs, output_mod)?;
 
 		return Ok(());
 	}
@@ -345,16 +434,100 @@ impl ApplicationState {
 			mounted_vfs.unmount()?;
 		}
 
-		self.save_mounted_vfs(Vec::new())?;
+		if let Some(selected_instance) = self.selected_instance.as_ref() {
+			selected_instance.undeploy_root_files()?;
+		}
+
+		// Files were written to the output mod while mounted
+		if let Some(mod_name) = self.vfs_output_mod.take() {
+			if let Some(selected_instance) = self.selected_instance.as_mut() {
+				selected_instance.refresh_output_mod(mod_name)?;
+			}
+		}
+
+		self.save_mounted_vfs(Vec::new(), None)?;
 
 		return Ok(());
 	}
 
+	// Remount the VFS if its writable layer is not the given output mod.
+	// Running executables would lose their files, so it is refused while any runs.
+	pub fn mount_vfs_for_output(&mut self, output_mod: Option<String>) -> Result<(), String> {
+		let is_vfs_mounted = self.check_vfs_mounted();
+		if output_mod.is_some() && self.vfs_output_mod.is_none() {
+			self.vfs_mounted_before_output = is_vfs_mounted;
+		}
+
+		if is_vfs_mounted {
+			if self.vfs_output_mod == output_mod {
+				return Ok(());
+			}
+
+			if self.running_executables.lock().unwrap().len() > 0 {
+				return Err(format!(
+					"Stop the running executables first, the VFS writes to \"{}\"",
+					self.vfs_output_mod
+						.clone()
+						.unwrap_or(String::from("overwrite"))
+				));
+			}
+
+			self.unmount_vfs()?;
+		}
+
+		return self.mount_vfs_with_output(output_mod);
+	}
+
+	// Whether executables get the VFS in their own mount namespace, instead of a global mount
+	pub fn is_vfs_isolated(&mut self) -> bool {
+		let fallback_vfs = self.application_config.default_vfs_config.clone();
+
+		return match self.selected_instance.as_ref() {
+			Some(selected_instance) => selected_instance.get_vfs_config(fallback_vfs).isolated,
+			None => false,
+		};
+	}
+
 	// ----------------
 	// Executables
 	// ----------------
 
-	pub fn running_executables_add(&mut self, executable_name: String, child: Child) -> () {
+	// Run an executable with the VFS mounted for it only, nothing is mounted globally
+	pub fn run_executable_isolated(
+		&mut self,
+		executable: InstanceExecutable,
+	) -> Result<(Child, NamespaceRun), String> {
+		// Its files would show up twice
+		if self.check_vfs_mounted() {
+			return Err(String::from(
+				"Unmount the VFS first, executables get their own in isolated mode",
+			));
+		}
+
+		let running_runs: Vec<NamespaceRun> = self
+			.running_executables
+			.lock()
+			.unwrap()
+			.iter()
+			.filter_map(|running_executable| running_executable.namespace_run.clone())
+			.collect();
+
+		let fallback_vfs = self.application_config.default_vfs_config.clone();
+		let selected_instance = self.selected_instance_or_fail();
+
+		if let Some(mod_name) = &executable.output_mod {
+			selected_instance.prepare_output_mod(mod_name.clone())?;
+		}
+
+		return selected_instance.run_executable_isolated(executable, fallback_vfs, &running_runs);
+	}
+
+	pub fn running_executables_add(
+		&mut self,
+		executable_name: String,
+		child: Child,
+		namespace_run: Option<NamespaceRun>,
+	) -> () {
 		self.running_executables_id
 			.entry(executable_name.clone())
 			.or_insert(Vec::new())
@@ -367,6 +540,7 @@ impl ApplicationState {
 		running_executables.push(RunningExecutable {
 			executable_name,
 			child_process: child,
+			namespace_run,
 		});
 	}
 
@@ -386,8 +560,15 @@ impl ApplicationState {
 			self.save().expect("Failed to save state");
 		}
 
-		// Remove from running executables
+		// Remove from running executables, with the work folders of their namespace
 		let mut running_executables = self.running_executables.lock().unwrap();
+		for running_executable in running_executables.iter() {
+			if running_executable.child_process.id() == pid {
+				if let Some(namespace_run) = &running_executable.namespace_run {
+					namespace_run.delete_workdirs();
+				}
+			}
+		}
 		running_executables.retain(|x| x.child_process.id() != pid);
 	}
 
@@ -447,6 +628,39 @@ impl ApplicationState {
 			);
 		}
 
+		// Isolated executables wrote to their output mod directly
+		if exited_children.len() > 0 && self.is_vfs_isolated() {
+			if let Some(selected_instance) = self.selected_instance.as_mut() {
+				for exited_child in exited_children.iter() {
+					let output_mod = selected_instance
+						.config
+						.executables
+						.iter()
+						.find(|executable| executable.name == exited_child.executable_name)
+						.and_then(|executable| executable.output_mod.clone());
+
+					if let Some(mod_name) = output_mod {
+						if let Err(e) = selected_instance.refresh_output_mod(mod_name) {
+							println!("Failed to refresh the output mod: {}", e);
+						}
+					}
+				}
+			}
+		}
+
+		// Output mods are only used while their executable runs,
+		// the VFS is put back the way it was before
+		let running_count = self.running_executables.lock().unwrap().len();
+		if exited_children.len() > 0 && running_count == 0 && self.vfs_output_mod.is_some() {
+			let result = match self.vfs_mounted_before_output {
+				true => self.mount_vfs_for_output(None),
+				false => self.unmount_vfs(),
+			};
+			if let Err(e) = result {
+				println!("Failed to restore the VFS after the output mod: {}", e);
+			}
+		}
+
 		// TODO: Update running executables using a different event
 		if exited_children.len() > 0 {
 			self.trigger_on_state_changed()
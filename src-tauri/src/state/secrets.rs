@@ -0,0 +1,320 @@
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use serde::Serializer;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+
+use super::root_config_path;
+
+// Service name used for every entry we store in the keyring
+pub const SECRETS_SERVICE: &str = "rusty-mod-manager";
+pub const NEXUS_API_KEY_SECRET: &str = "nexusmods-api-key";
+
+// What gets serialized (state.json and frontend) instead of a secret
+pub const REDACTED_SECRET: &str = "********";
+
+const SECRETS_FILE_NAME: &str = "secrets.enc";
+const SECRETS_KEY_FILE_NAME: &str = "secrets.key";
+const NONCE_LENGTH: usize = 12;
+
+// Serde helper, replaces a secret with a placeholder
+pub fn redact_secret<S: Serializer>(value: &String, serializer: S) -> Result<S::Ok, S::Error> {
+	if value.is_empty() {
+		return serializer.serialize_str("");
+	}
+
+	return serializer.serialize_str(REDACTED_SECRET);
+}
+
+pub fn redact_optional_secret<S: Serializer>(
+	value: &Option<String>,
+	serializer: S,
+) -> Result<S::Ok, S::Error> {
+	match value {
+		Some(value) => redact_secret(value, serializer),
+		None => serializer.serialize_none(),
+	}
+}
+
+pub fn is_redacted(value: &str) -> bool {
+	return value == REDACTED_SECRET;
+}
+
+// Stores secrets in the system keyring (Secret Service),
+// falls back to an encrypted file when no keyring daemon is available
+pub struct SecretStore {
+	use_keyring: bool,
+	file: EncryptedFileStore,
+}
+
+impl Default for SecretStore {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl SecretStore {
+	pub fn new() -> Self {
+		return Self {
+			use_keyring: true,
+			file: EncryptedFileStore::new(
+				root_config_path().join(SECRETS_FILE_NAME),
+				root_config_path().join(SECRETS_KEY_FILE_NAME),
+			),
+		};
+	}
+
+	// Store that never touches the keyring, only the encrypted file
+	pub fn file_only(file: EncryptedFileStore) -> Self {
+		return Self {
+			use_keyring: false,
+			file,
+		};
+	}
+
+	fn keyring_entry(&self, name: &str) -> Option<keyring::Entry> {
+		if !self.use_keyring {
+			return None;
+		}
+
+		return keyring::Entry::new(SECRETS_SERVICE, name).ok();
+	}
+
+	pub fn get(&self, name: &str) -> Result<Option<String>, String> {
+		if let Some(entry) = self.keyring_entry(name) {
+			match entry.get_password() {
+				Ok(secret) => return Ok(Some(secret)),
+				// Might have been stored while the keyring was unavailable
+				Err(keyring::Error::NoEntry) => {}
+				Err(e) => println!("Keyring unavailable, using encrypted file: {}", e),
+			}
+		}
+
+		return self.file.get(name);
+	}
+
+	pub fn set(&self, name: &str, secret: &str) -> Result<(), String> {
+		if let Some(entry) = self.keyring_entry(name) {
+			match entry.set_password(secret) {
+				Ok(_) => {
+					// Don't leave an outdated copy behind
+					let _ = self.file.delete(name);
+					return Ok(());
+				}
+				Err(e) => println!("Keyring unavailable, using encrypted file: {}", e),
+			}
+		}
+
+		return self.file.set(name, secret);
+	}
+
+	pub fn delete(&self, name: &str) -> Result<(), String> {
+		if let Some(entry) = self.keyring_entry(name) {
+			match entry.delete_password() {
+				Ok(_) | Err(keyring::Error::NoEntry) => {}
+				Err(e) => println!("Keyring unavailable, using encrypted file: {}", e),
+			}
+		}
+
+		return self.file.delete(name);
+	}
+}
+
+// Secrets encrypted with ChaCha20-Poly1305
+// The key lives next to it in a file only readable by the user,
+// this keeps secrets out of state.json and backups of it
+pub struct EncryptedFileStore {
+	path: PathBuf,
+	key_path: PathBuf,
+}
+
+impl EncryptedFileStore {
+	pub fn new(path: PathBuf, key_path: PathBuf) -> Self {
+		return Self { path, key_path };
+	}
+
+	fn cipher(&self, create_key: bool) -> Result<Option<ChaCha20Poly1305>, String> {
+		if !self.key_path.exists() {
+			if !create_key {
+				return Ok(None);
+			}
+
+			let key = ChaCha20Poly1305::generate_key(&mut rand::thread_rng());
+			write_private_file(&self.key_path, key.as_slice())
+				.map_err(|e| format!("Failed to save secrets key: {}", e))?;
+		}
+
+		let key =
+			fs::read(&self.key_path).map_err(|e| format!("Failed to read secrets key: {}", e))?;
+		if key.len() != 32 {
+			return Err("Invalid secrets key".to_string());
+		}
+
+		return Ok(Some(ChaCha20Poly1305::new(Key::from_slice(&key))));
+	}
+
+	fn read_all(&self) -> Result<HashMap<String, String>, String> {
+		if !self.path.exists() {
+			return Ok(HashMap::new());
+		}
+
+		let cipher = match self.cipher(false)? {
+			Some(cipher) => cipher,
+			None => return Err("Secrets key is missing, can't decrypt secrets".to_string()),
+		};
+
+		let data = fs::read(&self.path).map_err(|e| format!("Failed to read secrets: {}", e))?;
+		if data.len() < NONCE_LENGTH {
+			return Err("Secrets file is corrupted".to_string());
+		}
+
+		let (nonce, ciphertext) = data.split_at(NONCE_LENGTH);
+		let plaintext = cipher
+			.decrypt(Nonce::from_slice(nonce), ciphertext)
+			.map_err(|_| "Failed to decrypt secrets".to_string())?;
+
+		return serde_json::from_slice(&plaintext)
+			.map_err(|e| format!("Failed to parse secrets: {}", e));
+	}
+
+	fn write_all(&self, secrets: &HashMap<String, String>) -> Result<(), String> {
+		if secrets.is_empty() {
+			if self.path.exists() {
+				fs::remove_file(&self.path)
+					.map_err(|e| format!("Failed to delete secrets file: {}", e))?;
+			}
+			return Ok(());
+		}
+
+		let cipher = self.cipher(true)?.unwrap();
+
+		let plaintext = serde_json::to_vec(secrets).map_err(|e| e.to_string())?;
+		let nonce: [u8; NONCE_LENGTH] = rand::random();
+		let ciphertext = cipher
+			.encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+			.map_err(|_| "Failed to encrypt secrets".to_string())?;
+
+		let mut data = nonce.to_vec();
+		data.extend(ciphertext);
+
+		return write_private_file(&self.path, &data)
+			.map_err(|e| format!("Failed to save secrets: {}", e));
+	}
+
+	pub fn get(&self, name: &str) -> Result<Option<String>, String> {
+		return Ok(self.read_all()?.remove(name));
+	}
+
+	pub fn set(&self, name: &str, secret: &str) -> Result<(), String> {
+		let mut secrets = self.read_all()?;
+		secrets.insert(name.to_string(), secret.to_string());
+		return self.write_all(&secrets);
+	}
+
+	pub fn delete(&self, name: &str) -> Result<(), String> {
+		if !self.path.exists() {
+			return Ok(());
+		}
+
+		let mut secrets = self.read_all()?;
+		if secrets.remove(name).is_none() {
+			return Ok(());
+		}
+
+		return self.write_all(&secrets);
+	}
+}
+
+// Write a file readable only by the current user
+fn write_private_file(path: &PathBuf, data: &[u8]) -> std::io::Result<()> {
+	if let Some(parent) = path.parent() {
+		fs::create_dir_all(parent)?;
+	}
+
+	let temp_path = path.with_extension("tmp");
+	let mut file = fs::OpenOptions::new()
+		.write(true)
+		.create(true)
+		.truncate(true)
+		.mode(0o600)
+		.open(&temp_path)?;
+	file.write_all(data)?;
+	file.sync_all()?;
+
+	return fs::rename(temp_path, path);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn test_store(dir: &tempfile::TempDir) -> EncryptedFileStore {
+		return EncryptedFileStore::new(
+			dir.path().join("secrets.enc"),
+			dir.path().join("secrets.key"),
+		);
+	}
+
+	#[test]
+	fn encrypted_file_round_trip() {
+		let dir = tempfile::tempdir().unwrap();
+		let store = test_store(&dir);
+
+		assert_eq!(store.get("api-key"), Ok(None));
+
+		store.set("api-key", "my-secret-key").unwrap();
+		assert_eq!(store.get("api-key"), Ok(Some("my-secret-key".to_string())));
+
+		// The secret is not stored in plaintext
+		let data = fs::read(dir.path().join("secrets.enc")).unwrap();
+		assert!(!String::from_utf8_lossy(&data).contains("my-secret-key"));
+
+		// Only the user can read the key
+		let mode = fs::metadata(dir.path().join("secrets.key"))
+			.unwrap()
+			.permissions();
+		assert_eq!(
+			std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+			0o600
+		);
+
+		store.delete("api-key").unwrap();
+		assert_eq!(store.get("api-key"), Ok(None));
+	}
+
+	#[test]
+	fn encrypted_file_rejects_other_keys() {
+		let dir = tempfile::tempdir().unwrap();
+		let store = test_store(&dir);
+		store.set("api-key", "my-secret-key").unwrap();
+
+		// Replace the key, the secrets can't be decrypted anymore
+		fs::write(dir.path().join("secrets.key"), [7u8; 32]).unwrap();
+
+		assert_eq!(
+			store.get("api-key"),
+			Err("Failed to decrypt secrets".to_string())
+		);
+	}
+
+	#[test]
+	fn redacts_secrets() {
+		#[derive(serde::Serialize)]
+		struct WithSecret {
+			#[serde(serialize_with = "redact_optional_secret")]
+			api_key: Option<String>,
+		}
+
+		let json = serde_json::to_string(&WithSecret {
+			api_key: Some("my-secret-key".to_string()),
+		})
+		.unwrap();
+		assert_eq!(json, r#"{"api_key":"********"}"#);
+
+		let json = serde_json::to_string(&WithSecret { api_key: None }).unwrap();
+		assert_eq!(json, r#"{"api_key":null}"#);
+	}
+}
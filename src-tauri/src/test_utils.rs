@@ -0,0 +1,87 @@
+// Helpers shared by the tests of several modules
+
+use std::path::{Path, PathBuf};
+
+use crate::instances::instance_mod::{InstanceMod, ModInfo};
+use crate::instances::{
+	GameInstance, GameInstanceDeploymentPaths, GameInstanceInternalPaths, GameInstancePaths,
+};
+
+// Instance in "root", the game folder and its "Data" folder are in there too
+pub fn test_instance(root: &Path, name: &str) -> GameInstance {
+	let root = root.to_path_buf();
+	let mut instance = GameInstance::new(
+		name.to_string(),
+		GameInstancePaths {
+			root: root.clone(),
+			game: root.join("game"),
+			internal: GameInstanceInternalPaths {
+				mods: PathBuf::from("$instance/mods"),
+				downloads: PathBuf::from("$instance/downloads"),
+				settings: PathBuf::from("$instance/settings"),
+				saves: PathBuf::from("$instance/saves"),
+			},
+			deployment: GameInstanceDeploymentPaths {
+				mods: root.join("game/Data"),
+				settings: None,
+				saves: None,
+			},
+		},
+	)
+	.unwrap();
+	instance.load_mods().unwrap();
+
+	return instance;
+}
+
+// Add a version to a mod (created if missing) with the given files, their content is the mod name
+pub fn add_mod_with_files(
+	instance: &mut GameInstance,
+	name: &str,
+	version: &str,
+	files: Vec<&str>,
+) -> InstanceMod {
+	let instance_mod = instance
+		.create_mod_version(name.to_string(), version.to_string(), ModInfo::default())
+		.unwrap();
+
+	let version_path = instance_mod.get_selected_version_absolute_path();
+	for file in files {
+		let path = version_path.join(file);
+		std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+		std::fs::write(path, name).unwrap();
+	}
+
+	instance.load_mods().unwrap();
+
+	return instance.get_mod_by_name(name.to_string()).unwrap().clone();
+}
+
+// Uncompressed tar archive, every extractor reads it
+pub fn tar_archive(files: Vec<(&str, &str)>) -> Vec<u8> {
+	let mut archive = Vec::new();
+	for (path, content) in files {
+		let mut header = [0u8; 512];
+		header[..path.len()].copy_from_slice(path.as_bytes());
+		header[100..107].copy_from_slice(b"0000644");
+		header[108..115].copy_from_slice(b"0000000");
+		header[116..123].copy_from_slice(b"0000000");
+		header[124..135].copy_from_slice(format!("{:011o}", content.len()).as_bytes());
+		header[136..147].copy_from_slice(b"00000000000");
+		header[156] = b'0';
+		header[257..263].copy_from_slice(b"ustar\0");
+		header[263..265].copy_from_slice(b"00");
+
+		// The checksum is computed with its own field filled with spaces
+		header[148..156].copy_from_slice(b"        ");
+		let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+		header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+
+		archive.extend_from_slice(&header);
+		archive.extend_from_slice(content.as_bytes());
+		archive.resize((archive.len() + 511) / 512 * 512, 0);
+	}
+	archive.resize(archive.len() + 1024, 0);
+
+	return archive;
+}